@@ -0,0 +1,412 @@
+//! Startup latency of containers in the shim, against a fake containerd.
+//!
+//! Each iteration drives an instance of the reference engine through the calls containerd
+//! makes to create and start a task, and measures:
+//! * `create`: building the container, i.e., fetching its modules, the zygote and executor setup,
+//! * `start`: starting the container, until `start` returns its pid,
+//! * `first-instruction`: from the start of the container to the first instruction of its guest,
+//!   which writes a byte to stdout as soon as `_start` is entered.
+//!
+//! The guest is padded with a custom section to measure how startup scales with the size of
+//! the module. The fake containerd serves the container, its image, the content of the image
+//! and the leases of the shim from memory. The image has no wasm layers, so the module is read
+//! from the rootfs, and every other method is unimplemented.
+
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::path::Path;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use containerd_client::services::v1::{
+    Container, CreateRequest, CreateResponse, DeleteRequest, GetContainerRequest,
+    GetContainerResponse, GetImageRequest, GetImageResponse, Image, Info, InfoRequest,
+    InfoResponse, Lease, ReadContentRequest, ReadContentResponse,
+};
+use containerd_client::types::Descriptor;
+use containerd_shim_wasm::testing::WasiTest;
+use containerd_shim_wasmtime::WasmtimeInstance;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use tokio_stream::wrappers::UnixListenerStream;
+use tonic::body::BoxBody;
+use tonic::codec::ProstCodec;
+use tonic::codegen::{empty_body, http, Body, BoxFuture, Service, StdError};
+use tonic::server::{Grpc, NamedService, ServerStreamingService, UnaryService};
+use tonic::transport::Server;
+use tonic::{Code, Request, Response, Status};
+
+static MODULE_SIZES: &[usize] = &[0, 1 << 20, 16 << 20];
+
+/// The guest writes a byte to stdout with its first instruction.
+const GUEST: &str = r#"
+(module
+  (import "wasi_snapshot_preview1" "fd_write"
+    (func $fd_write (param i32 i32 i32 i32) (result i32)))
+  (memory (export "memory") 1)
+  ;; the iovec of the byte at offset 16
+  (data (i32.const 0) "\10\00\00\00\01\00\00\00")
+  (data (i32.const 16) "!")
+  (func (export "_start")
+    (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))))
+"#;
+
+const IMAGE_NAME: &str = "docker.io/library/startup-latency:latest";
+
+/// Returns the guest, padded to `size` bytes more with a custom section.
+fn padded_module(size: usize) -> Vec<u8> {
+    let mut module = wat::parse_str(GUEST).unwrap();
+    if size == 0 {
+        return module;
+    }
+    let name = b"padding";
+    let mut section = leb128(name.len());
+    section.extend_from_slice(name);
+    section.resize(section.len() + size, 0);
+
+    module.push(0); // custom section
+    module.extend(leb128(section.len()));
+    module.extend(section);
+    module
+}
+
+fn leb128(mut value: usize) -> Vec<u8> {
+    let mut bytes = vec![];
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            bytes.push(byte);
+            return bytes;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+/// The methods of containerd used by the shim to create and delete a container, served from
+/// memory for a single image without wasm layers.
+#[derive(Clone)]
+struct FakeContainerd {
+    manifest: Descriptor,
+    blobs: Arc<HashMap<String, Vec<u8>>>,
+}
+
+impl FakeContainerd {
+    fn new() -> Self {
+        let config = br#"{"architecture":"amd64","os":"linux"}"#.to_vec();
+        let config_digest = format!("sha256:{}", sha256::digest(config.as_slice()));
+        let manifest = format!(
+            r#"{{"schemaVersion":2,"mediaType":"application/vnd.oci.image.manifest.v1+json","config":{{"mediaType":"application/vnd.oci.image.config.v1+json","digest":"{config_digest}","size":{}}},"layers":[]}}"#,
+            config.len()
+        )
+        .into_bytes();
+        let manifest_digest = format!("sha256:{}", sha256::digest(manifest.as_slice()));
+
+        let descriptor = Descriptor {
+            media_type: "application/vnd.oci.image.manifest.v1+json".to_string(),
+            digest: manifest_digest.clone(),
+            size: manifest.len() as i64,
+            ..Default::default()
+        };
+        let blobs = HashMap::from([(config_digest, config), (manifest_digest, manifest)]);
+        Self {
+            manifest: descriptor,
+            blobs: Arc::new(blobs),
+        }
+    }
+
+    fn blob(&self, digest: &str) -> Result<&[u8], Status> {
+        self.blobs
+            .get(digest)
+            .map(Vec::as_slice)
+            .ok_or_else(|| Status::not_found(format!("content {digest} not found")))
+    }
+
+    async fn serve<B>(self, req: http::Request<B>) -> http::Response<BoxBody>
+    where
+        B: Body + Send + 'static,
+        B::Error: Into<StdError> + Send + 'static,
+    {
+        match req.uri().path() {
+            "/containerd.services.containers.v1.Containers/Get" => {
+                unary(req, |req: GetContainerRequest| {
+                    let container = Container {
+                        id: req.id,
+                        image: IMAGE_NAME.to_string(),
+                        ..Default::default()
+                    };
+                    Ok(GetContainerResponse {
+                        container: Some(container),
+                    })
+                })
+                .await
+            }
+            "/containerd.services.images.v1.Images/Get" => {
+                unary(req, move |req: GetImageRequest| {
+                    if req.name != IMAGE_NAME {
+                        return Err(Status::not_found(format!("image {} not found", req.name)));
+                    }
+                    let image = Image {
+                        name: req.name,
+                        target: Some(self.manifest.clone()),
+                        ..Default::default()
+                    };
+                    Ok(GetImageResponse { image: Some(image) })
+                })
+                .await
+            }
+            "/containerd.services.content.v1.Content/Info" => {
+                unary(req, move |req: InfoRequest| {
+                    let info = Info {
+                        size: self.blob(&req.digest)?.len() as i64,
+                        digest: req.digest,
+                        ..Default::default()
+                    };
+                    Ok(InfoResponse { info: Some(info) })
+                })
+                .await
+            }
+            "/containerd.services.content.v1.Content/Read" => {
+                server_streaming(req, move |req: ReadContentRequest| {
+                    let blob = self.blob(&req.digest)?;
+                    let offset = usize::try_from(req.offset)
+                        .ok()
+                        .filter(|offset| *offset <= blob.len())
+                        .ok_or_else(|| Status::out_of_range("offset out of range"))?;
+                    Ok(ReadContentResponse {
+                        offset: req.offset,
+                        data: blob[offset..].to_vec(),
+                    })
+                })
+                .await
+            }
+            "/containerd.services.leases.v1.Leases/Create" => {
+                unary(req, |req: CreateRequest| {
+                    let lease = Lease {
+                        id: req.id,
+                        labels: req.labels,
+                        ..Default::default()
+                    };
+                    Ok(CreateResponse { lease: Some(lease) })
+                })
+                .await
+            }
+            "/containerd.services.leases.v1.Leases/Delete" => {
+                unary(req, |_: DeleteRequest| Ok(())).await
+            }
+            _ => {
+                let mut response = http::Response::new(empty_body());
+                let headers = response.headers_mut();
+                headers.insert(Status::GRPC_STATUS, (Code::Unimplemented as i32).into());
+                headers.insert(
+                    http::header::CONTENT_TYPE,
+                    tonic::metadata::GRPC_CONTENT_TYPE,
+                );
+                response
+            }
+        }
+    }
+}
+
+/// Answers a unary request with `handle`.
+async fn unary<Req, Resp, B>(
+    req: http::Request<B>,
+    handle: impl FnMut(Req) -> Result<Resp, Status> + Send + 'static,
+) -> http::Response<BoxBody>
+where
+    Req: prost::Message + Default + Send + 'static,
+    Resp: prost::Message + Send + 'static,
+    B: Body + Send + 'static,
+    B::Error: Into<StdError> + Send + 'static,
+{
+    Grpc::new(ProstCodec::<Resp, Req>::default())
+        .unary(Handler(handle), req)
+        .await
+}
+
+/// Answers a server streaming request with the single message returned by `handle`.
+async fn server_streaming<Req, Resp, B>(
+    req: http::Request<B>,
+    handle: impl FnMut(Req) -> Result<Resp, Status> + Send + 'static,
+) -> http::Response<BoxBody>
+where
+    Req: prost::Message + Default + Send + 'static,
+    Resp: prost::Message + Send + 'static,
+    B: Body + Send + 'static,
+    B::Error: Into<StdError> + Send + 'static,
+{
+    Grpc::new(ProstCodec::<Resp, Req>::default())
+        .server_streaming(Handler(handle), req)
+        .await
+}
+
+struct Handler<F>(F);
+
+impl<Req, Resp, F> UnaryService<Req> for Handler<F>
+where
+    F: FnMut(Req) -> Result<Resp, Status>,
+{
+    type Response = Resp;
+    type Future = std::future::Ready<Result<Response<Resp>, Status>>;
+
+    fn call(&mut self, req: Request<Req>) -> Self::Future {
+        std::future::ready((self.0)(req.into_inner()).map(Response::new))
+    }
+}
+
+impl<Req, Resp, F> ServerStreamingService<Req> for Handler<F>
+where
+    F: FnMut(Req) -> Result<Resp, Status>,
+    Resp: Send + 'static,
+{
+    type Response = Resp;
+    type ResponseStream = tokio_stream::Once<Result<Resp, Status>>;
+    type Future = std::future::Ready<Result<Response<Self::ResponseStream>, Status>>;
+
+    fn call(&mut self, req: Request<Req>) -> Self::Future {
+        let stream = (self.0)(req.into_inner()).map(|msg| tokio_stream::once(Ok(msg)));
+        std::future::ready(stream.map(Response::new))
+    }
+}
+
+// the server routes the requests of each service by its name
+macro_rules! services {
+    ($($service:ident => $name:literal),* $(,)?) => {$(
+        #[derive(Clone)]
+        struct $service(FakeContainerd);
+
+        impl NamedService for $service {
+            const NAME: &'static str = $name;
+        }
+
+        impl<B> Service<http::Request<B>> for $service
+        where
+            B: Body + Send + 'static,
+            B::Error: Into<StdError> + Send + 'static,
+        {
+            type Response = http::Response<BoxBody>;
+            type Error = Infallible;
+            type Future = BoxFuture<Self::Response, Self::Error>;
+
+            fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+                Poll::Ready(Ok(()))
+            }
+
+            fn call(&mut self, req: http::Request<B>) -> Self::Future {
+                let containerd = self.0.clone();
+                Box::pin(async move { Ok(containerd.serve(req).await) })
+            }
+        }
+    )*};
+}
+
+services! {
+    Containers => "containerd.services.containers.v1.Containers",
+    Images => "containerd.services.images.v1.Images",
+    Content => "containerd.services.content.v1.Content",
+    Leases => "containerd.services.leases.v1.Leases",
+}
+
+/// Serves a fake containerd on `path`.
+fn fake_containerd(path: &Path) -> anyhow::Result<()> {
+    let listener = std::os::unix::net::UnixListener::bind(path)?;
+    listener.set_nonblocking(true)?;
+    let containerd = FakeContainerd::new();
+    thread::spawn(move || {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_io()
+            .build()
+            .unwrap();
+        rt.block_on(async move {
+            let listener = tokio::net::UnixListener::from_std(listener).unwrap();
+            Server::builder()
+                .add_service(Containers(containerd.clone()))
+                .add_service(Images(containerd.clone()))
+                .add_service(Content(containerd.clone()))
+                .add_service(Leases(containerd))
+                .serve_with_incoming(UnixListenerStream::new(listener))
+                .await
+                .unwrap();
+        });
+    });
+    Ok(())
+}
+
+#[derive(Default)]
+struct Latencies {
+    create: Duration,
+    start: Duration,
+    first_instruction: Duration,
+}
+
+fn run_container(containerd: &Path, module: &[u8]) -> anyhow::Result<Latencies> {
+    let stdout = tempfile::NamedTempFile::new()?;
+    let builder = WasiTest::<WasmtimeInstance>::builder()?
+        .with_containerd_address(containerd.to_string_lossy())
+        .with_wasm(module)?
+        .with_stdout(stdout.path())?;
+
+    let creating = Instant::now();
+    let test = builder.build()?;
+    let create = creating.elapsed();
+
+    let starting = Instant::now();
+    test.start()?;
+    let start = starting.elapsed();
+    while std::fs::metadata(stdout.path())?.len() == 0 {
+        if starting.elapsed() > Duration::from_secs(10) {
+            anyhow::bail!("the guest didn't write to stdout");
+        }
+        thread::yield_now();
+    }
+    let first_instruction = starting.elapsed();
+
+    test.wait(Duration::from_secs(10))?;
+    test.delete()?;
+    Ok(Latencies {
+        create,
+        start,
+        first_instruction,
+    })
+}
+
+fn benchmark_startup(c: &mut Criterion) {
+    let dir = tempfile::tempdir().unwrap();
+    let containerd = dir.path().join("containerd.sock");
+    fake_containerd(&containerd).unwrap();
+
+    let mut group = c.benchmark_group("startup");
+    for &size in MODULE_SIZES {
+        let module = padded_module(size);
+        let phases: [(&str, fn(&Latencies) -> Duration); 3] = [
+            ("create", |l| l.create),
+            ("start", |l| l.start),
+            ("first-instruction", |l| l.first_instruction),
+        ];
+        for (phase, latency) in phases {
+            group.bench_with_input(
+                BenchmarkId::new(format!("wasmtime/{phase}"), size),
+                &module,
+                |b, module| {
+                    b.iter_custom(|iters| {
+                        (0..iters)
+                            .map(|_| latency(&run_container(&containerd, module).unwrap()))
+                            .sum()
+                    })
+                },
+            );
+        }
+    }
+    group.finish();
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default()
+        .sample_size(10)
+        .warm_up_time(Duration::from_secs(3));
+    targets = benchmark_startup
+}
+
+criterion_main!(benches);
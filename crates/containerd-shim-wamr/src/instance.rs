@@ -1,5 +1,7 @@
-use anyhow::{Context, Result};
-use containerd_shim_wasm::container::{Engine, Entrypoint, Instance, RuntimeContext};
+use anyhow::{bail, Context, Result};
+use containerd_shim_wasm::container::{
+    Engine, Entrypoint, Instance, RuntimeContext, WRITE_ALLOW_ANNOTATION,
+};
 use wamr_rust_sdk::function::Function;
 use wamr_rust_sdk::instance::Instance as WamrInst;
 use wamr_rust_sdk::module::Module;
@@ -19,8 +21,15 @@ unsafe impl Sync for WamrEngine {}
 
 impl Default for WamrEngine {
     fn default() -> Self {
-        let runtime = Runtime::new().unwrap();
-        Self { runtime }
+        Self::new().unwrap()
+    }
+}
+
+impl WamrEngine {
+    fn new() -> Result<Self> {
+        let runtime = Runtime::new()
+            .map_err(|err| anyhow::anyhow!("failed to create the wamr runtime: {err:?}"))?;
+        Ok(Self { runtime })
     }
 }
 
@@ -36,7 +45,21 @@ impl Engine for WamrEngine {
         "wamr"
     }
 
+    fn try_default() -> Result<Self> {
+        Self::new()
+    }
+
     fn run_wasi(&self, ctx: &impl RuntimeContext) -> Result<i32> {
+        if ctx.capabilities().is_strict() {
+            bail!("strict WASI mode is not supported by wamr");
+        }
+        if ctx.capabilities().clock_throttle().is_some() {
+            bail!("throttled clocks are not supported by wamr");
+        }
+        if !ctx.write_policy()?.is_unrestricted() {
+            bail!("the {WRITE_ALLOW_ANNOTATION} annotation is not supported by wamr");
+        }
+
         let args = ctx.args();
         let envs = ctx.envs();
         let Entrypoint {
@@ -0,0 +1,27 @@
+use std::path::PathBuf;
+
+use ttrpc_codegen::{Codegen, Customize, ProtobufCustomize};
+
+fn main() {
+    println!("cargo:rerun-if-changed=protos");
+
+    let out_dir = PathBuf::from(std::env::var("OUT_DIR").unwrap()).join("protos");
+    std::fs::create_dir_all(&out_dir).unwrap();
+
+    Codegen::new()
+        .out_dir(&out_dir)
+        .inputs(["protos/manager.proto"])
+        .include("protos")
+        .rust_protobuf()
+        .customize(Customize::default())
+        .rust_protobuf_customize(ProtobufCustomize::default().gen_mod_rs(false))
+        .run()
+        .expect("failed to generate the code of the protos");
+
+    // the ttrpc services refer to their messages as `super::<proto>`
+    std::fs::write(
+        out_dir.join("mod.rs"),
+        "pub mod manager;\npub mod manager_ttrpc;\n",
+    )
+    .unwrap();
+}
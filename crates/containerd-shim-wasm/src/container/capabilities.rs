@@ -0,0 +1,82 @@
+//! Optional host capabilities a guest is allowed to use.
+//!
+//! Some multi-tenant platforms require a minimal WASI surface for the containers of some
+//! namespaces, regardless of what the engine supports. When `strict_wasi` is set in the runtime
+//! configuration, the guests in its namespaces get:
+//! * no sockets, and no name lookups,
+//! * no `wasi:http`, neither incoming nor outgoing,
+//! * no `wasi:nn`,
+//! * a read-only filesystem, whatever the `runwasi.io/write-allow` annotation says.
+//!
+//! The policy is set by the operator of the node, and can't be relaxed by a container.
+//! Engines must refuse to run a guest that needs a capability they can't disable.
+//!
+//! Independently, the clocks of the guests can be throttled, see
+//! [`ThrottledClock`](crate::container::ThrottledClock).
+
+use serde::{Deserialize, Serialize};
+
+use crate::container::clock::ClockThrottle;
+
+/// The optional host capabilities a guest is allowed to use.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Capabilities {
+    strict: bool,
+    clock_throttle: Option<ClockThrottle>,
+}
+
+impl Capabilities {
+    /// All the capabilities the engine supports.
+    pub fn all() -> Self {
+        Self {
+            strict: false,
+            clock_throttle: None,
+        }
+    }
+
+    /// The minimal WASI surface of the strict WASI mode.
+    pub fn strict() -> Self {
+        Self {
+            strict: true,
+            clock_throttle: None,
+        }
+    }
+
+    /// Throttles the clocks of the guest with `throttle`.
+    pub fn with_clock_throttle(mut self, throttle: ClockThrottle) -> Self {
+        self.clock_throttle = Some(throttle);
+        self
+    }
+
+    /// Returns true if the guest runs in strict WASI mode.
+    pub fn is_strict(&self) -> bool {
+        self.strict
+    }
+
+    /// Returns true if the guest can open sockets and look up names.
+    pub fn network(&self) -> bool {
+        !self.strict
+    }
+
+    /// Returns true if the guest can serve and send HTTP requests with `wasi:http`.
+    pub fn http(&self) -> bool {
+        !self.strict
+    }
+
+    /// Returns true if the guest can use `wasi:nn`.
+    pub fn nn(&self) -> bool {
+        !self.strict
+    }
+
+    /// Returns true if the guest can write to the filesystem, as allowed by its write policy.
+    pub fn writable_fs(&self) -> bool {
+        !self.strict
+    }
+
+    /// Returns how the clocks of the guest are throttled, if they are.
+    /// Engines read the clocks of the guest through a
+    /// [`ThrottledClock`](crate::container::ThrottledClock) then.
+    pub fn clock_throttle(&self) -> Option<ClockThrottle> {
+        self.clock_throttle
+    }
+}
@@ -0,0 +1,117 @@
+//! Throttled clocks, to reduce the timing side channels of guests.
+//!
+//! Precise clocks let a guest time the caches and branch predictors it shares with the other
+//! tenants of the node. When `throttled_clock` is set in the runtime configuration, the
+//! [`Capabilities`](crate::container::Capabilities) of the guests in its namespaces carry a
+//! [`ClockThrottle`], and engines read the `wasi:clocks` of the guest through a
+//! [`ThrottledClock`], which:
+//! * rounds the time down to a multiple of the resolution of the throttle,
+//! * returns the last time it returned once a guest reads it more than `max_reads_per_sec`
+//!   times in a second, so that a guest can't average many reads to recover the precision.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+/// How the clocks of a guest are throttled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClockThrottle {
+    /// The clocks only advance by multiples of this.
+    pub resolution: Duration,
+    /// Reads of a clock per second, past which the clock doesn't advance until the next second.
+    pub max_reads_per_sec: Option<u32>,
+}
+
+/// A clock of a guest, coarsened and rate limited by a [`ClockThrottle`].
+pub struct ThrottledClock {
+    throttle: ClockThrottle,
+    reads: Mutex<Reads>,
+}
+
+struct Reads {
+    window_start: Instant,
+    count: u32,
+    last: Option<Duration>,
+}
+
+impl ThrottledClock {
+    /// Creates a clock of a guest throttled by `throttle`, with no reads yet.
+    pub fn new(throttle: ClockThrottle) -> Self {
+        Self {
+            throttle,
+            reads: Mutex::new(Reads {
+                window_start: Instant::now(),
+                count: 0,
+                last: None,
+            }),
+        }
+    }
+
+    /// The resolution of the clock, as reported to the guest.
+    pub fn resolution(&self) -> Duration {
+        self.throttle.resolution.max(Duration::from_nanos(1))
+    }
+
+    /// Returns the time `now` of the underlying clock, as seen by the guest.
+    pub fn read(&self, now: impl FnOnce() -> Duration) -> Duration {
+        self.read_at(Instant::now(), now)
+    }
+
+    fn read_at(&self, at: Instant, now: impl FnOnce() -> Duration) -> Duration {
+        let mut reads = self.reads.lock().unwrap();
+        if at.duration_since(reads.window_start) >= Duration::from_secs(1) {
+            reads.window_start = at;
+            reads.count = 0;
+        }
+        reads.count = reads.count.saturating_add(1);
+
+        let throttled = self
+            .throttle
+            .max_reads_per_sec
+            .is_some_and(|max| reads.count > max);
+        if let (true, Some(last)) = (throttled, reads.last) {
+            return last;
+        }
+
+        let resolution = self.resolution().as_nanos();
+        let now = now().as_nanos();
+        let coarse = Duration::from_nanos((now - now % resolution) as u64);
+        reads.last = Some(coarse);
+        coarse
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clock(max_reads_per_sec: Option<u32>) -> ThrottledClock {
+        ThrottledClock::new(ClockThrottle {
+            resolution: Duration::from_millis(1),
+            max_reads_per_sec,
+        })
+    }
+
+    #[test]
+    fn test_resolution_is_coarsened() {
+        let clock = clock(None);
+        let now = Duration::new(12, 345_678_901);
+        assert_eq!(clock.read(|| now), Duration::new(12, 345_000_000));
+        assert_eq!(clock.resolution(), Duration::from_millis(1));
+    }
+
+    #[test]
+    fn test_reads_are_rate_limited() {
+        let clock = clock(Some(2));
+        let start = Instant::now();
+        let ms = Duration::from_millis;
+
+        assert_eq!(clock.read_at(start, || ms(10)), ms(10));
+        assert_eq!(clock.read_at(start, || ms(20)), ms(20));
+        assert_eq!(clock.read_at(start, || ms(30)), ms(20));
+
+        let next_window = start + Duration::from_secs(1);
+        assert_eq!(clock.read_at(next_window, || ms(40)), ms(40));
+    }
+}
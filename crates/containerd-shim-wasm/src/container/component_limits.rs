@@ -0,0 +1,110 @@
+//! Limits on the components run by the shim.
+//!
+//! A hostile component can exhaust the memory of the host with a deep tree of nested
+//! components, many instances, or by churning through resource handles. The limits are set
+//! with `component_limits` in the runtime configuration, and apply to all the containers of the
+//! shim. Engines get them with [`RuntimeContext::component_limits`](crate::container::RuntimeContext::component_limits).
+//!
+//! [`ComponentLimits::check`] enforces the limits that can be read from the binary of a
+//! component before it's compiled: components can't instantiate more instances than they
+//! declare. Engines should enforce `max_instances` at runtime too, for precompiled components,
+//! and `max_resource_handles` on the resource tables of their stores.
+
+use anyhow::{bail, Result};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use wasmparser::{Parser, Payload};
+
+/// Limits on the components run by the shim. Unset limits are unlimited.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct ComponentLimits {
+    /// Maximum number of core and component instances of a component, including the ones
+    /// of its nested components.
+    pub max_instances: Option<u32>,
+    /// Maximum number of resource handles a component holds at once.
+    pub max_resource_handles: Option<u32>,
+    /// Maximum depth of the tree of nested components of a component.
+    pub max_nesting_depth: Option<u32>,
+}
+
+impl ComponentLimits {
+    /// Checks the limits that can be read from the binary of the `component`: its instances
+    /// and the depth of its nested components.
+    pub fn check(&self, component: &[u8]) -> Result<()> {
+        if self.max_instances.is_none() && self.max_nesting_depth.is_none() {
+            return Ok(());
+        }
+
+        // whether each section being parsed is a nested component, or a core module
+        let mut nested = vec![];
+        let mut depth = 0;
+        let mut instances = 0u32;
+        for payload in Parser::new(0).parse_all(component) {
+            match payload? {
+                Payload::ComponentSection { .. } => {
+                    nested.push(true);
+                    depth += 1;
+                    if let Some(max) = self.max_nesting_depth.filter(|max| depth > *max) {
+                        bail!("the component nests components deeper than {max} levels");
+                    }
+                }
+                Payload::ModuleSection { .. } => nested.push(false),
+                Payload::End(_) => {
+                    if nested.pop() == Some(true) {
+                        depth -= 1;
+                    }
+                }
+                Payload::InstanceSection(reader) => instances += reader.count(),
+                Payload::ComponentInstanceSection(reader) => instances += reader.count(),
+                _ => {}
+            }
+            if let Some(max) = self.max_instances.filter(|max| instances > *max) {
+                bail!("the component has more than {max} instances");
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NESTED: &str = r#"(component
+        (component $a
+            (component $b
+                (core module $m)
+                (core instance (instantiate $m))
+            )
+            (instance (instantiate $b))
+        )
+        (instance (instantiate $a))
+    )"#;
+
+    #[test]
+    fn test_check() -> Result<()> {
+        let component = wat::parse_str(NESTED)?;
+        ComponentLimits::default().check(&component)?;
+
+        let limits = ComponentLimits {
+            max_instances: Some(3),
+            max_nesting_depth: Some(2),
+            ..Default::default()
+        };
+        limits.check(&component)?;
+
+        let limits = ComponentLimits {
+            max_instances: Some(2),
+            ..Default::default()
+        };
+        limits.check(&component).unwrap_err();
+
+        let limits = ComponentLimits {
+            max_nesting_depth: Some(1),
+            ..Default::default()
+        };
+        limits.check(&component).unwrap_err();
+        Ok(())
+    }
+}
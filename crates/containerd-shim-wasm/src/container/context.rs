@@ -1,41 +1,169 @@
-use std::borrow::Cow;
+use std::fs::File;
+use std::io::Write;
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::Duration;
 
-use anyhow::{bail, Context};
+use anyhow::Context;
 use oci_spec::image::Platform;
 use oci_spec::runtime::Spec;
 
+use crate::container::capabilities::Capabilities;
+use crate::container::component_limits::ComponentLimits;
+use crate::container::drain::{DrainWindow, DRAIN_WINDOW_ANNOTATION};
+use crate::container::entrypoint::split_entrypoint;
+use crate::container::exit_report::Terminator;
+use crate::container::guest_log::{GuestLogger, GUEST_LOG_ANNOTATION};
+use crate::container::inherit_fd::InheritedFd;
+use crate::container::instance_info::InstanceInfo;
+use crate::container::layers::{self, WasmLayers};
+use crate::container::module_bytes::ModuleBytes;
 use crate::container::path::PathResolve;
+use crate::container::precompiled_artifact::PrecompiledArtifact;
+use crate::container::termination::{
+    TerminationDeadline, DEFAULT_TERMINATION_GRACE_PERIOD, TERMINATION_GRACE_PERIOD_ANNOTATION,
+};
+use crate::container::write_policy::{WritePolicy, WRITE_ALLOW_ANNOTATION};
 use crate::sandbox::oci::WasmLayer;
 
 /// The `RuntimeContext` trait provides access to the runtime context that includes
 /// the arguments, environment variables, and entrypoint for the container.
+///
+/// The methods that give access to the optional features of the shim have a default
+/// implementation, for the contexts that don't support them.
 pub trait RuntimeContext {
-    // ctx.args() returns arguments from the runtime spec process field, including the
-    // path to the entrypoint executable.
+    /// ctx.args() returns arguments from the runtime spec process field, including the
+    /// path to the entrypoint executable.
     fn args(&self) -> &[String];
 
-    // ctx.envs() returns environment variables in the format `ENV_VAR_NAME=VALUE` from the runtime spec process field.
+    /// ctx.envs() returns environment variables in the format `ENV_VAR_NAME=VALUE` from the runtime spec process field.
     fn envs(&self) -> &[String];
 
-    // ctx.entrypoint() returns a `Entrypoint` with the following fields obtained from the first argument in the OCI spec for entrypoint:
-    //   - `arg0` - raw entrypoint from the OCI spec
-    //   - `name` - provided as the file name of the module in the entrypoint without the extension
-    //   - `func` - name of the exported function to call, obtained from the
-    // arguments on process OCI spec.
-    //  - `Source` - either a `File(PathBuf)` or `Oci(WasmLayer)`. When a `File` source the `PathBuf`` is provided by entrypoint in OCI spec.
-    //     If the image contains custom OCI Wasm layers, the source is provided as an array of `WasmLayer` structs.
-    //
-    // The first argument in the OCI spec for entrypoint is specified as `path#func` where `func` is optional
-    // and defaults to _start, e.g.:
-    //   "/app/app.wasm#entry" -> { source: File("/app/app.wasm"), func: "entry", name: "Some(app)", arg0: "/app/app.wasm#entry" }
-    //   "my_module.wat" -> { source: File("my_module.wat"), func: "_start", name: "Some(my_module)", arg0: "my_module.wat" }
-    //   "#init" -> { source: File(""), func: "init", name: None, arg0: "#init" }
+    /// ctx.entrypoint() returns a `Entrypoint` with the following fields obtained from the first argument in the OCI spec for entrypoint:
+    ///   - `arg0` - raw entrypoint from the OCI spec
+    ///   - `name` - provided as the file name of the module in the entrypoint without the extension
+    ///   - `func` - name of the exported function to call, obtained from the
+    ///     arguments on process OCI spec.
+    ///   - `Source` - either a `File(PathBuf)` or `Oci(WasmLayer)`. When a `File` source the `PathBuf`` is provided by entrypoint in OCI spec.
+    ///     If the image contains custom OCI Wasm layers, the source is provided as an array of `WasmLayer` structs.
+    ///
+    /// The first argument in the OCI spec for entrypoint is specified as `path#func` where `func` is optional
+    /// and defaults to _start, e.g.:
+    ///   "/app/app.wasm#entry" -> { source: File("/app/app.wasm"), func: "entry", name: "Some(app)", arg0: "/app/app.wasm#entry" }
+    ///   "my_module.wat" -> { source: File("my_module.wat"), func: "_start", name: "Some(my_module)", arg0: "my_module.wat" }
+    ///   "#init" -> { source: File(""), func: "init", name: None, arg0: "#init" }
     fn entrypoint(&self) -> Entrypoint;
 
-    // the platform for the container using the struct defined on the OCI spec definition
-    // https://github.com/opencontainers/image-spec/blob/v1.1.0-rc5/image-index.md
+    /// The platform for the container using the struct defined on the OCI spec definition
+    /// <https://github.com/opencontainers/image-spec/blob/v1.1.0-rc5/image-index.md>
     fn platform(&self) -> &Platform;
+
+    /// The paths the guest is allowed to write to, from the `runwasi.io/write-allow`
+    /// annotation. Engines should enforce it on top of the permissions of the container
+    /// mounts. In strict WASI mode, it denies all writes.
+    ///
+    /// Defaults to no restriction.
+    fn write_policy(&self) -> anyhow::Result<WritePolicy> {
+        Ok(WritePolicy::default())
+    }
+
+    /// The optional host capabilities the guest is allowed to use, restricted to a minimal
+    /// WASI surface when the namespace of the container is subject to the `strict_wasi`
+    /// policy of the runtime configuration.
+    /// Engines must not link the capabilities that aren't allowed.
+    ///
+    /// Defaults to all the capabilities.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::all()
+    }
+
+    /// The limits on the components run by the shim, from the `component_limits` of the
+    /// runtime configuration. Engines should check them with [`ComponentLimits::check`]
+    /// before compiling a component, and enforce the others at runtime.
+    ///
+    /// Defaults to no limits.
+    fn component_limits(&self) -> ComponentLimits {
+        ComponentLimits::default()
+    }
+
+    /// A read-only mapping of the precompiled artifact of the command, shared with the
+    /// other containers of the node running the same layer, if the command is precompiled.
+    /// Engines should load their code from its file, so that the code pages are shared too.
+    ///
+    /// Defaults to none.
+    fn precompiled_artifact(&self) -> Option<&PrecompiledArtifact> {
+        None
+    }
+
+    /// The deadline for the guest to terminate once its termination is requested, with the
+    /// grace period from the `runwasi.io/termination-grace-period` annotation.
+    /// Host capabilities can bound their timeouts with it to fail before the guest is killed.
+    ///
+    /// Defaults to the default grace period.
+    fn termination_deadline(&self) -> TerminationDeadline {
+        TerminationDeadline::for_process(DEFAULT_TERMINATION_GRACE_PERIOD)
+    }
+
+    /// How long the background tasks of the guest, e.g., streaming bodies, are waited for
+    /// once it exits with 0, from the `runwasi.io/drain-window` annotation. Engines should
+    /// drain them with [`DrainWindow::drain`] before tearing the instance down.
+    ///
+    /// Defaults to no drain window.
+    fn drain_window(&self) -> DrainWindow {
+        DrainWindow::default()
+    }
+
+    /// The wasm layers of the image by name, with their role: the command to run, the
+    /// libraries it links to, and the data it uses. The roles are obtained from the
+    /// `runwasi.io/layer-role` annotation of the layers, or from their media type.
+    ///
+    /// Defaults to no layers.
+    fn wasm_layers(&self) -> anyhow::Result<WasmLayers<'_>> {
+        Ok(WasmLayers::default())
+    }
+
+    /// The metadata of the instance running the guest: the container id, the pid of the
+    /// container process, its cgroup, the state directory of the container, and the time
+    /// the guest was started.
+    ///
+    /// Defaults to empty metadata.
+    fn instance_info(&self) -> &InstanceInfo {
+        static UNKNOWN: LazyLock<InstanceInfo> = LazyLock::new(InstanceInfo::default);
+        &UNKNOWN
+    }
+
+    /// Tells the shim that the guest is compiled and instantiated, and is about to run.
+    /// Engines should call it right before running guest code, so that the startup CPU boost
+    /// requested with the `runwasi.io/startup-cpu-boost` annotation ends before the guest
+    /// runs. Only the first call has an effect.
+    fn startup_complete(&self) {}
+
+    /// The file descriptors passed by the platform with the `runwasi.io/inherit-fds`
+    /// annotation, e.g., pre-bound sockets or log pipes, with their name and role.
+    /// Engines should hand them to the guest as per their role.
+    ///
+    /// Defaults to none.
+    fn inherited_fds(&self) -> &[InheritedFd] {
+        &[]
+    }
+
+    /// The host side of the `wasi:logging` interface, writing the log records of the guest
+    /// to the output of the container, or to the logs of the shim with the
+    /// `runwasi.io/guest-log: shim` annotation.
+    ///
+    /// Defaults to the output of the container.
+    fn guest_logger(&self) -> GuestLogger {
+        GuestLogger::default()
+    }
+
+    /// A handle that terminates the container with an exit report, for engines that can't
+    /// go on running the guest, e.g., on a fatal error in their configuration, so that the
+    /// shim reports why the container exited.
+    ///
+    /// Defaults to a handle that only exits.
+    fn terminator(&self) -> Terminator {
+        Terminator::default()
+    }
 }
 
 /// The source for a WASI module / components.
@@ -50,22 +178,30 @@ pub enum Source<'a> {
     // Runtimes can additionally provide a list of layer types they support,
     // and they will be included in this array, e.g., a `toml` file with the
     // runtime configuration.
+    // Use `RuntimeContext::wasm_layers` to tell the command from the other layers.
     Oci(&'a [WasmLayer]),
 }
 
 impl<'a> Source<'a> {
-    pub fn as_bytes(&self) -> anyhow::Result<Cow<'a, [u8]>> {
+    /// Returns the bytes of the module, mapped from the artifact cache of the node for the wasm
+    /// layers shared with the other containers, see [`WasmLayer::bytes`].
+    pub fn as_bytes(&self) -> anyhow::Result<ModuleBytes<'a>> {
         match self {
             Source::File(path) => {
                 let path = path
                     .resolve_in_path_or_cwd()
                     .next()
                     .context("module not found")?;
-                Ok(Cow::Owned(std::fs::read(path)?))
+                Ok(ModuleBytes::read(path)?)
             }
-            Source::Oci([module]) => Ok(Cow::Borrowed(&module.layer)),
-            Source::Oci(_modules) => {
-                bail!("only a single module is supported when using images with OCI layers")
+            Source::Oci([module]) => Ok(module.bytes()),
+            Source::Oci(layers) => {
+                let layers = WasmLayers::new(layers)?;
+                let command: &'a WasmLayer = layers
+                    .command()
+                    .context("no command layer in the image with OCI layers")?
+                    .layer;
+                Ok(command.bytes())
             }
         }
     }
@@ -84,6 +220,31 @@ pub(crate) struct WasiContext<'a> {
     pub spec: &'a Spec,
     pub wasm_layers: &'a [WasmLayer],
     pub platform: &'a Platform,
+    pub instance_info: InstanceInfo,
+    pub capabilities: Capabilities,
+    pub component_limits: ComponentLimits,
+    pub precompiled_artifacts: &'a [PrecompiledArtifact],
+    pub startup_signal: Option<&'a StartupSignal>,
+    pub inherited_fds: &'a [InheritedFd],
+    pub terminator: Terminator,
+}
+
+/// Signals the end of the startup of the container to the shim, through a fifo.
+#[derive(Clone, Default)]
+pub(crate) struct StartupSignal(Arc<Mutex<Option<File>>>);
+
+impl StartupSignal {
+    pub fn new(fifo: File) -> Self {
+        Self(Arc::new(Mutex::new(Some(fifo))))
+    }
+
+    pub fn complete(&self) {
+        if let Some(mut fifo) = self.0.lock().unwrap().take() {
+            if let Err(err) = fifo.write_all(&[1]) {
+                log::warn!("failed to signal the end of the startup: {err}");
+            }
+        }
+    }
 }
 
 impl RuntimeContext for WasiContext<'_> {
@@ -109,41 +270,161 @@ impl RuntimeContext for WasiContext<'_> {
         let arg0 = self.args().first();
 
         let entry_point = arg0.map(String::as_str).unwrap_or("");
-        let (path, func) = entry_point
-            .split_once('#')
-            .unwrap_or((entry_point, "_start"));
+        let (path, func, name) = split_entrypoint(entry_point);
 
-        let source = if self.wasm_layers.is_empty() {
-            Source::File(PathBuf::from(path))
+        // the libraries pulled for a container running a file of its rootfs aren't its command
+        let source = if self.wasm_layers.iter().all(layers::is_library) {
+            Source::File(path)
         } else {
             Source::Oci(self.wasm_layers)
         };
 
-        let module_name = PathBuf::from(path)
-            .file_stem()
-            .map(|name| name.to_string_lossy().to_string());
-
         Entrypoint {
-            func: func.to_string(),
+            func,
             arg0: arg0.map(Path::new),
             source,
-            name: module_name,
+            name,
         }
     }
 
     fn platform(&self) -> &Platform {
         self.platform
     }
+
+    fn write_policy(&self) -> anyhow::Result<WritePolicy> {
+        if !self.capabilities.writable_fs() {
+            return Ok(WritePolicy::read_only());
+        }
+        let allow = self
+            .spec
+            .annotations()
+            .as_ref()
+            .and_then(|a| a.get(WRITE_ALLOW_ANNOTATION));
+        match allow {
+            Some(allow) => WritePolicy::parse(allow),
+            None => Ok(WritePolicy::default()),
+        }
+    }
+
+    fn termination_deadline(&self) -> TerminationDeadline {
+        let grace_period = self
+            .spec
+            .annotations()
+            .as_ref()
+            .and_then(|a| a.get(TERMINATION_GRACE_PERIOD_ANNOTATION))
+            .and_then(|secs| match secs.parse() {
+                Ok(secs) => Some(Duration::from_secs(secs)),
+                Err(err) => {
+                    log::warn!(
+                        "ignoring invalid {TERMINATION_GRACE_PERIOD_ANNOTATION} {secs:?}: {err}"
+                    );
+                    None
+                }
+            })
+            .unwrap_or(DEFAULT_TERMINATION_GRACE_PERIOD);
+        TerminationDeadline::for_process(grace_period)
+    }
+
+    fn drain_window(&self) -> DrainWindow {
+        let window = self
+            .spec
+            .annotations()
+            .as_ref()
+            .and_then(|a| a.get(DRAIN_WINDOW_ANNOTATION))
+            .and_then(|ms| match ms.parse() {
+                Ok(ms) => Some(Duration::from_millis(ms)),
+                Err(err) => {
+                    log::warn!("ignoring invalid {DRAIN_WINDOW_ANNOTATION} {ms:?}: {err}");
+                    None
+                }
+            });
+        DrainWindow::new(window)
+    }
+
+    fn wasm_layers(&self) -> anyhow::Result<WasmLayers<'_>> {
+        WasmLayers::new(self.wasm_layers)
+    }
+
+    fn instance_info(&self) -> &InstanceInfo {
+        &self.instance_info
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.capabilities
+    }
+
+    fn component_limits(&self) -> ComponentLimits {
+        self.component_limits
+    }
+
+    fn precompiled_artifact(&self) -> Option<&PrecompiledArtifact> {
+        let wasm_layers = self.wasm_layers().ok()?;
+        let digest = wasm_layers.command()?.layer.config.digest().to_string();
+        self.precompiled_artifacts
+            .iter()
+            .find(|artifact| artifact.digest() == digest)
+    }
+
+    fn startup_complete(&self) {
+        if let Some(signal) = self.startup_signal {
+            signal.complete();
+        }
+    }
+
+    fn inherited_fds(&self) -> &[InheritedFd] {
+        self.inherited_fds
+    }
+
+    fn guest_logger(&self) -> GuestLogger {
+        let target = self
+            .spec
+            .annotations()
+            .as_ref()
+            .and_then(|a| a.get(GUEST_LOG_ANNOTATION))
+            .and_then(|target| match target.parse() {
+                Ok(target) => Some(target),
+                Err(err) => {
+                    log::warn!("ignoring invalid {GUEST_LOG_ANNOTATION}: {err}");
+                    None
+                }
+            })
+            .unwrap_or_default();
+        GuestLogger::new(&self.instance_info.id, target)
+    }
+
+    fn terminator(&self) -> Terminator {
+        self.terminator.clone()
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::collections::HashMap;
+
     use anyhow::Result;
     use oci_spec::image::{Descriptor, Digest};
     use oci_spec::runtime::{ProcessBuilder, RootBuilder, SpecBuilder};
 
     use super::*;
 
+    static PLATFORM: LazyLock<Platform> = LazyLock::new(Platform::default);
+
+    /// A context for `spec` without wasm layers, with the defaults of the shim.
+    fn context(spec: &Spec) -> WasiContext<'_> {
+        WasiContext {
+            spec,
+            wasm_layers: &[],
+            platform: &PLATFORM,
+            instance_info: InstanceInfo::default(),
+            capabilities: Capabilities::all(),
+            component_limits: ComponentLimits::default(),
+            precompiled_artifacts: &[],
+            startup_signal: None,
+            inherited_fds: &[],
+            terminator: Terminator::default(),
+        }
+    }
+
     #[test]
     fn test_get_args() -> Result<()> {
         let spec = SpecBuilder::default()
@@ -156,11 +437,7 @@ mod tests {
             )
             .build()?;
 
-        let ctx = WasiContext {
-            spec: &spec,
-            wasm_layers: &[],
-            platform: &Platform::default(),
-        };
+        let ctx = context(&spec);
 
         let args = ctx.args();
         assert_eq!(args.len(), 1);
@@ -176,11 +453,7 @@ mod tests {
             .process(ProcessBuilder::default().cwd("/").args(vec![]).build()?)
             .build()?;
 
-        let ctx = WasiContext {
-            spec: &spec,
-            wasm_layers: &[],
-            platform: &Platform::default(),
-        };
+        let ctx = context(&spec);
 
         let args = ctx.args();
         assert_eq!(args.len(), 0);
@@ -204,11 +477,7 @@ mod tests {
             )
             .build()?;
 
-        let ctx = WasiContext {
-            spec: &spec,
-            wasm_layers: &[],
-            platform: &Platform::default(),
-        };
+        let ctx = context(&spec);
 
         let args = ctx.args();
         assert_eq!(args.len(), 3);
@@ -226,11 +495,7 @@ mod tests {
             .process(ProcessBuilder::default().cwd("/").args(vec![]).build()?)
             .build()?;
 
-        let ctx = WasiContext {
-            spec: &spec,
-            wasm_layers: &[],
-            platform: &Platform::default(),
-        };
+        let ctx = context(&spec);
 
         let path = ctx.entrypoint().source;
         assert!(matches!(
@@ -257,11 +522,7 @@ mod tests {
             )
             .build()?;
 
-        let ctx = WasiContext {
-            spec: &spec,
-            wasm_layers: &[],
-            platform: &Platform::default(),
-        };
+        let ctx = context(&spec);
 
         let expected_path = PathBuf::from("hello.wat");
         let Entrypoint {
@@ -297,11 +558,7 @@ mod tests {
             )
             .build()?;
 
-        let ctx = WasiContext {
-            spec: &spec,
-            wasm_layers: &[],
-            platform: &Platform::default(),
-        };
+        let ctx = context(&spec);
 
         let expected_path = PathBuf::from("/root/hello.wat");
         let Entrypoint {
@@ -337,11 +594,7 @@ mod tests {
             )
             .build()?;
 
-        let ctx = WasiContext {
-            spec: &spec,
-            wasm_layers: &[],
-            platform: &Platform::default(),
-        };
+        let ctx = context(&spec);
 
         let expected_path = PathBuf::from("/root/hello.wat");
         assert!(matches!(
@@ -369,7 +622,6 @@ mod tests {
             .build()?;
 
         let ctx = WasiContext {
-            spec: &spec,
             wasm_layers: &[WasmLayer {
                 layer: vec![],
                 config: Descriptor::new(
@@ -378,7 +630,7 @@ mod tests {
                     Digest::try_from(format!("sha256:{:064?}", 0))?,
                 ),
             }],
-            platform: &Platform::default(),
+            ..context(&spec)
         };
 
         assert!(matches!(ctx.entrypoint().source, Source::Oci(_)));
@@ -387,23 +639,57 @@ mod tests {
     }
 
     #[test]
-    fn test_get_envs() -> Result<()> {
+    fn test_loading_strategy_is_file_with_pulled_libraries() -> Result<()> {
         let spec = SpecBuilder::default()
             .root(RootBuilder::default().path("rootfs").build()?)
             .process(
                 ProcessBuilder::default()
                     .cwd("/")
-                    .env(vec!["KEY1=VALUE1".to_string(), "KEY2=VALUE2".to_string()])
+                    .args(vec!["/root/hello.wasm".to_string()])
                     .build()?,
             )
             .build()?;
 
+        let mut config = Descriptor::new(
+            oci_spec::image::MediaType::Other("application/wasm".to_string()),
+            1,
+            Digest::try_from(format!("sha256:{:064}", 0))?,
+        );
+        config.set_annotations(Some(HashMap::from([(
+            layers::LAYER_ROLE_ANNOTATION.to_string(),
+            "library".to_string(),
+        )])));
         let ctx = WasiContext {
-            spec: &spec,
-            wasm_layers: &[],
-            platform: &Platform::default(),
+            wasm_layers: &[WasmLayer {
+                layer: vec![0],
+                config,
+            }],
+            ..context(&spec)
         };
 
+        assert!(matches!(
+            ctx.entrypoint().source,
+            Source::File(p) if p == Path::new("/root/hello.wasm")
+        ));
+        assert_eq!(ctx.wasm_layers()?.len(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_envs() -> Result<()> {
+        let spec = SpecBuilder::default()
+            .root(RootBuilder::default().path("rootfs").build()?)
+            .process(
+                ProcessBuilder::default()
+                    .cwd("/")
+                    .env(vec!["KEY1=VALUE1".to_string(), "KEY2=VALUE2".to_string()])
+                    .build()?,
+            )
+            .build()?;
+
+        let ctx = context(&spec);
+
         let envs = ctx.envs();
         assert_eq!(envs.len(), 2);
         assert_eq!(envs[0], "KEY1=VALUE1");
@@ -419,11 +705,7 @@ mod tests {
             .process(ProcessBuilder::default().cwd("/").env(vec![]).build()?)
             .build()?;
 
-        let ctx = WasiContext {
-            spec: &spec,
-            wasm_layers: &[],
-            platform: &Platform::default(),
-        };
+        let ctx = context(&spec);
 
         let envs = ctx.envs();
         assert_eq!(envs.len(), 0);
@@ -438,15 +720,159 @@ mod tests {
             .process(ProcessBuilder::default().cwd("/").build()?)
             .build()?;
 
-        let ctx = WasiContext {
-            spec: &spec,
-            wasm_layers: &[],
-            platform: &Platform::default(),
-        };
+        let ctx = context(&spec);
 
         let envs = ctx.envs();
         assert_eq!(envs.len(), 2);
 
         Ok(())
     }
+
+    #[test]
+    fn test_write_policy_from_annotation() -> Result<()> {
+        let spec = SpecBuilder::default()
+            .root(RootBuilder::default().path("rootfs").build()?)
+            .process(ProcessBuilder::default().cwd("/").build()?)
+            .annotations(HashMap::from([(
+                WRITE_ALLOW_ANNOTATION.to_string(),
+                "/data/*".to_string(),
+            )]))
+            .build()?;
+
+        let ctx = context(&spec);
+
+        let policy = ctx.write_policy()?;
+        assert!(policy.allows("/data/uploads"));
+        assert!(!policy.allows("/etc"));
+
+        // strict WASI can't be relaxed by the annotation
+        let ctx = WasiContext {
+            capabilities: Capabilities::strict(),
+            ..ctx
+        };
+        let policy = ctx.write_policy()?;
+        assert!(!policy.allows("/data/uploads"));
+        assert!(!ctx.capabilities().network());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_policy_unrestricted_by_default() -> Result<()> {
+        let spec = SpecBuilder::default()
+            .root(RootBuilder::default().path("rootfs").build()?)
+            .process(ProcessBuilder::default().cwd("/").build()?)
+            .build()?;
+
+        let ctx = context(&spec);
+
+        assert!(ctx.write_policy()?.is_unrestricted());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_termination_grace_period() -> Result<()> {
+        let spec = SpecBuilder::default()
+            .root(RootBuilder::default().path("rootfs").build()?)
+            .process(ProcessBuilder::default().cwd("/").build()?)
+            .annotations(HashMap::from([(
+                TERMINATION_GRACE_PERIOD_ANNOTATION.to_string(),
+                "5".to_string(),
+            )]))
+            .build()?;
+
+        let ctx = context(&spec);
+        assert_eq!(
+            ctx.termination_deadline().grace_period(),
+            Duration::from_secs(5)
+        );
+
+        let spec = SpecBuilder::default()
+            .root(RootBuilder::default().path("rootfs").build()?)
+            .process(ProcessBuilder::default().cwd("/").build()?)
+            .build()?;
+
+        let ctx = context(&spec);
+        assert_eq!(
+            ctx.termination_deadline().grace_period(),
+            DEFAULT_TERMINATION_GRACE_PERIOD
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_drain_window() -> Result<()> {
+        let spec = SpecBuilder::default()
+            .root(RootBuilder::default().path("rootfs").build()?)
+            .process(ProcessBuilder::default().cwd("/").build()?)
+            .annotations(HashMap::from([(
+                DRAIN_WINDOW_ANNOTATION.to_string(),
+                "1500".to_string(),
+            )]))
+            .build()?;
+
+        let ctx = context(&spec);
+        assert_eq!(
+            ctx.drain_window().window(),
+            Some(Duration::from_millis(1500))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_command_layer_source() -> Result<()> {
+        let spec = SpecBuilder::default()
+            .root(RootBuilder::default().path("rootfs").build()?)
+            .process(ProcessBuilder::default().cwd("/").build()?)
+            .build()?;
+
+        let layer = |media_type: &str, seed: u8| -> Result<WasmLayer> {
+            Ok(WasmLayer {
+                layer: vec![seed],
+                config: Descriptor::new(
+                    oci_spec::image::MediaType::Other(media_type.to_string()),
+                    1,
+                    Digest::try_from(format!("sha256:{:064}", seed))?,
+                ),
+            })
+        };
+        let wasm_layers = [
+            layer("application/toml", 0)?,
+            layer("application/wasm", 1)?,
+            layer("application/wasm", 2)?,
+        ];
+
+        let ctx = WasiContext {
+            wasm_layers: &wasm_layers,
+            ..context(&spec)
+        };
+
+        assert_eq!(ctx.entrypoint().source.as_bytes()?.as_ref(), [1]);
+        assert_eq!(ctx.wasm_layers()?.len(), 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_instance_info() -> Result<()> {
+        let spec = SpecBuilder::default().build()?;
+        let instance_info = InstanceInfo {
+            id: "test".to_string(),
+            pid: 1,
+            cgroup_path: Some("/kubepods/test".into()),
+            state_dir: "/run/containerd/wasm/default/test".into(),
+            started_at: chrono::Utc::now(),
+        };
+
+        let ctx = WasiContext {
+            instance_info: instance_info.clone(),
+            ..context(&spec)
+        };
+
+        assert_eq!(ctx.instance_info(), &instance_info);
+
+        Ok(())
+    }
 }
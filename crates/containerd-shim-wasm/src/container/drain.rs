@@ -0,0 +1,97 @@
+//! Draining of the background tasks of a guest once it exits successfully.
+//!
+//! A component can return from `run`, or a proxy stop serving, while the host still has
+//! tasks in flight for it, e.g., the streaming body of a response or an outgoing request.
+//! Engines tear the instance down as soon as the guest returns, dropping them.
+//!
+//! With the `runwasi.io/drain-window` annotation, in milliseconds, engines wait for up to
+//! that long for these tasks with [`DrainWindow::drain`] when the guest exits with 0, before
+//! tearing the instance down. The tasks that completed and the ones dropped are counted in
+//! the [`EngineMetrics`](crate::container::EngineMetrics) of the container, which the
+//! executor writes before the container exits.
+
+use std::sync::Mutex;
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+/// Annotation with the number of milliseconds the background tasks of a guest are waited for.
+pub const DRAIN_WINDOW_ANNOTATION: &str = "runwasi.io/drain-window";
+
+/// How often the pending tasks are counted while draining.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+// A container process runs a single guest, so its drained and dropped tasks are counted for
+// the process.
+static STATS: Mutex<Option<(u64, u64)>> = Mutex::new(None);
+
+/// The time the background tasks of a guest are waited for once it exits.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DrainWindow {
+    window: Option<Duration>,
+}
+
+impl DrainWindow {
+    pub fn new(window: Option<Duration>) -> Self {
+        Self { window }
+    }
+
+    /// The time the tasks are waited for, if the container has a drain window.
+    pub fn window(&self) -> Option<Duration> {
+        self.window
+    }
+
+    /// Waits for up to the drain window for `pending`, the number of pending tasks of the
+    /// guest, to reach zero, and counts the tasks that completed and the ones left, which
+    /// the engine drops. Returns the number of tasks left.
+    pub async fn drain(&self, pending: impl Fn() -> usize) -> usize {
+        let Some(window) = self.window else {
+            return pending();
+        };
+        let deadline = Instant::now() + window;
+        let initial = pending();
+        let mut left = initial;
+        while left > 0 && Instant::now() < deadline {
+            tokio::time::sleep(POLL_INTERVAL.min(deadline - Instant::now())).await;
+            left = pending();
+        }
+
+        let drained = initial.saturating_sub(left);
+        log::info!("drained {drained} background tasks of the guest, dropping {left}");
+        let mut stats = STATS.lock().unwrap();
+        let (total_drained, total_dropped) = stats.get_or_insert((0, 0));
+        *total_drained += drained as u64;
+        *total_dropped += left as u64;
+        left
+    }
+}
+
+/// The numbers of background tasks drained and dropped in this process, if the guest was
+/// drained.
+pub(crate) fn stats() -> Option<(u64, u64)> {
+    *STATS.lock().unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(flavor = "current_thread", start_paused = true)]
+    async fn test_drain() {
+        let window = DrainWindow::new(Some(Duration::from_millis(100)));
+
+        // a task completes every 30ms
+        let start = Instant::now();
+        let pending = || 5usize.saturating_sub((start.elapsed().as_millis() / 30) as usize);
+        assert_eq!(window.drain(pending).await, 2);
+        assert_eq!(start.elapsed(), Duration::from_millis(100));
+
+        // the drain stops once the tasks completed
+        let start = Instant::now();
+        let pending = || 2usize.saturating_sub((start.elapsed().as_millis() / 30) as usize);
+        assert_eq!(window.drain(pending).await, 0);
+        assert_eq!(start.elapsed(), Duration::from_millis(60));
+
+        assert_eq!(DrainWindow::default().drain(|| 3).await, 3);
+    }
+}
@@ -4,7 +4,7 @@ use std::io::Read;
 use anyhow::{bail, Context, Result};
 
 use super::Source;
-use crate::container::{PathResolve, RuntimeContext};
+use crate::container::{EngineMetrics, PathResolve, RuntimeContext};
 use crate::sandbox::oci::WasmLayer;
 
 /// The `Engine` trait provides a simplified API for running WebAssembly containers.
@@ -17,6 +17,17 @@ pub trait Engine: Clone + Send + Sync + 'static {
     /// Run a WebAssembly container
     fn run_wasi(&self, ctx: &impl RuntimeContext) -> Result<i32>;
 
+    /// Builds the engine, failing if it can't run on this host, e.g., when its compiler
+    /// doesn't support the CPU. This is used by the health checks of the shim, which report
+    /// the failure rather than panic.
+    /// The default implementation returns `Self::default()`.
+    fn try_default() -> Result<Self>
+    where
+        Self: Default,
+    {
+        Ok(Self::default())
+    }
+
     /// Check that the runtime can run the container.
     /// This checks runs after the container creation and before the container starts.
     /// By default it checks that the wasi_entrypoint is either:
@@ -51,7 +62,12 @@ pub trait Engine: Clone + Send + Sync + 'static {
     /// The default implementation returns the OCI layer type 'application/vnd.bytecodealliance.wasm.component.layer.v0+wasm'
     /// for WASM modules which can be contain with wasip1 or wasip2 components.
     /// Runtimes can override this to support other layer types
-    /// such as lays that contain runtime specific configuration
+    /// such as lays that contain runtime specific configuration,
+    /// or other packaging formats, e.g. `application/vnd.wasmer.webc`.
+    /// Overrides replace the default list, so they should include the types above to keep running wasm layers.
+    /// Layers of these types are kept when loading the image of a container, and when pulling the images of
+    /// `runwasi.io/pull-modules`. Layers without a wasm media type are data, unless they are the only layer
+    /// or are annotated with `runwasi.io/layer-role: command`.
     fn supported_layers_types() -> &'static [&'static str] {
         &[
             "application/vnd.bytecodealliance.wasm.component.layer.v0+wasm",
@@ -65,6 +81,10 @@ pub trait Engine: Clone + Send + Sync + 'static {
     /// The cached, precompiled layers will be reloaded on subsequent runs.
     /// The runtime is expected to return the same number of layers passed in, if the layer cannot be precompiled it should return `None` for that layer.
     /// In some edge cases it is possible that the layers may already be precompiled and None should be returned in this case.
+    ///
+    /// This is the only code cache of the shim that outlives a process: every container runs in its own process,
+    /// so the in-memory caches of an engine die with its container, and the shim has no long-lived daemon whose
+    /// caches could be saved on shutdown and restored on startup. Engines should persist compiled code here instead.
     fn precompile(&self, _layers: &[WasmLayer]) -> Result<Vec<Option<Vec<u8>>>> {
         bail!("precompile not supported");
     }
@@ -83,4 +103,23 @@ pub trait Engine: Clone + Send + Sync + 'static {
     fn can_precompile(&self) -> Option<String> {
         None
     }
+
+    /// Shared lets the shim build a single instance of the engine for all its containers.
+    /// When it returns `true` the engine is built in the zygote the container processes are forked from,
+    /// and each container runs its guest with a clone of it, so clones must not share mutable state between guests.
+    ///
+    /// The engine must not start threads when it is built, as they don't survive the fork of the container processes.
+    /// When it returns `false` each container builds its own engine.  This is the default value.
+    fn shared() -> bool {
+        false
+    }
+
+    /// Metrics returns the counters of the guest run by this engine, e.g., the fuel it consumed.
+    /// It is called periodically from another thread of the container process while `run_wasi` runs,
+    /// and the last counters are returned with the stats of the task, next to its cgroup metrics.
+    ///
+    /// When it returns None no counters are reported.  This is the default value.
+    fn metrics(&self) -> Option<EngineMetrics> {
+        None
+    }
 }
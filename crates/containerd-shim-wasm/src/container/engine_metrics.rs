@@ -0,0 +1,142 @@
+//! Counters an engine reports about the guest it runs, returned with the task stats.
+//!
+//! The guest runs in the container process, while the stats of a task are served by the
+//! shim. The container process periodically writes the counters of
+//! [`Engine::metrics`](crate::container::Engine::metrics) to `engine-metrics.json` in the
+//! bundle, and the shim appends the last ones to the cgroup metrics of the `Stats` response.
+//!
+//! They are appended as the field [`ENGINE_METRICS_FIELD`] of the metrics message, a
+//! `runwasi.EngineMetrics` message with the fields of [`EngineMetrics`] numbered in order.
+//! Clients that don't know about it, like containerd, ignore it as an unknown field.
+
+use std::path::Path;
+
+use protobuf::well_known_types::any::Any;
+use protobuf::CodedOutputStream;
+use serde::{Deserialize, Serialize};
+
+/// The file in the bundle the counters of the engine are written to.
+pub(crate) const ENGINE_METRICS_FILE: &str = "engine-metrics.json";
+
+/// The field number of the counters of the engine in the metrics of the `Stats` response.
+pub const ENGINE_METRICS_FIELD: u32 = 1000;
+
+/// Counters reported by an engine about the guest it runs.
+/// Counters the engine doesn't track are left as None.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EngineMetrics {
+    /// Fuel consumed by the guest, for engines that meter it.
+    pub fuel_consumed: Option<u64>,
+    /// Size of the linear memories of the guest, in bytes.
+    pub memory_bytes: Option<u64>,
+    /// Calls of the guest to host functions.
+    pub host_calls: Option<u64>,
+    /// Background tasks of the guest that completed in its drain window, see
+    /// [`DrainWindow`](crate::container::DrainWindow).
+    pub drained_tasks: Option<u64>,
+    /// Background tasks of the guest dropped at the end of its drain window.
+    pub dropped_tasks: Option<u64>,
+}
+
+impl EngineMetrics {
+    /// Reads the last counters written to the bundle of a task, if any.
+    pub(crate) fn read(bundle: &Path) -> Option<Self> {
+        let data = std::fs::read(bundle.join(ENGINE_METRICS_FILE)).ok()?;
+        serde_json::from_slice(&data)
+            .inspect_err(|err| log::debug!("ignoring invalid engine metrics: {err}"))
+            .ok()
+    }
+
+    /// Writes the counters to `dir`, where [`read`](Self::read) finds them.
+    /// This is for engines running outside of the shim, whose process is attached with a
+    /// [`sidecar`](crate::sandbox::sidecar); the shim writes the counters of its containers itself.
+    pub fn write(&self, dir: &Path) -> std::io::Result<()> {
+        // written to a temporary file and renamed, so that readers never see a partial write
+        let tmp = dir.join(format!("{ENGINE_METRICS_FILE}.tmp"));
+        std::fs::write(&tmp, serde_json::to_vec(self)?)?;
+        std::fs::rename(tmp, dir.join(ENGINE_METRICS_FILE))
+    }
+
+    /// Appends the last counters written to `dir`, if any, to the encoded `metrics` of a task.
+    pub(crate) fn append_to(dir: &Path, metrics: &mut Any) -> protobuf::Result<()> {
+        if let Some(engine_metrics) = Self::read(dir) {
+            metrics.value.extend(engine_metrics.to_extension()?);
+        }
+        Ok(())
+    }
+
+    /// Encodes the counters as the field [`ENGINE_METRICS_FIELD`] of a protobuf message,
+    /// to be appended to an encoded message.
+    pub(crate) fn to_extension(&self) -> protobuf::Result<Vec<u8>> {
+        let counters = [
+            self.fuel_consumed,
+            self.memory_bytes,
+            self.host_calls,
+            self.drained_tasks,
+            self.dropped_tasks,
+        ];
+        let mut message = vec![];
+        let mut os = CodedOutputStream::vec(&mut message);
+        for (field, counter) in (1..).zip(counters) {
+            if let Some(counter) = counter {
+                os.write_uint64(field, counter)?;
+            }
+        }
+        os.flush()?;
+        drop(os);
+
+        let mut extension = vec![];
+        let mut os = CodedOutputStream::vec(&mut extension);
+        os.write_bytes(ENGINE_METRICS_FIELD, &message)?;
+        os.flush()?;
+        drop(os);
+        Ok(extension)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_extension() -> anyhow::Result<()> {
+        let metrics = EngineMetrics {
+            fuel_consumed: Some(300),
+            host_calls: Some(2),
+            ..Default::default()
+        };
+        // field 1000, length 5, field 1 = 300, field 3 = 2
+        assert_eq!(
+            metrics.to_extension()?,
+            [0xc2, 0x3e, 5, 0x08, 0xac, 0x02, 0x18, 0x02]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_read() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        assert_eq!(EngineMetrics::read(dir.path()), None);
+        let metrics = EngineMetrics {
+            host_calls: Some(7),
+            ..Default::default()
+        };
+        metrics.write(dir.path())?;
+        assert_eq!(EngineMetrics::read(dir.path()), Some(metrics));
+        std::fs::write(
+            dir.path().join(ENGINE_METRICS_FILE),
+            r#"{"fuel_consumed":1,"memory_bytes":65536,"host_calls":null}"#,
+        )?;
+        assert_eq!(
+            EngineMetrics::read(dir.path()),
+            Some(EngineMetrics {
+                fuel_consumed: Some(1),
+                memory_bytes: Some(65536),
+                host_calls: None,
+                drained_tasks: None,
+                dropped_tasks: None,
+            })
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,189 @@
+//! Resolution of the entrypoint of a container, without running it.
+//!
+//! [`resolve_entrypoint`] tells which module and function the shim runs for a runtime spec,
+//! with the same logic as [`RuntimeContext::entrypoint`](crate::container::RuntimeContext::entrypoint),
+//! so that admission webhooks and build tools can check an image before it is deployed.
+//! It has no side effects, and doesn't need containerd.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Result};
+use oci_spec::image::ImageConfiguration;
+use oci_spec::runtime::Spec;
+
+/// The default exported function called when the entrypoint doesn't name one.
+pub const DEFAULT_FUNC: &str = "_start";
+
+/// The entrypoint of a container, as resolved by the shim.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ResolvedEntrypoint {
+    /// Arguments of the guest, starting with the raw entrypoint, e.g. `/app/app.wasm#entry`.
+    pub args: Vec<String>,
+    /// Path of the module in the container, empty if the entrypoint only names a function.
+    /// It is ignored for images with wasm layers, which run their command layer.
+    pub module: PathBuf,
+    /// Name of the exported function to call.
+    pub func: String,
+    /// Name of the module, the file name of `module` without its extension.
+    pub name: Option<String>,
+    /// Absolute paths in the root filesystem of the container where the module is looked
+    /// up, in order. The first one that is a file is run.
+    pub search_paths: Vec<PathBuf>,
+}
+
+/// Resolves the entrypoint of a container from its runtime spec.
+///
+/// When the spec has no process arguments, they are taken from the `Entrypoint` and `Cmd`
+/// of `image_config`, as containerd does when creating a container from an image.
+pub fn resolve_entrypoint(
+    spec: &Spec,
+    image_config: Option<&ImageConfiguration>,
+) -> Result<ResolvedEntrypoint> {
+    let process = spec.process().as_ref();
+    let image_config = image_config.and_then(|c| c.config().as_ref());
+
+    let args = process.and_then(|p| p.args().clone()).unwrap_or_default();
+    // the process of the spec isn't populated from the image yet
+    let from_image = args.is_empty().then_some(image_config).flatten();
+
+    let (args, env, cwd) = match from_image {
+        Some(config) => {
+            let args = config.entrypoint().iter().flatten();
+            let args = args.chain(config.cmd().iter().flatten()).cloned().collect();
+            let cwd = config.working_dir().as_ref().map(PathBuf::from);
+            (args, config.env().clone(), cwd)
+        }
+        None => {
+            let env = process.and_then(|p| p.env().clone());
+            (args, env, process.map(|p| p.cwd().clone()))
+        }
+    };
+    let Some(arg0) = args.first() else {
+        bail!("the container has no entrypoint");
+    };
+    let (module, func, name) = split_entrypoint(arg0);
+
+    let path_env = env
+        .iter()
+        .flatten()
+        .find_map(|var| var.strip_prefix("PATH="));
+    let cwd = cwd
+        .filter(|cwd| !cwd.as_os_str().is_empty())
+        .unwrap_or_else(|| PathBuf::from("/"));
+    let search_paths = search_paths(&module, path_env, &cwd);
+
+    Ok(ResolvedEntrypoint {
+        args,
+        module,
+        func,
+        name,
+        search_paths,
+    })
+}
+
+/// Splits the raw entrypoint `path#func` into the path of the module, the function to call,
+/// and the name of the module.
+pub(crate) fn split_entrypoint(arg0: &str) -> (PathBuf, String, Option<String>) {
+    let (path, func) = arg0.split_once('#').unwrap_or((arg0, DEFAULT_FUNC));
+    let path = PathBuf::from(path);
+    let name = path
+        .file_stem()
+        .map(|name| name.to_string_lossy().to_string());
+    (path, func.to_string(), name)
+}
+
+// The paths the module is looked up at, as `PathResolve::resolve_in_path_or_cwd` does
+// in the container: relative to the working directory if it has a separator, or else in
+// the directories of `PATH` and then in the working directory.
+fn search_paths(module: &Path, path_env: Option<&str>, cwd: &Path) -> Vec<PathBuf> {
+    if module.as_os_str().is_empty() {
+        return vec![];
+    }
+    if module.components().count() > 1 {
+        return vec![cwd.join(module)];
+    }
+    std::env::split_paths(path_env.unwrap_or_default())
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .map(|dir| cwd.join(dir))
+        .chain([cwd.to_path_buf()])
+        .map(|dir| dir.join(module))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use oci_spec::image::{ConfigBuilder, ImageConfigurationBuilder};
+    use oci_spec::runtime::{ProcessBuilder, SpecBuilder};
+
+    use super::*;
+
+    #[test]
+    fn test_resolve_entrypoint() -> Result<()> {
+        let spec = SpecBuilder::default()
+            .process(
+                ProcessBuilder::default()
+                    .cwd("/app")
+                    .args(vec!["/app/app.wasm#entry".to_string(), "arg".to_string()])
+                    .build()?,
+            )
+            .build()?;
+        let entrypoint = resolve_entrypoint(&spec, None)?;
+        assert_eq!(entrypoint.args, ["/app/app.wasm#entry", "arg"]);
+        assert_eq!(entrypoint.module, Path::new("/app/app.wasm"));
+        assert_eq!(entrypoint.func, "entry");
+        assert_eq!(entrypoint.name.as_deref(), Some("app"));
+        assert_eq!(entrypoint.search_paths, [Path::new("/app/app.wasm")]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_entrypoint_from_image() -> Result<()> {
+        let spec = SpecBuilder::default()
+            .process(ProcessBuilder::default().args(vec![]).build()?)
+            .build()?;
+        let image_config = ImageConfigurationBuilder::default()
+            .config(
+                ConfigBuilder::default()
+                    .entrypoint(vec!["app.wasm".to_string()])
+                    .cmd(vec!["serve".to_string()])
+                    .env(vec!["PATH=/bin:lib".to_string()])
+                    .working_dir("/srv")
+                    .build()?,
+            )
+            .build()?;
+        let entrypoint = resolve_entrypoint(&spec, Some(&image_config))?;
+        assert_eq!(entrypoint.args, ["app.wasm", "serve"]);
+        assert_eq!(entrypoint.func, DEFAULT_FUNC);
+        assert_eq!(
+            entrypoint.search_paths,
+            [
+                Path::new("/bin/app.wasm"),
+                Path::new("/srv/lib/app.wasm"),
+                Path::new("/srv/app.wasm"),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_entrypoint_func_only() -> Result<()> {
+        let spec = SpecBuilder::default()
+            .process(
+                ProcessBuilder::default()
+                    .args(vec!["#init".to_string()])
+                    .build()?,
+            )
+            .build()?;
+        let entrypoint = resolve_entrypoint(&spec, None)?;
+        assert_eq!(entrypoint.module, Path::new(""));
+        assert_eq!(entrypoint.func, "init");
+        assert_eq!(entrypoint.name, None);
+        assert!(entrypoint.search_paths.is_empty());
+
+        let spec = SpecBuilder::default()
+            .process(ProcessBuilder::default().args(vec![]).build()?)
+            .build()?;
+        resolve_entrypoint(&spec, None).unwrap_err();
+        Ok(())
+    }
+}
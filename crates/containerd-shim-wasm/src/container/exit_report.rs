@@ -0,0 +1,258 @@
+//! Termination of a container by its engine, with a report of why.
+//!
+//! An engine that finds out mid-run that its guest can't go on, e.g., on a fatal error in
+//! its configuration, terminates the container with the [`Terminator`] of
+//! [`RuntimeContext::terminator`](crate::container::RuntimeContext::terminator), instead of
+//! calling `std::process::exit`. The container exits with the exit code of the report as usual,
+//! and the shim logs the reason and details of the report along with the exit of the task.
+//!
+//! The report is written to `exit-report.json` in the bundle, which the shim opens for the
+//! container, as the bundle isn't visible from the container.
+//!
+//! The exit events and the state of a task only carry its exit code, so the shim also writes
+//! the message of the report to the termination message of the container, which the kubelet
+//! mounts at its `terminationMessagePath`, `/dev/termination-log` by default. The kubelet
+//! reports it as the message of the terminated state of the container, unless the guest wrote
+//! its own termination message.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Write as _;
+#[cfg(unix)]
+use std::os::unix::fs::{FileExt, OpenOptionsExt as _};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use oci_spec::runtime::Spec;
+use serde::{Deserialize, Serialize};
+
+/// The file in the bundle the exit report of a container is written to.
+pub(crate) const EXIT_REPORT_FILE: &str = "exit-report.json";
+
+/// Annotation of the kubelet with the path of the termination message of a container.
+pub const TERMINATION_MESSAGE_PATH_ANNOTATION: &str =
+    "io.kubernetes.container.terminationMessagePath";
+
+/// The path of the termination message of a container without the annotation.
+const DEFAULT_TERMINATION_MESSAGE_PATH: &str = "/dev/termination-log";
+
+/// The kubelet truncates longer termination messages.
+const MAX_TERMINATION_MESSAGE_LEN: usize = 4096;
+
+/// Why an engine terminated its container.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExitReport {
+    /// The exit code of the container.
+    pub exit_code: i32,
+    /// What made the engine terminate the container, e.g. `invalid runtime config`.
+    pub reason: String,
+    /// More about the reason, e.g. the name of the invalid setting.
+    pub details: BTreeMap<String, String>,
+}
+
+impl ExitReport {
+    /// Creates a report of the termination of the container with `exit_code` for `reason`.
+    pub fn new(exit_code: i32, reason: impl Into<String>) -> Self {
+        Self {
+            exit_code,
+            reason: reason.into(),
+            details: BTreeMap::new(),
+        }
+    }
+
+    /// Adds a detail to the report.
+    pub fn with_detail(mut self, key: impl Into<String>, value: impl ToString) -> Self {
+        self.details.insert(key.into(), value.to_string());
+        self
+    }
+
+    /// The reason of the report followed by its details, e.g. `invalid runtime config: setting=fuel`.
+    pub fn message(&self) -> String {
+        let mut message = self.reason.clone();
+        for (i, (key, value)) in self.details.iter().enumerate() {
+            message.push_str(if i == 0 { ": " } else { ", " });
+            message.push_str(&format!("{key}={value}"));
+        }
+        message
+    }
+
+    /// Returns the file of the host mounted as the termination message of the container of
+    /// `spec`, if any.
+    #[cfg_attr(windows, allow(dead_code))] // the termination message is written by the unix shim
+    pub(crate) fn termination_log(spec: &Spec) -> Option<PathBuf> {
+        let destination = spec
+            .annotations()
+            .as_ref()
+            .and_then(|a| a.get(TERMINATION_MESSAGE_PATH_ANNOTATION))
+            .map(String::as_str)
+            .filter(|path| !path.is_empty())
+            .unwrap_or(DEFAULT_TERMINATION_MESSAGE_PATH);
+        spec.mounts()
+            .iter()
+            .flatten()
+            .rev() // the last mount of a destination shadows the others
+            .find(|mount| mount.destination() == Path::new(destination))
+            .and_then(|mount| mount.source().clone())
+    }
+
+    /// Writes the message of the report to the termination message at `path`, unless the
+    /// guest wrote its own. The file is never created, nor followed if it's a symlink.
+    #[cfg_attr(windows, allow(dead_code))] // the termination message is written by the unix shim
+    pub(crate) fn write_termination_message(&self, path: &Path) -> std::io::Result<()> {
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true);
+        #[cfg(unix)]
+        options.custom_flags(libc::O_NOFOLLOW);
+        let mut file = options.open(path)?;
+        let metadata = file.metadata()?;
+        if !metadata.is_file() || metadata.len() > 0 {
+            return Ok(());
+        }
+        let mut message = self.message();
+        if message.len() > MAX_TERMINATION_MESSAGE_LEN {
+            let mut end = MAX_TERMINATION_MESSAGE_LEN;
+            while !message.is_char_boundary(end) {
+                end -= 1;
+            }
+            message.truncate(end);
+        }
+        file.write_all(message.as_bytes())
+    }
+
+    /// Opens the file in `bundle` the report of a container is written to, emptied.
+    /// It is opened by the shim, as the bundle isn't visible from the container.
+    pub(crate) fn open(bundle: &Path) -> std::io::Result<File> {
+        std::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(bundle.join(EXIT_REPORT_FILE))
+    }
+
+    /// Reads the report written to the bundle of a task, if its engine terminated it.
+    pub(crate) fn read(bundle: &Path) -> Option<Self> {
+        let data = std::fs::read(bundle.join(EXIT_REPORT_FILE)).ok()?;
+        if data.is_empty() {
+            return None;
+        }
+        serde_json::from_slice(&data)
+            .inspect_err(|err| log::debug!("ignoring invalid exit report: {err}"))
+            .ok()
+    }
+}
+
+/// Terminates the container of an engine, reporting why to the shim.
+#[derive(Clone, Debug, Default)]
+pub struct Terminator {
+    file: Option<Arc<File>>,
+}
+
+impl Terminator {
+    pub(crate) fn new(file: Option<Arc<File>>) -> Self {
+        Self { file }
+    }
+
+    /// Writes `report` for the shim, flushes the output of the container, and exits the
+    /// container with the exit code of the report.
+    pub fn terminate(&self, report: ExitReport) -> ! {
+        log::error!(
+            "terminating with exit code {}: {}",
+            report.exit_code,
+            report.reason
+        );
+        if let Err(err) = self.write(&report) {
+            log::warn!("failed to write the exit report: {err}");
+        }
+        let _ = std::io::stdout().flush();
+        let _ = std::io::stderr().flush();
+        std::process::exit(report.exit_code)
+    }
+
+    #[cfg(unix)]
+    fn write(&self, report: &ExitReport) -> std::io::Result<()> {
+        let Some(file) = &self.file else {
+            return Ok(());
+        };
+        let data = serde_json::to_vec(report)?;
+        file.write_all_at(&data, 0)?;
+        file.set_len(data.len() as u64)
+    }
+
+    #[cfg(not(unix))]
+    fn write(&self, _report: &ExitReport) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_exit_report() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        assert_eq!(ExitReport::read(dir.path()), None);
+
+        std::fs::write(dir.path().join(EXIT_REPORT_FILE), "")?;
+        assert_eq!(ExitReport::read(dir.path()), None);
+
+        let report = ExitReport::new(78, "invalid runtime config").with_detail("setting", "fuel");
+        std::fs::write(
+            dir.path().join(EXIT_REPORT_FILE),
+            serde_json::to_vec(&report)?,
+        )?;
+        assert_eq!(ExitReport::read(dir.path()), Some(report));
+        Ok(())
+    }
+
+    #[test]
+    fn test_termination_message() -> anyhow::Result<()> {
+        use oci_spec::runtime::{MountBuilder, SpecBuilder};
+
+        let dir = tempfile::tempdir()?;
+        let log = dir.path().join("termination-log");
+        std::fs::write(&log, "")?;
+        let mount = |destination: &str| {
+            MountBuilder::default()
+                .destination(destination)
+                .source(&log)
+                .build()
+                .unwrap()
+        };
+
+        let spec = SpecBuilder::default()
+            .mounts(vec![mount("/dev/termination-log")])
+            .build()?;
+        assert_eq!(ExitReport::termination_log(&spec), Some(log.clone()));
+
+        let spec = SpecBuilder::default()
+            .mounts(vec![mount("/dev/termination-log")])
+            .annotations([(
+                TERMINATION_MESSAGE_PATH_ANNOTATION.to_string(),
+                "/tmp/message".to_string(),
+            )])
+            .build()?;
+        assert_eq!(ExitReport::termination_log(&spec), None);
+
+        let report = ExitReport::new(78, "invalid runtime config")
+            .with_detail("setting", "fuel")
+            .with_detail("value", -1);
+        assert_eq!(
+            report.message(),
+            "invalid runtime config: setting=fuel, value=-1"
+        );
+        report.write_termination_message(&log)?;
+        assert_eq!(std::fs::read_to_string(&log)?, report.message());
+
+        // the message of the guest is kept
+        std::fs::write(&log, "out of cheese")?;
+        report.write_termination_message(&log)?;
+        assert_eq!(std::fs::read_to_string(&log)?, "out of cheese");
+
+        // the termination message is never created
+        let missing = dir.path().join("missing");
+        assert!(report.write_termination_message(&missing).is_err());
+        assert!(!missing.exists());
+        Ok(())
+    }
+}
@@ -0,0 +1,147 @@
+//! Host side of the `wasi:logging` interface, for engines to hand the log calls of
+//! components to.
+//!
+//! By default, log records are written to the stdout of the container, or its stderr from
+//! the `warn` level, as `[LEVEL] context: message` lines. With the
+//! `runwasi.io/guest-log: shim` annotation, they are logged by the shim instead, with the
+//! `guest` target and the id of the container.
+
+use std::io::Write;
+use std::str::FromStr;
+
+use anyhow::bail;
+
+/// Annotation with where the log records of the guest go, `stdio` or `shim`.
+pub const GUEST_LOG_ANNOTATION: &str = "runwasi.io/guest-log";
+
+/// Level of a log record of the guest, as in `wasi:logging`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum GuestLogLevel {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+    Critical,
+}
+
+impl GuestLogLevel {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Trace => "TRACE",
+            Self::Debug => "DEBUG",
+            Self::Info => "INFO",
+            Self::Warn => "WARN",
+            Self::Error => "ERROR",
+            Self::Critical => "CRITICAL",
+        }
+    }
+}
+
+impl From<GuestLogLevel> for log::Level {
+    fn from(level: GuestLogLevel) -> Self {
+        match level {
+            GuestLogLevel::Trace => log::Level::Trace,
+            GuestLogLevel::Debug => log::Level::Debug,
+            GuestLogLevel::Info => log::Level::Info,
+            GuestLogLevel::Warn => log::Level::Warn,
+            GuestLogLevel::Error | GuestLogLevel::Critical => log::Level::Error,
+        }
+    }
+}
+
+/// Where the log records of the guest go.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GuestLogTarget {
+    /// The stdout and stderr of the container.
+    #[default]
+    Stdio,
+    /// The logs of the shim.
+    Shim,
+}
+
+impl FromStr for GuestLogTarget {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "stdio" => Ok(Self::Stdio),
+            "shim" => Ok(Self::Shim),
+            _ => bail!("invalid guest log target {s:?}"),
+        }
+    }
+}
+
+/// Routes the log records of a guest to the output of its container, or to the shim.
+#[derive(Clone, Debug, Default)]
+pub struct GuestLogger {
+    id: String,
+    target: GuestLogTarget,
+}
+
+impl GuestLogger {
+    pub fn new(id: impl Into<String>, target: GuestLogTarget) -> Self {
+        Self {
+            id: id.into(),
+            target,
+        }
+    }
+
+    /// Logs a record of the guest, as called through `wasi:logging/logging.log`.
+    pub fn log(&self, level: GuestLogLevel, context: &str, message: &str) {
+        match self.target {
+            GuestLogTarget::Stdio => {
+                let line = format_line(level, context, message);
+                // a guest can't do anything about its output being closed
+                let _ = if level >= GuestLogLevel::Warn {
+                    std::io::stderr().lock().write_all(line.as_bytes())
+                } else {
+                    std::io::stdout().lock().write_all(line.as_bytes())
+                };
+            }
+            GuestLogTarget::Shim => {
+                let id = &self.id;
+                let level = log::Level::from(level);
+                match context {
+                    "" => log::log!(target: "guest", level, "{id}: {message}"),
+                    _ => log::log!(target: "guest", level, "{id}: {context}: {message}"),
+                }
+            }
+        }
+    }
+}
+
+fn format_line(level: GuestLogLevel, context: &str, message: &str) -> String {
+    let level = level.as_str();
+    match context {
+        "" => format!("[{level}] {message}\n"),
+        _ => format!("[{level}] {context}: {message}\n"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_line() {
+        assert_eq!(
+            format_line(GuestLogLevel::Warn, "db", "slow query"),
+            "[WARN] db: slow query\n"
+        );
+        assert_eq!(
+            format_line(GuestLogLevel::Critical, "", "out of memory"),
+            "[CRITICAL] out of memory\n"
+        );
+        assert_eq!(log::Level::from(GuestLogLevel::Critical), log::Level::Error);
+    }
+
+    #[test]
+    fn test_guest_log_target() {
+        assert_eq!(
+            "shim".parse::<GuestLogTarget>().unwrap(),
+            GuestLogTarget::Shim
+        );
+        "syslog".parse::<GuestLogTarget>().unwrap_err();
+    }
+}
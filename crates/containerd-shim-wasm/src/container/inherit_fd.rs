@@ -0,0 +1,93 @@
+//! File descriptors passed by the platform to the guest.
+//!
+//! Some platforms hand resources to a container as open file descriptors rather than
+//! paths, e.g., a service mesh binding the listening socket of a service on its behalf.
+//! With the `runwasi.io/inherit-fds` annotation, the container gets the file descriptors
+//! the platform sends over a unix socket, each with a name and a role telling the engine
+//! what to do with it.
+//!
+//! The annotation is the path of the socket, relative to the bundle, which it can't leave.
+//! When creating the container, the shim connects to it and receives a single message, which
+//! must be sent within a few seconds, and whose payload is a JSON array with the name and
+//! role of each file descriptor, e.g.:
+//!
+//! ```json
+//! [{"name": "http", "role": "listener"}, {"name": "audit", "role": "log"}]
+//! ```
+//!
+//! and whose `SCM_RIGHTS` ancillary data has the file descriptors, in the same order.
+//!
+//! The wasmtime shim serves `wasi:http` proxies on the first `listener`, instead of binding
+//! its own socket.
+
+use std::fs::File;
+#[cfg(unix)]
+use std::os::fd::{AsFd, BorrowedFd};
+
+use serde::{Deserialize, Serialize};
+
+/// Annotation with the path of the socket the platform sends file descriptors on.
+pub const INHERIT_FDS_ANNOTATION: &str = "runwasi.io/inherit-fds";
+
+/// What a file descriptor passed by the platform is for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FdRole {
+    /// A bound socket, listening for connections the guest accepts.
+    Listener,
+    /// A connected socket.
+    Socket,
+    /// A pipe or file the guest writes logs to.
+    Log,
+    /// Any other file.
+    File,
+}
+
+/// The name and role of a file descriptor, as sent by the platform.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FdDescriptor {
+    pub name: String,
+    pub role: FdRole,
+}
+
+/// A file descriptor passed by the platform to the guest.
+#[derive(Debug)]
+pub struct InheritedFd {
+    name: String,
+    role: FdRole,
+    file: File,
+}
+
+impl InheritedFd {
+    pub fn new(name: impl Into<String>, role: FdRole, file: File) -> Self {
+        Self {
+            name: name.into(),
+            role,
+            file,
+        }
+    }
+
+    /// The name of the file descriptor, unique in the container.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// What the file descriptor is for.
+    pub fn role(&self) -> FdRole {
+        self.role
+    }
+
+    /// The file descriptor, as a file.
+    /// Engines can duplicate it with `try_clone`, and convert it to the type of its role.
+    pub fn file(&self) -> &File {
+        &self.file
+    }
+}
+
+#[cfg(unix)]
+impl AsFd for InheritedFd {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.file.as_fd()
+    }
+}
@@ -0,0 +1,26 @@
+//! Metadata of the instance running a guest.
+//!
+//! Engines implementing host capabilities often need to know which container they run in,
+//! e.g., to label metrics or to name debug dumps. [`RuntimeContext::instance_info`] provides
+//! it from inside `run_wasi`, without parsing `/proc` or relying on environment variables.
+//!
+//! [`RuntimeContext::instance_info`]: crate::container::RuntimeContext::instance_info
+
+use std::path::PathBuf;
+
+use chrono::{DateTime, Utc};
+
+/// Metadata of the instance running the guest.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct InstanceInfo {
+    /// The id of the container.
+    pub id: String,
+    /// The pid of the container process, as seen from its pid namespace.
+    pub pid: u32,
+    /// The cgroup of the container, relative to the cgroup root, if the runtime spec sets one.
+    pub cgroup_path: Option<PathBuf>,
+    /// The directory the state of the container is kept in by the shim.
+    pub state_dir: PathBuf,
+    /// The time the guest was started.
+    pub started_at: DateTime<Utc>,
+}
@@ -0,0 +1,214 @@
+//! Roles and names of the wasm layers of an image.
+//!
+//! An image can have several wasm layers, of which only one is the command to run.
+//! The others are libraries the command links to, or data it reads (e.g., a runtime
+//! configuration file). The role of a layer is set with the `runwasi.io/layer-role`
+//! annotation on its descriptor (`command`, `library` or `data`). Otherwise, layers with
+//! a wasm media type are modules, the first of which is the command, and other layers are data.
+//!
+//! Layers are named after their `org.opencontainers.image.title` annotation,
+//! or their digest when it isn't set.
+
+use anyhow::bail;
+use oci_spec::image::Descriptor;
+
+use crate::sandbox::oci::WasmLayer;
+
+/// Annotation with the role of a wasm layer: `command`, `library` or `data`.
+pub const LAYER_ROLE_ANNOTATION: &str = "runwasi.io/layer-role";
+
+const TITLE_ANNOTATION: &str = "org.opencontainers.image.title";
+
+/// The role of a wasm layer in an image.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LayerRole {
+    /// The module / component to run.
+    Command,
+    /// A module / component the command links to.
+    Library,
+    /// Data used by the command.
+    Data,
+}
+
+/// A wasm layer with its name and role.
+#[derive(Clone, Debug)]
+pub struct NamedLayer<'a> {
+    pub name: String,
+    pub role: LayerRole,
+    pub layer: &'a WasmLayer,
+}
+
+/// The wasm layers of an image, by name.
+#[derive(Clone, Debug, Default)]
+pub struct WasmLayers<'a> {
+    // in the order of the manifest
+    layers: Vec<NamedLayer<'a>>,
+}
+
+impl<'a> WasmLayers<'a> {
+    /// Names the layers and assigns their roles.
+    /// Fails if several layers are annotated as the command, or if names aren't unique.
+    pub fn new(layers: &'a [WasmLayer]) -> anyhow::Result<Self> {
+        let mut named = Vec::with_capacity(layers.len());
+        let mut has_command = false;
+
+        for layer in layers {
+            let role = match annotation(&layer.config, LAYER_ROLE_ANNOTATION) {
+                Some("command") => LayerRole::Command,
+                Some("library") => LayerRole::Library,
+                Some("data") => LayerRole::Data,
+                Some(role) => bail!("invalid {LAYER_ROLE_ANNOTATION} {role:?}"),
+                None if !is_module(&layer.config) => LayerRole::Data,
+                // resolved once all the layers are known
+                None => LayerRole::Library,
+            };
+            if role == LayerRole::Command {
+                if has_command {
+                    bail!("only one layer can be the command");
+                }
+                has_command = true;
+            }
+
+            let name = annotation(&layer.config, TITLE_ANNOTATION)
+                .map(ToString::to_string)
+                .unwrap_or_else(|| layer.config.digest().to_string());
+            if named.iter().any(|l: &NamedLayer| l.name == name) {
+                bail!("duplicate wasm layer name {name:?}");
+            }
+
+            named.push(NamedLayer { name, role, layer });
+        }
+
+        if !has_command {
+            let first_module = named.iter_mut().find(|l| {
+                l.role == LayerRole::Library
+                    && annotation(&l.layer.config, LAYER_ROLE_ANNOTATION).is_none()
+            });
+            if let Some(layer) = first_module {
+                layer.role = LayerRole::Command;
+            }
+        }
+
+        Ok(Self { layers: named })
+    }
+
+    /// Returns the layer to run.
+    pub fn command(&self) -> Option<&NamedLayer<'a>> {
+        self.layers.iter().find(|l| l.role == LayerRole::Command)
+    }
+
+    /// Returns the layer named `name`.
+    pub fn get(&self, name: &str) -> Option<&NamedLayer<'a>> {
+        self.layers.iter().find(|l| l.name == name)
+    }
+
+    /// Returns the layers with the given role, in the order of the manifest.
+    pub fn with_role(&self, role: LayerRole) -> impl Iterator<Item = &NamedLayer<'a>> {
+        self.layers.iter().filter(move |l| l.role == role)
+    }
+
+    /// Returns all the layers, in the order of the manifest.
+    pub fn iter(&self) -> impl Iterator<Item = &NamedLayer<'a>> {
+        self.layers.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.layers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.layers.is_empty()
+    }
+}
+
+/// Returns true if `layer` is annotated as a library, e.g., a module pulled with the
+/// `runwasi.io/pull-modules` annotation.
+pub(crate) fn is_library(layer: &WasmLayer) -> bool {
+    annotation(&layer.config, LAYER_ROLE_ANNOTATION) == Some("library")
+}
+
+fn annotation<'a>(descriptor: &'a Descriptor, key: &str) -> Option<&'a str> {
+    descriptor
+        .annotations()
+        .as_ref()
+        .and_then(|a| a.get(key))
+        .map(String::as_str)
+}
+
+fn is_module(descriptor: &Descriptor) -> bool {
+    let media_type = descriptor.media_type().to_string();
+    media_type == "application/wasm" || media_type.ends_with("+wasm")
+}
+
+#[cfg(test)]
+mod tests {
+    use oci_spec::image::{Digest, MediaType};
+
+    use super::*;
+
+    fn layer(media_type: &str, seed: u8, annotations: &[(&str, &str)]) -> WasmLayer {
+        let digest = Digest::try_from(format!("sha256:{:064}", seed)).unwrap();
+        let mut config = Descriptor::new(MediaType::Other(media_type.to_string()), 1, digest);
+        config.set_annotations(Some(
+            annotations
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        ));
+        WasmLayer {
+            config,
+            layer: vec![seed],
+        }
+    }
+
+    const MODULE: &str = "application/vnd.w3c.wasm.module.v1+wasm";
+
+    #[test]
+    fn test_first_module_is_the_command() -> anyhow::Result<()> {
+        let layers = [
+            layer("application/toml", 0, &[(TITLE_ANNOTATION, "config.toml")]),
+            layer(MODULE, 1, &[(TITLE_ANNOTATION, "app.wasm")]),
+            layer(MODULE, 2, &[(TITLE_ANNOTATION, "lib.wasm")]),
+        ];
+        let layers = WasmLayers::new(&layers)?;
+
+        assert_eq!(layers.command().unwrap().name, "app.wasm");
+        assert_eq!(layers.get("lib.wasm").unwrap().role, LayerRole::Library);
+        assert_eq!(layers.get("config.toml").unwrap().role, LayerRole::Data);
+        assert_eq!(layers.get("config.toml").unwrap().layer.layer, [0]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_annotated_roles() -> anyhow::Result<()> {
+        let layers = [
+            layer(MODULE, 0, &[(LAYER_ROLE_ANNOTATION, "library")]),
+            layer(MODULE, 1, &[(LAYER_ROLE_ANNOTATION, "command")]),
+        ];
+        let layers = WasmLayers::new(&layers)?;
+
+        let command = layers.command().unwrap();
+        assert_eq!(command.layer.layer, [1]);
+        assert_eq!(command.name, layers.iter().nth(1).unwrap().name);
+        assert_eq!(layers.with_role(LayerRole::Library).count(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_layers() {
+        let layers = [
+            layer(MODULE, 0, &[(LAYER_ROLE_ANNOTATION, "command")]),
+            layer(MODULE, 1, &[(LAYER_ROLE_ANNOTATION, "command")]),
+        ];
+        assert!(WasmLayers::new(&layers).is_err());
+
+        let layers = [
+            layer(MODULE, 0, &[(TITLE_ANNOTATION, "app.wasm")]),
+            layer(MODULE, 1, &[(TITLE_ANNOTATION, "app.wasm")]),
+        ];
+        assert!(WasmLayers::new(&layers).is_err());
+
+        let layers = [layer(MODULE, 0, &[(LAYER_ROLE_ANNOTATION, "plugin")])];
+        assert!(WasmLayers::new(&layers).is_err());
+    }
+}
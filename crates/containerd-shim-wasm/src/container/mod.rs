@@ -10,17 +10,52 @@
 //! * Less customizable
 //! * Currently only works on Linux
 
+mod capabilities;
+mod clock;
+mod component_limits;
 mod context;
+mod drain;
 mod engine;
+mod engine_metrics;
+mod entrypoint;
+mod exit_report;
+mod guest_log;
+mod inherit_fd;
+mod instance_info;
+mod layers;
+mod module_bytes;
 mod path;
+mod precompiled_artifact;
+mod termination;
 mod wasm;
+mod write_policy;
 
-pub(crate) use context::WasiContext;
+pub use capabilities::Capabilities;
+pub use clock::{ClockThrottle, ThrottledClock};
+pub use component_limits::ComponentLimits;
 pub use context::{Entrypoint, RuntimeContext, Source};
+pub(crate) use context::{StartupSignal, WasiContext};
+pub(crate) use drain::stats as drain_stats;
+pub use drain::{DrainWindow, DRAIN_WINDOW_ANNOTATION};
 pub use engine::Engine;
+pub(crate) use engine_metrics::ENGINE_METRICS_FILE;
+pub use engine_metrics::{EngineMetrics, ENGINE_METRICS_FIELD};
+pub use entrypoint::{resolve_entrypoint, ResolvedEntrypoint, DEFAULT_FUNC};
+pub use exit_report::{ExitReport, Terminator, TERMINATION_MESSAGE_PATH_ANNOTATION};
+pub use guest_log::{GuestLogLevel, GuestLogTarget, GuestLogger, GUEST_LOG_ANNOTATION};
+pub use inherit_fd::{FdDescriptor, FdRole, InheritedFd, INHERIT_FDS_ANNOTATION};
 pub use instance::Instance;
+pub use instance_info::InstanceInfo;
+pub use layers::{LayerRole, NamedLayer, WasmLayers, LAYER_ROLE_ANNOTATION};
+pub use module_bytes::ModuleBytes;
+pub(crate) use module_bytes::{share_layers, shared_layer};
 pub(crate) use path::PathResolve;
+pub use precompiled_artifact::PrecompiledArtifact;
+pub use termination::{
+    TerminationDeadline, DEFAULT_TERMINATION_GRACE_PERIOD, TERMINATION_GRACE_PERIOD_ANNOTATION,
+};
 pub use wasm::WasmBinaryType;
+pub use write_policy::{WritePolicy, WRITE_ALLOW_ANNOTATION};
 
 use crate::sys::container::instance;
 
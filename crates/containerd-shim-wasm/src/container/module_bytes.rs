@@ -0,0 +1,106 @@
+//! The bytes of a module, as returned by [`Source::as_bytes`](crate::container::Source::as_bytes).
+//!
+//! The wasm modules of the layers of an image are written once per node to the artifact cache
+//! of the shim, keyed by the digest of the layer, and the zygote of each container maps them
+//! before the container process is forked, dropping their copy in the create request. The
+//! containers running the same image share the pages of its modules in the page cache, instead
+//! of each keeping its own copy. Engines get them with
+//! [`WasmLayer::bytes`](crate::sandbox::WasmLayer::bytes).
+//!
+//! Modules read from a file of the bundle are copied to the heap: the rootfs is writable by
+//! the guest, and a mapping of a file that's truncated while it's read isn't sound.
+
+use std::collections::HashMap;
+use std::io::Result;
+use std::ops::Deref;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use memmap2::Mmap;
+
+// A container process runs a single container, so the layers it maps are kept for the process.
+static SHARED_LAYERS: OnceLock<HashMap<String, Mmap>> = OnceLock::new();
+
+/// Sets the mappings of the layers shared with the other containers, keyed by their digest.
+/// Returns false if the layers of this process were already set.
+#[cfg_attr(windows, allow(dead_code))] // containers only run in their own process on linux
+pub(crate) fn share_layers(layers: HashMap<String, Mmap>) -> bool {
+    SHARED_LAYERS.set(layers).is_ok()
+}
+
+/// Returns the mapping of the layer `digest`, if it's shared with the other containers.
+pub(crate) fn shared_layer(digest: &str) -> Option<&'static [u8]> {
+    SHARED_LAYERS.get()?.get(digest).map(|mmap| &mmap[..])
+}
+
+/// The bytes of a module, used by engines as a `&[u8]`.
+pub struct ModuleBytes<'a>(Inner<'a>);
+
+enum Inner<'a> {
+    Borrowed(&'a [u8]),
+    Owned(Vec<u8>),
+}
+
+impl ModuleBytes<'_> {
+    /// Reads the module at `path`.
+    pub fn read(path: impl AsRef<Path>) -> Result<Self> {
+        Ok(Self(Inner::Owned(std::fs::read(path)?)))
+    }
+}
+
+impl<'a> From<&'a [u8]> for ModuleBytes<'a> {
+    fn from(bytes: &'a [u8]) -> Self {
+        Self(Inner::Borrowed(bytes))
+    }
+}
+
+impl From<Vec<u8>> for ModuleBytes<'_> {
+    fn from(bytes: Vec<u8>) -> Self {
+        Self(Inner::Owned(bytes))
+    }
+}
+
+impl Deref for ModuleBytes<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match &self.0 {
+            Inner::Borrowed(bytes) => bytes,
+            Inner::Owned(bytes) => bytes,
+        }
+    }
+}
+
+impl AsRef<[u8]> for ModuleBytes<'_> {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl std::fmt::Debug for ModuleBytes<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ModuleBytes")
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("app.wasm");
+        std::fs::write(&path, b"\0asm\x01\0\0\0")?;
+
+        let bytes = ModuleBytes::read(&path)?;
+        assert_eq!(&*bytes, b"\0asm\x01\0\0\0");
+
+        std::fs::write(&path, b"")?;
+        let bytes = ModuleBytes::read(&path)?;
+        assert!(bytes.is_empty());
+        Ok(())
+    }
+}
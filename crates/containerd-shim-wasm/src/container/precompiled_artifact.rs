@@ -0,0 +1,83 @@
+//! Precompiled artifacts shared by the containers of a node.
+//!
+//! The precompiled layers of an image are written once per node to an artifact cache keyed by
+//! the digest of the layer, and each container gets a read-only mapping of the cached file
+//! instead of compiled code of its own, so that the replicas of an image share its pages in
+//! the page cache. The cache is in the root directory of the shim, on tmpfs, so it is cleared
+//! on reboot.
+//!
+//! Engines get the artifact of the command of a container with
+//! [`RuntimeContext::precompiled_artifact`](crate::container::RuntimeContext::precompiled_artifact).
+//! The code pages are only shared if the engine loads its code from the file of the artifact,
+//! e.g., with [`path`](PrecompiledArtifact::path), rather than copying its bytes.
+
+use std::fs::File;
+use std::ops::Deref;
+#[cfg(unix)]
+use std::os::fd::AsRawFd;
+use std::path::Path;
+
+use memmap2::Mmap;
+
+/// A read-only mapping of a precompiled artifact, shared with the other containers of the node
+/// running the same layer.
+pub struct PrecompiledArtifact {
+    digest: String,
+    file: File,
+    mmap: Mmap,
+}
+
+impl PrecompiledArtifact {
+    /// Maps the precompiled artifact of the layer `digest` cached at `path`.
+    pub(crate) fn open(digest: impl Into<String>, path: &Path) -> std::io::Result<Self> {
+        let file = File::open(path)?;
+        // SAFETY: cached artifacts are written to a temporary file and renamed, so the file
+        // is never modified once it's in the cache, and the mapping is read-only.
+        let mmap = unsafe { Mmap::map(&file)? };
+        Ok(Self {
+            digest: digest.into(),
+            file,
+            mmap,
+        })
+    }
+
+    /// Returns the digest of the layer the artifact was compiled from.
+    pub fn digest(&self) -> &str {
+        &self.digest
+    }
+
+    /// Returns the cached file of the artifact, opened read-only.
+    pub fn file(&self) -> &File {
+        &self.file
+    }
+
+    /// Returns a path to the cached file of the artifact, through `/proc/self/fd`, which is
+    /// valid in the container, where the artifact cache itself isn't visible.
+    #[cfg(unix)]
+    pub fn path(&self) -> std::path::PathBuf {
+        std::path::PathBuf::from(format!("/proc/self/fd/{}", self.file.as_raw_fd()))
+    }
+}
+
+impl Deref for PrecompiledArtifact {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.mmap
+    }
+}
+
+impl AsRef<[u8]> for PrecompiledArtifact {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl std::fmt::Debug for PrecompiledArtifact {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PrecompiledArtifact")
+            .field("digest", &self.digest)
+            .field("len", &self.mmap.len())
+            .finish()
+    }
+}
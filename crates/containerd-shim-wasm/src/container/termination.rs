@@ -0,0 +1,129 @@
+//! Deadline for a guest to finish its work once its termination is requested.
+//!
+//! When a task is stopped, it is first asked to terminate, and killed once its grace period
+//! elapses. Host capabilities that can block for a long time (e.g., outbound HTTP requests)
+//! can bound their timeouts by the time left, so that they fail cooperatively before the
+//! hard kill.
+//!
+//! The grace period is set with the `runwasi.io/termination-grace-period` annotation, in seconds,
+//! and defaults to 30 seconds, the default grace period of a Kubernetes pod.
+//!
+//! Engines that handle termination signals gracefully start the countdown with
+//! [`TerminationDeadline::terminate`] when the signal arrives.
+
+use std::sync::{Arc, LazyLock, OnceLock};
+use std::time::{Duration, Instant};
+
+/// Annotation with the number of seconds a guest is given to terminate.
+pub const TERMINATION_GRACE_PERIOD_ANNOTATION: &str = "runwasi.io/termination-grace-period";
+
+/// The grace period used when the annotation isn't set.
+pub const DEFAULT_TERMINATION_GRACE_PERIOD: Duration = Duration::from_secs(30);
+
+// A container process runs a single task, so the time its termination was requested
+// is shared by all the contexts in the process.
+static REQUESTED_AT: LazyLock<Arc<OnceLock<Instant>>> = LazyLock::new(Arc::default);
+
+/// The time left for a guest to terminate.
+#[derive(Clone, Debug)]
+pub struct TerminationDeadline {
+    grace_period: Duration,
+    requested_at: Arc<OnceLock<Instant>>,
+}
+
+impl TerminationDeadline {
+    /// Creates a deadline that is independent from the termination of the current process.
+    pub fn new(grace_period: Duration) -> Self {
+        Self {
+            grace_period,
+            requested_at: Arc::default(),
+        }
+    }
+
+    pub(crate) fn for_process(grace_period: Duration) -> Self {
+        Self {
+            grace_period,
+            requested_at: REQUESTED_AT.clone(),
+        }
+    }
+
+    /// The time the guest is given to terminate.
+    pub fn grace_period(&self) -> Duration {
+        self.grace_period
+    }
+
+    /// Records that termination was requested, starting the countdown.
+    /// Subsequent calls have no effect.
+    pub fn terminate(&self) {
+        let _ = self.requested_at.set(Instant::now());
+    }
+
+    /// Returns when the guest will be killed, if its termination was requested.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.requested_at.get().map(|t| *t + self.grace_period)
+    }
+
+    /// Returns the time left before the guest is killed, if its termination was requested.
+    pub fn remaining(&self) -> Option<Duration> {
+        self.deadline()
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    /// Bounds `timeout` by the time left before the guest is killed.
+    pub fn bound(&self, timeout: Duration) -> Duration {
+        match self.remaining() {
+            Some(remaining) => timeout.min(remaining),
+            None => timeout,
+        }
+    }
+}
+
+impl Default for TerminationDeadline {
+    fn default() -> Self {
+        Self::new(DEFAULT_TERMINATION_GRACE_PERIOD)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_no_deadline_before_termination() {
+        let deadline = TerminationDeadline::new(Duration::from_secs(10));
+        assert!(deadline.deadline().is_none());
+        assert!(deadline.remaining().is_none());
+        assert_eq!(
+            deadline.bound(Duration::from_secs(600)),
+            Duration::from_secs(600)
+        );
+    }
+
+    #[test]
+    fn test_deadline_after_termination() {
+        let deadline = TerminationDeadline::new(Duration::from_secs(10));
+        let copy = deadline.clone();
+        copy.terminate();
+
+        let remaining = deadline.remaining().unwrap();
+        assert!(remaining <= Duration::from_secs(10));
+        assert!(remaining > Duration::from_secs(5));
+        assert!(deadline.bound(Duration::from_secs(600)) <= remaining);
+        assert_eq!(
+            deadline.bound(Duration::from_secs(1)),
+            Duration::from_secs(1)
+        );
+
+        // the countdown isn't restarted
+        let first = deadline.deadline();
+        deadline.terminate();
+        assert_eq!(deadline.deadline(), first);
+    }
+
+    #[test]
+    fn test_zero_grace_period() {
+        let deadline = TerminationDeadline::new(Duration::ZERO);
+        deadline.terminate();
+        assert_eq!(deadline.bound(Duration::from_secs(1)), Duration::ZERO);
+    }
+}
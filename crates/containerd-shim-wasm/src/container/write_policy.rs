@@ -0,0 +1,139 @@
+//! Restrictions on the paths a guest can write to.
+//!
+//! Even when a volume is mounted read-write, a partially trusted module might only need to
+//! write to a few of its directories. The write allow-list is set with the
+//! `runwasi.io/write-allow` annotation, as a comma separated list of glob patterns, e.g.:
+//!
+//! ```text
+//! runwasi.io/write-allow: /data/uploads,/tmp/*
+//! ```
+//!
+//! When the annotation is set, engines should preopen the root of the container as read-only,
+//! and layer on top of it the directories matching the allow-list with write access.
+//! When it isn't set, the guest can write anywhere its mounts allow.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use glob::Pattern;
+
+/// Annotation with the comma separated list of glob patterns the guest is allowed to write to.
+pub const WRITE_ALLOW_ANNOTATION: &str = "runwasi.io/write-allow";
+
+/// The paths a guest is allowed to write to.
+#[derive(Clone, Debug, Default)]
+pub struct WritePolicy {
+    allow: Option<Vec<Pattern>>,
+}
+
+impl WritePolicy {
+    /// Parses a comma separated list of glob patterns.
+    pub fn parse(allow: &str) -> Result<Self> {
+        let allow = allow
+            .split(',')
+            .map(str::trim)
+            .filter(|p| !p.is_empty())
+            .map(|p| Pattern::new(p).with_context(|| format!("invalid write-allow pattern {p:?}")))
+            .collect::<Result<_>>()?;
+        Ok(Self { allow: Some(allow) })
+    }
+
+    /// A policy that denies all writes.
+    pub fn read_only() -> Self {
+        Self {
+            allow: Some(vec![]),
+        }
+    }
+
+    /// Returns true if the guest can write anywhere its mounts allow.
+    pub fn is_unrestricted(&self) -> bool {
+        self.allow.is_none()
+    }
+
+    /// Returns true if the guest is allowed to write to `path`.
+    pub fn allows(&self, path: impl AsRef<Path>) -> bool {
+        let path = path.as_ref();
+        match &self.allow {
+            None => true,
+            Some(allow) => allow.iter().any(|p| p.matches_path(path)),
+        }
+    }
+
+    /// Returns the existing directories that match the allow-list.
+    ///
+    /// Preopens can only grant write access to whole directories, so
+    /// patterns matching regular files are ignored.
+    pub fn writable_dirs(&self) -> Vec<PathBuf> {
+        let Some(allow) = &self.allow else {
+            return vec![PathBuf::from("/")];
+        };
+
+        let mut dirs: Vec<PathBuf> = allow
+            .iter()
+            .filter_map(|p| glob::glob(p.as_str()).ok())
+            .flatten()
+            .filter_map(|entry| entry.ok())
+            .filter(|path| {
+                let is_dir = path.is_dir();
+                if !is_dir {
+                    log::warn!("ignoring write-allow match {path:?}: not a directory");
+                }
+                is_dir
+            })
+            .collect();
+        dirs.sort();
+        dirs.dedup();
+        dirs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn test_default_is_unrestricted() {
+        let policy = WritePolicy::default();
+        assert!(policy.is_unrestricted());
+        assert!(policy.allows("/etc/passwd"));
+        assert_eq!(policy.writable_dirs(), vec![PathBuf::from("/")]);
+    }
+
+    #[test]
+    fn test_allows() -> Result<()> {
+        let policy = WritePolicy::parse("/data/uploads, /tmp/*")?;
+        assert!(!policy.is_unrestricted());
+        assert!(policy.allows("/data/uploads"));
+        assert!(policy.allows("/tmp/cache"));
+        assert!(!policy.allows("/data"));
+        assert!(!policy.allows("/etc/passwd"));
+
+        // an empty allow-list denies all writes
+        let policy = WritePolicy::parse("")?;
+        assert!(!policy.allows("/tmp"));
+        assert!(policy.writable_dirs().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_pattern() {
+        WritePolicy::parse("/data/[").unwrap_err();
+    }
+
+    #[test]
+    fn test_writable_dirs() -> Result<()> {
+        let dir = tempdir()?;
+        std::fs::create_dir(dir.path().join("a"))?;
+        std::fs::create_dir(dir.path().join("b"))?;
+        std::fs::write(dir.path().join("file"), "")?;
+
+        let policy = WritePolicy::parse(&format!("{}/*", dir.path().display()))?;
+        assert_eq!(
+            policy.writable_dirs(),
+            vec![dir.path().join("a"), dir.path().join("b")]
+        );
+        Ok(())
+    }
+}
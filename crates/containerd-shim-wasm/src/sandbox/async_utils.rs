@@ -1,26 +1,45 @@
 #![cfg_attr(windows, allow(dead_code))] // this is currently used only for linux
 
 use std::future::Future;
+use std::sync::LazyLock;
 
-thread_local! {
-    // A thread local runtime that can be used to run futures to completion.
-    // It is a current_thread runtime so that it doesn't spawn new threads.
-    // It is thread local as different threads might want to run futures concurrently.
-    static RUNTIME: tokio::runtime::Runtime = {
-        tokio::runtime::Builder::new_current_thread()
+use crate::sandbox::config::RuntimeConfig;
+
+// The runtime of the shim, that can be used to run futures to completion.
+// Different threads might want to run futures concurrently: each of them drives its own future,
+// while the tasks they spawn, e.g., the connections to containerd shared by the instances, are
+// driven by the workers of the runtime, even when no thread blocks on it.
+// It is only started once the shim serves, after its zygotes are forked, so that they don't
+// inherit its threads.
+// It is sized with the `async_runtime` of the runtime configuration.
+static RUNTIME: LazyLock<tokio::runtime::Runtime> = LazyLock::new(|| {
+    let config = RuntimeConfig::current().async_runtime.clone();
+    let mut builder = tokio::runtime::Builder::new_multi_thread();
+    config.configure(&mut builder);
+    builder
+        .worker_threads(config.worker_threads.unwrap_or(1))
+        .thread_name("runwasi-async")
         .enable_all()
         .build()
         .unwrap()
-    };
-}
+});
 
 pub trait AmbientRuntime: Future {
     fn block_on(self) -> Self::Output
     where
         Self: Sized,
     {
-        RUNTIME.with(|runtime| runtime.block_on(self))
+        RUNTIME.block_on(self)
     }
 }
 
+/// Spawns `future` on the runtime of the shim.
+pub(crate) fn spawn<F>(future: F) -> tokio::task::JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    RUNTIME.spawn(future)
+}
+
 impl<F: Future> AmbientRuntime for F {}
@@ -34,6 +34,63 @@
 //! - [`version!()`] - Returns the crate version from Cargo.toml
 //! - [`revision!()`] - Returns the Git revision hash, if available
 //!
+//! `--version` prints them for humans, and `--version --json` prints a [`VersionInfo`]
+//! as JSON for inventory tooling, e.g.:
+//!
+//! ```json
+//! {"binary":"containerd-shim-wasmtime-v1","runtime":"wasmtime","version":"0.6.0","revision":"3f9c1a7b2d4e5f6","shim_id":"io.containerd.wasmtime.v1","crates":{"containerd-shim-wasm":"0.9.0"},"api":{"task":"v2"},"features":["opentelemetry"]}
+//! ```
+//!
+//! ## Embedded Module
+//!
+//! [`embed_module!()`](crate::embed_module) embeds a fallback wasm module in the shim
+//! binary, run for the containers without a wasm entrypoint when the runtime configuration
+//! allows it. See the [`embedded`](crate::sandbox::embedded) module.
+//!
+//! ## Health Check
+//!
+//! `healthcheck` checks that the shim can serve tasks on this node, for systemd and
+//! monitoring probes: the runtime configuration pointed by `RUNWASI_CONFIG` is valid, and
+//! the engine can be created. It prints a [`HealthReport`], as JSON with `--json`, and exits
+//! with a non-zero code if a check fails.
+//!
+//! ```json
+//! {"runtime":"wasmtime","version":"0.6.0","healthy":true,"checks":{"config":"ok","engine":"ok"}}
+//! ```
+//!
+//! With `-namespace <namespace> -id <id>`, it queries the running shim `id` instead, i.e., the
+//! shim of the pod sandbox `id` under CRI, with the `Health` RPC of its
+//! [`manager`](crate::sandbox::shim::manager) service, and also reports its tasks and its pid:
+//!
+//! ```json
+//! {"runtime":"wasmtime","version":"0.6.0","healthy":true,"checks":{"config":"ok","engine":"ok"},"instances":2,"pid":4242}
+//! ```
+//!
+//! ## Schema
+//!
+//! `schema` prints the JSON schema of the runtime configuration file and of the annotations
+//! read by the shim, as returned by [`schema::schema`](crate::sandbox::schema::schema),
+//! so that platforms can validate workloads against the shim they run on.
+//!
+//! ## Precompilation at pull time
+//!
+//! `precompile` is a containerd stream processor precompiling the wasm layers of images as they
+//! are pulled, so that the first container of an image doesn't compile them, e.g., for the
+//! `wasmtime` shim in the config of containerd:
+//!
+//! ```toml
+//! [stream_processors]
+//!   [stream_processors."io.containerd.wasmtime.precompile"]
+//!     accepts = ["application/vnd.bytecodealliance.wasm.component.layer.v0+wasm", "application/wasm"]
+//!     returns = "application/vnd.oci.image.layer.v1.tar"
+//!     path = "containerd-shim-wasmtime-v1"
+//!     args = ["-namespace", "k8s.io", "-address", "/run/containerd/containerd.sock", "precompile"]
+//! ```
+//!
+//! The precompiled content is stored like the one of the shim, and the layers are kept out of
+//! the snapshots of the image. Layers that fail to precompile are reported on stderr, and
+//! compiled by the first container of the image instead, so the pull doesn't fail.
+//!
 //! ## Example usage:
 //!
 //! ```rust, no_run
@@ -79,19 +136,24 @@
 //! - `OTEL_SDK_DISABLED`: Disable OpenTelemetry SDK
 //!
 
+use std::collections::BTreeMap;
 use std::path::PathBuf;
 
 use containerd_shim::{parse, run, Config};
+use serde::Serialize;
 
+use crate::sandbox::config::{RuntimeConfig, CONFIG_ENV};
+#[cfg(unix)]
+use crate::sandbox::shim::manager;
 #[cfg(feature = "opentelemetry")]
 use crate::sandbox::shim::{otel_traces_enabled, OtlpConfig};
-use crate::sandbox::{Instance, ShimCli};
+use crate::sandbox::{schema, stream_processor, Instance, ShimCli};
 
 pub mod r#impl {
     pub use git_version::git_version;
 }
 
-pub use crate::{revision, version};
+pub use crate::{embed_module, revision, version};
 
 /// Get the crate version from Cargo.toml.
 #[macro_export]
@@ -115,6 +177,144 @@ macro_rules! revision {
     };
 }
 
+/// Machine readable version information of a shim, printed by `--version --json`.
+/// The output only depends on the binary, so it is the same on every node running it.
+#[derive(Debug, Serialize)]
+pub struct VersionInfo {
+    /// Name of the shim binary.
+    pub binary: String,
+    /// Name of the runtime, e.g. `wasmtime`.
+    pub runtime: String,
+    /// Version of the shim.
+    pub version: String,
+    /// Git revision the shim was built from, if available.
+    pub revision: Option<String>,
+    /// The runtime id to configure in containerd, e.g. `io.containerd.wasmtime.v1`.
+    pub shim_id: String,
+    /// Versions of the crates the shim is built with.
+    pub crates: BTreeMap<&'static str, &'static str>,
+    /// containerd APIs served by the shim.
+    pub api: ApiLevels,
+    /// Optional features of `containerd-shim-wasm` the shim is built with.
+    pub features: Vec<&'static str>,
+}
+
+/// containerd APIs served by a shim.
+#[derive(Debug, Serialize)]
+pub struct ApiLevels {
+    /// Version of the containerd runtime (task) API.
+    pub task: &'static str,
+}
+
+impl VersionInfo {
+    fn new(
+        binary: &str,
+        runtime: &str,
+        version: &str,
+        revision: Option<&str>,
+        shim_id: &str,
+    ) -> Self {
+        let features = [
+            ("opentelemetry", cfg!(feature = "opentelemetry")),
+            ("tracing", cfg!(feature = "tracing")),
+        ];
+        Self {
+            binary: binary.to_string(),
+            runtime: runtime.to_string(),
+            version: version.to_string(),
+            revision: revision.map(str::to_string),
+            shim_id: shim_id.to_string(),
+            crates: BTreeMap::from([(env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"))]),
+            api: ApiLevels { task: "v2" },
+            features: features
+                .into_iter()
+                .filter_map(|(name, enabled)| enabled.then_some(name))
+                .collect(),
+        }
+    }
+}
+
+/// Result of the `healthcheck` command of a shim.
+#[derive(Debug, Serialize)]
+pub struct HealthReport {
+    /// Name of the runtime, e.g. `wasmtime`.
+    pub runtime: String,
+    /// Version of the shim.
+    pub version: String,
+    /// Whether all the checks passed.
+    pub healthy: bool,
+    /// `ok`, or the reason of the failure, of each check.
+    pub checks: BTreeMap<String, String>,
+    /// Number of the tasks of the queried shim.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instances: Option<u32>,
+    /// Pid of the queried shim.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pid: Option<u32>,
+}
+
+impl HealthReport {
+    fn check<I>(runtime: &str, version: &str) -> Self
+    where
+        I: Instance,
+        I::Engine: Default,
+    {
+        let engine = I::try_engine().map(|_| ()).map_err(|err| err.to_string());
+        Self::new(
+            runtime,
+            version,
+            [("config", check_config()), ("engine", engine)],
+        )
+    }
+
+    #[cfg(unix)]
+    fn query(runtime: &str, version: &str, namespace: &str, id: &str) -> Self {
+        match manager::health(namespace, id) {
+            Ok(health) => Self {
+                runtime: runtime.to_string(),
+                version: version.to_string(),
+                healthy: health.healthy,
+                checks: health.checks.into_iter().collect(),
+                instances: Some(health.instances),
+                pid: Some(health.pid),
+            },
+            Err(err) => Self::new(runtime, version, [("shim", Err(format!("{err:#}")))]),
+        }
+    }
+
+    fn new(
+        runtime: &str,
+        version: &str,
+        checks: impl IntoIterator<Item = (&'static str, Result<(), String>)>,
+    ) -> Self {
+        let checks: BTreeMap<_, _> = checks
+            .into_iter()
+            .map(|(name, res)| {
+                let res = res.err().unwrap_or_else(|| "ok".to_string());
+                (name.to_string(), res)
+            })
+            .collect();
+        Self {
+            runtime: runtime.to_string(),
+            version: version.to_string(),
+            healthy: checks.values().all(|res| res == "ok"),
+            checks,
+            instances: None,
+            pid: None,
+        }
+    }
+}
+
+/// Checks that the runtime configuration pointed by `RUNWASI_CONFIG`, if any, is valid.
+pub(crate) fn check_config() -> Result<(), String> {
+    match std::env::var_os(CONFIG_ENV) {
+        Some(path) => RuntimeConfig::load(&path)
+            .map(|_| ())
+            .map_err(|err| err.to_string()),
+        None => Ok(()),
+    }
+}
+
 #[cfg(target_os = "linux")]
 fn get_mem(pid: u32) -> (usize, usize) {
     let mut rss = 0;
@@ -217,24 +417,126 @@ fn shim_main_inner<'a, I>(
     }
     let os_args: Vec<_> = std::env::args_os().collect();
 
-    let flags = parse(&os_args[1..]).unwrap();
+    // `--json` is only meaningful with `--version`, and unknown to containerd-shim
+    let is_json = |arg: &std::ffi::OsString| arg == "--json" || arg == "-json";
+    let json = os_args[1..].iter().any(is_json);
+    let args: Vec<_> = os_args[1..]
+        .iter()
+        .filter(|a| !is_json(a))
+        .cloned()
+        .collect();
+
+    let flags = parse(&args).unwrap();
     let argv0 = PathBuf::from(&os_args[0]);
     let argv0 = argv0.file_stem().unwrap_or_default().to_string_lossy();
 
+    let shim_version = shim_version.into().unwrap_or("v1");
+
+    let lower_name = name.to_lowercase();
+    let shim_id = format!("io.containerd.{lower_name}.{shim_version}");
+
     if flags.version {
-        println!("{argv0}:");
-        println!("  Runtime: {name}");
-        println!("  Version: {version}");
-        println!("  Revision: {}", revision.into().unwrap_or("<none>"));
-        println!();
+        let revision = revision.into();
+        if json {
+            let info = VersionInfo::new(&argv0, name, version, revision, &shim_id);
+            println!("{}", serde_json::to_string(&info).unwrap());
+        } else {
+            println!("{argv0}:");
+            println!("  Runtime: {name}");
+            println!("  Version: {version}");
+            println!("  Revision: {}", revision.unwrap_or("<none>"));
+            println!();
+        }
 
         std::process::exit(0);
     }
 
-    let shim_version = shim_version.into().unwrap_or("v1");
+    if flags.action == "schema" {
+        println!("{}", serde_json::to_string(&schema::schema()).unwrap());
+        std::process::exit(0);
+    }
 
-    let lower_name = name.to_lowercase();
-    let shim_id = format!("io.containerd.{lower_name}.{shim_version}");
+    if flags.action == "healthcheck" {
+        #[cfg(unix)]
+        let report = if flags.id.is_empty() {
+            HealthReport::check::<I>(name, version)
+        } else {
+            HealthReport::query(name, version, &flags.namespace, &flags.id)
+        };
+        #[cfg(not(unix))]
+        let report = HealthReport::check::<I>(name, version);
+        if json {
+            println!("{}", serde_json::to_string(&report).unwrap());
+        } else {
+            println!(
+                "{argv0}: {}",
+                if report.healthy {
+                    "healthy"
+                } else {
+                    "unhealthy"
+                }
+            );
+            for (check, res) in &report.checks {
+                println!("  {check}: {res}");
+            }
+            if let (Some(instances), Some(pid)) = (report.instances, report.pid) {
+                println!("  instances: {instances}");
+                println!("  pid: {pid}");
+            }
+        }
+        std::process::exit(if report.healthy { 0 } else { 1 });
+    }
+
+    if flags.action == "precompile" {
+        if let Err(err) = stream_processor::run::<I>(&flags.address, &flags.namespace) {
+            eprintln!("{argv0}: {err:#}");
+            std::process::exit(1);
+        }
+        std::process::exit(0);
+    }
 
     run::<ShimCli<I>>(&shim_id, config);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_version_json() -> anyhow::Result<()> {
+        let info = VersionInfo::new(
+            "containerd-shim-test-v1",
+            "test",
+            "1.2.3",
+            None,
+            "io.containerd.test.v1",
+        );
+        let json: serde_json::Value = serde_json::to_value(&info)?;
+        assert_eq!(json["runtime"], "test");
+        assert_eq!(json["version"], "1.2.3");
+        assert_eq!(json["revision"], serde_json::Value::Null);
+        assert_eq!(json["shim_id"], "io.containerd.test.v1");
+        assert_eq!(
+            json["crates"]["containerd-shim-wasm"],
+            env!("CARGO_PKG_VERSION")
+        );
+        assert_eq!(json["api"]["task"], "v2");
+        assert!(json["api"].get("sandbox").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_health_report() {
+        let report = HealthReport::new("test", "1.2.3", [("config", Ok(())), ("engine", Ok(()))]);
+        assert!(report.healthy);
+        assert_eq!(report.checks["engine"], "ok");
+
+        let report = HealthReport::new(
+            "test",
+            "1.2.3",
+            [("config", Err("invalid log_level".to_string()))],
+        );
+        assert!(!report.healthy);
+        assert_eq!(report.checks["config"], "invalid log_level");
+    }
+}
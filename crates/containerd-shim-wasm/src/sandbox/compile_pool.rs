@@ -0,0 +1,414 @@
+//! A bounded pool of threads used to compile wasm modules.
+//!
+//! Compilation is CPU intensive and would otherwise compete freely with the guests
+//! that are already running. All instance creations in a shim process share this pool,
+//! which bounds the amount of CPU that compilation can take.
+//!
+//! Jobs are submitted to a global queue. Each thread takes a batch of jobs from it into a
+//! queue of its own, and idle threads steal the jobs queued by busy threads, so that a
+//! long compilation doesn't hold back the jobs taken with it.
+//!
+//! The pool can be configured with the following environment variables:
+//!
+//! - `RUNWASI_COMPILE_POOL_SIZE`: number of compile threads (defaults to half the available CPUs).
+//! - `RUNWASI_COMPILE_CGROUP`: path to a threaded cgroup v2 directory the compile threads will join.
+//!   This can be used to give compilation a lower `cpu.weight` than the running guests.
+//!
+//! Binaries hosting several engines can give an engine a pool of its own with the
+//! `engine_pools` of the runtime configuration, so that a slow engine doesn't starve the
+//! compilations of the others. Engines without a dedicated pool share the global pool.
+//! The counters of each pool are included in the debug dump of the shim.
+
+#![cfg_attr(windows, allow(dead_code))] // this is currently used only for linux
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, LazyLock, Mutex, OnceLock};
+use std::thread;
+
+use anyhow::{Context, Result};
+use crossbeam_deque::{Injector, Steal, Stealer, Worker};
+use tokio::sync::oneshot;
+
+use crate::sandbox::config::RuntimeConfig;
+
+const POOL_SIZE_ENV: &str = "RUNWASI_COMPILE_POOL_SIZE";
+const CGROUP_ENV: &str = "RUNWASI_COMPILE_CGROUP";
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// A fixed size, work-stealing pool of compile threads.
+pub struct CompilePool {
+    queue: Arc<Queue>,
+    size: usize,
+    workers: usize,
+    counters: Arc<Counters>,
+}
+
+/// The queues of the jobs of a pool.
+#[derive(Default)]
+struct Queue {
+    injector: Injector<Job>,
+    stealers: Vec<Stealer<Job>>,
+    // idle threads wait on `wakeup`, checking for jobs with `sleep` held so that no push is missed
+    sleep: Mutex<()>,
+    wakeup: Condvar,
+    stopped: AtomicBool,
+}
+
+#[derive(Default)]
+struct Counters {
+    queued: AtomicUsize,
+    running: AtomicUsize,
+    completed: AtomicU64,
+}
+
+/// Counters of the jobs of a pool.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PoolStats {
+    /// Number of threads in the pool.
+    pub size: usize,
+    /// Jobs waiting for a thread.
+    pub queued: usize,
+    /// Jobs being run.
+    pub running: usize,
+    /// Jobs run since the pool started.
+    pub completed: u64,
+}
+
+static GLOBAL: OnceLock<CompilePool> = OnceLock::new();
+static ENGINE_POOLS: LazyLock<Mutex<BTreeMap<String, &'static CompilePool>>> =
+    LazyLock::new(Default::default);
+
+impl CompilePool {
+    /// Returns the pool shared by all the instances in this process.
+    pub fn global() -> &'static CompilePool {
+        GLOBAL.get_or_init(|| {
+            let size = std::env::var(POOL_SIZE_ENV)
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or_else(default_pool_size);
+            let pool = CompilePool::new(size, cgroup_from_env());
+            log::info!("compile pool started with {} threads", pool.size());
+            pool
+        })
+    }
+
+    /// Returns the pool of the engine `engine`: its dedicated pool if it has one in the
+    /// `engine_pools` of the runtime configuration, or the global pool.
+    pub fn for_engine(engine: &str) -> &'static CompilePool {
+        let config = RuntimeConfig::current();
+        let Some(pool_config) = config.engine_pools.get(engine) else {
+            return Self::global();
+        };
+        let mut pools = ENGINE_POOLS.lock().unwrap();
+        pools.entry(engine.to_string()).or_insert_with(|| {
+            let size = pool_config.threads.unwrap_or_else(default_pool_size);
+            let pool = CompilePool::start(&format!("compile-{engine}"), size, cgroup_from_env());
+            log::info!(
+                "compile pool of engine {engine} started with {} threads",
+                pool.size()
+            );
+            // the pools of engines live as long as the shim, like the global pool
+            Box::leak(Box::new(pool))
+        })
+    }
+
+    /// Creates a new pool with `size` threads.
+    /// If `cgroup` is provided, the threads will move themselves into that cgroup.
+    pub fn new(size: usize, cgroup: Option<PathBuf>) -> Self {
+        Self::start("compile", size, cgroup)
+    }
+
+    fn start(name: &str, size: usize, cgroup: Option<PathBuf>) -> Self {
+        let size = size.max(1);
+        let locals: Vec<_> = (0..size).map(|_| Worker::new_fifo()).collect();
+        let queue = Arc::new(Queue {
+            stealers: locals.iter().map(Worker::stealer).collect(),
+            ..Default::default()
+        });
+
+        let mut workers = 0;
+        for (n, local) in locals.into_iter().enumerate() {
+            let queue = queue.clone();
+            let cgroup = cgroup.clone();
+            let res = thread::Builder::new()
+                .name(format!("{name}-{n}"))
+                .spawn(move || worker(&queue, local, cgroup));
+            match res {
+                Ok(_) => workers += 1,
+                Err(err) => log::warn!("failed to spawn compile thread: {err}"),
+            }
+        }
+
+        Self {
+            queue,
+            size,
+            workers,
+            counters: Default::default(),
+        }
+    }
+
+    /// Returns the number of threads in the pool.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Runs `f` in one of the threads of the pool, and waits for its result.
+    pub async fn run<T: Send + 'static>(
+        &self,
+        f: impl FnOnce() -> T + Send + 'static,
+    ) -> Result<T> {
+        let (tx, rx) = oneshot::channel();
+        let counters = self.counters.clone();
+        let job: Job = Box::new(move || {
+            counters.queued.fetch_sub(1, Ordering::Relaxed);
+            counters.running.fetch_add(1, Ordering::Relaxed);
+            let res = f();
+            counters.running.fetch_sub(1, Ordering::Relaxed);
+            counters.completed.fetch_add(1, Ordering::Relaxed);
+            let _ = tx.send(res);
+        });
+
+        if self.workers == 0 {
+            anyhow::bail!("compile pool is not running");
+        }
+        self.counters.queued.fetch_add(1, Ordering::Relaxed);
+        self.queue.injector.push(job);
+        self.queue.notify_one();
+
+        rx.await.context("compile job did not complete")
+    }
+
+    /// Returns the counters of the jobs of the pool.
+    pub fn stats(&self) -> PoolStats {
+        PoolStats {
+            size: self.size,
+            queued: self.counters.queued.load(Ordering::Relaxed),
+            running: self.counters.running.load(Ordering::Relaxed),
+            completed: self.counters.completed.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Writes the counters of the pools started in this process to `out`.
+    pub(crate) fn dump(out: &mut String) {
+        let _ = writeln!(out, "compile pools:");
+        if let Some(pool) = GLOBAL.get() {
+            let _ = writeln!(out, "  global: {}", pool.stats());
+        }
+        match ENGINE_POOLS.try_lock() {
+            Ok(pools) => {
+                for (engine, pool) in pools.iter() {
+                    let _ = writeln!(out, "  {engine}: {}", pool.stats());
+                }
+            }
+            Err(_) => {
+                let _ = writeln!(out, "  <engine pools locked>");
+            }
+        }
+    }
+}
+
+impl Drop for CompilePool {
+    fn drop(&mut self) {
+        // the threads exit once they are idle
+        self.queue.stopped.store(true, Ordering::Release);
+        let _sleep = self.queue.sleep.lock().unwrap();
+        self.queue.wakeup.notify_all();
+    }
+}
+
+impl Queue {
+    fn notify_one(&self) {
+        let _sleep = self.sleep.lock().unwrap();
+        self.wakeup.notify_one();
+    }
+
+    // Returns the next job of the thread with the queue `local`: its own jobs first, then a
+    // batch of the global queue, then a job stolen from another thread.
+    fn find_job(&self, local: &Worker<Job>) -> Option<Job> {
+        local.pop().or_else(|| {
+            std::iter::repeat_with(|| {
+                self.injector
+                    .steal_batch_and_pop(local)
+                    .or_else(|| self.stealers.iter().map(Stealer::steal).collect())
+            })
+            .find(|steal| !steal.is_retry())
+            .and_then(Steal::success)
+        })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.injector.is_empty() && self.stealers.iter().all(Stealer::is_empty)
+    }
+}
+
+impl std::fmt::Display for PoolStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "threads={} queued={} running={} completed={}",
+            self.size, self.queued, self.running, self.completed
+        )
+    }
+}
+
+fn cgroup_from_env() -> Option<PathBuf> {
+    std::env::var_os(CGROUP_ENV).map(PathBuf::from)
+}
+
+fn default_pool_size() -> usize {
+    let cpus = thread::available_parallelism().map_or(1, NonZeroUsize::get);
+    (cpus / 2).max(1)
+}
+
+fn worker(queue: &Queue, local: Worker<Job>, cgroup: Option<PathBuf>) {
+    if let Some(cgroup) = cgroup {
+        if let Err(err) = join_cgroup(&cgroup) {
+            log::warn!("failed to move compile thread into cgroup {cgroup:?}: {err}");
+        }
+    }
+
+    loop {
+        if let Some(job) = queue.find_job(&local) {
+            // the rest of the batch can be stolen by an idle thread while this job runs
+            if !local.is_empty() {
+                queue.notify_one();
+            }
+            job();
+            continue;
+        }
+
+        // Only hold the lock while waiting for a job, not while running it
+        let sleep = queue.sleep.lock().unwrap();
+        if queue.stopped.load(Ordering::Acquire) {
+            break;
+        }
+        if queue.is_empty() {
+            drop(queue.wakeup.wait(sleep).unwrap());
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn join_cgroup(cgroup: &std::path::Path) -> Result<()> {
+    let tid = unsafe { libc::gettid() };
+    std::fs::write(cgroup.join("cgroup.threads"), tid.to_string())?;
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn join_cgroup(_cgroup: &std::path::Path) -> Result<()> {
+    anyhow::bail!("cgroups are only supported on linux")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::sandbox::async_utils::AmbientRuntime as _;
+
+    #[test]
+    fn test_run_returns_result() -> Result<()> {
+        let pool = CompilePool::new(2, None);
+        let res = pool.run(|| 40 + 2).block_on()?;
+        assert_eq!(res, 42);
+        Ok(())
+    }
+
+    #[test]
+    fn test_pool_is_bounded() -> Result<()> {
+        let pool = Arc::new(CompilePool::new(2, None));
+        let running = Arc::new(AtomicUsize::new(0));
+        let max = Arc::new(AtomicUsize::new(0));
+
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let pool = pool.clone();
+                let running = running.clone();
+                let max = max.clone();
+                thread::spawn(move || {
+                    pool.run(move || {
+                        let n = running.fetch_add(1, Ordering::SeqCst) + 1;
+                        max.fetch_max(n, Ordering::SeqCst);
+                        thread::sleep(std::time::Duration::from_millis(10));
+                        running.fetch_sub(1, Ordering::SeqCst);
+                    })
+                    .block_on()
+                })
+            })
+            .collect();
+
+        for h in handles {
+            h.join().unwrap()?;
+        }
+
+        assert!(max.load(Ordering::SeqCst) <= 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_idle_threads_steal_queued_jobs() -> Result<()> {
+        let pool = Arc::new(CompilePool::new(2, None));
+        let ran = Arc::new(AtomicUsize::new(0));
+
+        // the long job only completes once the others ran on the other thread
+        let long = {
+            let pool = pool.clone();
+            let ran = ran.clone();
+            thread::spawn(move || {
+                pool.run(move || {
+                    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+                    while ran.load(Ordering::SeqCst) < 7 && std::time::Instant::now() < deadline {
+                        thread::sleep(std::time::Duration::from_millis(1));
+                    }
+                    ran.load(Ordering::SeqCst)
+                })
+                .block_on()
+            })
+        };
+        let handles: Vec<_> = (0..7)
+            .map(|_| {
+                let pool = pool.clone();
+                let ran = ran.clone();
+                thread::spawn(move || {
+                    pool.run(move || ran.fetch_add(1, Ordering::SeqCst))
+                        .block_on()
+                })
+            })
+            .collect();
+
+        for h in handles {
+            h.join().unwrap()?;
+        }
+        assert_eq!(long.join().unwrap()?, 7);
+        Ok(())
+    }
+
+    #[test]
+    fn test_stats() -> Result<()> {
+        let pool = CompilePool::new(1, None);
+        pool.run(|| ()).block_on()?;
+        pool.run(|| ()).block_on()?;
+        assert_eq!(
+            pool.stats(),
+            PoolStats {
+                size: 1,
+                queued: 0,
+                running: 0,
+                completed: 2,
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_pool_size_is_at_least_one() {
+        let pool = CompilePool::new(0, None);
+        assert_eq!(pool.size(), 1);
+    }
+}
@@ -0,0 +1,1824 @@
+//! Runtime configuration for the shim.
+//!
+//! The configuration is read from the JSON file pointed to by the `RUNWASI_CONFIG`
+//! environment variable, e.g.:
+//!
+//! ```json
+//! {
+//!     "log_level": "debug",
+//!     "log_format": "json",
+//!     "log_driver": "journald",
+//!     "log_sampling": {
+//!         "window_secs": { "warn": 60, "info": 10 }
+//!     },
+//!     "stop_timeout_secs": 30,
+//!     "create_timeout_secs": 120,
+//!     "state_root": "/var/lib/runwasi/state",
+//!     "stdio_open_timeout_secs": 5,
+//!     "strict_stdio": false,
+//!     "zygote_pool_size": 4,
+//!     "image_marker_ttl_secs": 300,
+//!     "image_eviction": true,
+//!     "max_registry_blob_bytes": 268435456,
+//!     "admission": {
+//!         "max_instances": 500,
+//!         "dir": "/run/runwasi/admission"
+//!     },
+//!     "async_runtime": {
+//!         "worker_threads": 1,
+//!         "max_blocking_threads": 16
+//!     },
+//!     "wire_debug": false,
+//!     "provenance": {
+//!         "namespaces": ["k8s.io"],
+//!         "keys": ["/etc/runwasi/keys/attestations.pub"],
+//!         "builder_ids": ["https://github.com/slsa-framework/slsa-github-generator/*"],
+//!         "source_repos": ["https://github.com/my-org/*"]
+//!     },
+//!     "signatures": {
+//!         "namespaces": ["k8s.io"],
+//!         "cosign_keys": ["/etc/runwasi/keys/cosign.pub"],
+//!         "wasmsign_keys": ["/etc/runwasi/keys/wasmsign.pub"]
+//!     },
+//!     "stdio": {
+//!         "pipe_size_bytes": 1048576,
+//!         "spill_dir": "/var/lib/runwasi/spill",
+//!         "max_spill_bytes": 67108864,
+//!         "on_full": "drop"
+//!     },
+//!     "log_rotation": {
+//!         "max_size_bytes": 10485760,
+//!         "max_files": 5
+//!     },
+//!     "module_cache": {
+//!         "url": "https://wasm-cache.example.com/precompiled",
+//!         "key_path": "/etc/runwasi/keys/module-cache.key",
+//!         "upload": true
+//!     },
+//!     "tee": {
+//!         "sinks": ["file:///var/log/wasm/{namespace}/{id}-{stream}.log"],
+//!         "namespaces": ["staging"]
+//!     },
+//!     "capture": {
+//!         "dir": "/var/lib/runwasi/capture",
+//!         "namespaces": ["payments"],
+//!         "rotation": {
+//!             "max_size_bytes": 10485760,
+//!             "max_files": 5
+//!         }
+//!     },
+//!     "otlp": {
+//!         "endpoint": "http://localhost:4318",
+//!         "protocol": "http/protobuf"
+//!     },
+//!     "cpu_boost": {
+//!         "cpus": 4.0,
+//!         "max_duration_secs": 30,
+//!         "dir": "/run/runwasi/cpu-boost"
+//!     },
+//!     "seccomp": {
+//!         "profile": "/etc/runwasi/seccomp.json"
+//!     },
+//!     "landlock": {
+//!         "read_write_paths": [
+//!             "/var/log/pods",
+//!             "/var/lib/kubelet/pods",
+//!             "/run/containerd/io.containerd.grpc.v1.cri/containers"
+//!         ],
+//!         "read_only_paths": ["/etc/runwasi"]
+//!     },
+//!     "strict_wasi": {
+//!         "namespaces": ["tenant-a", "tenant-b"]
+//!     },
+//!     "throttled_clock": {
+//!         "namespaces": ["tenant-a"],
+//!         "resolution_us": 1000,
+//!         "max_reads_per_sec": 10000
+//!     },
+//!     "embedded_module": {
+//!         "namespaces": ["appliance"]
+//!     },
+//!     "component_limits": {
+//!         "max_instances": 1000,
+//!         "max_resource_handles": 10000,
+//!         "max_nesting_depth": 8
+//!     },
+//!     "audit": {
+//!         "path": "/var/log/runwasi/audit.log",
+//!         "namespaces": ["k8s.io"]
+//!     },
+//!     "engine_pools": {
+//!         "wasmtime": { "threads": 4 }
+//!     },
+//!     "redaction": {
+//!         "keys": ["dsn", "cookie"]
+//!     },
+//!     "exit_notifier": {
+//!         "url": "unix:///run/edge-agent/exits.sock",
+//!         "namespaces": ["edge"]
+//!     },
+//!     "file_module_source": {
+//!         "dir": "/var/lib/runwasi/modules"
+//!     }
+//! }
+//! ```
+//!
+//! The directory containing the file is watched, and settings that are safe to change
+//! at runtime are applied without restarting the shim. Sending `SIGHUP` to the shim reloads
+//! the file too, e.g. after changing the log level to get debug logs of a running task.
+//! If the new file can't be parsed or validated, the previous configuration is kept.
+//! The log level of a running shim can also be changed without editing the file, with the
+//! `SetLogLevel` RPC of its [`manager`](crate::sandbox::shim::manager) service, until the file
+//! is reloaded.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, LazyLock, RwLock};
+use std::time::Duration;
+
+use log::LevelFilter;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use super::error::{Error, Result};
+use crate::container::{ClockThrottle, ComponentLimits};
+
+/// Environment variable with the path to the runtime configuration file.
+pub const CONFIG_ENV: &str = "RUNWASI_CONFIG";
+
+/// Environment variable with the log format of the shim, overriding `log_format`.
+pub const LOG_FORMAT_ENV: &str = "RUNWASI_LOG_FORMAT";
+
+const DEFAULT_MAX_REGISTRY_BLOB_BYTES: u64 = 1024 * 1024 * 1024;
+
+/// Runtime configuration of the shim.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct RuntimeConfig {
+    /// Log level for the shim, e.g. `info` or `debug`.
+    pub log_level: Option<String>,
+    /// Format of the logs of the shim. This is read when the shim starts.
+    pub log_format: LogFormat,
+    /// Where the stdout and stderr of containers go, unless overridden with the
+    /// `runwasi.io/log-driver` annotation.
+    pub log_driver: LogDriver,
+    /// Collapses the lines repeated for every container, by level.
+    /// See [`crate::sandbox::log_sampling`].
+    pub log_sampling: Option<LogSamplingConfig>,
+    /// Seconds to wait for a task to stop after it is signaled, before it is sent `SIGKILL`.
+    /// If unset, the signal is delivered and the task is never forcefully killed.
+    pub stop_timeout_secs: Option<u64>,
+    /// Seconds a task has to be created in, including fetching and compiling its modules, and
+    /// building its container.
+    /// If unset, creating a task only ends when containerd gives up on it.
+    pub create_timeout_secs: Option<u64>,
+    /// Directory the state of containers is kept in, for hosts where `/run/containerd`
+    /// isn't writable. It is overridden by the `root` option of the runtime in containerd.
+    pub state_root: Option<PathBuf>,
+    /// Requires images to have a SLSA provenance attestation matching this policy.
+    pub provenance: Option<ProvenancePolicy>,
+    /// Requires the wasm modules of containers to be signed by a key of this policy.
+    pub signatures: Option<SignaturePolicy>,
+    /// Logs every task service request and response, with secrets redacted.
+    pub wire_debug: bool,
+    /// Seconds to wait for containerd to create the stdio fifos of a container, when they don't
+    /// exist yet as it is created. If unset, they aren't waited for, and the container runs
+    /// without them, or fails to be created with `strict_stdio`.
+    pub stdio_open_timeout_secs: Option<u64>,
+    /// Fails the creation of containers whose stdio can't be opened, instead of running
+    /// them without it.
+    pub strict_stdio: bool,
+    /// Zygotes kept ready to build containers in, so that bursts of creations don't wait
+    /// for each container's zygote to be spawned. 0 disables the pool.
+    pub zygote_pool_size: usize,
+    /// Seconds the images found without wasm layers are remembered for, so that the following
+    /// containers of the image aren't looked up in containerd. If unset, images are always
+    /// looked up.
+    pub image_marker_ttl_secs: Option<u64>,
+    /// Watches the images and content deleted from containerd, to drop the image markers and
+    /// the layers of the artifact cache based on them. The layers of each image are recorded
+    /// as its containers load them.
+    pub image_eviction: bool,
+    /// Largest blob fetched from a registry, in bytes, e.g., a layer missing from the content
+    /// store or a provenance attestation. Defaults to 1GiB.
+    pub max_registry_blob_bytes: Option<u64>,
+    /// Limits the number of containers across all the shims of the node, rejecting the
+    /// creations beyond it with `RESOURCE_EXHAUSTED`.
+    pub admission: Option<AdmissionConfig>,
+    /// Sizing of the async runtimes of the shim.
+    pub async_runtime: AsyncRuntimeConfig,
+    /// Buffers the stdout and stderr of containers in the shim, so that a slow reader
+    /// doesn't block the engine.
+    pub stdio: Option<StdioConfig>,
+    /// Rotates the stdout and stderr of containers when they are files.
+    pub log_rotation: Option<LogRotationConfig>,
+    /// Shares precompiled modules with the other nodes of the cluster through an HTTP cache.
+    pub module_cache: Option<ModuleCacheConfig>,
+    /// Restricts the guests of some namespaces to a minimal WASI surface: no sockets, no HTTP,
+    /// no `wasi:nn`, and a read-only filesystem.
+    pub strict_wasi: Option<StrictWasiPolicy>,
+    /// Coarsens and rate limits the clocks of the guests of some namespaces, to reduce timing
+    /// side channels. See [`crate::container::ThrottledClock`].
+    pub throttled_clock: Option<ThrottledClockPolicy>,
+    /// Runs the module embedded in the shim binary for the containers of some namespaces
+    /// whose image has no wasm entrypoint. See [`crate::sandbox::embedded`].
+    pub embedded_module: Option<EmbeddedModulePolicy>,
+    /// Limits on the components run by the shim, against hostile components exhausting the
+    /// memory of the host. See [`ComponentLimits`].
+    pub component_limits: ComponentLimits,
+    /// Copies the stdout and stderr of containers to more sinks, on top of containerd.
+    pub tee: Option<TeeConfig>,
+    /// Captures the stdout and stderr of containers to files, for audit and forensics.
+    pub capture: Option<CaptureConfig>,
+    /// Raises the CPU quota of the containers that ask for it while they start.
+    pub cpu_boost: Option<CpuBoostConfig>,
+    /// Applies a seccomp profile to the containers whose spec has none.
+    pub seccomp: Option<SeccompConfig>,
+    /// Confines the shim process with Landlock, limiting the files a compromised shim can
+    /// access. This is read when the shim starts.
+    pub landlock: Option<LandlockConfig>,
+    /// Exports the traces of the shim with OTLP, with the `opentelemetry` feature.
+    /// This is read when the shim starts.
+    pub otlp: Option<OtlpExporterConfig>,
+    /// Records the lifecycle operations on tasks to an audit log.
+    pub audit: Option<AuditConfig>,
+    /// Dedicated compile pools of engines, by engine name, for binaries hosting several
+    /// engines. The other engines share the global compile pool.
+    /// This is read when the pool of an engine is first used.
+    pub engine_pools: BTreeMap<String, EnginePoolConfig>,
+    /// Redacts the env vars that look like secrets when the env of containers is logged.
+    pub redaction: RedactionConfig,
+    /// Notifies a webhook or a unix socket of the exit of tasks, for consumers that don't
+    /// subscribe to containerd events.
+    pub exit_notifier: Option<ExitNotifierConfig>,
+    /// Allows the `runwasi.io/module-source` annotation to run `file://` modules of the host.
+    /// See [`crate::sandbox::fetcher`].
+    pub file_module_source: Option<FileModuleSourceConfig>,
+}
+
+/// Format of the logs of the shim.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Plain text lines, as logged by containerd shims.
+    #[default]
+    Text,
+    /// A JSON object per line, with the namespace, instance id and operation as fields.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err(Error::InvalidArgument(format!("invalid log format {s:?}"))),
+        }
+    }
+}
+
+impl LogFormat {
+    /// Returns the log format set by `RUNWASI_LOG_FORMAT`, or by the configuration file
+    /// pointed by `RUNWASI_CONFIG`.
+    /// This runs before the logger is set up, so errors are printed to stderr.
+    pub fn from_env() -> Self {
+        if let Ok(format) = std::env::var(LOG_FORMAT_ENV) {
+            match format.parse() {
+                Ok(format) => return format,
+                Err(err) => eprintln!("ignoring {LOG_FORMAT_ENV}: {err}"),
+            }
+        }
+        load_from_env()
+            .map(|cfg| cfg.log_format)
+            .unwrap_or_default()
+    }
+}
+
+// Loads the configuration file pointed by `RUNWASI_CONFIG`, for the settings read when the
+// shim starts, before the logger is set up.
+fn load_from_env() -> Option<RuntimeConfig> {
+    let path = std::env::var_os(CONFIG_ENV)?;
+    RuntimeConfig::load(&path)
+        .inspect_err(|err| eprintln!("invalid runtime config {path:?}: {err}"))
+        .ok()
+}
+
+/// Where the output of containers goes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum LogDriver {
+    /// The fifos or log URIs passed by containerd.
+    #[default]
+    Containerd,
+    /// The systemd journal, with the container id as syslog identifier.
+    Journald,
+}
+
+impl FromStr for LogDriver {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "containerd" => Ok(Self::Containerd),
+            "journald" => Ok(Self::Journald),
+            _ => Err(Error::InvalidArgument(format!("invalid log driver {s:?}"))),
+        }
+    }
+}
+
+/// Sampling of the log lines repeated at high frequency.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogSamplingConfig {
+    /// Seconds the identical lines of a call site are collapsed for, by level, e.g. `warn`.
+    /// Lines of the levels not listed are all logged.
+    pub window_secs: BTreeMap<String, u64>,
+}
+
+impl Default for LogSamplingConfig {
+    fn default() -> Self {
+        Self {
+            window_secs: BTreeMap::from([("warn".to_string(), 60)]),
+        }
+    }
+}
+
+impl LogSamplingConfig {
+    /// Returns the window the lines of `level` are collapsed for, if they are sampled.
+    pub fn window(&self, level: log::Level) -> Option<Duration> {
+        self.window_secs
+            .iter()
+            .find(|(l, _)| log::Level::from_str(l).is_ok_and(|l| l == level))
+            .map(|(_, secs)| Duration::from_secs(*secs))
+    }
+
+    fn validate(&self) -> Result<()> {
+        for (level, secs) in &self.window_secs {
+            log::Level::from_str(level).map_err(|err| {
+                Error::InvalidArgument(format!("invalid log_sampling level {level:?}: {err}"))
+            })?;
+            if *secs == 0 {
+                return Err(Error::InvalidArgument(format!(
+                    "log_sampling.window_secs.{level} must not be 0"
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Sizing of the tokio runtimes of the shim, so that nodes running hundreds of shims
+/// don't spawn threads for every core in each of them.
+///
+/// The shim blocks on a single multi-thread runtime, whose workers drive its connections to
+/// containerd.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct AsyncRuntimeConfig {
+    /// Worker threads of the runtime. Defaults to 1.
+    pub worker_threads: Option<usize>,
+    /// Maximum number of threads of the blocking pool of the runtime.
+    /// Defaults to the default of tokio, 512.
+    pub max_blocking_threads: Option<usize>,
+}
+
+impl AsyncRuntimeConfig {
+    /// Applies the blocking pool limit to the `builder` of a runtime.
+    pub(crate) fn configure(&self, builder: &mut tokio::runtime::Builder) {
+        if let Some(max) = self.max_blocking_threads {
+            builder.max_blocking_threads(max);
+        }
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.worker_threads == Some(0) || self.max_blocking_threads == Some(0) {
+            return Err(Error::InvalidArgument(
+                "async_runtime.worker_threads and async_runtime.max_blocking_threads must not be 0"
+                    .to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Limit on the containers of the node, shared by all its shims.
+///
+/// A container holds one of the `max_instances` slots of the node from its creation until it
+/// is deleted. Lowering the limit doesn't evict the containers holding slots beyond it.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdmissionConfig {
+    /// Maximum number of containers on the node.
+    pub max_instances: usize,
+    /// Directory of the slots, which must be the same for all the shims of the node.
+    pub dir: PathBuf,
+}
+
+impl Default for AdmissionConfig {
+    fn default() -> Self {
+        Self {
+            max_instances: 1000,
+            dir: PathBuf::from("/run/runwasi/admission"),
+        }
+    }
+}
+
+impl AdmissionConfig {
+    fn validate(&self) -> Result<()> {
+        if self.max_instances == 0 {
+            return Err(Error::InvalidArgument(
+                "admission.max_instances must not be 0".to_string(),
+            ));
+        }
+        if !self.dir.is_absolute() {
+            return Err(Error::InvalidArgument(
+                "admission.dir must be an absolute path".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Buffering of the output of containers.
+///
+/// When set, the output of a container is copied to the fifos of containerd by the shim,
+/// which reports how long writes to them blocked.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct StdioConfig {
+    /// Size of the pipe buffers of the output fifos, set with `F_SETPIPE_SZ`.
+    /// If unset, the default size of the kernel is used (usually 64KiB).
+    pub pipe_size_bytes: Option<u32>,
+    /// Output buffered in memory while the reader is slow.
+    /// Once full, output goes to `spill_dir` if set, or else is handled as per `on_full`.
+    pub max_buffer_bytes: usize,
+    /// Directory to buffer output to once the memory buffer is full.
+    pub spill_dir: Option<PathBuf>,
+    /// Output buffered in the spill file of a stream. Once full, output is handled as per
+    /// `on_full`.
+    pub max_spill_bytes: u64,
+    /// What happens to output once the buffers are full.
+    pub on_full: OverflowPolicy,
+}
+
+impl Default for StdioConfig {
+    fn default() -> Self {
+        Self {
+            pipe_size_bytes: None,
+            max_buffer_bytes: 1024 * 1024,
+            spill_dir: None,
+            max_spill_bytes: 64 * 1024 * 1024,
+            on_full: OverflowPolicy::Block,
+        }
+    }
+}
+
+/// What happens to the output of a container once its buffers are full.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum OverflowPolicy {
+    /// The container blocks on writes until containerd catches up.
+    #[default]
+    Block,
+    /// Writes never block, and the output that doesn't fit in the buffers is discarded.
+    Drop,
+}
+
+/// Policy for the SLSA provenance attestations of the images run by the shim.
+///
+/// Attestations are looked up as OCI referrers of the image manifest in its registry, and
+/// are only trusted in a DSSE envelope signed with one of `keys`.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProvenancePolicy {
+    /// containerd namespaces the policy applies to. Empty means all namespaces.
+    pub namespaces: Vec<String>,
+    /// PEM files of the ECDSA P-256 or Ed25519 public keys the attestations are signed with.
+    pub keys: Vec<PathBuf>,
+    /// Glob patterns of the trusted builder ids. Empty means any builder.
+    pub builder_ids: Vec<String>,
+    /// Glob patterns of the trusted source repositories. Empty means any repository.
+    pub source_repos: Vec<String>,
+}
+
+/// Policy for the signatures of the wasm modules run by the shim.
+///
+/// A module is trusted if the manifest of its image has a cosign signature made with one of
+/// `cosign_keys`, or if it has an embedded wasmsign2 signature made with one of `wasmsign_keys`.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct SignaturePolicy {
+    /// containerd namespaces the policy applies to. Empty means all namespaces.
+    pub namespaces: Vec<String>,
+    /// PEM files of the ECDSA P-256 or Ed25519 public keys trusted for cosign signatures.
+    pub cosign_keys: Vec<PathBuf>,
+    /// Files of the public keys trusted for wasmsign2 signatures, in any format of wasmsign2.
+    pub wasmsign_keys: Vec<PathBuf>,
+}
+
+/// Additional sinks for the output of containers.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct TeeConfig {
+    /// URIs of the sinks, `file:///path` or `unix:///path`, where `{namespace}`, `{id}` and
+    /// `{stream}` are replaced by the namespace and id of the container, and the stream name.
+    pub sinks: Vec<String>,
+    /// containerd namespaces whose containers are copied. Empty means all namespaces.
+    pub namespaces: Vec<String>,
+}
+
+impl TeeConfig {
+    /// Returns true if the output of containers in `namespace` is copied to the sinks.
+    pub fn applies_to(&self, namespace: &str) -> bool {
+        self.namespaces.is_empty() || self.namespaces.iter().any(|ns| ns == namespace)
+    }
+
+    fn validate(&self) -> Result<()> {
+        for sink in &self.sinks {
+            let scheme = sink.split_once("://").map(|(scheme, _)| scheme);
+            if !matches!(scheme, Some("file" | "unix")) {
+                return Err(Error::InvalidArgument(format!(
+                    "invalid tee sink {sink:?}, expected a file:// or unix:// URI"
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Capture of the output of containers to files, for audit and forensics.
+///
+/// The stdout and stderr of the containers in `namespaces`, and of the containers with the
+/// `runwasi.io/capture-output` annotation, are copied to `{dir}/{namespace}/{id}/{stream}.log`,
+/// independently of the logs collected by containerd, within the limits of `rotation`.
+/// Captured files are kept once the container is deleted if `dir` is set.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct CaptureConfig {
+    /// Directory the output is captured to.
+    /// If unset, it is captured to the state directory of the containers, and removed with
+    /// them.
+    pub dir: Option<PathBuf>,
+    /// containerd namespaces whose containers are always captured. Empty means only the
+    /// containers with the annotation are captured.
+    pub namespaces: Vec<String>,
+    /// Rotation of the captured files of each stream.
+    pub rotation: LogRotationConfig,
+}
+
+impl CaptureConfig {
+    /// Returns true if the output of all the containers in `namespace` is captured.
+    pub fn applies_to(&self, namespace: &str) -> bool {
+        self.namespaces.iter().any(|ns| ns == namespace)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.dir.as_ref().is_some_and(|dir| !dir.is_absolute()) {
+            return Err(Error::InvalidArgument(
+                "capture.dir must be an absolute path".to_string(),
+            ));
+        }
+        if self.rotation.max_size_bytes == 0 || self.rotation.max_files == 0 {
+            return Err(Error::InvalidArgument(
+                "capture.rotation.max_size_bytes and capture.rotation.max_files must not be 0"
+                    .to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Startup CPU boost of the containers with the `runwasi.io/startup-cpu-boost` annotation.
+///
+/// The CPU quota of a container is raised from its creation until its guest starts running.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct CpuBoostConfig {
+    /// CPUs the quota is raised to, while the container starts.
+    pub cpus: f64,
+    /// Seconds after which the quota is restored, if the guest hasn't started running yet.
+    pub max_duration_secs: u64,
+    /// Directory where the boosts of the pods are counted, which must be the same for all the
+    /// shims of the node.
+    pub dir: PathBuf,
+}
+
+impl Default for CpuBoostConfig {
+    fn default() -> Self {
+        Self {
+            cpus: 2.0,
+            max_duration_secs: 30,
+            dir: PathBuf::from("/run/runwasi/cpu-boost"),
+        }
+    }
+}
+
+/// Seccomp profile of the containers whose spec has none.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct SeccompConfig {
+    /// JSON file of the profile, in the format of `linux.seccomp` in the OCI runtime spec.
+    /// Defaults to a profile of the shim denying the syscalls wasm engines don't need, like
+    /// `mount`, `ptrace` or `bpf`.
+    pub profile: Option<PathBuf>,
+}
+
+/// Landlock confinement of the shim process.
+///
+/// Each container gets a shim of its own, which can always access the bundle of its container,
+/// the state directory of its namespace, the temporary directory, the devices it uses and the
+/// paths of the other sections of the configuration, and read `/proc`, `/sys`, `/etc` and the
+/// system libraries. The zygotes building the containers
+/// aren't confined, so this isn't a security boundary.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct LandlockConfig {
+    /// Other paths the shim can read and write, e.g., the directories of the log files, stdio
+    /// fifos and termination messages of the containers.
+    pub read_write_paths: Vec<PathBuf>,
+    /// Other paths the shim can only read, e.g., the libraries of an engine installed in `/opt`.
+    pub read_only_paths: Vec<PathBuf>,
+}
+
+impl LandlockConfig {
+    fn validate(&self) -> Result<()> {
+        let relative = self
+            .read_write_paths
+            .iter()
+            .chain(&self.read_only_paths)
+            .find(|path| !path.is_absolute());
+        if let Some(path) = relative {
+            return Err(Error::InvalidArgument(format!(
+                "landlock paths must be absolute, got {path:?}"
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// OTLP exporter of the traces of the shim.
+///
+/// The standard `OTEL_EXPORTER_OTLP_*` environment variables take precedence over these.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct OtlpExporterConfig {
+    /// Endpoint the traces are sent to, e.g. `http://localhost:4318`.
+    pub endpoint: String,
+    /// Protocol the traces are sent with.
+    pub protocol: OtlpProtocol,
+}
+
+impl OtlpExporterConfig {
+    /// Returns the exporter set in the configuration file pointed by `RUNWASI_CONFIG`.
+    /// This runs before the logger is set up, so errors are printed to stderr.
+    pub fn from_env() -> Option<Self> {
+        load_from_env()?.otlp
+    }
+}
+
+/// Protocol of an OTLP exporter.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub enum OtlpProtocol {
+    #[default]
+    #[serde(rename = "http/protobuf")]
+    HttpProtobuf,
+    #[serde(rename = "http/json")]
+    HttpJson,
+    #[serde(rename = "grpc")]
+    Grpc,
+}
+
+/// Namespaces whose guests run in strict WASI mode.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct StrictWasiPolicy {
+    /// containerd namespaces the policy applies to. Empty means all namespaces.
+    pub namespaces: Vec<String>,
+}
+
+impl StrictWasiPolicy {
+    /// Returns true if the guests of containers in `namespace` run in strict WASI mode.
+    pub fn applies_to(&self, namespace: &str) -> bool {
+        self.namespaces.is_empty() || self.namespaces.iter().any(|ns| ns == namespace)
+    }
+}
+
+/// Namespaces whose guests read throttled clocks, and how the clocks are throttled.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct ThrottledClockPolicy {
+    /// containerd namespaces the policy applies to. Empty means all namespaces.
+    pub namespaces: Vec<String>,
+    /// Resolution of the clocks, in microseconds.
+    pub resolution_us: u64,
+    /// Reads of a clock per second by a guest, past which the clock stops until the next second.
+    /// Unlimited by default.
+    pub max_reads_per_sec: Option<u32>,
+}
+
+impl Default for ThrottledClockPolicy {
+    fn default() -> Self {
+        Self {
+            namespaces: vec![],
+            resolution_us: 1000,
+            max_reads_per_sec: None,
+        }
+    }
+}
+
+impl ThrottledClockPolicy {
+    /// Returns true if the guests of containers in `namespace` read throttled clocks.
+    pub fn applies_to(&self, namespace: &str) -> bool {
+        self.namespaces.is_empty() || self.namespaces.iter().any(|ns| ns == namespace)
+    }
+
+    /// The throttle of the clocks of the guests.
+    pub fn throttle(&self) -> ClockThrottle {
+        ClockThrottle {
+            resolution: Duration::from_micros(self.resolution_us),
+            max_reads_per_sec: self.max_reads_per_sec,
+        }
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.resolution_us == 0 || self.max_reads_per_sec == Some(0) {
+            return Err(Error::InvalidArgument(
+                "throttled_clock.resolution_us and throttled_clock.max_reads_per_sec must be positive"
+                    .to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Namespaces whose containers may run the module embedded in the shim binary.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct EmbeddedModulePolicy {
+    /// containerd namespaces the policy applies to. Empty means all namespaces.
+    pub namespaces: Vec<String>,
+}
+
+impl EmbeddedModulePolicy {
+    /// Returns true if the containers in `namespace` may run the embedded module.
+    pub fn applies_to(&self, namespace: &str) -> bool {
+        self.namespaces.is_empty() || self.namespaces.iter().any(|ns| ns == namespace)
+    }
+}
+
+/// Audit log of the lifecycle operations on tasks.
+///
+/// Each create, start, kill, delete and exec request is appended to the log as a JSON line,
+/// with the identity of the caller, when it was received, and whether it succeeded.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuditConfig {
+    /// File the records are appended to. It is shared by all the shims of the node.
+    pub path: PathBuf,
+    /// containerd namespaces whose operations are recorded. Empty means all namespaces.
+    pub namespaces: Vec<String>,
+}
+
+impl AuditConfig {
+    /// Returns true if the operations on tasks in `namespace` are recorded.
+    pub fn applies_to(&self, namespace: &str) -> bool {
+        self.namespaces.is_empty() || self.namespaces.iter().any(|ns| ns == namespace)
+    }
+}
+
+/// Redaction of the env vars of containers in logs and traces.
+///
+/// The values of env vars whose key contains one of the keys, ignoring case, are redacted.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct RedactionConfig {
+    /// Substrings of the keys to redact, on top of the default ones.
+    pub keys: Vec<String>,
+    /// Redacts the keys containing `secret`, `password`, `passwd`, `token`, `key`, `auth`
+    /// or `credential`.
+    pub default_keys: bool,
+}
+
+impl Default for RedactionConfig {
+    fn default() -> Self {
+        Self {
+            keys: vec![],
+            default_keys: true,
+        }
+    }
+}
+
+/// Notifier of the exit of tasks.
+///
+/// A JSON record of each exit is POSTed to `url`, or written as a line to a unix socket with
+/// a `unix:///path/to/socket` URL. Failed deliveries are retried with backoff.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct ExitNotifierConfig {
+    /// `http`, `https` or `unix` URL the records are sent to.
+    pub url: String,
+    /// containerd namespaces whose exits are notified. Empty means all namespaces.
+    pub namespaces: Vec<String>,
+    /// Number of times a record is sent before it is dropped.
+    pub max_attempts: u32,
+    /// Seconds to wait for a delivery before giving up on it.
+    pub timeout_secs: u64,
+}
+
+impl Default for ExitNotifierConfig {
+    fn default() -> Self {
+        Self {
+            url: String::new(),
+            namespaces: vec![],
+            max_attempts: 5,
+            timeout_secs: 10,
+        }
+    }
+}
+
+impl ExitNotifierConfig {
+    /// Returns true if the exits of tasks in `namespace` are notified.
+    pub fn applies_to(&self, namespace: &str) -> bool {
+        self.namespaces.is_empty() || self.namespaces.iter().any(|ns| ns == namespace)
+    }
+
+    fn validate(&self) -> Result<()> {
+        let url = url::Url::parse(&self.url)
+            .map_err(|err| Error::InvalidArgument(format!("invalid exit_notifier.url: {err}")))?;
+        match url.scheme() {
+            "http" | "https" => {}
+            "unix" if !url.path().is_empty() => {}
+            _ => {
+                return Err(Error::InvalidArgument(format!(
+                    "invalid exit_notifier.url: {url}"
+                )))
+            }
+        }
+        if self.max_attempts == 0 || self.timeout_secs == 0 {
+            return Err(Error::InvalidArgument(
+                "exit_notifier.max_attempts and exit_notifier.timeout_secs must not be 0"
+                    .to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Modules of the host that containers can run with `file://` module sources.
+///
+/// The modules are read by the shim without the digest, provenance and signature checks of
+/// images, so only the files under `dir` can be run, after resolving symlinks.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct FileModuleSourceConfig {
+    /// Directory of the modules.
+    pub dir: PathBuf,
+}
+
+/// Dedicated compile pool of an engine.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct EnginePoolConfig {
+    /// Number of compile threads of the engine. If unset, half the available CPUs.
+    pub threads: Option<usize>,
+}
+
+/// Size based rotation of the log files of containers.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogRotationConfig {
+    /// Size in bytes a log file is rotated at.
+    pub max_size_bytes: u64,
+    /// Number of log files to keep, including the current one.
+    pub max_files: usize,
+}
+
+impl Default for LogRotationConfig {
+    fn default() -> Self {
+        Self {
+            max_size_bytes: 10 * 1024 * 1024,
+            max_files: 5,
+        }
+    }
+}
+
+/// HTTP cache of precompiled modules shared by the nodes of a cluster.
+///
+/// Before compiling a module, the shim looks up its precompiled artifact in the cache,
+/// and uploads it after compiling it if `upload` is set.
+///
+/// The artifacts are authenticated with a MAC keyed with `key_path`, bound to the engine, its
+/// version and the layer, and the artifacts of the cache with an invalid MAC are ignored.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct ModuleCacheConfig {
+    /// Base URL of the cache.
+    pub url: String,
+    /// File with the secret key of the MAC of the artifacts, of at least 32 bytes, shared by
+    /// the nodes of the cluster. It must only be readable by the shims.
+    pub key_path: PathBuf,
+    /// Uploads the modules compiled by the shim to the cache.
+    pub upload: bool,
+    /// Seconds to wait for a request to the cache before giving up on it.
+    pub timeout_secs: u64,
+}
+
+impl Default for ModuleCacheConfig {
+    fn default() -> Self {
+        Self {
+            url: String::new(),
+            key_path: PathBuf::new(),
+            upload: false,
+            timeout_secs: 10,
+        }
+    }
+}
+
+impl ProvenancePolicy {
+    /// Returns true if the policy applies to containers in `namespace`.
+    pub fn applies_to(&self, namespace: &str) -> bool {
+        self.namespaces.is_empty() || self.namespaces.iter().any(|ns| ns == namespace)
+    }
+
+    /// Returns true if an attestation from `builder_id` and `source_repo` is trusted.
+    pub fn allows(&self, builder_id: &str, source_repo: Option<&str>) -> bool {
+        let matches = |patterns: &[String], value: &str| {
+            patterns.is_empty()
+                || patterns
+                    .iter()
+                    .filter_map(|p| glob::Pattern::new(p).ok())
+                    .any(|p| p.matches(value))
+        };
+        let source_ok = match source_repo {
+            Some(repo) => matches(&self.source_repos, repo),
+            None => self.source_repos.is_empty(),
+        };
+        matches(&self.builder_ids, builder_id) && source_ok
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.keys.is_empty() {
+            return Err(Error::InvalidArgument(
+                "provenance needs at least one key".to_string(),
+            ));
+        }
+        if let Some(key) = self.keys.iter().find(|key| !key.is_absolute()) {
+            return Err(Error::InvalidArgument(format!(
+                "provenance key {key:?} must be an absolute path"
+            )));
+        }
+        for p in self.builder_ids.iter().chain(&self.source_repos) {
+            glob::Pattern::new(p).map_err(|err| {
+                Error::InvalidArgument(format!("invalid provenance pattern {p:?}: {err}"))
+            })?;
+        }
+        Ok(())
+    }
+}
+
+impl SignaturePolicy {
+    /// Returns true if the policy applies to containers in `namespace`.
+    pub fn applies_to(&self, namespace: &str) -> bool {
+        self.namespaces.is_empty() || self.namespaces.iter().any(|ns| ns == namespace)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.cosign_keys.is_empty() && self.wasmsign_keys.is_empty() {
+            return Err(Error::InvalidArgument(
+                "signatures needs at least one of cosign_keys and wasmsign_keys".to_string(),
+            ));
+        }
+        if let Some(key) = self
+            .cosign_keys
+            .iter()
+            .chain(&self.wasmsign_keys)
+            .find(|key| !key.is_absolute())
+        {
+            return Err(Error::InvalidArgument(format!(
+                "signature key {key:?} must be an absolute path"
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl RuntimeConfig {
+    /// Returns the configuration currently in effect.
+    pub fn current() -> Arc<RuntimeConfig> {
+        ConfigStore::global().get()
+    }
+
+    /// Parses and validates a configuration from its JSON representation.
+    pub fn from_slice(data: &[u8]) -> Result<Self> {
+        let cfg: Self = serde_json::from_slice(data)?;
+        cfg.validate()?;
+        Ok(cfg)
+    }
+
+    /// Reads, parses and validates a configuration file.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        Self::from_slice(&std::fs::read(path)?)
+    }
+
+    /// How long to wait for a task to stop before escalating to `SIGKILL`.
+    pub fn stop_timeout(&self) -> Option<Duration> {
+        self.stop_timeout_secs.map(Duration::from_secs)
+    }
+
+    /// The largest blob fetched from a registry, in bytes.
+    pub fn max_registry_blob_bytes(&self) -> u64 {
+        self.max_registry_blob_bytes
+            .unwrap_or(DEFAULT_MAX_REGISTRY_BLOB_BYTES)
+    }
+
+    /// How long the creation of a task can take before it fails.
+    pub fn create_timeout(&self) -> Option<Duration> {
+        self.create_timeout_secs.map(Duration::from_secs)
+    }
+
+    fn validate(&self) -> Result<()> {
+        self.log_level_filter()?;
+        if let Some(provenance) = &self.provenance {
+            provenance.validate()?;
+        }
+        if let Some(signatures) = &self.signatures {
+            signatures.validate()?;
+        }
+        if let Some(throttled_clock) = &self.throttled_clock {
+            throttled_clock.validate()?;
+        }
+        self.async_runtime.validate()?;
+        if let Some(log_sampling) = &self.log_sampling {
+            log_sampling.validate()?;
+        }
+        if let Some(admission) = &self.admission {
+            admission.validate()?;
+        }
+        if self.create_timeout_secs == Some(0) {
+            return Err(Error::InvalidArgument(
+                "create_timeout_secs must not be 0".to_string(),
+            ));
+        }
+        if self.max_registry_blob_bytes == Some(0) {
+            return Err(Error::InvalidArgument(
+                "max_registry_blob_bytes must not be 0".to_string(),
+            ));
+        }
+        if self.state_root.as_ref().is_some_and(|r| !r.is_absolute()) {
+            return Err(Error::InvalidArgument(
+                "state_root must be an absolute path".to_string(),
+            ));
+        }
+        if self
+            .stdio
+            .as_ref()
+            .is_some_and(|s| s.pipe_size_bytes == Some(0) || s.max_spill_bytes == 0)
+        {
+            return Err(Error::InvalidArgument(
+                "stdio.pipe_size_bytes and stdio.max_spill_bytes must not be 0".to_string(),
+            ));
+        }
+        if self
+            .log_rotation
+            .as_ref()
+            .is_some_and(|r| r.max_size_bytes == 0 || r.max_files == 0)
+        {
+            return Err(Error::InvalidArgument(
+                "log_rotation.max_size_bytes and log_rotation.max_files must not be 0".to_string(),
+            ));
+        }
+        if self
+            .cpu_boost
+            .as_ref()
+            .is_some_and(|b| !b.cpus.is_finite() || b.cpus <= 0.0 || b.max_duration_secs == 0)
+        {
+            return Err(Error::InvalidArgument(
+                "cpu_boost.cpus and cpu_boost.max_duration_secs must be positive".to_string(),
+            ));
+        }
+        if self
+            .cpu_boost
+            .as_ref()
+            .is_some_and(|b| !b.dir.is_absolute())
+        {
+            return Err(Error::InvalidArgument(
+                "cpu_boost.dir must be an absolute path".to_string(),
+            ));
+        }
+        if let Some(tee) = &self.tee {
+            tee.validate()?;
+        }
+        if let Some(capture) = &self.capture {
+            capture.validate()?;
+        }
+        if self
+            .seccomp
+            .as_ref()
+            .and_then(|s| s.profile.as_ref())
+            .is_some_and(|p| !p.is_absolute())
+        {
+            return Err(Error::InvalidArgument(
+                "seccomp.profile must be an absolute path".to_string(),
+            ));
+        }
+        if let Some(landlock) = &self.landlock {
+            landlock.validate()?;
+        }
+        if self.audit.as_ref().is_some_and(|a| !a.path.is_absolute()) {
+            return Err(Error::InvalidArgument(
+                "audit.path must be an absolute path".to_string(),
+            ));
+        }
+        if let Some(engine) = self
+            .engine_pools
+            .iter()
+            .find_map(|(engine, pool)| (pool.threads == Some(0)).then_some(engine))
+        {
+            return Err(Error::InvalidArgument(format!(
+                "engine_pools.{engine}.threads must not be 0"
+            )));
+        }
+        if self.otlp.as_ref().is_some_and(|o| o.endpoint.is_empty()) {
+            return Err(Error::InvalidArgument(
+                "otlp.endpoint must not be empty".to_string(),
+            ));
+        }
+        if let Some(exit_notifier) = &self.exit_notifier {
+            exit_notifier.validate()?;
+        }
+        if self
+            .file_module_source
+            .as_ref()
+            .is_some_and(|f| !f.dir.is_absolute())
+        {
+            return Err(Error::InvalidArgument(
+                "file_module_source.dir must be an absolute path".to_string(),
+            ));
+        }
+        if let Some(module_cache) = &self.module_cache {
+            let url = url::Url::parse(&module_cache.url).map_err(|err| {
+                Error::InvalidArgument(format!("invalid module_cache.url: {err}"))
+            })?;
+            if url.cannot_be_a_base() {
+                return Err(Error::InvalidArgument(format!(
+                    "invalid module_cache.url: {url}"
+                )));
+            }
+            if !module_cache.key_path.is_absolute() {
+                return Err(Error::InvalidArgument(
+                    "module_cache.key_path must be an absolute path".to_string(),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn log_level_filter(&self) -> Result<Option<LevelFilter>> {
+        self.log_level
+            .as_deref()
+            .map(LevelFilter::from_str)
+            .transpose()
+            .map_err(|err| Error::InvalidArgument(format!("invalid log_level: {err}")))
+    }
+}
+
+/// Holds the configuration currently in effect.
+pub(crate) struct ConfigStore {
+    current: RwLock<Arc<RuntimeConfig>>,
+}
+
+impl ConfigStore {
+    fn new() -> Self {
+        Self {
+            current: RwLock::default(),
+        }
+    }
+
+    pub fn global() -> &'static ConfigStore {
+        static STORE: LazyLock<ConfigStore> = LazyLock::new(ConfigStore::new);
+        &STORE
+    }
+
+    pub fn get(&self) -> Arc<RuntimeConfig> {
+        self.current.read().unwrap().clone()
+    }
+
+    /// Changes the log level of the shim, until a reload of the configuration file changes it
+    /// again. Returns a description of the change, if any.
+    pub fn set_log_level(&self, level: &str) -> Result<Vec<String>> {
+        let mut new = RuntimeConfig::clone(&self.get());
+        new.log_level = Some(level.to_string());
+        new.validate()?;
+        Ok(self.apply(new))
+    }
+
+    /// Loads the configuration in `path` and applies it.
+    /// On error the current configuration is left untouched.
+    /// Returns a description of each applied change.
+    pub fn reload(&self, path: impl AsRef<Path>) -> Result<Vec<String>> {
+        let new = RuntimeConfig::load(path)?;
+        Ok(self.apply(new))
+    }
+
+    fn apply(&self, new: RuntimeConfig) -> Vec<String> {
+        let mut current = self.current.write().unwrap();
+        let mut changes = vec![];
+
+        if new.log_level != current.log_level {
+            // this was validated when loading the config
+            let level = new.log_level_filter().ok().flatten();
+            if let Some(level) = level {
+                log::set_max_level(level);
+            }
+            changes.push(format!(
+                "log_level: {:?} => {:?}",
+                current.log_level, new.log_level
+            ));
+        }
+
+        if new.log_format != current.log_format {
+            changes.push(format!(
+                "log_format: {:?} => {:?}, applied to new shims",
+                current.log_format, new.log_format
+            ));
+        }
+
+        if new.log_driver != current.log_driver {
+            changes.push(format!(
+                "log_driver: {:?} => {:?}",
+                current.log_driver, new.log_driver
+            ));
+        }
+
+        if new.log_sampling != current.log_sampling {
+            changes.push(format!(
+                "log_sampling: {:?} => {:?}",
+                current.log_sampling, new.log_sampling
+            ));
+        }
+
+        if new.stop_timeout_secs != current.stop_timeout_secs {
+            changes.push(format!(
+                "stop_timeout_secs: {:?} => {:?}",
+                current.stop_timeout_secs, new.stop_timeout_secs
+            ));
+        }
+
+        if new.create_timeout_secs != current.create_timeout_secs {
+            changes.push(format!(
+                "create_timeout_secs: {:?} => {:?}",
+                current.create_timeout_secs, new.create_timeout_secs
+            ));
+        }
+
+        if new.max_registry_blob_bytes != current.max_registry_blob_bytes {
+            changes.push(format!(
+                "max_registry_blob_bytes: {:?} => {:?}",
+                current.max_registry_blob_bytes, new.max_registry_blob_bytes
+            ));
+        }
+
+        if new.state_root != current.state_root {
+            changes.push(format!(
+                "state_root: {:?} => {:?}, applied to new containers",
+                current.state_root, new.state_root
+            ));
+        }
+
+        if new.stdio_open_timeout_secs != current.stdio_open_timeout_secs {
+            changes.push(format!(
+                "stdio_open_timeout_secs: {:?} => {:?}, applied to new containers",
+                current.stdio_open_timeout_secs, new.stdio_open_timeout_secs
+            ));
+        }
+
+        if new.strict_stdio != current.strict_stdio {
+            changes.push(format!(
+                "strict_stdio: {} => {}, applied to new containers",
+                current.strict_stdio, new.strict_stdio
+            ));
+        }
+
+        if new.image_marker_ttl_secs != current.image_marker_ttl_secs {
+            changes.push(format!(
+                "image_marker_ttl_secs: {:?} => {:?}, applied to new containers",
+                current.image_marker_ttl_secs, new.image_marker_ttl_secs
+            ));
+        }
+
+        if new.image_eviction != current.image_eviction {
+            changes.push(format!(
+                "image_eviction: {} => {}, applied to new containers",
+                current.image_eviction, new.image_eviction
+            ));
+        }
+
+        if new.admission != current.admission {
+            changes.push(format!(
+                "admission: {:?} => {:?}, applied to new containers",
+                current.admission, new.admission
+            ));
+        }
+
+        if new.async_runtime != current.async_runtime {
+            changes.push(format!(
+                "async_runtime: {:?} => {:?}, applied to new shims",
+                current.async_runtime, new.async_runtime
+            ));
+        }
+
+        if new.zygote_pool_size != current.zygote_pool_size {
+            changes.push(format!(
+                "zygote_pool_size: {} => {}",
+                current.zygote_pool_size, new.zygote_pool_size
+            ));
+        }
+
+        if new.provenance != current.provenance {
+            changes.push(format!(
+                "provenance: {:?} => {:?}",
+                current.provenance, new.provenance
+            ));
+        }
+
+        if new.signatures != current.signatures {
+            changes.push(format!(
+                "signatures: {:?} => {:?}",
+                current.signatures, new.signatures
+            ));
+        }
+
+        if new.throttled_clock != current.throttled_clock {
+            changes.push(format!(
+                "throttled_clock: {:?} => {:?}, applied to new containers",
+                current.throttled_clock, new.throttled_clock
+            ));
+        }
+
+        if new.strict_wasi != current.strict_wasi {
+            changes.push(format!(
+                "strict_wasi: {:?} => {:?}",
+                current.strict_wasi, new.strict_wasi
+            ));
+        }
+
+        if new.component_limits != current.component_limits {
+            changes.push(format!(
+                "component_limits: {:?} => {:?}, applied to new containers",
+                current.component_limits, new.component_limits
+            ));
+        }
+
+        if new.embedded_module != current.embedded_module {
+            changes.push(format!(
+                "embedded_module: {:?} => {:?}, applied to new containers",
+                current.embedded_module, new.embedded_module
+            ));
+        }
+
+        if new.tee != current.tee {
+            changes.push(format!("tee: {:?} => {:?}", current.tee, new.tee));
+        }
+
+        if new.capture != current.capture {
+            changes.push(format!(
+                "capture: {:?} => {:?}, applied to new containers",
+                current.capture, new.capture
+            ));
+        }
+
+        if new.cpu_boost != current.cpu_boost {
+            changes.push(format!(
+                "cpu_boost: {:?} => {:?}",
+                current.cpu_boost, new.cpu_boost
+            ));
+        }
+
+        if new.seccomp != current.seccomp {
+            changes.push(format!(
+                "seccomp: {:?} => {:?}, applied to new containers",
+                current.seccomp, new.seccomp
+            ));
+        }
+
+        if new.landlock != current.landlock {
+            changes.push(format!(
+                "landlock: {:?} => {:?}, applied to new shims",
+                current.landlock, new.landlock
+            ));
+        }
+
+        if new.otlp != current.otlp {
+            changes.push(format!(
+                "otlp: {:?} => {:?}, applied to new shims",
+                current.otlp, new.otlp
+            ));
+        }
+
+        if new.audit != current.audit {
+            changes.push(format!("audit: {:?} => {:?}", current.audit, new.audit));
+        }
+
+        if new.engine_pools != current.engine_pools {
+            changes.push(format!(
+                "engine_pools: {:?} => {:?}, applied to new shims",
+                current.engine_pools, new.engine_pools
+            ));
+        }
+
+        if new.redaction != current.redaction {
+            changes.push(format!(
+                "redaction: {:?} => {:?}",
+                current.redaction, new.redaction
+            ));
+        }
+
+        if new.exit_notifier != current.exit_notifier {
+            changes.push(format!(
+                "exit_notifier: {:?} => {:?}",
+                current.exit_notifier, new.exit_notifier
+            ));
+        }
+
+        if new.file_module_source != current.file_module_source {
+            changes.push(format!(
+                "file_module_source: {:?} => {:?}",
+                current.file_module_source, new.file_module_source
+            ));
+        }
+
+        if new.wire_debug != current.wire_debug {
+            changes.push(format!(
+                "wire_debug: {} => {}",
+                current.wire_debug, new.wire_debug
+            ));
+        }
+
+        *current = Arc::new(new);
+        changes
+    }
+}
+
+/// Loads the configuration file pointed by `RUNWASI_CONFIG`, if any, and watches it for changes.
+pub fn watch_from_env() {
+    if let Some(path) = load_from_env() {
+        watch(path);
+    }
+}
+
+/// Loads the configuration file pointed by `RUNWASI_CONFIG`, if any, returning its path.
+pub fn load_from_env() -> Option<PathBuf> {
+    let path = std::env::var_os(CONFIG_ENV).map(PathBuf::from)?;
+    reload(&path);
+    Some(path)
+}
+
+/// Watches the configuration file at `path` for changes, and reloads it on `SIGHUP`.
+pub fn watch(path: PathBuf) {
+    #[cfg(target_os = "linux")]
+    if let Err(err) = watcher::spawn_on_sighup(path.clone()) {
+        log::warn!("failed to reload runtime config on SIGHUP: {err}");
+    }
+
+    #[cfg(target_os = "linux")]
+    if let Err(err) = watcher::spawn(path) {
+        log::warn!("failed to watch runtime config: {err}");
+    }
+}
+
+/// Changes the log level of the shim, without restarting its tasks, until the configuration
+/// file is reloaded.
+pub fn set_log_level(level: &str) -> Result<()> {
+    for change in ConfigStore::global().set_log_level(level)? {
+        log::info!("applied runtime config change: {change}");
+    }
+    Ok(())
+}
+
+fn reload(path: &Path) {
+    match ConfigStore::global().reload(path) {
+        Ok(changes) => {
+            for change in changes {
+                log::info!("applied runtime config change from {path:?}: {change}");
+            }
+        }
+        Err(err) => {
+            log::error!("invalid runtime config {path:?}, keeping previous config: {err}");
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod watcher {
+    use std::path::PathBuf;
+    use std::thread;
+
+    use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify};
+
+    use super::reload;
+
+    // We watch the parent directory instead of the file itself so that
+    // we pick up files that are atomically replaced (written and renamed).
+    pub fn spawn(path: PathBuf) -> anyhow::Result<()> {
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+        let name = path.file_name().map(ToOwned::to_owned);
+
+        let inotify = Inotify::init(InitFlags::IN_CLOEXEC)?;
+        inotify.add_watch(
+            dir.as_path(),
+            AddWatchFlags::IN_CLOSE_WRITE | AddWatchFlags::IN_MOVED_TO | AddWatchFlags::IN_CREATE,
+        )?;
+
+        thread::Builder::new()
+            .name("config-watcher".to_string())
+            .spawn(move || loop {
+                let events = match inotify.read_events() {
+                    Ok(events) => events,
+                    Err(err) => {
+                        log::warn!("stopped watching runtime config: {err}");
+                        return;
+                    }
+                };
+                if events.iter().any(|ev| ev.name == name) {
+                    reload(&path);
+                }
+            })?;
+
+        Ok(())
+    }
+
+    // Reloads the configuration whenever the shim receives SIGHUP.
+    pub fn spawn_on_sighup(path: PathBuf) -> anyhow::Result<()> {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_io()
+            .build()?;
+        // the handler is installed right away, so that SIGHUP doesn't kill the shim
+        let mut sighup = runtime.block_on(async { signal(SignalKind::hangup()) })?;
+
+        thread::Builder::new()
+            .name("config-sighup".to_string())
+            .spawn(move || {
+                runtime.block_on(async {
+                    while sighup.recv().await.is_some() {
+                        log::info!("reloading runtime config on SIGHUP");
+                        reload(&path);
+                    }
+                })
+            })?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_config() -> Result<()> {
+        let cfg = RuntimeConfig::from_slice(br#"{ "log_level": "debug" }"#)?;
+        assert_eq!(cfg.log_level.as_deref(), Some("debug"));
+
+        let cfg = RuntimeConfig::from_slice(br#"{ "log_format": "json" }"#)?;
+        assert_eq!(cfg.log_format, LogFormat::Json);
+
+        let cfg = RuntimeConfig::from_slice(br#"{ "log_driver": "journald" }"#)?;
+        assert_eq!(cfg.log_driver, LogDriver::Journald);
+
+        let cfg =
+            RuntimeConfig::from_slice(br#"{ "log_sampling": { "window_secs": { "info": 10 } } }"#)?;
+        let log_sampling = cfg.log_sampling.unwrap();
+        assert_eq!(
+            log_sampling.window(log::Level::Info),
+            Some(Duration::from_secs(10))
+        );
+        assert_eq!(log_sampling.window(log::Level::Warn), None);
+
+        let cfg = RuntimeConfig::from_slice(br#"{ "stop_timeout_secs": 10 }"#)?;
+        assert_eq!(cfg.stop_timeout(), Some(Duration::from_secs(10)));
+
+        let cfg = RuntimeConfig::from_slice(br#"{ "create_timeout_secs": 60 }"#)?;
+        assert_eq!(cfg.create_timeout(), Some(Duration::from_secs(60)));
+
+        let cfg = RuntimeConfig::from_slice(
+            br#"{ "stdio_open_timeout_secs": 1, "strict_stdio": true }"#,
+        )?;
+        assert_eq!(cfg.stdio_open_timeout_secs, Some(1));
+        assert!(cfg.strict_stdio);
+
+        let cfg = RuntimeConfig::from_slice(br#"{ "zygote_pool_size": 4 }"#)?;
+        assert_eq!(cfg.zygote_pool_size, 4);
+
+        let cfg = RuntimeConfig::from_slice(br#"{ "image_marker_ttl_secs": 300 }"#)?;
+        assert_eq!(cfg.image_marker_ttl_secs, Some(300));
+
+        let cfg = RuntimeConfig::from_slice(br#"{ "image_eviction": true }"#)?;
+        assert!(cfg.image_eviction);
+
+        let cfg = RuntimeConfig::from_slice(br#"{ "admission": { "max_instances": 500 } }"#)?;
+        let admission = cfg.admission.unwrap();
+        assert_eq!(admission.max_instances, 500);
+        assert_eq!(admission.dir, AdmissionConfig::default().dir);
+
+        let cfg =
+            RuntimeConfig::from_slice(br#"{ "async_runtime": { "max_blocking_threads": 16 } }"#)?;
+        assert_eq!(cfg.async_runtime.max_blocking_threads, Some(16));
+        assert_eq!(cfg.async_runtime.worker_threads, None);
+
+        let cfg = RuntimeConfig::from_slice(br#"{ "capture": { "namespaces": ["payments"] } }"#)?;
+        let capture = cfg.capture.unwrap();
+        assert!(capture.applies_to("payments"));
+        assert!(!capture.applies_to("default"));
+        assert_eq!(capture.rotation, LogRotationConfig::default());
+
+        let cfg = RuntimeConfig::from_slice(br#"{ "redaction": { "keys": ["dsn"] } }"#)?;
+        assert_eq!(cfg.redaction.keys, ["dsn"]);
+        assert!(cfg.redaction.default_keys);
+
+        let cfg = RuntimeConfig::from_slice(
+            br#"{ "exit_notifier": { "url": "unix:///run/exits.sock" } }"#,
+        )?;
+        let exit_notifier = cfg.exit_notifier.unwrap();
+        assert_eq!(exit_notifier.max_attempts, 5);
+        assert!(exit_notifier.applies_to("default"));
+
+        let cfg = RuntimeConfig::from_slice(
+            br#"{ "file_module_source": { "dir": "/var/lib/runwasi/modules" } }"#,
+        )?;
+        assert_eq!(
+            cfg.file_module_source.unwrap().dir,
+            Path::new("/var/lib/runwasi/modules")
+        );
+
+        let cfg = RuntimeConfig::from_slice(br#"{ "wire_debug": true }"#)?;
+        assert!(cfg.wire_debug);
+
+        let cfg = RuntimeConfig::from_slice(br#"{ "stdio": { "pipe_size_bytes": 1048576 } }"#)?;
+        let stdio = cfg.stdio.unwrap();
+        assert_eq!(stdio.pipe_size_bytes, Some(1048576));
+        assert_eq!(
+            stdio.max_buffer_bytes,
+            StdioConfig::default().max_buffer_bytes
+        );
+        assert_eq!(stdio.on_full, OverflowPolicy::Block);
+
+        let cfg = RuntimeConfig::from_slice(br#"{ "stdio": { "on_full": "drop" } }"#)?;
+        assert_eq!(cfg.stdio.unwrap().on_full, OverflowPolicy::Drop);
+
+        let cfg = RuntimeConfig::from_slice(br#"{ "strict_wasi": { "namespaces": ["tenant"] } }"#)?;
+        let strict_wasi = cfg.strict_wasi.unwrap();
+        assert!(strict_wasi.applies_to("tenant"));
+        assert!(!strict_wasi.applies_to("k8s.io"));
+
+        let cfg = RuntimeConfig::from_slice(
+            br#"{ "throttled_clock": { "namespaces": ["tenant"], "max_reads_per_sec": 100 } }"#,
+        )?;
+        let throttled_clock = cfg.throttled_clock.unwrap();
+        assert!(throttled_clock.applies_to("tenant"));
+        assert_eq!(
+            throttled_clock.throttle(),
+            ClockThrottle {
+                resolution: Duration::from_millis(1),
+                max_reads_per_sec: Some(100),
+            }
+        );
+
+        let cfg =
+            RuntimeConfig::from_slice(br#"{ "component_limits": { "max_nesting_depth": 8 } }"#)?;
+        assert_eq!(cfg.component_limits.max_nesting_depth, Some(8));
+        assert_eq!(cfg.component_limits.max_instances, None);
+
+        let cfg = RuntimeConfig::from_slice(br#"{ "embedded_module": {} }"#)?;
+        assert!(cfg.embedded_module.unwrap().applies_to("k8s.io"));
+
+        let cfg = RuntimeConfig::from_slice(
+            br#"{ "otlp": { "endpoint": "http://otel:4317", "protocol": "grpc" } }"#,
+        )?;
+        let otlp = cfg.otlp.unwrap();
+        assert_eq!(otlp.endpoint, "http://otel:4317");
+        assert_eq!(otlp.protocol, OtlpProtocol::Grpc);
+
+        let cfg = RuntimeConfig::from_slice(b"{}")?;
+        assert_eq!(cfg, RuntimeConfig::default());
+        Ok(())
+    }
+
+    #[test]
+    fn test_provenance_policy() -> Result<()> {
+        let cfg = RuntimeConfig::from_slice(
+            br#"{ "provenance": {
+                "namespaces": ["k8s.io"],
+                "keys": ["/etc/runwasi/keys/attestations.pub"],
+                "builder_ids": ["https://github.com/slsa-framework/*"],
+                "source_repos": ["https://github.com/my-org/*"]
+            } }"#,
+        )?;
+        let policy = cfg.provenance.unwrap();
+
+        assert!(policy.applies_to("k8s.io"));
+        assert!(!policy.applies_to("default"));
+
+        let builder = "https://github.com/slsa-framework/slsa-github-generator/v1";
+        assert!(policy.allows(builder, Some("https://github.com/my-org/app")));
+        assert!(!policy.allows(builder, Some("https://github.com/other/app")));
+        assert!(!policy.allows(builder, None));
+        assert!(!policy.allows(
+            "https://example.com/builder",
+            Some("https://github.com/my-org/app")
+        ));
+
+        assert!(ProvenancePolicy::default().allows("anything", None));
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_config_is_rejected() {
+        RuntimeConfig::from_slice(br#"{ "log_level": "loud" }"#).unwrap_err();
+        RuntimeConfig::from_slice(br#"{ "unknown": true }"#).unwrap_err();
+        RuntimeConfig::from_slice(br#"{ "log_format": "xml" }"#).unwrap_err();
+        RuntimeConfig::from_slice(br#"{ "create_timeout_secs": 0 }"#).unwrap_err();
+        RuntimeConfig::from_slice(br#"{ "max_registry_blob_bytes": 0 }"#).unwrap_err();
+        RuntimeConfig::from_slice(br#"{ "state_root": "state" }"#).unwrap_err();
+        RuntimeConfig::from_slice(br#"{ "audit": { "path": "audit.log" } }"#).unwrap_err();
+        RuntimeConfig::from_slice(br#"{ "engine_pools": { "wasmtime": { "threads": 0 } } }"#)
+            .unwrap_err();
+        RuntimeConfig::from_slice(br#"{ "otlp": { "protocol": "grpc" } }"#).unwrap_err();
+        RuntimeConfig::from_slice(br#"{ "otlp": { "endpoint": "x", "protocol": "udp" } }"#)
+            .unwrap_err();
+        RuntimeConfig::from_slice(br#"{ "cpu_boost": { "cpus": 0 } }"#).unwrap_err();
+        RuntimeConfig::from_slice(br#"{ "cpu_boost": { "max_duration_secs": 0 } }"#).unwrap_err();
+        RuntimeConfig::from_slice(br#"{ "cpu_boost": { "dir": "cpu-boost" } }"#).unwrap_err();
+        RuntimeConfig::from_slice(br#"{ "seccomp": { "profile": "seccomp.json" } }"#).unwrap_err();
+        RuntimeConfig::from_slice(br#"{ "landlock": { "read_only_paths": ["keys"] } }"#)
+            .unwrap_err();
+        RuntimeConfig::from_slice(br#"{ "throttled_clock": { "resolution_us": 0 } }"#).unwrap_err();
+        RuntimeConfig::from_slice(br#"{ "tee": { "sinks": ["/var/log/app.log"] } }"#).unwrap_err();
+        RuntimeConfig::from_slice(br#"{ "provenance": {} }"#).unwrap_err();
+        RuntimeConfig::from_slice(br#"{ "provenance": { "keys": ["attestations.pub"] } }"#)
+            .unwrap_err();
+        RuntimeConfig::from_slice(
+            br#"{ "provenance": { "keys": ["/attestations.pub"], "builder_ids": ["["] } }"#,
+        )
+        .unwrap_err();
+        RuntimeConfig::from_slice(br#"{ "signatures": {} }"#).unwrap_err();
+        RuntimeConfig::from_slice(br#"{ "signatures": { "cosign_keys": ["cosign.pub"] } }"#)
+            .unwrap_err();
+        RuntimeConfig::from_slice(br#"{ "stdio": { "pipe_size_bytes": 0 } }"#).unwrap_err();
+        RuntimeConfig::from_slice(br#"{ "stdio": { "max_spill_bytes": 0 } }"#).unwrap_err();
+        RuntimeConfig::from_slice(br#"{ "module_cache": { "url": "cache" } }"#).unwrap_err();
+        RuntimeConfig::from_slice(br#"{ "module_cache": { "url": "http://cache" } }"#).unwrap_err();
+        RuntimeConfig::from_slice(br#"{ "exit_notifier": { "url": "ftp://host/exits" } }"#)
+            .unwrap_err();
+        RuntimeConfig::from_slice(br#"{ "log_rotation": { "max_files": 0 } }"#).unwrap_err();
+        RuntimeConfig::from_slice(br#"{ "file_module_source": {} }"#).unwrap_err();
+        RuntimeConfig::from_slice(br#"{ "async_runtime": { "worker_threads": 0 } }"#).unwrap_err();
+        RuntimeConfig::from_slice(br#"{ "capture": { "dir": "capture" } }"#).unwrap_err();
+        RuntimeConfig::from_slice(br#"{ "admission": { "max_instances": 0 } }"#).unwrap_err();
+        RuntimeConfig::from_slice(br#"{ "log_sampling": { "window_secs": { "loud": 10 } } }"#)
+            .unwrap_err();
+        RuntimeConfig::from_slice(br#"{ "log_sampling": { "window_secs": { "warn": 0 } } }"#)
+            .unwrap_err();
+        RuntimeConfig::from_slice(br#"{ "admission": { "dir": "admission" } }"#).unwrap_err();
+        RuntimeConfig::from_slice(b"not json").unwrap_err();
+    }
+
+    #[test]
+    fn test_reload_keeps_previous_config_on_error() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("config.json");
+        let store = ConfigStore::new();
+
+        std::fs::write(&path, r#"{ "log_level": "info" }"#)?;
+        let changes = store.reload(&path)?;
+        assert_eq!(changes.len(), 1);
+        assert_eq!(store.get().log_level.as_deref(), Some("info"));
+
+        std::fs::write(&path, r#"{ "log_level": "#)?;
+        store.reload(&path).unwrap_err();
+        assert_eq!(store.get().log_level.as_deref(), Some("info"));
+
+        // reloading the same config is a no-op
+        std::fs::write(&path, r#"{ "log_level": "info" }"#)?;
+        assert!(store.reload(&path)?.is_empty());
+
+        assert_eq!(store.set_log_level("debug")?.len(), 1);
+        assert_eq!(store.get().log_level.as_deref(), Some("debug"));
+        store.set_log_level("loud").unwrap_err();
+        assert_eq!(store.get().log_level.as_deref(), Some("debug"));
+
+        // reloading the file restores its log level
+        assert_eq!(store.reload(&path)?.len(), 1);
+        assert_eq!(store.get().log_level.as_deref(), Some("info"));
+        Ok(())
+    }
+}
@@ -1,32 +1,50 @@
 #![cfg(unix)]
 
 use std::collections::HashMap;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::Duration;
 
 use containerd_client;
+use containerd_client::events::{ContentDelete, ImageDelete};
 use containerd_client::services::v1::containers_client::ContainersClient;
 use containerd_client::services::v1::content_client::ContentClient;
+use containerd_client::services::v1::events_client::EventsClient;
 use containerd_client::services::v1::images_client::ImagesClient;
 use containerd_client::services::v1::leases_client::LeasesClient;
 use containerd_client::services::v1::{
     Container, DeleteContentRequest, GetContainerRequest, GetImageRequest, Image, Info,
-    InfoRequest, ReadContentRequest, UpdateRequest, WriteAction, WriteContentRequest,
-    WriteContentResponse,
+    InfoRequest, ReadContentRequest, SubscribeRequest, UpdateContainerRequest, UpdateRequest,
+    WriteAction, WriteContentRequest, WriteContentResponse,
 };
 use containerd_client::tonic::transport::Channel;
 use containerd_client::tonic::Streaming;
 use containerd_client::{tonic, with_namespace};
-use futures::TryStreamExt;
-use oci_spec::image::{Arch, Digest, ImageManifest, MediaType, Platform};
+use futures::{StreamExt, TryStreamExt};
+use oci_spec::image::{Arch, Digest, ImageManifest, MediaType, Os, Platform, PlatformBuilder};
+use prost::Message as _;
 use sha256::digest;
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, OnceCell};
 use tokio_stream::wrappers::ReceiverStream;
 use tonic::{Code, Request};
 
-use super::lease::LeaseGuard;
-use crate::container::Engine;
+use super::decompress::{self, LayerDecoder};
+use super::lease::{InstanceLease, LeaseGuard};
+use super::module_cache::ModuleCache;
+use super::provenance::{self, BUILDER_ID_LABEL, SOURCE_REPO_LABEL};
+use super::registry::{self, Access};
+use super::signature;
+use super::verify::{verify_digest, DigestVerifier};
+use crate::container::{Engine, LAYER_ROLE_ANNOTATION};
+use crate::sandbox::async_utils;
+use crate::sandbox::compile_pool::CompilePool;
+use crate::sandbox::config::RuntimeConfig;
+use crate::sandbox::cpu_features;
 use crate::sandbox::error::{Error as ShimError, Result};
+use crate::sandbox::log_sampling::sampled;
 use crate::sandbox::oci::{self, WasmLayer};
+use crate::sandbox::shim::audit;
+use crate::sandbox::timings::{Phase, Timings};
 use crate::with_lease;
 
 static PRECOMPILE_PREFIX: &str = "runwasi.io/precompiled";
@@ -35,12 +53,63 @@ static PRECOMPILE_PREFIX: &str = "runwasi.io/precompiled";
 // Conservatively set the max to 15MB to leave room for message overhead
 static MAX_WRITE_CHUNK_SIZE_BYTES: i64 = 1024 * 1024 * 15;
 
-#[derive(Debug)]
+/// Image configs larger than this aren't read for the revision of the image.
+const MAX_IMAGE_CONFIG_BYTES: usize = 1024 * 1024;
+
+/// Environment variable with the maximum number of layers fetched concurrently.
+const LAYER_FETCH_PARALLELISM_ENV: &str = "RUNWASI_LAYER_FETCH_PARALLELISM";
+const DEFAULT_LAYER_FETCH_PARALLELISM: usize = 4;
+
+// Media types of the CNCF wasm OCI artifact layout:
+// https://tag-runtime.cncf.io/wgs/wasm/deliverables/wasm-oci-artifact/
+const WASM_ARTIFACT_TYPE: &str = "application/vnd.wasm.component.v1+wasm";
+const WASM_CONFIG_MEDIA_TYPE: &str = "application/vnd.wasm.config.v0+json";
+const WASM_ARTIFACT_LAYER_MEDIA_TYPE: &str = "application/wasm";
+
+const IMAGE_REVISION_LABEL: &str = "org.opencontainers.image.revision";
+
+/// Annotation with the modules to pull at create time, in addition to the modules of the image,
+/// as a comma separated list of `name=reference`.
+pub(crate) const PULL_MODULES_ANNOTATION: &str = "runwasi.io/pull-modules";
+
+const TITLE_ANNOTATION: &str = "org.opencontainers.image.title";
+
+#[derive(Clone, Debug)]
 pub struct Client {
-    inner: Channel,
+    address: PathBuf,
+    // dialed on first use, and shared by the clones of the client
+    inner: Arc<OnceCell<Channel>>,
     namespace: String,
 }
 
+/// The connections to containerd shared by the instances of the shim, by address and namespace.
+static CLIENTS: LazyLock<Mutex<HashMap<(PathBuf, String), Client>>> =
+    LazyLock::new(Default::default);
+
+/// What was deleted from containerd, see [`Client::watch_deletions`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Deleted {
+    /// An image, by name.
+    Image(String),
+    /// A blob of the content store, by digest.
+    Content(String),
+}
+
+impl Deleted {
+    const IMAGE_TOPIC: &str = "/images/delete";
+    const CONTENT_TOPIC: &str = "/content/delete";
+
+    fn from_event(topic: &str, event: &[u8]) -> Option<Self> {
+        match topic {
+            Self::IMAGE_TOPIC => ImageDelete::decode(event).ok().map(|e| Self::Image(e.name)),
+            Self::CONTENT_TOPIC => ContentDelete::decode(event)
+                .ok()
+                .map(|e| Self::Content(e.digest)),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub(crate) struct WriteContent {
     lease: LeaseGuard,
@@ -48,8 +117,6 @@ pub(crate) struct WriteContent {
 }
 
 impl WriteContent {
-    // used in tests
-    #[allow(dead_code)]
     pub async fn release(self) -> anyhow::Result<()> {
         self.lease.release().await
     }
@@ -63,14 +130,97 @@ impl Client {
         address: impl AsRef<Path> + std::fmt::Debug,
         namespace: impl Into<String> + std::fmt::Debug,
     ) -> Result<Client> {
-        let inner = containerd_client::connect(address.as_ref())
-            .await
-            .map_err(|err| ShimError::Containerd(err.to_string()))?;
+        let client = Client::new(address.as_ref().to_path_buf(), namespace.into());
+        client.channel().await?;
+        Ok(client)
+    }
 
-        Ok(Client {
-            inner,
-            namespace: namespace.into(),
-        })
+    fn new(address: PathBuf, namespace: String) -> Client {
+        Client {
+            address,
+            inner: Default::default(),
+            namespace,
+        }
+    }
+
+    /// Returns a client of `namespace` on the containerd at `address`, sharing its connection
+    /// with the previous clients of the same address and namespace.
+    /// The connection is only established by the first request of a client, and reused by the
+    /// following instance creations instead of dialing containerd again.
+    pub fn shared(address: impl AsRef<Path>, namespace: impl Into<String>) -> Client {
+        let key = (address.as_ref().to_path_buf(), namespace.into());
+        let mut clients = CLIENTS.lock().unwrap();
+        clients
+            .entry(key)
+            .or_insert_with_key(|(address, namespace)| {
+                Client::new(address.clone(), namespace.clone())
+            })
+            .clone()
+    }
+
+    // The connection of the client, dialing containerd on first use.
+    // Failing to dial is retried by the next request.
+    async fn channel(&self) -> Result<Channel> {
+        let channel = self
+            .inner
+            .get_or_try_init(|| async {
+                // the connection is driven by the runtime of the shim, so that it keeps working
+                // for the other threads sharing it
+                let address = self.address.clone();
+                async_utils::spawn(async move { containerd_client::connect(address).await })
+                    .await
+                    .map_err(|err| ShimError::Containerd(err.to_string()))?
+                    .map_err(|err| ShimError::Containerd(err.to_string()))
+            })
+            .await?;
+        Ok(channel.clone())
+    }
+
+    /// Calls `on_deleted` with the images and content deleted from the namespace of the client,
+    /// for as long as the shim runs. The subscription is restored when the connection to
+    /// containerd is lost, and the deletions in between are missed.
+    pub fn watch_deletions(&self, on_deleted: impl Fn(Deleted) + Send + 'static) {
+        let client = self.clone();
+        async_utils::spawn(async move {
+            let mut backoff = Duration::from_secs(1);
+            loop {
+                match client.subscribe_deletions(&on_deleted).await {
+                    Ok(()) => backoff = Duration::from_secs(1),
+                    Err(err) => log::warn!(
+                        "lost the deletions of namespace {}, retrying in {backoff:?}: {err}",
+                        client.namespace
+                    ),
+                }
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(Duration::from_secs(60));
+            }
+        });
+    }
+
+    async fn subscribe_deletions(&self, on_deleted: &impl Fn(Deleted)) -> Result<()> {
+        let filters = [Deleted::IMAGE_TOPIC, Deleted::CONTENT_TOPIC]
+            .iter()
+            .map(|topic| format!(r#"namespace=="{}",topic=="{topic}""#, self.namespace))
+            .collect();
+        let mut events = EventsClient::new(self.channel().await?)
+            .subscribe(SubscribeRequest { filters })
+            .await
+            .map_err(|err| ShimError::Containerd(err.to_string()))?
+            .into_inner();
+        while let Some(envelope) = events
+            .message()
+            .await
+            .map_err(|err| ShimError::Containerd(err.to_string()))?
+        {
+            let deleted = envelope
+                .event
+                .and_then(|event| Deleted::from_event(&envelope.topic, &event.value));
+            if let Some(deleted) = deleted {
+                log::debug!("containerd deleted {deleted:?}");
+                on_deleted(deleted);
+            }
+        }
+        Ok(())
     }
 
     // wrapper around read that will read the entire content file
@@ -81,7 +231,7 @@ impl Client {
             ..Default::default()
         };
         let req = with_namespace!(req, self.namespace);
-        ContentClient::new(self.inner.clone())
+        ContentClient::new(self.channel().await?)
             .read(req)
             .await
             .map_err(|err| ShimError::Containerd(err.to_string()))?
@@ -92,6 +242,133 @@ impl Client {
             .map_err(|err| ShimError::Containerd(err.to_string()))
     }
 
+    // reads the content `digest`, failing if it's larger than `max_bytes`
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "Debug"))]
+    async fn read_content_at_most(
+        &self,
+        digest: impl ToString + std::fmt::Debug,
+        max_bytes: usize,
+    ) -> Result<Vec<u8>> {
+        let digest = digest.to_string();
+        let req = ReadContentRequest {
+            digest: digest.clone(),
+            // one more byte, to tell larger contents apart
+            size: max_bytes as i64 + 1,
+            ..Default::default()
+        };
+        let req = with_namespace!(req, self.namespace);
+        let content: Vec<u8> = ContentClient::new(self.channel().await?)
+            .read(req)
+            .await
+            .map_err(|err| ShimError::Containerd(err.to_string()))?
+            .into_inner()
+            .map_ok(|msg| msg.data)
+            .try_concat()
+            .await
+            .map_err(|err| ShimError::Containerd(err.to_string()))?;
+        if content.len() > max_bytes {
+            return Err(ShimError::FailedPrecondition(format!(
+                "content {digest} is larger than {max_bytes} bytes"
+            )));
+        }
+        Ok(content)
+    }
+
+    // read a layer from the content store, verifying its digest and
+    // decompressing its chunks as they are received
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "Debug"))]
+    async fn read_layer_content(
+        &self,
+        descriptor: &oci_spec::image::Descriptor,
+    ) -> Result<Vec<u8>> {
+        let req = ReadContentRequest {
+            digest: descriptor.digest().to_string(),
+            ..Default::default()
+        };
+        let req = with_namespace!(req, self.namespace);
+        let mut stream = ContentClient::new(self.channel().await?)
+            .read(req)
+            .await
+            .map_err(|err| ShimError::Containerd(err.to_string()))?
+            .into_inner();
+
+        let mut verifier = DigestVerifier::new(descriptor.digest())?;
+        let mut decoder = LayerDecoder::new(descriptor.media_type())?;
+        while let Some(msg) = stream
+            .try_next()
+            .await
+            .map_err(|err| ShimError::Containerd(err.to_string()))?
+        {
+            verifier.update(&msg.data);
+            decoder.write(&msg.data)?;
+        }
+        verifier.verify()?;
+        Ok(decoder.finish()?)
+    }
+
+    // read a layer from the content store, fetching it from the registry of the image if it's missing
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "Debug"))]
+    async fn read_layer(
+        &self,
+        image: &str,
+        image_digest: &Digest,
+        descriptor: &oci_spec::image::Descriptor,
+    ) -> Result<Vec<u8>> {
+        match self.read_layer_content(descriptor).await {
+            Ok(layer) => Ok(layer),
+            // corrupted content is reported rather than silently fetched again
+            Err(err @ ShimError::FailedPrecondition(_)) => Err(err),
+            Err(err) => {
+                log::info!(
+                    "layer {} not found in the content store, fetching it from {image}: {err}",
+                    descriptor.digest()
+                );
+                let layer = registry::fetch_blob(image, descriptor, Access::Node).await?;
+                verify_digest(descriptor.digest(), &layer)?;
+                if let Err(err) = self.store_layer(image_digest, descriptor, &layer).await {
+                    sampled!(
+                        log::Level::Warn,
+                        "failed to store layer {} in the content store: {err}",
+                        descriptor.digest()
+                    );
+                }
+                Ok(decompress::decode(descriptor.media_type(), &layer)?)
+            }
+        }
+    }
+
+    // store a layer fetched from the registry in the content store, so that it isn't fetched again
+    // and its precompiled artifact can be linked to it.
+    // The image content references the layer so that it isn't garbage collected.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(layer), level = "Debug"))]
+    async fn store_layer(
+        &self,
+        image_digest: &Digest,
+        descriptor: &oci_spec::image::Descriptor,
+        layer: &[u8],
+    ) -> Result<()> {
+        let content = self
+            .save_content(
+                layer.to_vec(),
+                &descriptor.digest().to_string(),
+                HashMap::new(),
+            )
+            .await?;
+
+        let mut image_content = self.get_info(image_digest).await?;
+        image_content.labels.insert(
+            format!(
+                "containerd.io/gc.ref.content.fetched.{}",
+                descriptor.digest().digest()
+            ),
+            content.digest.clone(),
+        );
+        self.update_info(image_content).await?;
+
+        let _ = content.lease.release().await;
+        Ok(())
+    }
+
     // used in tests to clean up content
     #[allow(dead_code)]
     #[cfg_attr(feature = "tracing", tracing::instrument(level = "Debug"))]
@@ -100,7 +377,7 @@ impl Client {
             digest: digest.to_string(),
         };
         let req = with_namespace!(req, self.namespace);
-        ContentClient::new(self.inner.clone())
+        ContentClient::new(self.channel().await?)
             .delete(req)
             .await
             .map_err(|err| ShimError::Containerd(err.to_string()))?;
@@ -119,7 +396,7 @@ impl Client {
             labels: lease_labels,
         };
 
-        let mut leases_client = LeasesClient::new(self.inner.clone());
+        let mut leases_client = LeasesClient::new(self.channel().await?);
         let lease = leases_client
             .create(with_namespace!(lease_request, self.namespace))
             .await
@@ -137,6 +414,14 @@ impl Client {
         ))
     }
 
+    /// Releases the lease on the content used by the container `containerd_id`.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "Debug"))]
+    pub async fn release_instance_lease(&self, containerd_id: &str) -> Result<()> {
+        let client = LeasesClient::new(self.channel().await?);
+        InstanceLease::release(client, containerd_id, &self.namespace).await?;
+        Ok(())
+    }
+
     #[cfg_attr(feature = "tracing", tracing::instrument(level = "Debug"))]
     async fn save_content(
         &self,
@@ -154,7 +439,7 @@ impl Client {
 
             let len = data.len() as i64;
             log::debug!("Writing {} bytes to content store", len);
-            let mut client = ContentClient::new(self.inner.clone());
+            let mut client = ContentClient::new(self.channel().await?);
 
             // Send write request with Stat action to containerd to let it know that we are going to write content
             // if the content is already there, it will return early with AlreadyExists
@@ -265,7 +550,7 @@ impl Client {
             digest: content_digest.to_string(),
         };
         let req = with_namespace!(req, self.namespace);
-        let info = ContentClient::new(self.inner.clone())
+        let info = ContentClient::new(self.channel().await?)
             .info(req)
             .await
             .map_err(|err| ShimError::Containerd(err.to_string()))?
@@ -288,7 +573,7 @@ impl Client {
         // Depending on it would mean keeping it's version in sync with the version in `containerd-client`.
         req.update_mask.as_mut().unwrap().paths = vec!["labels".to_string()];
         let req = with_namespace!(req, self.namespace);
-        let info = ContentClient::new(self.inner.clone())
+        let info = ContentClient::new(self.channel().await?)
             .update(req)
             .await
             .map_err(|err| ShimError::Containerd(err.to_string()))?
@@ -305,7 +590,7 @@ impl Client {
         let name = image_name.to_string();
         let req = GetImageRequest { name };
         let req = with_namespace!(req, self.namespace);
-        let image = ImagesClient::new(self.inner.clone())
+        let image = ImagesClient::new(self.channel().await?)
             .get(req)
             .await
             .map_err(|err| ShimError::Containerd(err.to_string()))?
@@ -344,7 +629,7 @@ impl Client {
         let id = container_name.to_string();
         let req = GetContainerRequest { id };
         let req = with_namespace!(req, self.namespace);
-        let container = ContainersClient::new(self.inner.clone())
+        let container = ContainersClient::new(self.channel().await?)
             .get(req)
             .await
             .map_err(|err| ShimError::Containerd(err.to_string()))?
@@ -359,6 +644,96 @@ impl Client {
         Ok(container)
     }
 
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "Debug"))]
+    async fn update_container(&self, container: Container) -> Result<Container> {
+        let id = container.id.clone();
+        let mut req = UpdateContainerRequest {
+            container: Some(container),
+            update_mask: Some(Default::default()),
+        };
+        // See `update_info` for why the update mask is not named directly.
+        req.update_mask.as_mut().unwrap().paths = vec!["labels".to_string()];
+        let req = with_namespace!(req, self.namespace);
+        let container = ContainersClient::new(self.channel().await?)
+            .update(req)
+            .await
+            .map_err(|err| ShimError::Containerd(err.to_string()))?
+            .into_inner()
+            .container
+            .ok_or_else(|| ShimError::Containerd(format!("failed to update container {id}")))?;
+        Ok(container)
+    }
+
+    // verify the provenance of the container image, and record the result in the container labels
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "Debug"))]
+    async fn verify_provenance(
+        &self,
+        mut container: Container,
+        image_digest: &Digest,
+    ) -> Result<()> {
+        let Some(provenance) = self
+            .check_provenance(&container.id, &container.image, image_digest, Access::Node)
+            .await?
+        else {
+            return Ok(());
+        };
+        container
+            .labels
+            .insert(BUILDER_ID_LABEL.to_string(), provenance.builder_id);
+        if let Some(source_repo) = provenance.source_repo {
+            container
+                .labels
+                .insert(SOURCE_REPO_LABEL.to_string(), source_repo);
+        }
+        self.update_container(container).await?;
+        Ok(())
+    }
+
+    // verify the provenance of `image`, used by the container `id`, when the namespace has a
+    // provenance policy, and record the result in the audit log
+    async fn check_provenance(
+        &self,
+        id: &str,
+        image: &str,
+        image_digest: &Digest,
+        access: Access,
+    ) -> Result<Option<provenance::Provenance>> {
+        let config = RuntimeConfig::current();
+        let Some(policy) = config
+            .provenance
+            .as_ref()
+            .filter(|policy| policy.applies_to(&self.namespace))
+        else {
+            return Ok(None);
+        };
+
+        let res = provenance::verify(image, &image_digest.to_string(), policy, access).await;
+        let details = match &res {
+            Ok(provenance) => serde_json::json!({
+                "image": image,
+                "digest": image_digest.to_string(),
+                "builder_id": provenance.builder_id,
+                "source_repo": provenance.source_repo,
+            }),
+            Err(_) => serde_json::json!({ "image": image, "digest": image_digest.to_string() }),
+        };
+        let error = res.as_ref().err().map(ToString::to_string);
+        audit::record(&self.namespace, "verify-provenance", id, error, details);
+
+        match res {
+            Ok(provenance) => {
+                log::info!(
+                    "verified provenance of image {image} of container {id}: {provenance:?}"
+                );
+                Ok(Some(provenance))
+            }
+            Err(err) => {
+                log::warn!("rejecting image {image} of container {id}: {err}");
+                Err(err)
+            }
+        }
+    }
+
     #[cfg_attr(feature = "tracing", tracing::instrument(level = "Debug"))]
     async fn get_image_manifest_and_digest(
         &self,
@@ -371,6 +746,141 @@ impl Client {
         Ok((manifest, image_digest))
     }
 
+    /// Pulls the wasm layers of the image `reference`, to be used by the container `containerd_id`
+    /// as libraries named `name`.
+    /// Layers are kept if they have a wasm media type, or one of the media types the engine
+    /// supports, as when loading the modules of the container.
+    /// The image is read from the content store when it was pulled by containerd, and from its
+    /// registry otherwise, anonymously, as `reference` is chosen by the tenant. Layers fetched
+    /// from the registry are stored in the content store, and leased for the lifetime of the
+    /// container.
+    /// The image must pass the provenance and signature policies of the namespace, as the image
+    /// of the container does.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "Debug"))]
+    pub async fn pull_module<T: Engine>(
+        &self,
+        containerd_id: &str,
+        name: &str,
+        reference: &str,
+    ) -> Result<Vec<WasmLayer>> {
+        let lease = InstanceLease::acquire(
+            LeasesClient::new(self.channel().await?),
+            containerd_id,
+            &self.namespace,
+        )
+        .await?;
+
+        let (manifest, image_digest, in_store) =
+            match self.get_image_manifest_and_digest(reference).await {
+                Ok((manifest, digest)) => (manifest, digest, true),
+                Err(err) => {
+                    log::info!("image {reference} not found in containerd, pulling it: {err}");
+                    let (manifest, digest) =
+                        registry::fetch_manifest(reference, Access::Anonymous).await?;
+                    (manifest, digest, false)
+                }
+            };
+        if in_store {
+            lease.add_content(&image_digest).await?;
+        }
+        self.check_provenance(containerd_id, reference, &image_digest, Access::Anonymous)
+            .await?;
+
+        let signatures = signature::policy_for(&self.namespace);
+        let image_signed = match &signatures {
+            Some(policy) => {
+                signature::image_is_signed(reference, &image_digest, policy, Access::Anonymous)
+                    .await?
+            }
+            None => true,
+        };
+
+        let descriptors: Vec<_> = manifest
+            .layers()
+            .iter()
+            .filter(|x| {
+                let media_type = x.media_type().to_string();
+                media_type == WASM_ARTIFACT_LAYER_MEDIA_TYPE
+                    || media_type.ends_with("+wasm")
+                    || is_wasm_layer(x.media_type(), T::supported_layers_types())
+            })
+            .collect();
+        if descriptors.is_empty() {
+            return Err(ShimError::InvalidArgument(format!(
+                "image {reference} has no wasm layers"
+            )));
+        }
+
+        let mut layers = Vec::with_capacity(descriptors.len());
+        for (i, descriptor) in descriptors.iter().enumerate() {
+            lease.add_content(descriptor.digest()).await?;
+            let layer = match self.read_layer_content(descriptor).await {
+                Ok(layer) => layer,
+                Err(err @ ShimError::FailedPrecondition(_)) => return Err(err),
+                Err(_) => {
+                    let layer =
+                        registry::fetch_blob(reference, descriptor, Access::Anonymous).await?;
+                    verify_digest(descriptor.digest(), &layer)?;
+                    let content = self
+                        .save_content(
+                            layer.clone(),
+                            &descriptor.digest().to_string(),
+                            HashMap::new(),
+                        )
+                        .await?;
+                    let _ = content.release().await;
+                    decompress::decode(descriptor.media_type(), &layer)?
+                }
+            };
+
+            let title = match descriptors.len() {
+                1 => name.to_string(),
+                _ => format!("{name}/{i}"),
+            };
+            if let (Some(policy), false) = (&signatures, image_signed) {
+                signature::verify_module(&title, &layer, policy)?;
+            }
+            let mut config = (*descriptor).clone();
+            let mut annotations = config.annotations().clone().unwrap_or_default();
+            annotations.insert(TITLE_ANNOTATION.to_string(), title);
+            annotations.insert(LAYER_ROLE_ANNOTATION.to_string(), "library".to_string());
+            config.set_annotations(Some(annotations));
+
+            layers.push(WasmLayer { config, layer });
+        }
+
+        log::info!("pulled {} wasm layers from {reference}", layers.len());
+        Ok(layers)
+    }
+
+    /// Returns the digest of the image of the container `containerd_id`.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "Debug"))]
+    pub async fn image_digest(&self, containerd_id: &str) -> Result<String> {
+        let container = self.get_container(containerd_id).await?;
+        let image = self.get_image(&container.image).await?;
+        self.extract_image_content_sha(&image)
+    }
+
+    /// Returns the revision of the image of the container `containerd_id`, from the
+    /// `org.opencontainers.image.revision` annotation of its manifest, or label of its config.
+    /// Configs larger than 1MiB aren't read.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "Debug"))]
+    pub async fn image_revision(&self, containerd_id: &str) -> Result<Option<String>> {
+        let container = self.get_container(containerd_id).await?;
+        let (manifest, _) = self.get_image_manifest_and_digest(&container.image).await?;
+        let annotation = manifest
+            .annotations()
+            .as_ref()
+            .and_then(|a| a.get(IMAGE_REVISION_LABEL));
+        if let Some(revision) = annotation {
+            return Ok(Some(revision.clone()));
+        }
+        let config = self
+            .read_content_at_most(manifest.config().digest(), MAX_IMAGE_CONFIG_BYTES)
+            .await?;
+        Ok(config_label(&config, IMAGE_REVISION_LABEL))
+    }
+
     // load module will query the containerd store to find an image that has an OS of type 'wasm'
     // If found it continues to parse the manifest and return the layers that contains the WASM modules
     // and possibly other configuration layers.
@@ -385,46 +895,108 @@ impl Client {
     ) -> Result<(Vec<oci::WasmLayer>, Platform)> {
         let container = self.get_container(containerd_id.to_string()).await?;
         let (manifest, image_digest) = self.get_image_manifest_and_digest(&container.image).await?;
+        self.verify_provenance(container.clone(), &image_digest)
+            .await?;
+        let signatures = signature::policy_for(&self.namespace);
+        let image_signed = match &signatures {
+            Some(policy) => {
+                signature::image_is_signed(&container.image, &image_digest, policy, Access::Node)
+                    .await?
+            }
+            None => true,
+        };
 
         let image_config_descriptor = manifest.config();
         let image_config = self.read_content(image_config_descriptor.digest()).await?;
         let image_config = image_config.as_slice();
 
-        // the only part we care about here is the platform values
-        let platform: Platform = serde_json::from_slice(image_config)?;
-        let Arch::Wasm = platform.architecture() else {
-            log::info!("manifest is not in WASM OCI image format");
-            return Ok((vec![], platform));
+        let is_artifact = is_wasm_artifact(&manifest);
+        let platform = if is_artifact {
+            log::info!("found manifest with WASM OCI artifact format");
+            artifact_platform(image_config)?
+        } else {
+            // the only part we care about here is the platform values
+            let platform: Platform = serde_json::from_slice(image_config)?;
+            let Arch::Wasm = platform.architecture() else {
+                log::info!("manifest is not in WASM OCI image format");
+                if !image_signed {
+                    return Err(signature::unsigned_image(&container.image));
+                }
+                return Ok((vec![], platform));
+            };
+            log::info!("found manifest with WASM OCI image format");
+            platform
         };
 
-        log::info!("found manifest with WASM OCI image format");
         // This label is unique across runtimes and version of the shim running
         // a precompiled component/module will not work across different runtimes or versions
-        let (can_precompile, precompile_id) = match engine.can_precompile() {
-            Some(precompile_id) => (true, precompile_label(T::name(), &precompile_id)),
+        // precompiled content isn't signed, so only the verified modules run under a signature policy
+        let engine_version = engine.can_precompile().filter(|_| signatures.is_none());
+        let (can_precompile, precompile_id) = match &engine_version {
+            Some(version) => (true, precompile_label(T::name(), version)),
             None => (false, "".to_string()),
         };
 
         let image_info = self.get_info(&image_digest).await?;
-        let mut needs_precompile =
-            can_precompile && !image_info.labels.contains_key(&precompile_id);
-        let configs = manifest
+        let image_precompiled = image_info.labels.contains_key(&precompile_id);
+        let configs: Vec<_> = manifest
             .layers()
             .iter()
-            .filter(|x| is_wasm_layer(x.media_type(), T::supported_layers_types()));
+            .filter(|x| {
+                (is_artifact && x.media_type().to_string() == WASM_ARTIFACT_LAYER_MEDIA_TYPE)
+                    || is_wasm_layer(x.media_type(), T::supported_layers_types())
+            })
+            .collect();
+
+        // keep the content needed to restart the instance until it is deleted
+        let lease = InstanceLease::acquire(
+            LeasesClient::new(self.channel().await?),
+            &containerd_id.to_string(),
+            &self.namespace,
+        )
+        .await?;
+        lease.add_content(&image_digest).await?;
+        lease.add_content(image_config_descriptor.digest()).await?;
+        for config in &configs {
+            lease.add_content(config.digest()).await?;
+        }
 
-        let mut layers = vec![];
-        for original_config in configs {
-            let layer = self
-                .read_wasm_layer(
+        // layers are fetched concurrently, but kept in the order of the manifest
+        let fetched: Vec<(WasmLayer, LoadedFrom)> = futures::stream::iter(&configs)
+            .map(|original_config| {
+                self.read_wasm_layer(
+                    &container.image,
+                    &image_digest,
                     original_config,
                     can_precompile,
                     &precompile_id,
-                    &mut needs_precompile,
+                    &lease,
                 )
-                .await?;
-            layers.push(layer);
+            })
+            .buffered(layer_fetch_parallelism())
+            .try_collect()
+            .await
+            .map_err(|err| unreadable_layers(&container.image, err))?;
+
+        // the layers might have been precompiled as they were pulled, without labeling the image
+        let all_precompiled = fetched
+            .iter()
+            .all(|(_, from)| *from == LoadedFrom::Precompiled);
+        let any_stale = fetched.iter().any(|(_, from)| *from == LoadedFrom::Stale);
+        let needs_precompile =
+            can_precompile && (any_stale || !(image_precompiled || all_precompiled));
+
+        // the signatures are embedded in the original modules, which are the only ones loaded
+        if let (Some(policy), false) = (&signatures, image_signed) {
+            if fetched.is_empty() {
+                return Err(signature::unsigned_image(&container.image));
+            }
+            for (layer, _) in &fetched {
+                let digest = layer.config.digest().to_string();
+                signature::verify_module(&digest, &layer.layer, policy)?;
+            }
         }
+        let layers: Vec<_> = fetched.into_iter().map(|(layer, _)| layer).collect();
 
         if layers.is_empty() {
             log::info!("no WASM layers found in OCI image");
@@ -432,8 +1004,44 @@ impl Client {
         }
 
         if needs_precompile {
-            log::info!("precompiling layers for image: {}", container.image);
-            let compiled_layers = match engine.precompile(&layers) {
+            let cache = engine_version
+                .as_deref()
+                .and_then(|version| ModuleCache::from_config(T::name(), version));
+            let cached = match &cache {
+                Some(cache) => cache.get_all(&layers).await,
+                None => None,
+            };
+            let compiled_layers = match cached {
+                Some(compiled_layers) => {
+                    log::info!(
+                        "using cached precompiled layers for image: {}",
+                        container.image
+                    );
+                    Ok(compiled_layers)
+                }
+                None => {
+                    log::info!("precompiling layers for image: {}", container.image);
+                    let compiling = std::time::Instant::now();
+                    let compiled_layers = CompilePool::for_engine(T::name())
+                        .run({
+                            let engine = engine.clone();
+                            let layers = layers.clone();
+                            move || engine.precompile(&layers)
+                        })
+                        .await
+                        .and_then(|res| res);
+                    Timings::global().record(
+                        &containerd_id.to_string(),
+                        Phase::Compile,
+                        compiling.elapsed(),
+                    );
+                    if let (Some(cache), Ok(compiled_layers)) = (&cache, &compiled_layers) {
+                        cache.put_all(&layers, compiled_layers).await;
+                    }
+                    compiled_layers
+                }
+            };
+            let compiled_layers = match compiled_layers {
                 Ok(compiled_layers) => {
                     if compiled_layers.len() != layers.len() {
                         return Err(ShimError::FailedPrecondition(
@@ -458,32 +1066,10 @@ impl Client {
 
                 let compiled_layer = compiled_layer.as_ref().unwrap();
                 let original_config = &layers[i].config;
-                let labels = HashMap::from([(
-                    format!("{precompile_id}/original"),
-                    original_config.digest().to_string(),
-                )]);
                 let precompiled_content = self
-                    .save_content(compiled_layer.clone(), &precompile_id, labels)
+                    .save_precompiled_layer(&precompile_id, i, original_config, compiled_layer)
                     .await?;
 
-                log::debug!(
-                    "updating original layer {} with compiled layer {}",
-                    original_config.digest(),
-                    precompiled_content.digest
-                );
-                // We add two labels here:
-                // - one with cache key per engine instance
-                // - one with a gc ref flag so it doesn't get cleaned up as long as the original layer exists
-                let mut original_layer = self.get_info(original_config.digest()).await?;
-                original_layer
-                    .labels
-                    .insert(precompile_id.clone(), precompiled_content.digest.clone());
-                original_layer.labels.insert(
-                    format!("containerd.io/gc.ref.content.precompile.{}", i),
-                    precompiled_content.digest.clone(),
-                );
-                self.update_info(original_layer).await?;
-
                 // The original image is considered a root object, by adding a ref to the new compiled content
                 // We tell containerd to not garbage collect the new content until this image is removed from the system
                 // this ensures that we keep the content around after the lease is dropped
@@ -500,6 +1086,7 @@ impl Client {
                     .labels
                     .insert(precompile_id.clone(), "true".to_string());
                 self.update_info(image_content).await?;
+                lease.add_content(&precompiled_content.digest).await?;
 
                 layers_for_runtime.push(WasmLayer {
                     config: original_config.clone(),
@@ -515,54 +1102,261 @@ impl Client {
         Ok((layers, platform))
     }
 
+    // Stores the precompiled content of the layer `original_config`, at index `i` of its image,
+    // and labels the layer with it.
+    async fn save_precompiled_layer(
+        &self,
+        precompile_id: &str,
+        i: usize,
+        original_config: &oci_spec::image::Descriptor,
+        compiled_layer: &[u8],
+    ) -> Result<WriteContent> {
+        let labels = HashMap::from([
+            (
+                format!("{precompile_id}/original"),
+                original_config.digest().to_string(),
+            ),
+            (
+                format!("{precompile_id}/cpu"),
+                cpu_features::fingerprint().to_string(),
+            ),
+        ]);
+        let precompiled_content = self
+            .save_content(compiled_layer.to_vec(), precompile_id, labels)
+            .await?;
+
+        // the content might have been stored by an earlier version, without the CPU label
+        let cpu_label = format!("{precompile_id}/cpu");
+        let mut precompiled_info = self.get_info(&precompiled_content.digest.parse()?).await?;
+        if precompiled_info.labels.get(&cpu_label).map(String::as_str)
+            != Some(cpu_features::fingerprint())
+        {
+            precompiled_info
+                .labels
+                .insert(cpu_label, cpu_features::fingerprint().to_string());
+            self.update_info(precompiled_info).await?;
+        }
+
+        log::debug!(
+            "updating original layer {} with compiled layer {}",
+            original_config.digest(),
+            precompiled_content.digest
+        );
+        // We add two labels here:
+        // - one with cache key per engine instance
+        // - one with a gc ref flag so it doesn't get cleaned up as long as the original layer exists
+        let mut original_layer = self.get_info(original_config.digest()).await?;
+        original_layer.labels.insert(
+            precompile_id.to_string(),
+            precompiled_content.digest.clone(),
+        );
+        original_layer.labels.insert(
+            format!("containerd.io/gc.ref.content.precompile.{}", i),
+            precompiled_content.digest.clone(),
+        );
+        self.update_info(original_layer).await?;
+        Ok(precompiled_content)
+    }
+
+    /// Precompiles `layers`, as they are pulled, and stores their precompiled content, so that
+    /// the first container of their image doesn't compile them.
+    /// The layers must already be in the content store.
+    pub async fn precompile_pulled_layers<T: Engine>(
+        &self,
+        engine: &T,
+        layers: &[WasmLayer],
+    ) -> Result<()> {
+        let Some(version) = engine.can_precompile() else {
+            log::info!("the {} engine doesn't precompile layers", T::name());
+            return Ok(());
+        };
+        let precompile_id = precompile_label(T::name(), &version);
+        let compiled_layers = engine.precompile(layers)?;
+        if compiled_layers.len() != layers.len() {
+            return Err(ShimError::FailedPrecondition(
+                "precompile returned wrong number of layers".to_string(),
+            ));
+        }
+        for (i, (layer, compiled_layer)) in layers.iter().zip(&compiled_layers).enumerate() {
+            let Some(compiled_layer) = compiled_layer else {
+                log::debug!("layer {} isn't precompiled", layer.config.digest());
+                continue;
+            };
+            let precompiled_content = self
+                .save_precompiled_layer(&precompile_id, i, &layer.config, compiled_layer)
+                .await?;
+            log::info!(
+                "stored the precompiled content {} of layer {}",
+                precompiled_content.digest,
+                layer.config.digest()
+            );
+            // the label of the original layer keeps the content from being garbage collected
+            let _ = precompiled_content.release().await;
+        }
+        Ok(())
+    }
+
+    // Returns true if the precompiled content was compiled for the CPU of the host.
+    async fn is_compatible(&self, precompiled: &Digest, precompile_id: &str) -> bool {
+        let info = match self.get_info(precompiled).await {
+            Ok(info) => info,
+            Err(err) => {
+                log::debug!("no content info for precompiled layer {precompiled}: {err}");
+                return false;
+            }
+        };
+        info.labels
+            .get(&format!("{precompile_id}/cpu"))
+            .is_some_and(|compiled_on| cpu_features::is_compatible(compiled_on))
+    }
+
     #[cfg_attr(feature = "tracing", tracing::instrument(level = "Debug"))]
     async fn read_wasm_layer(
         &self,
+        image: &str,
+        image_digest: &Digest,
         original_config: &oci_spec::image::Descriptor,
         can_precompile: bool,
         precompile_id: &String,
-        needs_precompile: &mut bool,
-    ) -> std::prelude::v1::Result<WasmLayer, ShimError> {
+        lease: &InstanceLease,
+    ) -> Result<(WasmLayer, LoadedFrom)> {
         let mut digest_to_load = original_config.digest().clone();
+        let mut needs_recompile = false;
         if can_precompile {
-            let info = self.get_info(&digest_to_load).await?;
-            if let Some(label) = info.labels.get(precompile_id) {
-                // Safe to unwrap here since we already checked for the label's existence
-                digest_to_load = label.parse()?;
-                log::info!(
-                    "layer {} has pre-compiled content: {} ",
-                    info.digest,
-                    &digest_to_load
-                );
+            // the layer might be missing from the content store, in which case it has no precompiled content
+            let labels = match self.get_info(&digest_to_load).await {
+                Ok(info) => info.labels,
+                Err(err) => {
+                    log::debug!("no content info for layer {digest_to_load}: {err}");
+                    HashMap::new()
+                }
+            };
+            if let Some(label) = labels.get(precompile_id) {
+                let precompiled: Digest = label.parse()?;
+                if self.is_compatible(&precompiled, precompile_id).await {
+                    log::info!(
+                        "layer {} has pre-compiled content: {} ",
+                        original_config.digest(),
+                        &precompiled
+                    );
+                    digest_to_load = precompiled;
+                } else {
+                    sampled!(
+                        log::Level::Warn,
+                        "pre-compiled content {precompiled} of layer {} was compiled for another CPU, marking for recompile",
+                        original_config.digest(),
+                    );
+                    needs_recompile = true;
+                }
             }
         }
         log::debug!("loading digest: {} ", &digest_to_load);
-        let res = self
-            .read_content(&digest_to_load)
-            .await
-            .map(|module| WasmLayer {
-                config: original_config.clone(),
-                layer: module,
-            });
+        let res = if digest_to_load == *original_config.digest() {
+            self.read_layer(image, image_digest, original_config).await
+        } else {
+            match lease.add_content(&digest_to_load).await {
+                Ok(()) => self
+                    .read_content(&digest_to_load)
+                    .await
+                    .and_then(|module| verify_digest(&digest_to_load, &module).map(|_| module)),
+                Err(err) => Err(err.into()),
+            }
+        };
+        let res = res.map(|module| WasmLayer {
+            config: original_config.clone(),
+            layer: module,
+        });
 
         match res {
-            Ok(res) => Ok(res),
+            Ok(res) if needs_recompile => Ok((res, LoadedFrom::Stale)),
+            Ok(res) if digest_to_load == *original_config.digest() => {
+                Ok((res, LoadedFrom::Original))
+            }
+            Ok(res) => Ok((res, LoadedFrom::Precompiled)),
             Err(err) if digest_to_load == *original_config.digest() => Err(err),
             Err(err) => {
                 log::error!("failed to load precompiled layer: {err}");
                 log::error!("falling back to original layer and marking for recompile");
-                *needs_precompile = can_precompile; // only mark for recompile if engine is capable
-                self.read_content(original_config.digest())
-                    .await
-                    .map(|module| WasmLayer {
+                let layer = self
+                    .read_layer(image, image_digest, original_config)
+                    .await?;
+                // only mark for recompile if engine is capable
+                Ok((
+                    WasmLayer {
                         config: original_config.clone(),
-                        layer: module,
-                    })
+                        layer,
+                    },
+                    if can_precompile {
+                        LoadedFrom::Stale
+                    } else {
+                        LoadedFrom::Original
+                    },
+                ))
             }
         }
     }
 }
 
+// Where the content of a wasm layer was loaded from.
+#[derive(Debug, PartialEq, Eq)]
+enum LoadedFrom {
+    // the layer itself
+    Original,
+    // its precompiled content
+    Precompiled,
+    // the layer itself, its precompiled content can't be used and must be compiled again
+    Stale,
+}
+
+/// Parses the value of the `runwasi.io/pull-modules` annotation,
+/// returning the names and references of the modules.
+pub(crate) fn parse_pull_modules(value: &str) -> Result<Vec<(String, String)>> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| match entry.split_once('=') {
+            Some((name, reference)) if !name.trim().is_empty() && !reference.trim().is_empty() => {
+                Ok((name.trim().to_string(), reference.trim().to_string()))
+            }
+            _ => Err(ShimError::InvalidArgument(format!(
+                "invalid {PULL_MODULES_ANNOTATION} entry {entry:?}: expected name=reference"
+            ))),
+        })
+        .collect()
+}
+
+// Labels are in the `config` object of the image config:
+// https://github.com/opencontainers/image-spec/blob/v1.1.0/config.md#properties
+fn config_label(config: &[u8], label: &str) -> Option<String> {
+    let config: serde_json::Value = serde_json::from_slice(config).ok()?;
+    config
+        .get("config")?
+        .get("Labels")?
+        .get(label)?
+        .as_str()
+        .map(ToString::to_string)
+}
+
+// Errors reading the wasm layers of an image fail the creation of the container, rather than
+// falling back to the files of its rootfs, as the image is known to have wasm layers.
+fn unreadable_layers(image: &str, err: ShimError) -> ShimError {
+    match err {
+        err @ (ShimError::PermissionDenied(_) | ShimError::FailedPrecondition(_)) => err,
+        err => ShimError::FailedPrecondition(format!(
+            "failed to read the wasm layers of image {image}: {err}"
+        )),
+    }
+}
+
+fn layer_fetch_parallelism() -> usize {
+    std::env::var(LAYER_FETCH_PARALLELISM_ENV)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_LAYER_FETCH_PARALLELISM)
+        .max(1)
+}
+
 fn precompile_label(name: &str, version: &str) -> String {
     format!("{}/{}/{}", PRECOMPILE_PREFIX, name, version)
 }
@@ -577,6 +1371,31 @@ fn is_wasm_layer(media_type: &MediaType, supported_layer_types: &[&str]) -> bool
     supported
 }
 
+// Images following the wasm OCI artifact layout are identified by their artifact type,
+// or by the media type of their config when the artifact type isn't set.
+fn is_wasm_artifact(manifest: &ImageManifest) -> bool {
+    let artifact_type = manifest.artifact_type().as_ref().map(ToString::to_string);
+    artifact_type.as_deref() == Some(WASM_ARTIFACT_TYPE)
+        || manifest.config().media_type().to_string() == WASM_CONFIG_MEDIA_TYPE
+}
+
+// The config of a wasm artifact only has the `os` we care about (`wasip1` or `wasip2`),
+// and can be the empty `{}` config when the manifest sets an artifact type.
+fn artifact_platform(config: &[u8]) -> Result<Platform> {
+    #[derive(serde::Deserialize, Default)]
+    #[serde(default)]
+    struct WasmConfig {
+        os: Option<String>,
+    }
+
+    let config: WasmConfig = serde_json::from_slice(config).unwrap_or_default();
+    let os = config.os.unwrap_or_else(|| "wasip1".to_string());
+    Ok(PlatformBuilder::default()
+        .architecture(Arch::Wasm)
+        .os(Os::from(os.as_str()))
+        .build()?)
+}
+
 async fn send_message(
     request: WriteContentRequest,
     response_stream: &mut Streaming<WriteContentResponse>,
@@ -611,6 +1430,111 @@ mod tests {
     use crate::testing::oci_helpers::ImageContent;
     use crate::testing::{oci_helpers, TEST_NAMESPACE};
 
+    fn manifest(artifact_type: Option<&str>, config_media_type: &str) -> ImageManifest {
+        let mut manifest = serde_json::json!({
+            "schemaVersion": 2,
+            "mediaType": "application/vnd.oci.image.manifest.v1+json",
+            "config": {
+                "mediaType": config_media_type,
+                "digest": "sha256:44136fa355b3678a1146ad16f7e8649e94fb4fc21fe77e8310c060f61caaff8a",
+                "size": 2
+            },
+            "layers": []
+        });
+        if let Some(artifact_type) = artifact_type {
+            manifest["artifactType"] = artifact_type.into();
+        }
+        serde_json::from_value(manifest).unwrap()
+    }
+
+    #[test]
+    fn test_is_wasm_artifact() {
+        assert!(is_wasm_artifact(&manifest(
+            Some(WASM_ARTIFACT_TYPE),
+            "application/vnd.oci.empty.v1+json"
+        )));
+        assert!(is_wasm_artifact(&manifest(None, WASM_CONFIG_MEDIA_TYPE)));
+        assert!(!is_wasm_artifact(&manifest(
+            None,
+            "application/vnd.oci.image.config.v1+json"
+        )));
+    }
+
+    #[test]
+    fn test_artifact_platform() -> Result<()> {
+        let platform = artifact_platform(br#"{ "architecture": "wasm", "os": "wasip2" }"#)?;
+        assert_eq!(platform.architecture(), &Arch::Wasm);
+        assert_eq!(platform.os(), &Os::from("wasip2"));
+
+        let platform = artifact_platform(b"{}")?;
+        assert_eq!(platform.architecture(), &Arch::Wasm);
+        assert_eq!(platform.os(), &Os::from("wasip1"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_deleted_from_event() {
+        let image = ImageDelete {
+            name: "docker.io/library/app:latest".to_string(),
+        };
+        assert_eq!(
+            Deleted::from_event("/images/delete", &image.encode_to_vec()),
+            Some(Deleted::Image("docker.io/library/app:latest".to_string()))
+        );
+
+        let content = ContentDelete {
+            digest: "sha256:abc".to_string(),
+        };
+        assert_eq!(
+            Deleted::from_event("/content/delete", &content.encode_to_vec()),
+            Some(Deleted::Content("sha256:abc".to_string()))
+        );
+        assert_eq!(
+            Deleted::from_event("/images/create", &image.encode_to_vec()),
+            None
+        );
+    }
+
+    #[test]
+    fn test_unreadable_layers() {
+        let err = unreadable_layers("app", ShimError::Containerd("unavailable".to_string()));
+        assert!(matches!(err, ShimError::FailedPrecondition(_)), "{err}");
+
+        let err = unreadable_layers("app", ShimError::PermissionDenied("unsigned".to_string()));
+        assert!(matches!(err, ShimError::PermissionDenied(_)), "{err}");
+    }
+
+    #[test]
+    fn test_parse_pull_modules() -> Result<()> {
+        let modules =
+            parse_pull_modules("auth=ghcr.io/org/auth:v1, log = ghcr.io/org/log@sha256:abc,")?;
+        assert_eq!(
+            modules,
+            [
+                ("auth".to_string(), "ghcr.io/org/auth:v1".to_string()),
+                ("log".to_string(), "ghcr.io/org/log@sha256:abc".to_string()),
+            ]
+        );
+        assert!(parse_pull_modules("ghcr.io/org/auth:v1").is_err());
+        assert!(parse_pull_modules("=ghcr.io/org/auth:v1").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_config_label() {
+        let config =
+            br#"{ "config": { "Labels": { "org.opencontainers.image.revision": "abc123" } } }"#;
+        assert_eq!(
+            config_label(config, IMAGE_REVISION_LABEL).as_deref(),
+            Some("abc123")
+        );
+        assert_eq!(
+            config_label(br#"{ "config": {} }"#, IMAGE_REVISION_LABEL),
+            None
+        );
+        assert_eq!(config_label(b"not json", IMAGE_REVISION_LABEL), None);
+    }
+
     #[tokio::test(flavor = "current_thread")]
     async fn test_save_content() {
         let path = PathBuf::from("/run/containerd/containerd.sock");
@@ -673,6 +1597,44 @@ mod tests {
         assert_eq!(engine.precompile_called.load(Ordering::SeqCst), 0);
     }
 
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_instance_lease() {
+        let path = PathBuf::from("/run/containerd/containerd.sock");
+        let path = path.to_str().unwrap();
+        let client = Client::connect(path, TEST_NAMESPACE).await.unwrap();
+
+        let fake_bytes = generate_content("original", WASM_LAYER_MEDIA_TYPE);
+        let (_, container_name, _cleanup) = generate_test_container(None, &[&fake_bytes]);
+        let engine = FakePrecomiplerEngine::new(None);
+
+        let list_leases = || async {
+            let req = containerd_client::services::v1::ListRequest {
+                filters: vec![format!("id==runwasi-instance-{container_name}")],
+            };
+            LeasesClient::new(client.channel().await.unwrap())
+                .list(with_namespace!(req, TEST_NAMESPACE))
+                .await
+                .unwrap()
+                .into_inner()
+                .leases
+        };
+
+        client.load_modules(&container_name, &engine).await.unwrap();
+        assert_eq!(list_leases().await.len(), 1);
+
+        client
+            .release_instance_lease(&container_name)
+            .await
+            .unwrap();
+        assert!(list_leases().await.is_empty());
+
+        // releasing a lease that doesn't exist is not an error
+        client
+            .release_instance_lease(&container_name)
+            .await
+            .unwrap();
+    }
+
     #[tokio::test(flavor = "current_thread")]
     async fn test_layers_are_precompiled_once() {
         let path = PathBuf::from("/run/containerd/containerd.sock");
@@ -757,6 +1719,15 @@ mod tests {
             actual_digest.to_string(),
             format!("sha256:{}", &digest(fake_precompiled_bytes.bytes.clone()))
         );
+
+        let precompiled_info = client
+            .get_info(&actual_digest.parse().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(
+            precompiled_info.labels[&format!("{expected_id}/cpu")],
+            cpu_features::fingerprint()
+        );
     }
 
     #[tokio::test(flavor = "current_thread")]
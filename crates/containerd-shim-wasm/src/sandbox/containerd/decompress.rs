@@ -0,0 +1,131 @@
+//! Decompression of compressed wasm layers.
+//!
+//! Layers with a `+gzip` or `+zstd` media type suffix are decompressed as their chunks are read
+//! from the content store, so that the compressed layer is never fully buffered in memory.
+//! `zstd:chunked` layers use the `+zstd` media type too; their chunk metadata is stored in
+//! skippable frames, which are ignored when decompressing the whole layer.
+
+use std::io::{self, Write};
+
+use flate2::write::GzDecoder;
+use oci_spec::image::MediaType;
+use zstd::stream::write::Decoder as ZstdDecoder;
+
+/// Incrementally decodes the content of a layer based on its media type.
+pub(crate) enum LayerDecoder {
+    Plain(Vec<u8>),
+    Gzip(GzDecoder<Vec<u8>>),
+    Zstd(ZstdDecoder<'static, Vec<u8>>),
+}
+
+impl LayerDecoder {
+    pub fn new(media_type: &MediaType) -> io::Result<Self> {
+        let decoder = match media_type {
+            MediaType::ImageLayerGzip => Self::Gzip(GzDecoder::new(vec![])),
+            MediaType::ImageLayerZstd => Self::Zstd(ZstdDecoder::new(vec![])?),
+            media_type => match media_type.to_string() {
+                media_type if media_type.ends_with("+gzip") => Self::Gzip(GzDecoder::new(vec![])),
+                media_type if media_type.ends_with("+zstd") => {
+                    Self::Zstd(ZstdDecoder::new(vec![])?)
+                }
+                _ => Self::Plain(vec![]),
+            },
+        };
+        Ok(decoder)
+    }
+
+    /// Decodes the next chunk of the layer.
+    pub fn write(&mut self, chunk: &[u8]) -> io::Result<()> {
+        match self {
+            Self::Plain(data) => data.extend_from_slice(chunk),
+            Self::Gzip(decoder) => decoder.write_all(chunk)?,
+            Self::Zstd(decoder) => decoder.write_all(chunk)?,
+        }
+        Ok(())
+    }
+
+    /// Returns the decoded layer.
+    pub fn finish(self) -> io::Result<Vec<u8>> {
+        match self {
+            Self::Plain(data) => Ok(data),
+            Self::Gzip(decoder) => decoder.finish(),
+            Self::Zstd(mut decoder) => {
+                decoder.flush()?;
+                Ok(decoder.into_inner())
+            }
+        }
+    }
+}
+
+/// Decodes a whole layer at once.
+pub(crate) fn decode(media_type: &MediaType, data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut decoder = LayerDecoder::new(media_type)?;
+    decoder.write(data)?;
+    decoder.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    use super::*;
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(vec![], Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn test_plain_layer() -> io::Result<()> {
+        let media_type = MediaType::Other("application/wasm".to_string());
+        assert_eq!(decode(&media_type, b"\0asm")?, b"\0asm");
+        Ok(())
+    }
+
+    #[test]
+    fn test_gzip_layer_in_chunks() -> io::Result<()> {
+        let module = b"\0asm\x01\0\0\0".repeat(1024);
+        let compressed = gzip(&module);
+
+        let media_type = MediaType::Other("application/wasm+gzip".to_string());
+        let mut decoder = LayerDecoder::new(&media_type)?;
+        for chunk in compressed.chunks(7) {
+            decoder.write(chunk)?;
+        }
+        assert_eq!(decoder.finish()?, module);
+        Ok(())
+    }
+
+    #[test]
+    fn test_zstd_layer_in_chunks() -> io::Result<()> {
+        let module = b"\0asm\x01\0\0\0".repeat(1024);
+        let compressed = zstd::encode_all(&module[..], 0)?;
+
+        let mut decoder = LayerDecoder::new(&MediaType::ImageLayerZstd)?;
+        for chunk in compressed.chunks(7) {
+            decoder.write(chunk)?;
+        }
+        assert_eq!(decoder.finish()?, module);
+        Ok(())
+    }
+
+    #[test]
+    fn test_zstd_chunked_layer() -> io::Result<()> {
+        let module = b"\0asm\x01\0\0\0".repeat(1024);
+        let (head, tail) = module.split_at(4000);
+
+        // zstd:chunked layers are a sequence of frames, followed by a skippable frame
+        // with the table of contents
+        let mut compressed = zstd::encode_all(head, 0)?;
+        compressed.extend(zstd::encode_all(tail, 0)?);
+        compressed.extend(0x184D2A50u32.to_le_bytes());
+        compressed.extend(4u32.to_le_bytes());
+        compressed.extend(b"toc!");
+
+        let media_type = MediaType::Other("application/wasm+zstd".to_string());
+        assert_eq!(decode(&media_type, &compressed)?, module);
+        Ok(())
+    }
+}
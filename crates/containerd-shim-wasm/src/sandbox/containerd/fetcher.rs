@@ -0,0 +1,33 @@
+use futures::future::LocalBoxFuture;
+use oci_spec::image::Platform;
+
+use super::Client;
+use crate::container::Engine;
+use crate::sandbox::fetcher::{FetchRequest, ModuleFetcher};
+use crate::sandbox::oci::WasmLayer;
+
+/// Reads modules from the wasm layers of the container image, in the containerd content store.
+/// Layers are precompiled by `engine` when it supports it.
+pub(crate) struct ContainerdFetcher<E: Engine> {
+    client: Client,
+    engine: E,
+}
+
+impl<E: Engine> ContainerdFetcher<E> {
+    pub fn new(client: Client, engine: E) -> Self {
+        Self { client, engine }
+    }
+
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+}
+
+impl<E: Engine> ModuleFetcher for ContainerdFetcher<E> {
+    fn fetch<'a>(
+        &'a self,
+        req: &'a FetchRequest,
+    ) -> LocalBoxFuture<'a, anyhow::Result<(Vec<WasmLayer>, Platform)>> {
+        Box::pin(async move { Ok(self.client.load_modules(&req.id, &self.engine).await?) })
+    }
+}
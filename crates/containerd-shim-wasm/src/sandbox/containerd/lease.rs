@@ -4,10 +4,12 @@ use std::mem::ManuallyDrop;
 
 use anyhow::Context as _;
 use containerd_client::services::v1::leases_client::LeasesClient;
-use containerd_client::services::v1::DeleteRequest;
+use containerd_client::services::v1::{AddResourceRequest, CreateRequest, DeleteRequest, Resource};
 use containerd_client::tonic::transport::Channel;
 use containerd_client::{tonic, with_namespace};
-use tonic::Request;
+use tonic::{Code, Request};
+
+use crate::sandbox::log_sampling::sampled;
 
 // Adds lease info to grpc header
 // https://github.com/containerd/containerd/blob/8459273f806e068e1a6bacfaf1355bbbad738d5e/docs/garbage-collection.md#using-grpc
@@ -77,8 +79,90 @@ impl Drop for LeaseGuard {
         tokio::spawn(async move {
             match inner.release().await {
                 Ok(()) => log::info!("removed lease"),
-                Err(err) => log::warn!("error removing lease: {err}"),
+                Err(err) => sampled!(log::Level::Warn, "error removing lease: {err}"),
             }
         });
     }
 }
+
+/// A lease on the content used by an instance (image, wasm layers and precompiled layers).
+///
+/// Unlike a [`LeaseGuard`], it is not released when dropped, but when the instance is deleted,
+/// so that a garbage collection while the instance exists can't remove content that is needed
+/// to restart it. The lease id is derived from the container id so that it can be released
+/// by a shim that didn't create it.
+#[derive(Debug)]
+pub(crate) struct InstanceLease {
+    client: LeasesClient<Channel>,
+    id: String,
+    namespace: String,
+}
+
+impl InstanceLease {
+    /// Creates the lease of the container `containerd_id`, or reuses it if it already exists.
+    pub async fn acquire(
+        mut client: LeasesClient<Channel>,
+        containerd_id: &str,
+        namespace: impl Into<String>,
+    ) -> anyhow::Result<Self> {
+        let id = instance_lease_id(containerd_id);
+        let namespace = namespace.into();
+        let req = CreateRequest {
+            id: id.clone(),
+            ..Default::default()
+        };
+        match client.create(with_namespace!(req, namespace)).await {
+            Ok(_) => log::debug!("created lease {id}"),
+            Err(err) if err.code() == Code::AlreadyExists => log::debug!("reusing lease {id}"),
+            Err(err) => return Err(err).context("Failed to create instance lease"),
+        }
+        Ok(Self {
+            client,
+            id,
+            namespace,
+        })
+    }
+
+    /// Adds the content with `digest` to the lease.
+    pub async fn add_content(&self, digest: impl ToString) -> anyhow::Result<()> {
+        let req = AddResourceRequest {
+            id: self.id.clone(),
+            resource: Some(Resource {
+                id: digest.to_string(),
+                r#type: "content".to_string(),
+            }),
+        };
+        self.client
+            .clone()
+            .add_resource(with_namespace!(req, self.namespace))
+            .await
+            .context("Failed to add content to instance lease")?;
+        Ok(())
+    }
+
+    /// Releases the lease of the container `containerd_id`, if any.
+    pub async fn release(
+        mut client: LeasesClient<Channel>,
+        containerd_id: &str,
+        namespace: impl AsRef<str>,
+    ) -> anyhow::Result<()> {
+        let id = instance_lease_id(containerd_id);
+        let req = DeleteRequest {
+            id: id.clone(),
+            sync: false,
+        };
+        match client
+            .delete(with_namespace!(req, namespace.as_ref()))
+            .await
+        {
+            Ok(_) => log::info!("removed lease {id}"),
+            Err(err) if err.code() == Code::NotFound => {}
+            Err(err) => return Err(err).context("Failed to remove instance lease"),
+        }
+        Ok(())
+    }
+}
+
+fn instance_lease_id(containerd_id: &str) -> String {
+    format!("runwasi-instance-{containerd_id}")
+}
@@ -1,6 +1,15 @@
 #![cfg(unix)]
 
 mod client;
+mod decompress;
+mod fetcher;
 mod lease;
+mod module_cache;
+mod provenance;
+mod registry;
+mod signature;
+mod verify;
 
-pub(crate) use client::Client;
+pub(crate) use client::{parse_pull_modules, Client, Deleted, PULL_MODULES_ANNOTATION};
+pub(crate) use fetcher::ContainerdFetcher;
+pub(crate) use signature::{is_required as signatures_required, verify_fetched};
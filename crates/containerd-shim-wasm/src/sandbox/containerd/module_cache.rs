@@ -0,0 +1,292 @@
+//! Cache of precompiled modules shared by the nodes of a cluster.
+//!
+//! When `module_cache` is set in the runtime configuration, the precompiled artifact of a layer
+//! is looked up in an HTTP cache before the layer is compiled, and uploaded once it is compiled,
+//! so that a module is only compiled once per cluster:
+//!
+//! ```text
+//! GET {url}/{engine}/{engine version}/{cpu features}/{algorithm}/{layer digest}
+//! PUT {url}/{engine}/{engine version}/{cpu features}/{algorithm}/{layer digest}
+//! ```
+//!
+//! Layers the engine doesn't compile (e.g., data layers) are cached with an empty artifact.
+//! The cache is best effort: failed requests are logged, and the layers are compiled locally.
+//!
+//! The shim runs the native code of the artifacts, so they are authenticated with an
+//! HMAC-SHA256 keyed with the `key_path` file of the configuration, shared by the nodes of the
+//! cluster and by nobody else. The MAC covers the engine, its version, the CPU features, the
+//! layer digest and the artifact, and prefixes the artifact in the cache. Artifacts with an
+//! invalid MAC are ignored, and the layers compiled locally. Uploads are disabled by default.
+
+use std::time::Duration;
+
+use anyhow::Context as _;
+use oci_spec::image::Digest;
+use reqwest::StatusCode;
+use ring::hmac;
+use url::Url;
+
+use crate::sandbox::config::{ModuleCacheConfig, RuntimeConfig};
+use crate::sandbox::cpu_features;
+use crate::sandbox::log_sampling::sampled;
+use crate::sandbox::oci::WasmLayer;
+
+/// Client of the module cache, for the precompiled artifacts of an engine.
+pub(crate) struct ModuleCache {
+    client: reqwest::Client,
+    url: Url,
+    key: hmac::Key,
+    upload: bool,
+    engine: String,
+    version: String,
+}
+
+impl ModuleCache {
+    /// Returns the client of the module cache configured in the runtime configuration, if any.
+    pub fn from_config(engine: &str, version: &str) -> Option<Self> {
+        let config = RuntimeConfig::current().module_cache.clone()?;
+        read_key(&config)
+            .and_then(|key| Self::new(&config, key, engine, version))
+            .inspect_err(|err| log::warn!("module cache disabled: {err:#}"))
+            .ok()
+    }
+
+    fn new(
+        config: &ModuleCacheConfig,
+        key: hmac::Key,
+        engine: &str,
+        version: &str,
+    ) -> anyhow::Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.timeout_secs))
+            .build()?;
+        let url = Url::parse(&config.url)?;
+        if url.cannot_be_a_base() {
+            anyhow::bail!("invalid module cache URL {url}");
+        }
+        Ok(Self {
+            client,
+            url,
+            key,
+            upload: config.upload,
+            engine: engine.to_string(),
+            version: version.to_string(),
+        })
+    }
+
+    fn artifact_url(&self, digest: &Digest) -> Url {
+        let algorithm = digest.algorithm().to_string();
+        let mut url = self.url.clone();
+        url.path_segments_mut()
+            .expect("the URL is checked to be a base in `new`")
+            .pop_if_empty()
+            .extend([
+                self.engine.as_str(),
+                self.version.as_str(),
+                cpu_features::fingerprint(),
+                algorithm.as_str(),
+                digest.digest(),
+            ]);
+        url
+    }
+
+    // The data authenticated by the MAC of the artifact of the layer `digest`.
+    fn authenticated_data(&self, digest: &Digest, artifact: &[u8]) -> Vec<u8> {
+        let context = format!(
+            "{}\0{}\0{}\0{digest}\0",
+            self.engine,
+            self.version,
+            cpu_features::fingerprint()
+        );
+        [context.as_bytes(), artifact].concat()
+    }
+
+    fn seal(&self, digest: &Digest, artifact: &[u8]) -> Vec<u8> {
+        let tag = hmac::sign(&self.key, &self.authenticated_data(digest, artifact));
+        [tag.as_ref(), artifact].concat()
+    }
+
+    // Returns the artifact of a cache entry, if its MAC is valid.
+    fn open<'a>(&self, digest: &Digest, entry: &'a [u8]) -> Option<&'a [u8]> {
+        let tag_len = hmac::HMAC_SHA256.digest_algorithm().output_len();
+        let (tag, artifact) = entry.split_at_checked(tag_len)?;
+        hmac::verify(&self.key, &self.authenticated_data(digest, artifact), tag).ok()?;
+        Some(artifact)
+    }
+
+    /// Returns the precompiled artifacts of all the `layers`, or None unless they are all cached.
+    pub async fn get_all(&self, layers: &[WasmLayer]) -> Option<Vec<Option<Vec<u8>>>> {
+        let mut artifacts = Vec::with_capacity(layers.len());
+        for layer in layers {
+            artifacts.push(self.get(layer.config.digest()).await?);
+        }
+        Some(artifacts)
+    }
+
+    // Returns the cached artifact of the layer `digest`, which is None if the layer isn't compiled.
+    async fn get(&self, digest: &Digest) -> Option<Option<Vec<u8>>> {
+        let url = self.artifact_url(digest);
+        let res = self
+            .client
+            .get(url.clone())
+            .send()
+            .await
+            .and_then(|res| res.error_for_status());
+        let artifact = match res {
+            Ok(res) => res.bytes().await,
+            Err(err) if err.status() == Some(StatusCode::NOT_FOUND) => {
+                log::debug!("module cache miss for {digest}");
+                return None;
+            }
+            Err(err) => Err(err),
+        };
+        match artifact {
+            Ok(entry) => {
+                let Some(artifact) = self.open(digest, &entry) else {
+                    sampled!(
+                        log::Level::Warn,
+                        "ignoring {url} from the module cache, its MAC is invalid"
+                    );
+                    return None;
+                };
+                log::info!("module cache hit for {digest}");
+                Some(Some(artifact.to_vec()).filter(|a| !a.is_empty()))
+            }
+            Err(err) => {
+                sampled!(
+                    log::Level::Warn,
+                    "failed to get {url} from the module cache: {err}"
+                );
+                None
+            }
+        }
+    }
+
+    /// Uploads the artifacts compiled for `layers`, unless uploads are disabled.
+    pub async fn put_all(&self, layers: &[WasmLayer], compiled: &[Option<Vec<u8>>]) {
+        if !self.upload {
+            return;
+        }
+        for (layer, artifact) in layers.iter().zip(compiled) {
+            let digest = layer.config.digest();
+            let entry = self.seal(digest, artifact.as_deref().unwrap_or_default());
+            self.put(digest, entry).await;
+        }
+    }
+
+    async fn put(&self, digest: &Digest, entry: Vec<u8>) {
+        let url = self.artifact_url(digest);
+        let res = self
+            .client
+            .put(url.clone())
+            .body(entry)
+            .send()
+            .await
+            .and_then(|res| res.error_for_status());
+        match res {
+            Ok(_) => log::info!("uploaded the precompiled {digest} to the module cache"),
+            Err(err) => sampled!(
+                log::Level::Warn,
+                "failed to put {url} in the module cache: {err}"
+            ),
+        }
+    }
+}
+
+/// Minimum size of the MAC key, the output size of SHA-256.
+const MIN_KEY_LEN: usize = 32;
+
+fn read_key(config: &ModuleCacheConfig) -> anyhow::Result<hmac::Key> {
+    let key = std::fs::read(&config.key_path)
+        .with_context(|| format!("failed to read the key {:?}", config.key_path))?;
+    if key.len() < MIN_KEY_LEN {
+        anyhow::bail!(
+            "the key {:?} must be at least {MIN_KEY_LEN} bytes",
+            config.key_path
+        );
+    }
+    Ok(hmac::Key::new(hmac::HMAC_SHA256, &key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cache(key: &[u8], engine: &str, version: &str) -> anyhow::Result<ModuleCache> {
+        let config = ModuleCacheConfig {
+            url: "https://cache.example.com/wasm".to_string(),
+            ..Default::default()
+        };
+        let key = hmac::Key::new(hmac::HMAC_SHA256, key);
+        ModuleCache::new(&config, key, engine, version)
+    }
+
+    #[test]
+    fn test_artifact_url() -> anyhow::Result<()> {
+        let digest: Digest = format!("sha256:{}", "a".repeat(64)).parse()?;
+        for base in [
+            "https://cache.example.com/wasm",
+            "https://cache.example.com/wasm/",
+        ] {
+            let config = ModuleCacheConfig {
+                url: base.to_string(),
+                ..Default::default()
+            };
+            let key = hmac::Key::new(hmac::HMAC_SHA256, &[0; MIN_KEY_LEN]);
+            let cache = ModuleCache::new(&config, key, "wasmtime", "v29.0.1/abc")?;
+            let url = cache.artifact_url(&digest);
+            let segments: Vec<_> = url.path_segments().unwrap().collect();
+            assert_eq!(segments[..3], ["wasm", "wasmtime", "v29.0.1%2Fabc"]);
+            assert!(segments[3].starts_with(std::env::consts::ARCH));
+            assert_eq!(segments[4..], ["sha256", "a".repeat(64).as_str()]);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_artifacts_are_authenticated() -> anyhow::Result<()> {
+        let digest: Digest = format!("sha256:{}", "a".repeat(64)).parse()?;
+        let other: Digest = format!("sha256:{}", "b".repeat(64)).parse()?;
+        let cache = cache(&[1; MIN_KEY_LEN], "wasmtime", "29.0.1")?;
+
+        let entry = cache.seal(&digest, b"native code");
+        assert_eq!(cache.open(&digest, &entry), Some(&b"native code"[..]));
+        let entry = cache.seal(&digest, b"");
+        assert_eq!(cache.open(&digest, &entry), Some(&b""[..]));
+
+        let entry = cache.seal(&digest, b"native code");
+        // the MAC is bound to the layer, the engine, its version, and the key
+        assert_eq!(cache.open(&other, &entry), None);
+        let wasmer = self::cache(&[1; MIN_KEY_LEN], "wasmer", "29.0.1")?;
+        assert_eq!(wasmer.open(&digest, &entry), None);
+        let newer = self::cache(&[1; MIN_KEY_LEN], "wasmtime", "30.0.0")?;
+        assert_eq!(newer.open(&digest, &entry), None);
+        let other_key = self::cache(&[2; MIN_KEY_LEN], "wasmtime", "29.0.1")?;
+        assert_eq!(other_key.open(&digest, &entry), None);
+
+        // tampered and unauthenticated artifacts are refused
+        let mut tampered = entry.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert_eq!(cache.open(&digest, &tampered), None);
+        assert_eq!(cache.open(&digest, b"native code"), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_short_key_is_rejected() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let key_path = dir.path().join("key");
+        std::fs::write(&key_path, b"short")?;
+        let mut config = ModuleCacheConfig {
+            key_path: key_path.clone(),
+            ..Default::default()
+        };
+        assert!(read_key(&config).is_err());
+
+        std::fs::write(&key_path, [7; MIN_KEY_LEN])?;
+        assert!(read_key(&config).is_ok());
+        config.key_path = dir.path().join("missing");
+        assert!(read_key(&config).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,285 @@
+//! Verification of SLSA provenance attestations.
+//!
+//! Attestations are in-toto statements wrapped in a DSSE envelope, attached to the image
+//! manifest as OCI referrers. They are fetched from the registry the image was pulled from.
+//!
+//! An attestation is only trusted if its envelope is signed with one of the keys of the
+//! policy, and its content matches the policy. Bare statements and unsigned envelopes are
+//! ignored.
+
+use std::collections::HashMap;
+
+use base64::prelude::BASE64_STANDARD;
+use base64::Engine as _;
+use oci_client::Reference;
+use serde::Deserialize;
+use serde_json::Value;
+
+use super::registry::{self, Access};
+use super::signature::VerifyingKey;
+use crate::sandbox::config::ProvenancePolicy;
+use crate::sandbox::error::{Error as ShimError, Result};
+
+const IN_TOTO_ARTIFACT_TYPE: &str = "application/vnd.in-toto+json";
+const SLSA_PREDICATE_PREFIX: &str = "https://slsa.dev/provenance/";
+
+/// Label set on the container with the builder id of the verified attestation.
+pub(crate) const BUILDER_ID_LABEL: &str = "runwasi.io/provenance.builder-id";
+/// Label set on the container with the source repository of the verified attestation.
+pub(crate) const SOURCE_REPO_LABEL: &str = "runwasi.io/provenance.source-repo";
+
+/// The provenance of an image, as stated by an attestation.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Provenance {
+    pub builder_id: String,
+    pub source_repo: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Envelope {
+    payload_type: String,
+    payload: String,
+    #[serde(default)]
+    signatures: Vec<EnvelopeSignature>,
+}
+
+#[derive(Deserialize)]
+struct EnvelopeSignature {
+    sig: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Statement {
+    #[serde(default)]
+    subject: Vec<Subject>,
+    predicate_type: String,
+    #[serde(default)]
+    predicate: Value,
+}
+
+#[derive(Deserialize)]
+struct Subject {
+    #[serde(default)]
+    digest: HashMap<String, String>,
+}
+
+/// Looks for an attestation of `image_digest` trusted by `policy`.
+/// The attestations are fetched from the registry of `image` with `access`.
+pub(crate) async fn verify(
+    image: &str,
+    image_digest: &str,
+    policy: &ProvenancePolicy,
+    access: Access,
+) -> Result<Provenance> {
+    let keys = policy
+        .keys
+        .iter()
+        .map(|path| VerifyingKey::load(path))
+        .collect::<Result<Vec<_>>>()?;
+    let reference: Reference = image.parse().map_err(|err| {
+        ShimError::InvalidArgument(format!("invalid image reference {image:?}: {err}"))
+    })?;
+
+    let referrers =
+        registry::fetch_referrers(image, image_digest, IN_TOTO_ARTIFACT_TYPE, access).await?;
+    for digest in referrers {
+        let attestation = format!(
+            "{}/{}@{digest}",
+            reference.registry(),
+            reference.repository()
+        );
+        let (manifest, _) = registry::fetch_manifest(&attestation, access).await?;
+
+        for layer in manifest.layers() {
+            let data = registry::fetch_blob(&attestation, layer, access).await?;
+            let Some(statement) = open_envelope(&data, &keys) else {
+                log::warn!("provenance attestation {digest} of {image} has no trusted signature");
+                continue;
+            };
+            let Some(provenance) = parse_provenance(&statement, image_digest) else {
+                continue;
+            };
+            if policy.allows(&provenance.builder_id, provenance.source_repo.as_deref()) {
+                return Ok(provenance);
+            }
+            log::warn!("provenance attestation {digest} of {image} is not trusted: {provenance:?}");
+        }
+    }
+
+    Err(ShimError::FailedPrecondition(format!(
+        "no trusted SLSA provenance attestation found for {image}@{image_digest}"
+    )))
+}
+
+// Returns the payload of the DSSE envelope `data`, if it is signed with one of `keys`.
+fn open_envelope(data: &[u8], keys: &[VerifyingKey]) -> Option<Vec<u8>> {
+    let envelope: Envelope = serde_json::from_slice(data).ok()?;
+    let payload = BASE64_STANDARD.decode(&envelope.payload).ok()?;
+    let signed = pre_authentication_encoding(&envelope.payload_type, &payload);
+    let trusted = envelope
+        .signatures
+        .iter()
+        .filter_map(|signature| BASE64_STANDARD.decode(&signature.sig).ok())
+        .any(|signature| keys.iter().any(|key| key.verify(&signed, &signature)));
+    trusted.then_some(payload)
+}
+
+// The message signed in a DSSE envelope, see
+// https://github.com/secure-systems-lab/dsse/blob/master/protocol.md
+fn pre_authentication_encoding(payload_type: &str, payload: &[u8]) -> Vec<u8> {
+    let mut pae = format!(
+        "DSSEv1 {} {payload_type} {} ",
+        payload_type.len(),
+        payload.len()
+    )
+    .into_bytes();
+    pae.extend_from_slice(payload);
+    pae
+}
+
+// Parses a SLSA provenance statement for `image_digest`, supporting both the v0.2 and v1 predicates.
+fn parse_provenance(data: &[u8], image_digest: &str) -> Option<Provenance> {
+    let statement: Statement = serde_json::from_slice(data).ok()?;
+
+    if !statement.predicate_type.starts_with(SLSA_PREDICATE_PREFIX) {
+        return None;
+    }
+
+    let (algorithm, hash) = image_digest.split_once(':')?;
+    let is_subject = statement
+        .subject
+        .iter()
+        .any(|s| s.digest.get(algorithm).map(String::as_str) == Some(hash));
+    if !is_subject {
+        return None;
+    }
+
+    let predicate = &statement.predicate;
+    let builder_id = predicate
+        .pointer("/runDetails/builder/id")
+        .or_else(|| predicate.pointer("/builder/id"))
+        .and_then(Value::as_str)?
+        .to_string();
+    let source_repo = predicate
+        .pointer("/buildDefinition/externalParameters/workflow/repository")
+        .or_else(|| predicate.pointer("/invocation/configSource/uri"))
+        .and_then(Value::as_str)
+        .map(ToString::to_string);
+
+    Some(Provenance {
+        builder_id,
+        source_repo,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use ring::rand::SystemRandom;
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+
+    use super::*;
+
+    const DIGEST: &str = "sha256:4d2a3ac1b4a5b2c7e8f6b7c8d9e0f1a2b3c4d5e6f7a8b9c0d1e2f3a4b5c6d7e8";
+
+    fn statement(predicate_type: &str, predicate: Value) -> Vec<u8> {
+        serde_json::to_vec(&serde_json::json!({
+            "_type": "https://in-toto.io/Statement/v1",
+            "subject": [{ "name": "app", "digest": { "sha256": &DIGEST[7..] } }],
+            "predicateType": predicate_type,
+            "predicate": predicate,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_parse_slsa_v1() {
+        let data = statement(
+            "https://slsa.dev/provenance/v1",
+            serde_json::json!({
+                "buildDefinition": { "externalParameters": { "workflow": {
+                    "repository": "https://github.com/my-org/app"
+                } } },
+                "runDetails": { "builder": { "id": "https://github.com/actions/runner" } },
+            }),
+        );
+        let provenance = parse_provenance(&data, DIGEST).unwrap();
+        assert_eq!(provenance.builder_id, "https://github.com/actions/runner");
+        assert_eq!(
+            provenance.source_repo.as_deref(),
+            Some("https://github.com/my-org/app")
+        );
+    }
+
+    #[test]
+    fn test_parse_slsa_v02() {
+        let data = statement(
+            "https://slsa.dev/provenance/v0.2",
+            serde_json::json!({
+                "builder": { "id": "https://example.com/builder" },
+                "invocation": { "configSource": { "uri": "git+https://example.com/app" } },
+            }),
+        );
+        let provenance = parse_provenance(&data, DIGEST).unwrap();
+        assert_eq!(provenance.builder_id, "https://example.com/builder");
+        assert_eq!(
+            provenance.source_repo.as_deref(),
+            Some("git+https://example.com/app")
+        );
+    }
+
+    #[test]
+    fn test_open_envelope() {
+        let pair = || {
+            let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+            Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap()
+        };
+        let (signer, other) = (pair(), pair());
+        let keys = [VerifyingKey::Ed25519(signer.public_key().as_ref().to_vec())];
+
+        let payload = statement("https://slsa.dev/provenance/v1", Value::Null);
+        let envelope = |signers: &[&Ed25519KeyPair]| {
+            let signed = pre_authentication_encoding(IN_TOTO_ARTIFACT_TYPE, &payload);
+            let signatures: Vec<_> = signers
+                .iter()
+                .map(
+                    |pair| serde_json::json!({ "sig": BASE64_STANDARD.encode(pair.sign(&signed)) }),
+                )
+                .collect();
+            serde_json::to_vec(&serde_json::json!({
+                "payloadType": IN_TOTO_ARTIFACT_TYPE,
+                "payload": BASE64_STANDARD.encode(&payload),
+                "signatures": signatures,
+            }))
+            .unwrap()
+        };
+
+        assert_eq!(
+            open_envelope(&envelope(&[&signer]), &keys),
+            Some(payload.clone())
+        );
+        assert_eq!(
+            open_envelope(&envelope(&[&other, &signer]), &keys),
+            Some(payload.clone())
+        );
+        // neither unsigned envelopes, envelopes signed with other keys, nor bare statements
+        assert_eq!(open_envelope(&envelope(&[]), &keys), None);
+        assert_eq!(open_envelope(&envelope(&[&other]), &keys), None);
+        assert_eq!(open_envelope(&payload, &keys), None);
+    }
+
+    #[test]
+    fn test_parse_ignores_other_statements() {
+        let predicate = serde_json::json!({ "builder": { "id": "https://example.com/builder" } });
+
+        let data = statement("https://spdx.dev/Document", predicate.clone());
+        assert!(parse_provenance(&data, DIGEST).is_none());
+
+        let data = statement("https://slsa.dev/provenance/v0.2", predicate);
+        let other = "sha256:0000000000000000000000000000000000000000000000000000000000000000";
+        assert!(parse_provenance(&data, other).is_none());
+
+        assert!(parse_provenance(b"not json", DIGEST).is_none());
+    }
+}
@@ -0,0 +1,506 @@
+//! Access to the registries images are pulled from.
+//!
+//! Layers can be missing from the containerd content store, e.g., when the image was pulled
+//! by a snapshotter that fetches content lazily. Missing layers are fetched from the registry
+//! of the image instead.
+//!
+//! Credentials for a registry are looked up, in order, from:
+//! - the docker credential helper named by `RUNWASI_REGISTRY_CREDENTIAL_HELPER`
+//! - the CRI registry configuration in the containerd config file,
+//!   `RUNWASI_CONTAINERD_CONFIG` (defaults to `/etc/containerd/config.toml`)
+//! - the docker config file, `$DOCKER_CONFIG/config.json` (defaults to `~/.docker/config.json`)
+//!
+//! If none of them has credentials for the registry, it is accessed anonymously.
+//!
+//! The images named by tenants, e.g., with the `runwasi.io/pull-modules` annotation, are
+//! always pulled anonymously: the credentials of the node are for the images of the pods it
+//! was asked to pull, and the pull secrets of the pod aren't passed to the shim.
+
+use std::collections::HashMap;
+use std::io::{ErrorKind, Read as _, Write as _};
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::process::{Command, Stdio};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context as _};
+use base64::prelude::BASE64_STANDARD;
+use base64::Engine as _;
+use oci_client::client::ClientConfig;
+use oci_client::manifest::OciDescriptor;
+use oci_client::secrets::RegistryAuth;
+use oci_client::{Client, Reference, RegistryOperation};
+use oci_spec::image::{Digest, ImageManifest};
+use serde::Deserialize;
+use tokio::io::AsyncWrite;
+
+use crate::sandbox::config::RuntimeConfig;
+use crate::sandbox::error::{Error as ShimError, Result};
+
+const CREDENTIAL_HELPER_ENV: &str = "RUNWASI_REGISTRY_CREDENTIAL_HELPER";
+const CONTAINERD_CONFIG_ENV: &str = "RUNWASI_CONTAINERD_CONFIG";
+const DEFAULT_CONTAINERD_CONFIG: &str = "/etc/containerd/config.toml";
+
+/// How long a credential helper has to return the credentials of a registry.
+const CREDENTIAL_HELPER_TIMEOUT: Duration = Duration::from_secs(10);
+const CREDENTIAL_HELPER_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// A source of registry credentials.
+pub(crate) trait CredentialProvider {
+    /// Returns the credentials for `registry`, if the provider has any.
+    fn credentials(&self, registry: &str) -> anyhow::Result<Option<RegistryAuth>>;
+}
+
+/// The credentials a registry is accessed with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Access {
+    /// The credentials of the node, for the images pulled by containerd.
+    Node,
+    /// No credentials, for the images named by tenants.
+    Anonymous,
+}
+
+impl Access {
+    fn auth(self, registry: &str) -> RegistryAuth {
+        match self {
+            Access::Node => credentials(registry),
+            Access::Anonymous => RegistryAuth::Anonymous,
+        }
+    }
+}
+
+/// Returns the credentials to access `registry`.
+pub(crate) fn credentials(registry: &str) -> RegistryAuth {
+    let helper = std::env::var(CREDENTIAL_HELPER_ENV)
+        .ok()
+        .map(CredentialHelper);
+    let containerd = ContainerdConfig(
+        std::env::var_os(CONTAINERD_CONFIG_ENV)
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_CONTAINERD_CONFIG)),
+    );
+    let docker = docker_config_path().map(DockerConfig);
+
+    let mut providers: Vec<&dyn CredentialProvider> = vec![];
+    if let Some(helper) = &helper {
+        providers.push(helper);
+    }
+    providers.push(&containerd);
+    if let Some(docker) = &docker {
+        providers.push(docker);
+    }
+
+    for provider in providers {
+        match provider.credentials(registry) {
+            Ok(Some(auth)) => return auth,
+            Ok(None) => {}
+            Err(err) => log::warn!("failed to read credentials for {registry}: {err:#}"),
+        }
+    }
+    RegistryAuth::Anonymous
+}
+
+/// Fetches the manifest of `image` from its registry.
+pub(crate) async fn fetch_manifest(image: &str, access: Access) -> Result<(ImageManifest, Digest)> {
+    let reference: Reference = image.parse().map_err(|err| {
+        ShimError::InvalidArgument(format!("invalid image reference {image:?}: {err}"))
+    })?;
+    let registry_err =
+        |err| ShimError::Others(format!("failed to fetch the manifest of {image}: {err}"));
+
+    let auth = access.auth(reference.resolve_registry());
+    let client = Client::new(ClientConfig::default());
+    let (manifest, digest) = client
+        .pull_image_manifest(&reference, &auth)
+        .await
+        .map_err(registry_err)?;
+
+    let manifest = ImageManifest::from_reader(serde_json::to_vec(&manifest)?.as_slice())?;
+    Ok((manifest, digest.parse()?))
+}
+
+/// Fetches the digests of the manifests of `artifact_type` referring to the manifest `digest`
+/// of `image`, from its registry.
+pub(crate) async fn fetch_referrers(
+    image: &str,
+    digest: &str,
+    artifact_type: &str,
+    access: Access,
+) -> Result<Vec<String>> {
+    let reference: Reference = image.parse().map_err(|err| {
+        ShimError::InvalidArgument(format!("invalid image reference {image:?}: {err}"))
+    })?;
+    let subject = Reference::with_digest(
+        reference.registry().to_string(),
+        reference.repository().to_string(),
+        digest.to_string(),
+    );
+    let registry_err =
+        |err| ShimError::Others(format!("failed to fetch the referrers of {image}: {err}"));
+
+    let auth = access.auth(reference.resolve_registry());
+    let client = Client::new(ClientConfig::default());
+    client
+        .auth(&subject, &auth, RegistryOperation::Pull)
+        .await
+        .map_err(registry_err)?;
+    let referrers = client
+        .pull_referrers(&subject, Some(artifact_type))
+        .await
+        .map_err(registry_err)?;
+
+    Ok(referrers
+        .manifests
+        .into_iter()
+        .map(|entry| entry.digest)
+        .collect())
+}
+
+/// Fetches the blob described by `descriptor` from the registry of `image`.
+pub(crate) async fn fetch_blob(
+    image: &str,
+    descriptor: &oci_spec::image::Descriptor,
+    access: Access,
+) -> Result<Vec<u8>> {
+    let reference: Reference = image.parse().map_err(|err| {
+        ShimError::InvalidArgument(format!("invalid image reference {image:?}: {err}"))
+    })?;
+    let digest = descriptor.digest().to_string();
+    let registry_err =
+        |err| ShimError::Others(format!("failed to fetch {digest} from {image}: {err}"));
+
+    // the size is declared by the manifest, and is checked before it is allocated
+    let max_size = RuntimeConfig::current().max_registry_blob_bytes();
+    let size = descriptor.size();
+    let Some(declared_size) = i64::try_from(size).ok().filter(|_| size <= max_size) else {
+        return Err(ShimError::InvalidArgument(format!(
+            "{digest} of {image} has a size of {size} bytes, over the maximum of {max_size} bytes"
+        )));
+    };
+
+    let auth = access.auth(reference.resolve_registry());
+    let client = Client::new(ClientConfig::default());
+    client
+        .auth(&reference, &auth, RegistryOperation::Pull)
+        .await
+        .map_err(registry_err)?;
+
+    let layer = OciDescriptor {
+        media_type: descriptor.media_type().to_string(),
+        digest: digest.clone(),
+        size: declared_size,
+        ..Default::default()
+    };
+    let mut data = BoundedBuffer::new(size as usize);
+    client
+        .pull_blob(&reference, &layer, &mut data)
+        .await
+        .map_err(registry_err)?;
+
+    Ok(data.buf)
+}
+
+// A buffer failing the writes beyond `limit` bytes, so that a registry can't send more than
+// the declared size of a blob.
+struct BoundedBuffer {
+    buf: Vec<u8>,
+    limit: usize,
+}
+
+impl BoundedBuffer {
+    fn new(limit: usize) -> Self {
+        Self {
+            buf: Vec::with_capacity(limit),
+            limit,
+        }
+    }
+}
+
+impl AsyncWrite for BoundedBuffer {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        data: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        if self.buf.len() + data.len() > self.limit {
+            return Poll::Ready(Err(std::io::Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "blob is larger than its declared size of {} bytes",
+                    self.limit
+                ),
+            )));
+        }
+        self.buf.extend_from_slice(data);
+        Poll::Ready(Ok(data.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Gets credentials from a docker credential helper, `docker-credential-<name>`.
+struct CredentialHelper(String);
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct HelperCredentials {
+    username: String,
+    secret: String,
+}
+
+impl CredentialProvider for CredentialHelper {
+    fn credentials(&self, registry: &str) -> anyhow::Result<Option<RegistryAuth>> {
+        let helper = format!("docker-credential-{}", self.0);
+        let mut child = Command::new(&helper)
+            .arg("get")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .with_context(|| format!("failed to run {helper}"))?;
+        child
+            .stdin
+            .take()
+            .context("credential helper stdin")?
+            .write_all(registry.as_bytes())?;
+
+        // the output is read as the helper runs, so that it doesn't block on a full pipe
+        let mut stdout = child.stdout.take().context("credential helper stdout")?;
+        let output = std::thread::spawn(move || {
+            let mut output = vec![];
+            stdout.read_to_end(&mut output).map(|_| output)
+        });
+
+        let deadline = Instant::now() + CREDENTIAL_HELPER_TIMEOUT;
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if Instant::now() >= deadline {
+                let _ = child.kill();
+                let _ = child.wait();
+                bail!("{helper} didn't return in {CREDENTIAL_HELPER_TIMEOUT:?}");
+            }
+            std::thread::sleep(CREDENTIAL_HELPER_POLL_INTERVAL);
+        };
+        if !status.success() {
+            // helpers exit with an error when they have no credentials for the registry
+            return Ok(None);
+        }
+        let output = output
+            .join()
+            .map_err(|_| anyhow::anyhow!("failed to read the output of {helper}"))??;
+        let creds: HelperCredentials = serde_json::from_slice(&output)?;
+        Ok(Some(RegistryAuth::Basic(creds.username, creds.secret)))
+    }
+}
+
+/// Gets credentials from the CRI registry configuration in the containerd config file.
+struct ContainerdConfig(PathBuf);
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct RegistryConfig {
+    username: String,
+    password: String,
+    auth: String,
+}
+
+impl CredentialProvider for ContainerdConfig {
+    fn credentials(&self, registry: &str) -> anyhow::Result<Option<RegistryAuth>> {
+        let Ok(content) = std::fs::read_to_string(&self.0) else {
+            return Ok(None);
+        };
+        let config: toml::Value = toml::from_str(&content)?;
+
+        // The CRI plugin is `io.containerd.grpc.v1.cri` in config version 2,
+        // and `io.containerd.cri.v1.images` in version 3.
+        let auth = ["io.containerd.grpc.v1.cri", "io.containerd.cri.v1.images"]
+            .iter()
+            .find_map(|plugin| {
+                config
+                    .get("plugins")?
+                    .get(plugin)?
+                    .get("registry")?
+                    .get("configs")?
+                    .get(registry)?
+                    .get("auth")
+                    .cloned()
+            });
+        let Some(auth) = auth else {
+            return Ok(None);
+        };
+
+        let auth: RegistryConfig = auth.try_into()?;
+        basic_auth(&auth.username, &auth.password, &auth.auth).map(Some)
+    }
+}
+
+/// Gets credentials from the docker config file.
+struct DockerConfig(PathBuf);
+
+#[derive(Deserialize, Default)]
+#[serde(default, rename_all = "camelCase")]
+struct DockerConfigFile {
+    auths: HashMap<String, RegistryConfig>,
+    cred_helpers: HashMap<String, String>,
+    creds_store: Option<String>,
+}
+
+impl CredentialProvider for DockerConfig {
+    fn credentials(&self, registry: &str) -> anyhow::Result<Option<RegistryAuth>> {
+        let Ok(content) = std::fs::read(&self.0) else {
+            return Ok(None);
+        };
+        let config: DockerConfigFile = serde_json::from_slice(&content)?;
+
+        if let Some(helper) = config
+            .cred_helpers
+            .get(registry)
+            .or(config.creds_store.as_ref())
+        {
+            return CredentialHelper(helper.clone()).credentials(registry);
+        }
+
+        // keys can be urls, e.g., docker hub credentials are stored under its legacy v1 url
+        let auth = config.auths.iter().find_map(|(key, auth)| {
+            let host = key
+                .trim_start_matches("https://")
+                .trim_start_matches("http://")
+                .split('/')
+                .next()
+                .unwrap_or_default();
+            (normalize_registry(host) == normalize_registry(registry)).then_some(auth)
+        });
+        let Some(auth) = auth else {
+            return Ok(None);
+        };
+        basic_auth(&auth.username, &auth.password, &auth.auth).map(Some)
+    }
+}
+
+fn normalize_registry(host: &str) -> &str {
+    match host {
+        "index.docker.io" | "registry-1.docker.io" => "docker.io",
+        host => host,
+    }
+}
+
+fn docker_config_path() -> Option<PathBuf> {
+    let dir = std::env::var_os("DOCKER_CONFIG")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".docker")))?;
+    Some(dir.join("config.json"))
+}
+
+// `auth` is the base64 encoding of `username:password`, and takes precedence when set.
+fn basic_auth(username: &str, password: &str, auth: &str) -> anyhow::Result<RegistryAuth> {
+    if auth.is_empty() {
+        return Ok(RegistryAuth::Basic(
+            username.to_string(),
+            password.to_string(),
+        ));
+    }
+    let auth = String::from_utf8(BASE64_STANDARD.decode(auth)?)?;
+    let Some((username, password)) = auth.split_once(':') else {
+        bail!("invalid registry auth, expected base64 encoded `username:password`");
+    };
+    Ok(RegistryAuth::Basic(
+        username.to_string(),
+        password.to_string(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    fn assert_basic(auth: Option<RegistryAuth>, username: &str, password: &str) {
+        match auth {
+            Some(RegistryAuth::Basic(u, p)) => {
+                assert_eq!(u, username);
+                assert_eq!(p, password);
+            }
+            _ => panic!("expected basic auth"),
+        }
+    }
+
+    #[test]
+    fn test_containerd_config() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("config.toml");
+        std::fs::write(
+            &path,
+            r#"
+            version = 2
+            [plugins."io.containerd.grpc.v1.cri".registry.configs."ghcr.io".auth]
+              username = "user"
+              password = "pass"
+            [plugins."io.containerd.grpc.v1.cri".registry.configs."example.com".auth]
+              auth = "Zm9vOmJhcg=="
+            "#,
+        )?;
+
+        let provider = ContainerdConfig(path);
+        assert_basic(provider.credentials("ghcr.io")?, "user", "pass");
+        assert_basic(provider.credentials("example.com")?, "foo", "bar");
+        assert!(provider.credentials("docker.io")?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_docker_config() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("config.json");
+        std::fs::write(
+            &path,
+            r#"{ "auths": {
+                "https://index.docker.io/v1/": { "auth": "Zm9vOmJhcg==" },
+                "ghcr.io": { "auth": "dXNlcjpwYXNz" }
+            } }"#,
+        )?;
+
+        let provider = DockerConfig(path);
+        assert_basic(provider.credentials("index.docker.io")?, "foo", "bar");
+        assert_basic(provider.credentials("ghcr.io")?, "user", "pass");
+        assert!(provider.credentials("example.com")?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_missing_config_has_no_credentials() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("missing");
+        assert!(ContainerdConfig(path.clone())
+            .credentials("ghcr.io")?
+            .is_none());
+        assert!(DockerConfig(path).credentials("ghcr.io")?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_bounded_buffer() {
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+        let mut buf = BoundedBuffer::new(4);
+        let res = Pin::new(&mut buf).poll_write(&mut cx, b"abc");
+        assert!(matches!(res, Poll::Ready(Ok(3))));
+        let res = Pin::new(&mut buf).poll_write(&mut cx, b"de");
+        assert!(matches!(res, Poll::Ready(Err(_))));
+        assert_eq!(buf.buf, b"abc");
+    }
+
+    #[test]
+    fn test_invalid_auth() {
+        basic_auth("", "", "not base64!").unwrap_err();
+        basic_auth("", "", &BASE64_STANDARD.encode("no-colon")).unwrap_err();
+    }
+}
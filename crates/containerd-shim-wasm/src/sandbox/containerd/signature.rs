@@ -0,0 +1,284 @@
+//! Verification of the signatures of wasm modules, before they run.
+//!
+//! With `signatures` in the runtime configuration, the modules of the containers in the
+//! namespaces of the policy only run if they are signed by one of its keys, either:
+//! * with a cosign signature of the manifest of their image, made with one of `cosign_keys`.
+//!   Signatures are fetched from the registry of the image, under the `sha256-<digest>.sig`
+//!   tag cosign pushes them to. Keyless signatures aren't supported.
+//! * or with a wasmsign2 signature embedded in the module, made with one of `wasmsign_keys`.
+//!
+//! Modules fetched from a `runwasi.io/module-source` have no image, and must have a wasmsign2
+//! signature.
+//!
+//! Only the wasm modules are signed, not the native code the engines compile them to. For the
+//! containers of the policy, the precompiled content of the layers and the module cache are
+//! ignored, and the engine compiles the verified modules in the container.
+//!
+//! A container whose modules aren't trusted fails to be created with a `PERMISSION_DENIED`
+//! error, rather than falling back to the files of its rootfs.
+
+use std::io::Cursor;
+use std::path::Path;
+
+use base64::prelude::BASE64_STANDARD;
+use base64::Engine as _;
+use oci_client::Reference;
+use oci_spec::image::Digest;
+use ring::signature::{UnparsedPublicKey, ECDSA_P256_SHA256_ASN1, ED25519};
+use serde_json::Value;
+
+use super::registry::{self, Access};
+use crate::sandbox::config::{RuntimeConfig, SignaturePolicy};
+use crate::sandbox::error::{Error as ShimError, Result};
+use crate::sandbox::oci::WasmLayer;
+
+/// Annotation of the layers of a cosign signature with the signature of their payload.
+const COSIGN_SIGNATURE_ANNOTATION: &str = "dev.cosignproject.cosign/signature";
+
+// DER prefixes of the SubjectPublicKeyInfo of the keys, followed by the key itself
+const P256_SPKI_PREFIX: &[u8] = &[
+    0x30, 0x59, 0x30, 0x13, 0x06, 0x07, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01, 0x06, 0x08, 0x2a,
+    0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07, 0x03, 0x42, 0x00,
+];
+const ED25519_SPKI_PREFIX: &[u8] = &[
+    0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00,
+];
+
+/// Returns the signature policy of the containers in `namespace`, if any.
+pub(crate) fn policy_for(namespace: &str) -> Option<SignaturePolicy> {
+    RuntimeConfig::current()
+        .signatures
+        .clone()
+        .filter(|policy| policy.applies_to(namespace))
+}
+
+/// Returns true if the modules of the containers in `namespace` must be signed.
+pub fn is_required(namespace: &str) -> bool {
+    policy_for(namespace).is_some()
+}
+
+/// Returns true if the manifest `image_digest` of `image` has a cosign signature trusted by
+/// `policy`. The signatures are fetched from the registry of `image` with `access`.
+pub(crate) async fn image_is_signed(
+    image: &str,
+    image_digest: &Digest,
+    policy: &SignaturePolicy,
+    access: Access,
+) -> Result<bool> {
+    if policy.cosign_keys.is_empty() {
+        return Ok(false);
+    }
+    let keys = policy
+        .cosign_keys
+        .iter()
+        .map(|path| VerifyingKey::load(path))
+        .collect::<Result<Vec<_>>>()?;
+
+    let reference: Reference = image.parse().map_err(|err| {
+        ShimError::InvalidArgument(format!("invalid image reference {image:?}: {err}"))
+    })?;
+    let signatures = format!(
+        "{}/{}:{}.sig",
+        reference.registry(),
+        reference.repository(),
+        image_digest.to_string().replace(':', "-")
+    );
+    let manifest = match registry::fetch_manifest(&signatures, access).await {
+        Ok((manifest, _)) => manifest,
+        Err(err) => {
+            log::info!("no cosign signature found for {image}@{image_digest}: {err}");
+            return Ok(false);
+        }
+    };
+
+    for layer in manifest.layers() {
+        let Some(signature) = layer
+            .annotations()
+            .as_ref()
+            .and_then(|a| a.get(COSIGN_SIGNATURE_ANNOTATION))
+        else {
+            continue;
+        };
+        let Ok(signature) = BASE64_STANDARD.decode(signature) else {
+            continue;
+        };
+        let payload = registry::fetch_blob(&signatures, layer, access).await?;
+        if !signs_manifest(&payload, image_digest) {
+            continue;
+        }
+        if keys.iter().any(|key| key.verify(&payload, &signature)) {
+            log::info!("verified cosign signature of {image}@{image_digest}");
+            return Ok(true);
+        }
+        log::warn!(
+            "cosign signature {} of {image} is not trusted",
+            layer.digest()
+        );
+    }
+    Ok(false)
+}
+
+/// Fails if `module` has no embedded wasmsign2 signature trusted by `policy`.
+pub(crate) fn verify_module(name: &str, module: &[u8], policy: &SignaturePolicy) -> Result<()> {
+    for path in &policy.wasmsign_keys {
+        let key = std::fs::read(path)
+            .map_err(|err| err.to_string())
+            .and_then(|key| wasmsign2::PublicKey::from_any(&key).map_err(|err| err.to_string()))
+            .map_err(|err| {
+                ShimError::PermissionDenied(format!("invalid signature key {path:?}: {err}"))
+            })?;
+        match key.verify(&mut Cursor::new(module), None) {
+            Ok(()) => {
+                log::info!("verified wasmsign2 signature of module {name}");
+                return Ok(());
+            }
+            Err(err) => log::debug!("module {name} is not signed with {path:?}: {err}"),
+        }
+    }
+    Err(ShimError::PermissionDenied(format!(
+        "module {name} is not signed by a trusted key"
+    )))
+}
+
+/// Fails if the modules fetched from `source` for a container in `namespace` have no trusted
+/// wasmsign2 signature, when the namespace requires signatures.
+pub fn verify_fetched(namespace: &str, source: &str, modules: &[WasmLayer]) -> Result<()> {
+    let Some(policy) = policy_for(namespace) else {
+        return Ok(());
+    };
+    if modules.is_empty() {
+        return Err(ShimError::PermissionDenied(format!(
+            "{source} returned no modules to verify"
+        )));
+    }
+    for module in modules {
+        verify_module(&module.config.digest().to_string(), &module.layer, &policy)?;
+    }
+    Ok(())
+}
+
+/// The error of an image without trusted signature, which modules are read from its rootfs.
+pub(crate) fn unsigned_image(image: &str) -> ShimError {
+    ShimError::PermissionDenied(format!(
+        "image {image} has no wasm layers and no trusted signature"
+    ))
+}
+
+// Returns true if `payload` is a cosign simple signing payload of the manifest `digest`.
+fn signs_manifest(payload: &[u8], digest: &Digest) -> bool {
+    serde_json::from_slice::<Value>(payload).is_ok_and(|payload| {
+        payload["critical"]["image"]["docker-manifest-digest"].as_str()
+            == Some(digest.to_string().as_str())
+    })
+}
+
+/// A public key of cosign signatures, or of the DSSE envelopes of provenance attestations.
+pub(super) enum VerifyingKey {
+    EcdsaP256(Vec<u8>),
+    Ed25519(Vec<u8>),
+}
+
+impl VerifyingKey {
+    pub(super) fn load(path: &Path) -> Result<Self> {
+        let pem = std::fs::read_to_string(path).map_err(|err| {
+            ShimError::PermissionDenied(format!("failed to read signature key {path:?}: {err}"))
+        })?;
+        Self::from_pem(&pem).ok_or_else(|| {
+            ShimError::PermissionDenied(format!(
+                "signature key {path:?} is not an ECDSA P-256 or Ed25519 public key"
+            ))
+        })
+    }
+
+    fn from_pem(pem: &str) -> Option<Self> {
+        let der: String = pem
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.starts_with("-----"))
+            .collect();
+        let der = BASE64_STANDARD.decode(der).ok()?;
+        if let Some(key) = der.strip_prefix(P256_SPKI_PREFIX) {
+            Some(Self::EcdsaP256(key.to_vec()))
+        } else {
+            der.strip_prefix(ED25519_SPKI_PREFIX)
+                .map(|key| Self::Ed25519(key.to_vec()))
+        }
+    }
+
+    pub(super) fn verify(&self, payload: &[u8], signature: &[u8]) -> bool {
+        let res = match self {
+            Self::EcdsaP256(key) => {
+                UnparsedPublicKey::new(&ECDSA_P256_SHA256_ASN1, key).verify(payload, signature)
+            }
+            Self::Ed25519(key) => UnparsedPublicKey::new(&ED25519, key).verify(payload, signature),
+        };
+        res.is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ring::rand::SystemRandom;
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+
+    use super::*;
+
+    const DIGEST: &str = "sha256:6c3c624b58dbbcd3c0dd82b4c53f04194d1247c6eebdaab7c610cf7d66709b3b";
+
+    fn payload(digest: &str) -> Vec<u8> {
+        serde_json::to_vec(&serde_json::json!({
+            "critical": {
+                "identity": { "docker-reference": "ghcr.io/my-org/app" },
+                "image": { "docker-manifest-digest": digest },
+                "type": "cosign container image signature"
+            },
+            "optional": null
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_signs_manifest() {
+        let digest: Digest = DIGEST.parse().unwrap();
+        assert!(signs_manifest(&payload(DIGEST), &digest));
+
+        let other = "sha256:0000000000000000000000000000000000000000000000000000000000000000";
+        assert!(!signs_manifest(&payload(other), &digest));
+        assert!(!signs_manifest(b"not json", &digest));
+    }
+
+    #[test]
+    fn test_verifying_key() {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        let pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+        let spki = [ED25519_SPKI_PREFIX, pair.public_key().as_ref()].concat();
+        let pem = format!(
+            "-----BEGIN PUBLIC KEY-----\n{}\n-----END PUBLIC KEY-----\n",
+            BASE64_STANDARD.encode(spki)
+        );
+        let key = VerifyingKey::from_pem(&pem).unwrap();
+
+        let payload = payload(DIGEST);
+        let signature = pair.sign(&payload);
+        assert!(key.verify(&payload, signature.as_ref()));
+        assert!(!key.verify(b"another payload", signature.as_ref()));
+
+        assert!(VerifyingKey::from_pem("-----BEGIN PUBLIC KEY-----\nAAAA\n").is_none());
+    }
+
+    #[test]
+    fn test_unsigned_module_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let pk = wasmsign2::KeyPair::generate().pk;
+        let path = dir.path().join("wasmsign.pub");
+        std::fs::write(&path, pk.to_pem()).unwrap();
+        let policy = SignaturePolicy {
+            wasmsign_keys: vec![path],
+            ..Default::default()
+        };
+
+        let module = wat::parse_str("(module)").unwrap();
+        let err = verify_module("app.wasm", &module, &policy).unwrap_err();
+        assert!(matches!(err, ShimError::PermissionDenied(_)), "{err}");
+    }
+}
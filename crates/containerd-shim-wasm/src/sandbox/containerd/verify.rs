@@ -0,0 +1,95 @@
+//! Verification of the content read for a descriptor against its digest.
+//!
+//! Content is hashed as it is read, so that corrupted or tampered layers are rejected
+//! before they are handed to an engine.
+//! Only `sha256` digests are supported, content with other digests is rejected, as it
+//! can't be verified.
+
+use oci_spec::image::{Digest, DigestAlgorithm};
+
+use crate::sandbox::error::{Error as ShimError, Result};
+
+/// Collects content as it is read, and checks it against its expected digest.
+pub(crate) struct DigestVerifier {
+    expected: Digest,
+    content: Vec<u8>,
+}
+
+impl DigestVerifier {
+    /// Fails if the content of `expected` can't be verified.
+    pub fn new(expected: &Digest) -> Result<Self> {
+        if !matches!(expected.algorithm(), DigestAlgorithm::Sha256) {
+            return Err(ShimError::FailedPrecondition(format!(
+                "can't verify {expected}: unsupported digest algorithm {}",
+                expected.algorithm()
+            )));
+        }
+        Ok(Self {
+            expected: expected.clone(),
+            content: vec![],
+        })
+    }
+
+    /// Adds the next chunk of the content.
+    pub fn update(&mut self, chunk: &[u8]) {
+        self.content.extend_from_slice(chunk);
+    }
+
+    /// Fails if the content doesn't match the expected digest.
+    pub fn verify(self) -> Result<()> {
+        let actual = sha256::digest(self.content.as_slice());
+        if actual != self.expected.digest() {
+            return Err(ShimError::FailedPrecondition(format!(
+                "content digest mismatch: expected {}, got sha256:{actual}",
+                self.expected
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Checks `data` against its expected digest.
+pub(crate) fn verify_digest(expected: &Digest, data: &[u8]) -> Result<()> {
+    let mut verifier = DigestVerifier::new(expected)?;
+    verifier.update(data);
+    verifier.verify()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sha256_digest(data: &[u8]) -> Digest {
+        format!("sha256:{}", sha256::digest(data)).parse().unwrap()
+    }
+
+    #[test]
+    fn test_verify_digest() -> Result<()> {
+        let data = b"\0asm\x01\0\0\0";
+        verify_digest(&sha256_digest(data), data)?;
+
+        let err = verify_digest(&sha256_digest(data), b"\0asm\x02\0\0\0").unwrap_err();
+        assert!(err.to_string().contains("content digest mismatch"), "{err}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_in_chunks() -> Result<()> {
+        let data = b"\0asm\x01\0\0\0".repeat(100);
+        let mut verifier = DigestVerifier::new(&sha256_digest(&data))?;
+        for chunk in data.chunks(7) {
+            verifier.update(chunk);
+        }
+        verifier.verify()
+    }
+
+    #[test]
+    fn test_unsupported_digest_is_rejected() {
+        let data = b"\0asm\x01\0\0\0";
+        let digest: Digest = format!("sha512:{}", "a".repeat(128)).parse().unwrap();
+        assert!(matches!(
+            verify_digest(&digest, data),
+            Err(ShimError::FailedPrecondition(_))
+        ));
+    }
+}
@@ -0,0 +1,103 @@
+//! Fingerprint of the CPU features of the host.
+//!
+//! Precompiled modules can use any instruction supported by the CPU they were compiled on,
+//! and crash on a node without it. The fingerprint records the features engines compile for,
+//! so that precompiled artifacts are only used on compatible CPUs.
+
+#![cfg_attr(windows, allow(dead_code))] // this is currently used only for linux
+
+use std::sync::LazyLock;
+
+/// Returns the fingerprint of the CPU features of the host, e.g. `x86_64:avx,avx2,bmi1`.
+pub(crate) fn fingerprint() -> &'static str {
+    static FINGERPRINT: LazyLock<String> =
+        LazyLock::new(|| format!("{}:{}", std::env::consts::ARCH, features().join(",")));
+    &FINGERPRINT
+}
+
+/// Returns true if an artifact compiled on a CPU with the `compiled_on` fingerprint can be
+/// used on the host.
+pub(crate) fn is_compatible(compiled_on: &str) -> bool {
+    let Some((arch, features)) = compiled_on.split_once(':') else {
+        return false;
+    };
+    let host = features_of(fingerprint());
+    arch == std::env::consts::ARCH
+        && features
+            .split(',')
+            .filter(|f| !f.is_empty())
+            .all(|f| host.contains(&f))
+}
+
+fn features_of(fingerprint: &str) -> Vec<&str> {
+    let features = fingerprint.split_once(':').map_or("", |(_, f)| f);
+    features.split(',').filter(|f| !f.is_empty()).collect()
+}
+
+#[cfg(target_arch = "x86_64")]
+fn features() -> Vec<&'static str> {
+    macro_rules! detect {
+        ($($feature:tt),*) => {
+            [$(($feature, std::arch::is_x86_feature_detected!($feature))),*]
+        };
+    }
+    let detected = detect!(
+        "sse3",
+        "ssse3",
+        "sse4.1",
+        "sse4.2",
+        "popcnt",
+        "avx",
+        "avx2",
+        "fma",
+        "bmi1",
+        "bmi2",
+        "lzcnt",
+        "avx512f",
+        "avx512vl",
+        "avx512dq",
+        "avx512bw",
+        "avx512vbmi"
+    );
+    detected
+        .into_iter()
+        .filter(|(_, d)| *d)
+        .map(|(f, _)| f)
+        .collect()
+}
+
+#[cfg(target_arch = "aarch64")]
+fn features() -> Vec<&'static str> {
+    macro_rules! detect {
+        ($($feature:tt),*) => {
+            [$(($feature, std::arch::is_aarch64_feature_detected!($feature))),*]
+        };
+    }
+    let detected = detect!("neon", "lse", "fp16", "paca", "bti", "sve");
+    detected
+        .into_iter()
+        .filter(|(_, d)| *d)
+        .map(|(f, _)| f)
+        .collect()
+}
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+fn features() -> Vec<&'static str> {
+    vec![]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compatibility() {
+        let arch = std::env::consts::ARCH;
+        assert!(is_compatible(fingerprint()));
+        // a CPU with fewer features
+        assert!(is_compatible(&format!("{arch}:")));
+        assert!(!is_compatible(&format!("{arch}:quantum")));
+        assert!(!is_compatible("riscv128:"));
+        assert!(!is_compatible("garbage"));
+    }
+}
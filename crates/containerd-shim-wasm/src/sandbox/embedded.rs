@@ -0,0 +1,90 @@
+//! A default wasm module embedded in the shim binary.
+//!
+//! Single-purpose shims, e.g. appliances, can embed a fallback module with the
+//! [`embed_module!`](crate::embed_module) macro, before calling
+//! [`shim_main`](crate::sandbox::cli::shim_main):
+//!
+//! ```rust, ignore
+//! embed_module!("../default.wasm");
+//! shim_main::<Instance<MyEngine>>("my-engine", version!(), revision!(), "v1", None);
+//! ```
+//!
+//! The embedded module runs instead of the image of a container when the image has no wasm
+//! layers, its entrypoint is neither a linux executable nor a module the engine can handle,
+//! and the namespace of the container is allowed by the `embedded_module` policy of the
+//! runtime configuration. Without the policy, the embedded module never runs.
+
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use oci_spec::image::{Descriptor, Digest, MediaType};
+
+use crate::sandbox::oci::WasmLayer;
+
+/// Name of the embedded module, in the `org.opencontainers.image.title` annotation of its layer.
+pub const EMBEDDED_MODULE_TITLE: &str = "embedded.wasm";
+
+const TITLE_ANNOTATION: &str = "org.opencontainers.image.title";
+
+static MODULE: OnceLock<&'static [u8]> = OnceLock::new();
+
+/// Embeds the wasm module at `path`, relative to the calling file, in the shim binary.
+/// See the [`embedded`](crate::sandbox::embedded) module.
+#[macro_export]
+macro_rules! embed_module {
+    ($path:literal) => {
+        $crate::sandbox::embedded::set_module(include_bytes!($path))
+    };
+}
+
+/// Sets the module embedded in the shim binary.
+/// This must be called before [`shim_main`](crate::sandbox::cli::shim_main), so that the
+/// zygote of the shim sees it too. Only the first call has an effect.
+pub fn set_module(module: &'static [u8]) {
+    let _ = MODULE.set(module);
+}
+
+/// Returns the module embedded in the shim binary, if any.
+pub fn module() -> Option<&'static [u8]> {
+    MODULE.get().copied()
+}
+
+/// Returns the embedded module as a wasm layer, if any.
+#[cfg_attr(windows, allow(dead_code))] // containers only run in their own process on linux
+pub(crate) fn layer() -> Option<WasmLayer> {
+    let module = module()?;
+    let digest = Digest::try_from(format!("sha256:{}", sha256::digest(module)))
+        .expect("sha256 digests are valid");
+    let mut config = Descriptor::new(
+        MediaType::Other("application/wasm".to_string()),
+        module.len() as u64,
+        digest,
+    );
+    config.set_annotations(Some(HashMap::from([(
+        TITLE_ANNOTATION.to_string(),
+        EMBEDDED_MODULE_TITLE.to_string(),
+    )])));
+    Some(WasmLayer {
+        config,
+        layer: module.to_vec(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_embedded_layer() {
+        crate::embed_module!(
+            "../../../containerd-shim-wasm-test-modules/src/modules/hello_world.wat"
+        );
+        let layer = layer().unwrap();
+        assert_eq!(layer.layer, module().unwrap());
+        assert_eq!(layer.config.size(), layer.layer.len() as u64);
+        assert_eq!(
+            layer.config.annotations().as_ref().unwrap()[TITLE_ANNOTATION],
+            EMBEDDED_MODULE_TITLE
+        );
+    }
+}
@@ -48,6 +48,18 @@ pub enum Error {
     Libcontainer(#[from] libcontainer::error::LibcontainerError),
     #[error("{0}")]
     Containerd(String),
+    /// The operation was cancelled before it completed
+    #[error("cancelled: {0}")]
+    Cancelled(String),
+    /// The operation didn't complete before its deadline
+    #[error("timed out: {0}")]
+    TimedOut(String),
+    /// The operation was rejected because a resource limit was reached
+    #[error("resource exhausted: {0}")]
+    ResourceExhausted(String),
+    /// The operation was rejected by a security policy
+    #[error("permission denied: {0}")]
+    PermissionDenied(String),
 }
 
 pub type Result<T, E = Error> = ::std::result::Result<T, E>;
@@ -62,6 +74,9 @@ impl From<Error> for ttrpc::Error {
                 ShimError::NotFoundError(s) => {
                     ttrpc::Error::RpcStatus(ttrpc::get_status(ttrpc::Code::NOT_FOUND, s))
                 }
+                ShimError::Unimplemented(s) => {
+                    ttrpc::Error::RpcStatus(ttrpc::get_status(ttrpc::Code::UNIMPLEMENTED, s))
+                }
                 _ => ttrpc::Error::RpcStatus(ttrpc::get_status(ttrpc::Code::UNKNOWN, s)),
             },
             Error::NotFound(ref s) => {
@@ -76,6 +91,18 @@ impl From<Error> for ttrpc::Error {
             Error::FailedPrecondition(ref s) => {
                 ttrpc::Error::RpcStatus(ttrpc::get_status(ttrpc::Code::FAILED_PRECONDITION, s))
             }
+            Error::Cancelled(ref s) => {
+                ttrpc::Error::RpcStatus(ttrpc::get_status(ttrpc::Code::CANCELLED, s))
+            }
+            Error::TimedOut(ref s) => {
+                ttrpc::Error::RpcStatus(ttrpc::get_status(ttrpc::Code::DEADLINE_EXCEEDED, s))
+            }
+            Error::ResourceExhausted(ref s) => {
+                ttrpc::Error::RpcStatus(ttrpc::get_status(ttrpc::Code::RESOURCE_EXHAUSTED, s))
+            }
+            Error::PermissionDenied(ref s) => {
+                ttrpc::Error::RpcStatus(ttrpc::get_status(ttrpc::Code::PERMISSION_DENIED, s))
+            }
             Error::Oci(ref _s) => {
                 ttrpc::Error::RpcStatus(ttrpc::get_status(ttrpc::Code::UNKNOWN, e.to_string()))
             }
@@ -151,6 +178,46 @@ mod tests {
             _ => panic!("unexpected error"),
         }
 
+        let e = Error::Shim(ShimError::Unimplemented("exec is not supported".to_string()));
+        let t: ttrpc::Error = e.into();
+        match t {
+            ttrpc::Error::RpcStatus(s) => {
+                assert_eq!(s.code(), ttrpc::Code::UNIMPLEMENTED);
+                assert_eq!(s.message, "exec is not supported");
+            }
+            _ => panic!("unexpected error"),
+        }
+
+        let e = Error::TimedOut("timed out".to_string());
+        let t: ttrpc::Error = e.into();
+        match t {
+            ttrpc::Error::RpcStatus(s) => {
+                assert_eq!(s.code(), ttrpc::Code::DEADLINE_EXCEEDED);
+                assert_eq!(s.message, "timed out");
+            }
+            _ => panic!("unexpected error"),
+        }
+
+        let e = Error::ResourceExhausted("resource exhausted".to_string());
+        let t: ttrpc::Error = e.into();
+        match t {
+            ttrpc::Error::RpcStatus(s) => {
+                assert_eq!(s.code(), ttrpc::Code::RESOURCE_EXHAUSTED);
+                assert_eq!(s.message, "resource exhausted");
+            }
+            _ => panic!("unexpected error"),
+        }
+
+        let e = Error::PermissionDenied("permission denied".to_string());
+        let t: ttrpc::Error = e.into();
+        match t {
+            ttrpc::Error::RpcStatus(s) => {
+                assert_eq!(s.code(), ttrpc::Code::PERMISSION_DENIED);
+                assert_eq!(s.message, "permission denied");
+            }
+            _ => panic!("unexpected error"),
+        }
+
         let e = Error::Any(AnyError::new(TestError::AnError("any error".to_string())));
         let t: ttrpc::Error = e.into();
         match t {
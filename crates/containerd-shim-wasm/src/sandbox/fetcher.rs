@@ -0,0 +1,334 @@
+//! Sources of the wasm modules of a container.
+//!
+//! By default, the modules are read from the wasm layers of the container image in the
+//! containerd content store. Platforms that distribute modules outside of OCI registries
+//! (e.g., S3, IPFS or an internal CAS) can set the `runwasi.io/module-source` annotation to
+//! a URI, and install a [`ModuleFetcher`] for its scheme before starting the shim:
+//!
+//! ```rust
+//! use containerd_shim_wasm::sandbox::fetcher::{FetchRequest, ModuleFetcher, ModuleFetchers};
+//! use containerd_shim_wasm::sandbox::WasmLayer;
+//! use futures::future::LocalBoxFuture;
+//! use oci_spec::image::Platform;
+//!
+//! struct S3Fetcher;
+//!
+//! impl ModuleFetcher for S3Fetcher {
+//!     fn fetch<'a>(
+//!         &'a self,
+//!         req: &'a FetchRequest,
+//!     ) -> LocalBoxFuture<'a, anyhow::Result<(Vec<WasmLayer>, Platform)>> {
+//!         Box::pin(async move {
+//!             // download `req.source`, e.g. `s3://bucket/app.wasm`
+//!             anyhow::bail!("not implemented")
+//!         })
+//!     }
+//! }
+//!
+//! ModuleFetchers::new()
+//!     .with_fetcher("s3", S3Fetcher)
+//!     .install()
+//!     .expect("module fetchers are installed once");
+//! ```
+//!
+//! Modules from `file://` URIs are read from the host filesystem by the shim, which bypasses
+//! the digest, provenance and signature checks of images. They are only allowed with the
+//! `file_module_source` section of the runtime configuration, for the files under its `dir`.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+
+use anyhow::Context;
+use futures::future::LocalBoxFuture;
+use oci_spec::image::{Descriptor, Digest, MediaType, Platform};
+use oci_spec::runtime::Spec;
+
+use crate::sandbox::config::RuntimeConfig;
+use crate::sandbox::error::{Error, Result};
+use crate::sandbox::oci::WasmLayer;
+
+/// Annotation with the URI of the module of the container, when it isn't in the image.
+pub const MODULE_SOURCE_ANNOTATION: &str = "runwasi.io/module-source";
+
+const TITLE_ANNOTATION: &str = "org.opencontainers.image.title";
+
+/// What to fetch the modules of.
+#[derive(Clone, Debug)]
+pub struct FetchRequest {
+    /// The id of the container.
+    pub id: String,
+    /// The URI of the module, from the `runwasi.io/module-source` annotation.
+    /// Empty for the default fetcher.
+    pub source: String,
+}
+
+/// Fetches the modules of a container.
+pub trait ModuleFetcher: Send + Sync {
+    /// Returns the wasm layers of the container, and the platform they target.
+    fn fetch<'a>(
+        &'a self,
+        req: &'a FetchRequest,
+    ) -> LocalBoxFuture<'a, anyhow::Result<(Vec<WasmLayer>, Platform)>>;
+}
+
+const FILE_SCHEME: &str = "file";
+
+static INSTALLED: OnceLock<ModuleFetchers> = OnceLock::new();
+
+/// The fetchers of the module sources of the shim, by URI scheme.
+#[derive(Clone, Default)]
+pub struct ModuleFetchers {
+    fetchers: HashMap<String, Arc<dyn ModuleFetcher>>,
+}
+
+impl ModuleFetchers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fetches the module sources with the URI `scheme` with `fetcher`,
+    /// replacing any fetcher previously added for it.
+    pub fn with_fetcher(
+        mut self,
+        scheme: impl Into<String>,
+        fetcher: impl ModuleFetcher + 'static,
+    ) -> Self {
+        self.fetchers.insert(scheme.into(), Arc::new(fetcher));
+        self
+    }
+
+    /// Installs the fetchers for the containers of the shim. Called once, before
+    /// [`shim_main`](crate::sandbox::cli::shim_main). The `file` scheme is reserved for the
+    /// built-in fetcher, enabled by the runtime configuration.
+    pub fn install(self) -> anyhow::Result<()> {
+        if self.fetchers.contains_key(FILE_SCHEME) {
+            anyhow::bail!("the {FILE_SCHEME:?} scheme is reserved for the built-in fetcher");
+        }
+        INSTALLED
+            .set(self)
+            .map_err(|_| anyhow::anyhow!("module fetchers are already installed"))
+    }
+
+    fn get(&self, scheme: &str) -> Option<Arc<dyn ModuleFetcher>> {
+        self.fetchers.get(scheme).cloned()
+    }
+}
+
+/// Returns the module source of the container, if it isn't in the image.
+pub(crate) fn module_source(spec: &Spec) -> Option<&str> {
+    spec.annotations()
+        .as_ref()
+        .and_then(|a| a.get(MODULE_SOURCE_ANNOTATION))
+        .map(String::as_str)
+}
+
+/// Returns the fetcher installed for the scheme of `source`.
+pub(crate) fn fetcher_for(source: &str) -> Result<Arc<dyn ModuleFetcher>> {
+    let installed = INSTALLED.get_or_init(ModuleFetchers::default);
+    fetcher_in(installed, &RuntimeConfig::current(), source)
+}
+
+fn fetcher_in(
+    fetchers: &ModuleFetchers,
+    config: &RuntimeConfig,
+    source: &str,
+) -> Result<Arc<dyn ModuleFetcher>> {
+    let Some((scheme, _)) = source.split_once("://") else {
+        return Err(Error::InvalidArgument(format!(
+            "invalid {MODULE_SOURCE_ANNOTATION} {source:?}: expected a URI"
+        )));
+    };
+    if scheme == FILE_SCHEME {
+        let Some(file) = &config.file_module_source else {
+            return Err(Error::PermissionDenied(format!(
+                "{MODULE_SOURCE_ANNOTATION} {source:?}: file module sources are not enabled"
+            )));
+        };
+        return Ok(Arc::new(FileFetcher::new(&file.dir)));
+    }
+    fetchers.get(scheme).ok_or_else(|| {
+        Error::InvalidArgument(format!("no module fetcher installed for {scheme:?}"))
+    })
+}
+
+/// Reads modules from the host filesystem, from `file://` URIs under a directory.
+pub struct FileFetcher {
+    dir: PathBuf,
+}
+
+impl FileFetcher {
+    /// Reads the modules under `dir`, rejecting the URIs of other files.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    fn resolve(&self, source: &str) -> anyhow::Result<PathBuf> {
+        let path = source
+            .strip_prefix("file://")
+            .with_context(|| format!("not a file URI: {source:?}"))?;
+        let dir = self
+            .dir
+            .canonicalize()
+            .with_context(|| format!("failed to resolve {:?}", self.dir))?;
+        // symlinks and `..` are resolved before checking the file is under the directory
+        let path = Path::new(path)
+            .canonicalize()
+            .with_context(|| format!("failed to resolve {path:?}"))?;
+        if !path.starts_with(&dir) || !path.is_file() {
+            return Err(Error::PermissionDenied(format!(
+                "{path:?} is not a file under {dir:?}, the directory of file module sources"
+            ))
+            .into());
+        }
+        Ok(path)
+    }
+}
+
+impl ModuleFetcher for FileFetcher {
+    fn fetch<'a>(
+        &'a self,
+        req: &'a FetchRequest,
+    ) -> LocalBoxFuture<'a, anyhow::Result<(Vec<WasmLayer>, Platform)>> {
+        Box::pin(async move {
+            let path = self.resolve(&req.source)?;
+            let layer = read_file_layer(&path)?;
+            Ok((vec![layer], Platform::default()))
+        })
+    }
+}
+
+fn read_file_layer(path: &Path) -> anyhow::Result<WasmLayer> {
+    let module = std::fs::read(path).with_context(|| format!("failed to read {path:?}"))?;
+    let digest = Digest::try_from(format!("sha256:{}", sha256::digest(module.as_slice())))?;
+    let mut config = Descriptor::new(
+        MediaType::Other("application/wasm".to_string()),
+        module.len() as u64,
+        digest,
+    );
+    if let Some(name) = path.file_name() {
+        config.set_annotations(Some(HashMap::from([(
+            TITLE_ANNOTATION.to_string(),
+            name.to_string_lossy().to_string(),
+        )])));
+    }
+    Ok(WasmLayer {
+        config,
+        layer: module,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sandbox::async_utils::AmbientRuntime as _;
+    use crate::sandbox::config::FileModuleSourceConfig;
+
+    use super::*;
+
+    struct NoopFetcher;
+
+    impl ModuleFetcher for NoopFetcher {
+        fn fetch<'a>(
+            &'a self,
+            _req: &'a FetchRequest,
+        ) -> LocalBoxFuture<'a, anyhow::Result<(Vec<WasmLayer>, Platform)>> {
+            Box::pin(async { Ok((vec![], Platform::default())) })
+        }
+    }
+
+    fn file_config(dir: &Path) -> RuntimeConfig {
+        RuntimeConfig {
+            file_module_source: Some(FileModuleSourceConfig {
+                dir: dir.to_path_buf(),
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_file_fetcher() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("app.wasm");
+        std::fs::write(&path, b"\0asm\x01\0\0\0")?;
+
+        let req = FetchRequest {
+            id: "task".to_string(),
+            source: format!("file://{}", path.display()),
+        };
+        let fetcher = fetcher_in(
+            &ModuleFetchers::new(),
+            &file_config(dir.path()),
+            &req.source,
+        )?;
+        let (layers, _) = fetcher.fetch(&req).block_on()?;
+
+        assert_eq!(layers.len(), 1);
+        assert_eq!(layers[0].layer, b"\0asm\x01\0\0\0");
+        assert_eq!(
+            layers[0].config.annotations().as_ref().unwrap()[TITLE_ANNOTATION],
+            "app.wasm"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_file_fetcher_is_opt_in() {
+        let res = fetcher_in(
+            &ModuleFetchers::new(),
+            &RuntimeConfig::default(),
+            "file:///etc/shadow",
+        );
+        assert!(matches!(res, Err(Error::PermissionDenied(_))));
+    }
+
+    #[test]
+    fn test_file_fetcher_rejects_files_outside_dir() -> anyhow::Result<()> {
+        let root = tempfile::tempdir()?;
+        let dir = root.path().join("modules");
+        std::fs::create_dir(&dir)?;
+        let outside = root.path().join("secret");
+        std::fs::write(&outside, b"secret")?;
+        std::os::unix::fs::symlink(&outside, dir.join("link.wasm"))?;
+
+        let fetcher = FileFetcher::new(&dir);
+        for source in [
+            format!("file://{}", outside.display()),
+            format!("file://{}/../secret", dir.display()),
+            format!("file://{}/link.wasm", dir.display()),
+            format!("file://{}", dir.display()),
+        ] {
+            let req = FetchRequest {
+                id: "task".to_string(),
+                source,
+            };
+            let err = fetcher.fetch(&req).block_on().unwrap_err();
+            assert!(
+                matches!(err.downcast_ref(), Some(Error::PermissionDenied(_))),
+                "{err:#}"
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_installed_fetchers() -> anyhow::Result<()> {
+        let fetchers = ModuleFetchers::new().with_fetcher("ipfs", NoopFetcher);
+        let config = RuntimeConfig::default();
+        let req = FetchRequest {
+            id: "task".to_string(),
+            source: "ipfs://bafy".to_string(),
+        };
+        let (layers, _) = fetcher_in(&fetchers, &config, &req.source)?
+            .fetch(&req)
+            .block_on()?;
+        assert!(layers.is_empty());
+
+        assert!(fetcher_in(&fetchers, &config, "s3://bucket/app.wasm").is_err());
+        assert!(fetcher_in(&fetchers, &config, "/app.wasm").is_err());
+        assert!(ModuleFetchers::new()
+            .with_fetcher("file", NoopFetcher)
+            .install()
+            .is_err());
+        Ok(())
+    }
+}
@@ -5,8 +5,10 @@ use std::time::Duration;
 
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use tokio_util::sync::CancellationToken;
 
 use super::error::Error;
+use super::oci::WasmLayer;
 
 /// Generic options builder for creating a wasm instance.
 /// This is passed to the `Instance::new` method.
@@ -24,6 +26,11 @@ pub struct InstanceConfig {
     namespace: String,
     /// GRPC address back to main containerd
     containerd_address: String,
+    /// Whether the instance should be attached to a pseudo terminal
+    terminal: bool,
+    /// Cancelled when the task is killed or deleted before its creation completes
+    #[serde(skip)]
+    cancellation_token: CancellationToken,
 }
 
 impl InstanceConfig {
@@ -37,6 +44,8 @@ impl InstanceConfig {
             stdout: PathBuf::default(),
             stderr: PathBuf::default(),
             bundle: PathBuf::default(),
+            terminal: false,
+            cancellation_token: CancellationToken::new(),
         }
     }
 
@@ -84,6 +93,30 @@ impl InstanceConfig {
         &self.bundle
     }
 
+    /// set whether the instance should be attached to a pseudo terminal
+    pub fn set_terminal(&mut self, terminal: bool) -> &mut Self {
+        self.terminal = terminal;
+        self
+    }
+
+    /// get whether the instance should be attached to a pseudo terminal
+    pub fn get_terminal(&self) -> bool {
+        self.terminal
+    }
+
+    /// set the token cancelled when the creation of the instance should be abandoned
+    pub fn set_cancellation_token(&mut self, token: CancellationToken) -> &mut Self {
+        self.cancellation_token = token;
+        self
+    }
+
+    /// get the token cancelled when the creation of the instance should be abandoned.
+    /// Long running steps of `Instance::new` (e.g., fetching or compiling modules)
+    /// should stop early when it is cancelled.
+    pub fn get_cancellation_token(&self) -> &CancellationToken {
+        &self.cancellation_token
+    }
+
     /// get the namespace for the instance
     pub fn get_namespace(&self) -> String {
         self.namespace.clone()
@@ -120,6 +153,55 @@ pub trait Instance: 'static {
     /// This is called after the instance has exited.
     fn delete(&self) -> Result<(), Error>;
 
+    /// Resize the pseudo terminal of the instance
+    /// This is only called for instances created with `terminal: true`.
+    fn resize_pty(&self, _width: u32, _height: u32) -> Result<(), Error> {
+        Err(Error::FailedPrecondition(
+            "terminal is not supported".to_string(),
+        ))
+    }
+
+    /// Close the stdin of the instance
+    fn close_stdin(&self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Precompiles the wasm `layers` of an image as it is pulled, and stores their precompiled
+    /// content in containerd, so that the first container of the image doesn't compile them.
+    /// This is called by the `precompile` command of the shim, see [`crate::sandbox::cli`].
+    fn precompile_pulled_layers(
+        _containerd_address: &str,
+        _namespace: &str,
+        _layers: &[WasmLayer],
+    ) -> Result<(), Error>
+    where
+        Self: Sized,
+    {
+        Err(Error::FailedPrecondition(
+            "the shim doesn't precompile layers".to_string(),
+        ))
+    }
+
+    /// Builds the engine of the instances, failing if it can't run on this host.
+    /// This is used by the `healthcheck` command of the shim.
+    fn try_engine() -> Result<Self::Engine, Error>
+    where
+        Self: Sized,
+        Self::Engine: Default,
+    {
+        Ok(Self::Engine::default())
+    }
+
+    /// The directories the state of the instances can be kept in, in order of preference.
+    /// With `landlock`, the shim is confined to the state directory of its namespace in the
+    /// first writable of them, see [`crate::sandbox::config::LandlockConfig`].
+    fn state_roots() -> Vec<PathBuf>
+    where
+        Self: Sized,
+    {
+        Vec::new()
+    }
+
     /// Waits for the instance to finish and returns its exit code
     /// This is a blocking call.
     #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Info"))]
@@ -25,19 +25,69 @@ pub fn determine_rootdir(
     namespace: &str,
     rootdir: impl AsRef<Path> + std::fmt::Debug,
 ) -> Result<PathBuf, Error> {
-    let file = match File::open(bundle.as_ref().join("options.json")) {
-        Ok(f) => f,
-        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(rootdir.as_ref().join(namespace)),
-        Err(e) => return Err(e.into()),
-    };
-    let path = serde_json::from_reader::<_, Options>(file)?
-        .root
+    let path = options_root(bundle.as_ref())?
         .unwrap_or_else(|| rootdir.as_ref().to_owned())
         .join(namespace);
     log::info!("container runtime root path is {path:?}");
     Ok(path)
 }
 
+/// Resolves the root directory for the container runtime, and checks that it is writable.
+///
+/// The `root` in the `options.json` file of the `bundle` is used if it is set, and must be
+/// writable. Otherwise, the first writable of `rootdirs` is used, joined with `namespace`.
+/// This fails with a description of each directory that was tried when none is writable,
+/// e.g., on hosts with a read-only `/run`.
+#[cfg_attr(feature = "tracing", tracing::instrument(level = "Debug"))]
+pub fn resolve_rootdir(
+    bundle: impl AsRef<Path> + std::fmt::Debug,
+    namespace: &str,
+    rootdirs: &[PathBuf],
+) -> Result<PathBuf, Error> {
+    let rootdirs = match options_root(bundle.as_ref())? {
+        Some(root) => vec![root],
+        None => rootdirs.to_vec(),
+    };
+    let mut failures = vec![];
+    for rootdir in rootdirs {
+        let path = rootdir.join(namespace);
+        match probe_writable(&path) {
+            Ok(()) => {
+                log::info!("container runtime root path is {path:?}");
+                return Ok(path);
+            }
+            Err(err) => {
+                log::warn!("container runtime root path {path:?} is not writable: {err}");
+                failures.push(format!("{path:?}: {err}"));
+            }
+        }
+    }
+    Err(Error::FailedPrecondition(format!(
+        "no writable state directory ({}), configure `state_root` in the runtime configuration or the `root` option of the runtime in containerd",
+        failures.join(", ")
+    )))
+}
+
+// The `root` option of the runtime, passed by containerd in `options.json`.
+pub(crate) fn options_root(bundle: &Path) -> Result<Option<PathBuf>, Error> {
+    let file = match File::open(bundle.join("options.json")) {
+        Ok(f) => f,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    Ok(serde_json::from_reader::<_, Options>(file)?.root)
+}
+
+// Creates `dir` if needed, and checks that files can be created in it.
+// This fails early on a read-only or full filesystem, where libcontainer fails later with
+// less helpful errors.
+fn probe_writable(dir: &Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(dir)?;
+    let probe = dir.join(format!(".probe-{}", std::process::id()));
+    File::create(&probe)?;
+    std::fs::remove_file(&probe)
+}
+
 #[cfg(unix)]
 #[cfg(test)]
 mod tests {
@@ -74,6 +124,29 @@ mod tests {
         );
         Ok(())
     }
+
+    #[test]
+    fn test_resolve_rootdir_falls_back() -> Result<(), Error> {
+        let dir = tempdir()?;
+        let namespace = "test_namespace";
+        // a directory can't be created under a file
+        let unwritable = dir.path().join("file");
+        std::fs::write(&unwritable, "")?;
+        let fallback = dir.path().join("fallback");
+
+        let root = resolve_rootdir(
+            dir.path(),
+            namespace,
+            &[unwritable.clone(), fallback.clone()],
+        )?;
+        assert_eq!(root, fallback.join(namespace));
+        assert!(root.is_dir());
+
+        let err = resolve_rootdir(dir.path(), namespace, &[unwritable]).unwrap_err();
+        assert!(matches!(err, Error::FailedPrecondition(_)));
+        assert!(err.to_string().contains("state_root"));
+        Ok(())
+    }
 }
 
 #[cfg(test)]
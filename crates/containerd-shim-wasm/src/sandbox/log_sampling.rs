@@ -0,0 +1,133 @@
+//! Sampling of the log lines repeated at high frequency.
+//!
+//! Some lines are logged for every container, e.g., when the image of a container has no wasm
+//! layers, and flood the logs of busy nodes. Those are logged with [`sampled!`], which, for the
+//! levels in `log_sampling` in the runtime configuration, logs the same message of a call site,
+//! with the same fields, e.g., the id of the container and the error, at most once per window.
+//! The first line logged after a window says how many identical lines were collapsed:
+//!
+//! ```text
+//! Error obtaining wasm layers for container c2 [...] (12 identical lines suppressed)
+//! ```
+//!
+//! Lines of other levels, or without `log_sampling`, are all logged.
+
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+use log::Level;
+
+use super::config::RuntimeConfig;
+
+/// A call site of [`sampled!`].
+pub(crate) type CallSite = (&'static str, u32);
+
+/// The windows that expired are dropped once there are this many, with their counts.
+const MAX_WINDOWS: usize = 1024;
+
+struct Window {
+    started: Instant,
+    suppressed: u64,
+}
+
+// the windows of the messages of each call site
+static WINDOWS: LazyLock<Mutex<HashMap<(CallSite, String), Window>>> =
+    LazyLock::new(Default::default);
+
+/// Returns whether the `message` of `level` at `site` is logged, and if so how many identical
+/// lines were suppressed since the last one.
+pub(crate) fn admit(level: Level, site: CallSite, message: &str) -> Option<u64> {
+    let config = RuntimeConfig::current();
+    let Some(window) = config.log_sampling.as_ref().and_then(|s| s.window(level)) else {
+        return Some(0);
+    };
+    admit_in(
+        &mut WINDOWS.lock().unwrap(),
+        (site, message.to_string()),
+        window,
+        Instant::now(),
+    )
+}
+
+fn admit_in(
+    windows: &mut HashMap<(CallSite, String), Window>,
+    key: (CallSite, String),
+    window: Duration,
+    now: Instant,
+) -> Option<u64> {
+    match windows.get_mut(&key) {
+        Some(w) if now.duration_since(w.started) < window => {
+            w.suppressed += 1;
+            None
+        }
+        Some(w) => {
+            let suppressed = std::mem::take(&mut w.suppressed);
+            w.started = now;
+            Some(suppressed)
+        }
+        None => {
+            if windows.len() >= MAX_WINDOWS {
+                windows.retain(|_, w| now.duration_since(w.started) < window);
+            }
+            windows.insert(
+                key,
+                Window {
+                    started: now,
+                    suppressed: 0,
+                },
+            );
+            Some(0)
+        }
+    }
+}
+
+/// Logs like [`log::log!`], collapsing the identical lines of the call site repeated within
+/// the window of `level` in the `log_sampling` of the runtime configuration.
+macro_rules! sampled {
+    ($level:expr, $($arg:tt)+) => {{
+        let level = $level;
+        if log::log_enabled!(level) {
+            let message = format!($($arg)+);
+            match $crate::sandbox::log_sampling::admit(level, (module_path!(), line!()), &message) {
+                Some(0) => log::log!(level, "{message}"),
+                Some(suppressed) => log::log!(
+                    level,
+                    "{message} ({suppressed} identical lines suppressed)"
+                ),
+                None => {}
+            }
+        }
+    }};
+}
+
+pub(crate) use sampled;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_admit() {
+        let mut windows = HashMap::new();
+        let key = |line, message: &str| (("test", line), message.to_string());
+        let window = Duration::from_secs(60);
+        let now = Instant::now();
+
+        let c1 = key(1, "no wasm layers for c1");
+        assert_eq!(admit_in(&mut windows, c1.clone(), window, now), Some(0));
+        assert_eq!(admit_in(&mut windows, c1.clone(), window, now), None);
+        assert_eq!(admit_in(&mut windows, c1.clone(), window, now), None);
+        // other messages of the call site, or other call sites, aren't collapsed with it
+        let c2 = key(1, "no wasm layers for c2");
+        assert_eq!(admit_in(&mut windows, c2, window, now), Some(0));
+        assert_eq!(
+            admit_in(&mut windows, key(2, "no wasm layers for c1"), window, now),
+            Some(0)
+        );
+
+        let later = now + window;
+        assert_eq!(admit_in(&mut windows, c1.clone(), window, later), Some(2));
+        assert_eq!(admit_in(&mut windows, c1, window, later), None);
+    }
+}
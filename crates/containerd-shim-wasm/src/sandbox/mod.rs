@@ -73,10 +73,19 @@
 //! For simpler use cases, consider using the [`crate::container`] module instead.
 
 pub mod cli;
+pub mod config;
+pub mod embedded;
 pub mod error;
+pub mod fetcher;
 pub mod instance;
 pub mod instance_utils;
+pub mod redact;
+pub mod schema;
 pub mod shim;
+#[cfg(unix)]
+pub mod sidecar;
+pub mod spec_mutator;
+pub mod suspend;
 pub mod sync;
 
 pub use error::{Error, Result};
@@ -88,3 +97,9 @@ pub(crate) mod oci;
 pub use oci::WasmLayer;
 
 pub(crate) mod async_utils;
+pub(crate) mod compile_pool;
+pub(crate) mod cpu_features;
+pub(crate) mod log_sampling;
+pub(crate) mod panics;
+pub(crate) mod stream_processor;
+pub(crate) mod timings;
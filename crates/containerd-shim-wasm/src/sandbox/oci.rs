@@ -11,14 +11,29 @@ use oci_spec::image::Descriptor;
 use serde::{Deserialize, Serialize};
 
 use super::error::Result;
+use super::redact::Redacted;
+use crate::container::{shared_layer, ModuleBytes};
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct WasmLayer {
     pub config: Descriptor,
+    /// The content of the layer. Empty in the container process for the wasm modules shared
+    /// with the other containers of the node, see [`WasmLayer::bytes`].
     #[serde(with = "serde_bytes")]
     pub layer: Vec<u8>,
 }
 
+impl WasmLayer {
+    /// Returns the content of the layer, mapped from the artifact cache of the node if it's
+    /// shared with the other containers.
+    pub fn bytes(&self) -> ModuleBytes<'_> {
+        match shared_layer(self.config.digest()) {
+            Some(bytes) => bytes.into(),
+            None => self.layer.as_slice().into(),
+        }
+    }
+}
+
 fn parse_env(envs: &[String]) -> HashMap<String, String> {
     // make NAME=VALUE to HashMap<NAME, VALUE>.
     envs.iter()
@@ -71,7 +86,7 @@ pub(crate) fn setup_prestart_hooks(hooks: &Option<oci_spec::runtime::Hooks>) ->
             } else {
                 HashMap::new()
             };
-            log::debug!("run_hooks envs: {:?}", envs);
+            log::debug!("run_hooks envs: {:?}", Redacted(hook.env()));
 
             let mut hook_process = hook_command
                 .env_clear()
@@ -0,0 +1,82 @@
+//! Logging of the panics of the shim.
+//!
+//! Much of the work of the shim runs on background threads, like the threads waiting for
+//! tasks to exit and the pumps of their output. A panic is only printed to the stderr of the
+//! shim, which containerd doesn't keep, and leaves whatever depends on the thread stuck.
+//!
+//! [`install_hook`] logs panics with the logger of the shim as well as to stderr, and
+//! [`guarded`] runs the body of a thread so that it can fall back to a safe state, like
+//! reporting its task as failed, instead of silently never finishing.
+//!
+//! The fallbacks rely on unwinding, so the shims are built with `panic = "unwind"`. A shim built
+//! with `panic = "abort"` still logs the panic, with a warning when it starts, but aborts before
+//! any fallback runs, and containerd cleans its tasks up as failed.
+
+#![cfg_attr(windows, allow(dead_code))] // background threads are only guarded on linux
+
+use std::any::Any;
+use std::fmt::Display;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::Once;
+use std::thread;
+
+/// Logs panics with the logger of the shim, before printing them to stderr as usual.
+pub(crate) fn install_hook() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            let thread = thread::current();
+            let name = thread.name().unwrap_or("<unnamed>");
+            let location = info.location().map(ToString::to_string);
+            log::error!(
+                "thread {name:?} panicked at {}: {}",
+                location.as_deref().unwrap_or("<unknown>"),
+                message(info.payload())
+            );
+            previous(info);
+        }));
+        if cfg!(panic = "abort") {
+            log::warn!("the shim is built with panic = \"abort\", a panic of any thread aborts it");
+        }
+    });
+}
+
+/// Runs `f`, the body of a background thread doing `context`.
+/// Returns None if it panicked, after logging the panic with `context`, so that the caller
+/// can degrade safely.
+pub(crate) fn guarded<T>(context: impl Display, f: impl FnOnce() -> T) -> Option<T> {
+    match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(res) => Some(res),
+        Err(payload) => {
+            log::error!("panic in {context}: {}", message(payload.as_ref()));
+            None
+        }
+    }
+}
+
+fn message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(msg) = payload.downcast_ref::<&str>() {
+        msg
+    } else if let Some(msg) = payload.downcast_ref::<String>() {
+        msg
+    } else {
+        "Box<dyn Any>"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_guarded() {
+        assert_eq!(guarded("test", || 42), Some(42));
+        assert_eq!(guarded("test", || -> i32 { panic!("boom") }), None);
+
+        let payload = catch_unwind(|| panic!("{} {}", "formatted", "boom")).unwrap_err();
+        assert_eq!(message(payload.as_ref()), "formatted boom");
+        let payload = catch_unwind(|| std::panic::panic_any(42)).unwrap_err();
+        assert_eq!(message(payload.as_ref()), "Box<dyn Any>");
+    }
+}
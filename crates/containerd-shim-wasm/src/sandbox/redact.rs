@@ -0,0 +1,167 @@
+//! Redaction of the secrets in the environment variables of containers.
+//!
+//! The env of a container often carries credentials, which end up in the logs, in the
+//! attributes of spans, and in the wire-debug output whenever the spec or the env is
+//! printed. Values of `KEY=VALUE` variables whose key looks like a secret are replaced with
+//! `<redacted>` before they are printed.
+//!
+//! Keys are matched case-insensitively against the substrings in [`DEFAULT_KEYS`], and the
+//! `keys` of the `redaction` of the runtime configuration.
+//! The debug dump and the audit log of the shim don't include the env of containers.
+
+use std::borrow::Cow;
+use std::fmt::{Debug, Formatter};
+use std::sync::RwLock;
+
+use crate::sandbox::config::{RedactionConfig, RuntimeConfig};
+
+/// Replaces the values of secrets.
+pub const REDACTED: &str = "<redacted>";
+
+/// Substrings of the keys redacted by default.
+pub const DEFAULT_KEYS: &[&str] = &[
+    "secret",
+    "password",
+    "passwd",
+    "token",
+    "key",
+    "auth",
+    "credential",
+];
+
+// The container process doesn't see the runtime config, the shim passes it the redaction of
+// every build, so that a reloaded configuration applies to the containers built afterwards.
+static PROCESS_CONFIG: RwLock<Option<RedactionConfig>> = RwLock::new(None);
+
+/// Sets the redaction used by this process instead of the one of the runtime configuration,
+/// replacing the one set before.
+#[cfg_attr(windows, allow(dead_code))] // containers only run in their own process on linux
+pub(crate) fn set_process_config(config: RedactionConfig) {
+    *PROCESS_CONFIG.write().unwrap() = Some(config);
+}
+
+/// The keys redacted by this process.
+struct Keys(Vec<String>);
+
+impl Keys {
+    fn current() -> Self {
+        match PROCESS_CONFIG.read().unwrap().as_ref() {
+            Some(config) => Self::from_config(config),
+            None => Self::from_config(&RuntimeConfig::current().redaction),
+        }
+    }
+
+    fn from_config(config: &RedactionConfig) -> Self {
+        let defaults = DEFAULT_KEYS
+            .iter()
+            .filter(|_| config.default_keys)
+            .map(|k| k.to_string());
+        let custom = config.keys.iter().map(|k| k.to_ascii_lowercase());
+        Self(defaults.chain(custom).collect())
+    }
+
+    fn is_sensitive(&self, key: &str) -> bool {
+        let key = key.to_ascii_lowercase();
+        self.0.iter().any(|k| key.contains(k.as_str()))
+    }
+
+    fn assignment<'a>(&self, assignment: &'a str) -> Cow<'a, str> {
+        match assignment.split_once('=') {
+            Some((key, _)) if self.is_sensitive(key) => format!("{key}={REDACTED}").into(),
+            _ => assignment.into(),
+        }
+    }
+}
+
+/// Returns true if the value of the env var `key` is redacted.
+pub fn is_sensitive_key(key: &str) -> bool {
+    Keys::current().is_sensitive(key)
+}
+
+/// Redacts the value of the `KEY=VALUE` env var `assignment`, if `KEY` is sensitive.
+pub fn assignment(assignment: &str) -> Cow<'_, str> {
+    Keys::current().assignment(assignment)
+}
+
+/// Formats a value with [`Debug`], with the values of the `"KEY=VALUE"` strings whose key is
+/// sensitive redacted, e.g. `Redacted(spec)` or `Redacted(ctx.envs())`.
+pub struct Redacted<T>(pub T);
+
+impl<T: Debug> Debug for Redacted<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let text = if f.alternate() {
+            format!("{:#?}", self.0)
+        } else {
+            format!("{:?}", self.0)
+        };
+        f.write_str(&redact_strings(&text, &Keys::current()))
+    }
+}
+
+// Redacts the quoted strings of `text` that are sensitive assignments.
+fn redact_strings(text: &str, keys: &Keys) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('"') {
+        let (head, tail) = rest.split_at(start + 1);
+        out.push_str(head);
+        let len = quoted_len(tail);
+        out.push_str(&keys.assignment(&tail[..len]));
+        // the closing quote, if any
+        let end = (len + 1).min(tail.len());
+        out.push_str(&tail[len..end]);
+        rest = &tail[end..];
+    }
+    out.push_str(rest);
+    out
+}
+
+// Length of the quoted string at the start of `text`, up to its closing quote.
+fn quoted_len(text: &str) -> usize {
+    let mut escaped = false;
+    for (i, c) in text.char_indices() {
+        match c {
+            '\\' if !escaped => escaped = true,
+            '"' if !escaped => return i,
+            _ => escaped = false,
+        }
+    }
+    text.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redact_strings() {
+        let keys = Keys::from_config(&RedactionConfig::default());
+        let env = vec![
+            "PATH=/bin".to_string(),
+            "DB_PASSWORD=hunter2".to_string(),
+            "Api_Key=\"quoted\"".to_string(),
+        ];
+        let text = redact_strings(&format!("{env:?}"), &keys);
+        assert_eq!(
+            text,
+            r#"["PATH=/bin", "DB_PASSWORD=<redacted>", "Api_Key=<redacted>"]"#
+        );
+    }
+
+    #[test]
+    fn test_custom_keys() {
+        let keys = Keys::from_config(&RedactionConfig {
+            keys: vec!["DSN".to_string()],
+            default_keys: false,
+        });
+        assert_eq!(
+            keys.assignment("SENTRY_DSN=https://x"),
+            "SENTRY_DSN=<redacted>"
+        );
+        assert_eq!(
+            keys.assignment("DB_PASSWORD=hunter2"),
+            "DB_PASSWORD=hunter2"
+        );
+        assert_eq!(keys.assignment("DSN"), "DSN");
+    }
+}
@@ -0,0 +1,291 @@
+//! Machine-readable schema of the annotations and the runtime configuration of the shim.
+//!
+//! `schema` prints the JSON schema returned by [`schema`], for platforms to validate
+//! workloads and configuration files against, and to generate documentation from:
+//!
+//! ```json
+//! {"config":{"$schema":"http://json-schema.org/draft-07/schema#","title":"RuntimeConfig",...},"annotations":{"type":"object","properties":{"runwasi.io/guest-log":{"type":"string","enum":["stdio","shim"],"default":"stdio",...},...}}}
+//! ```
+//!
+//! The schema of the configuration file is generated from [`RuntimeConfig`], and the
+//! annotations are listed in [`ANNOTATIONS`], next to the constants the shim reads them with.
+
+use serde_json::{json, Map, Value};
+
+use crate::container::{
+    DRAIN_WINDOW_ANNOTATION, GUEST_LOG_ANNOTATION, INHERIT_FDS_ANNOTATION, LAYER_ROLE_ANNOTATION,
+    TERMINATION_GRACE_PERIOD_ANNOTATION, TERMINATION_MESSAGE_PATH_ANNOTATION,
+    WRITE_ALLOW_ANNOTATION,
+};
+use crate::sandbox::config::RuntimeConfig;
+use crate::sandbox::containerd::PULL_MODULES_ANNOTATION;
+use crate::sandbox::fetcher::MODULE_SOURCE_ANNOTATION;
+#[cfg(unix)]
+use crate::sys::container::{
+    ATTACHABLE_ANNOTATION, CAPTURE_OUTPUT_ANNOTATION, COMBINED_OUTPUT_ANNOTATION,
+    CPU_BOOST_ANNOTATION, LOG_DRIVER_ANNOTATION, LOG_LINE_FORMAT_ANNOTATION,
+    LOG_MAX_FILES_ANNOTATION, LOG_MAX_SIZE_ANNOTATION, STDIN_DATA_ANNOTATION,
+    STDIN_FILE_ANNOTATION,
+};
+
+/// The values an annotation accepts. Annotation values are always strings.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AnnotationValue {
+    /// `true` or `false`.
+    Bool,
+    /// A non-negative integer.
+    Integer,
+    /// One of the listed values.
+    Enum(&'static [&'static str]),
+    /// Any string, in the format of the description.
+    String,
+}
+
+/// An annotation read by the shim.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AnnotationSchema {
+    /// Name of the annotation.
+    pub name: &'static str,
+    /// The values it accepts.
+    pub value: AnnotationValue,
+    /// The value used when it isn't set, if any.
+    pub default: Option<&'static str>,
+    /// What it does.
+    pub description: &'static str,
+}
+
+/// The annotations of the container read by the shim.
+/// `runwasi.io/layer-role` is read from the layers of the image instead.
+pub const ANNOTATIONS: &[AnnotationSchema] = &[
+    AnnotationSchema {
+        name: MODULE_SOURCE_ANNOTATION,
+        value: AnnotationValue::String,
+        default: None,
+        description: "URI of the module of the container, when it isn't in the image.",
+    },
+    AnnotationSchema {
+        name: PULL_MODULES_ANNOTATION,
+        value: AnnotationValue::String,
+        default: None,
+        description: "Modules to pull at create time, in addition to the modules of the image, as a comma separated list of `name=reference`.",
+    },
+    AnnotationSchema {
+        name: LAYER_ROLE_ANNOTATION,
+        value: AnnotationValue::Enum(&["command", "library", "data"]),
+        default: None,
+        description: "Role of a wasm layer of the image, set on the layer.",
+    },
+    AnnotationSchema {
+        name: WRITE_ALLOW_ANNOTATION,
+        value: AnnotationValue::String,
+        default: None,
+        description: "Comma separated list of glob patterns the guest is allowed to write to. If unset, the guest can write anywhere its mounts allow.",
+    },
+    AnnotationSchema {
+        name: TERMINATION_GRACE_PERIOD_ANNOTATION,
+        value: AnnotationValue::Integer,
+        default: Some("30"),
+        description: "Number of seconds a guest is given to terminate.",
+    },
+    AnnotationSchema {
+        name: TERMINATION_MESSAGE_PATH_ANNOTATION,
+        value: AnnotationValue::String,
+        default: Some("/dev/termination-log"),
+        description: "Path of the termination message of the container, set by the kubelet. The shim writes the exit report of a container its engine terminated to the file mounted there.",
+    },
+    AnnotationSchema {
+        name: DRAIN_WINDOW_ANNOTATION,
+        value: AnnotationValue::Integer,
+        default: None,
+        description: "Number of milliseconds the background tasks of a guest that exited with 0 are waited for, before its instance is torn down.",
+    },
+    AnnotationSchema {
+        name: INHERIT_FDS_ANNOTATION,
+        value: AnnotationValue::String,
+        default: None,
+        description: "Path of the socket the platform sends file descriptors on.",
+    },
+    AnnotationSchema {
+        name: GUEST_LOG_ANNOTATION,
+        value: AnnotationValue::Enum(&["stdio", "shim"]),
+        default: Some("stdio"),
+        description: "Where the log records of the guest go.",
+    },
+    #[cfg(unix)]
+    AnnotationSchema {
+        name: LOG_DRIVER_ANNOTATION,
+        value: AnnotationValue::Enum(&["containerd", "journald"]),
+        default: None,
+        description: "Log driver of the container. If unset, the `log_driver` of the runtime configuration is used.",
+    },
+    #[cfg(unix)]
+    AnnotationSchema {
+        name: LOG_LINE_FORMAT_ANNOTATION,
+        value: AnnotationValue::Enum(&["raw", "cri"]),
+        default: Some("raw"),
+        description: "Format of the lines of the output of the container.",
+    },
+    #[cfg(unix)]
+    AnnotationSchema {
+        name: LOG_MAX_SIZE_ANNOTATION,
+        value: AnnotationValue::Integer,
+        default: None,
+        description: "Size in bytes a log file is rotated at. If unset, the `log_rotation` of the runtime configuration is used.",
+    },
+    #[cfg(unix)]
+    AnnotationSchema {
+        name: LOG_MAX_FILES_ANNOTATION,
+        value: AnnotationValue::Integer,
+        default: None,
+        description: "Number of log files to keep, including the current one. If unset, the `log_rotation` of the runtime configuration is used.",
+    },
+    #[cfg(unix)]
+    AnnotationSchema {
+        name: COMBINED_OUTPUT_ANNOTATION,
+        value: AnnotationValue::Bool,
+        default: Some("false"),
+        description: "Writes stdout and stderr to a single stream, using docker's stream framing.",
+    },
+    #[cfg(unix)]
+    AnnotationSchema {
+        name: CAPTURE_OUTPUT_ANNOTATION,
+        value: AnnotationValue::Bool,
+        default: Some("false"),
+        description: "Captures the output of the container to files, as per the `capture` of the runtime configuration.",
+    },
+    #[cfg(unix)]
+    AnnotationSchema {
+        name: ATTACHABLE_ANNOTATION,
+        value: AnnotationValue::Bool,
+        default: Some("false"),
+        description: "Keeps the output of the container while no reader is attached.",
+    },
+    #[cfg(unix)]
+    AnnotationSchema {
+        name: CPU_BOOST_ANNOTATION,
+        value: AnnotationValue::Bool,
+        default: Some("false"),
+        description: "Boosts the CPU quota of the container while it starts, as per the `cpu_boost` of the runtime configuration.",
+    },
+    #[cfg(unix)]
+    AnnotationSchema {
+        name: STDIN_FILE_ANNOTATION,
+        value: AnnotationValue::String,
+        default: None,
+        description: "Path of a file, inside the container, to use as the stdin of the guest.",
+    },
+    #[cfg(unix)]
+    AnnotationSchema {
+        name: STDIN_DATA_ANNOTATION,
+        value: AnnotationValue::String,
+        default: None,
+        description: "Literal data to use as the stdin of the guest.",
+    },
+];
+
+impl AnnotationSchema {
+    fn to_json(self) -> Value {
+        let mut schema = match self.value {
+            AnnotationValue::Bool => json!({"type": "string", "enum": ["true", "false"]}),
+            AnnotationValue::Integer => json!({"type": "string", "pattern": "^[0-9]+$"}),
+            AnnotationValue::Enum(values) => json!({"type": "string", "enum": values}),
+            AnnotationValue::String => json!({"type": "string"}),
+        };
+        if let Some(default) = self.default {
+            schema["default"] = default.into();
+        }
+        schema["description"] = self.description.into();
+        schema
+    }
+}
+
+/// Returns the JSON schema of the runtime configuration file, as `config`, and of the
+/// annotations of containers, as `annotations`.
+pub fn schema() -> Value {
+    let config = schemars::schema_for!(RuntimeConfig);
+    let annotations: Map<String, Value> = ANNOTATIONS
+        .iter()
+        .map(|annotation| (annotation.name.to_string(), annotation.to_json()))
+        .collect();
+    json!({
+        "config": config,
+        "annotations": {
+            "type": "object",
+            "properties": annotations,
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::container::DEFAULT_TERMINATION_GRACE_PERIOD;
+
+    #[test]
+    fn test_schema() {
+        let schema = schema();
+        let config = &schema["config"]["properties"];
+        assert_eq!(
+            config["stop_timeout_secs"]["type"],
+            json!(["integer", "null"])
+        );
+        assert_eq!(config["log_format"]["default"], "text");
+        assert!(config["otlp"].is_object());
+
+        let guest_log = &schema["annotations"]["properties"][GUEST_LOG_ANNOTATION];
+        assert_eq!(guest_log["enum"], json!(["stdio", "shim"]));
+        assert_eq!(guest_log["default"], "stdio");
+
+        let termination = &schema["annotations"]["properties"][TERMINATION_GRACE_PERIOD_ANNOTATION];
+        assert_eq!(
+            termination["default"],
+            DEFAULT_TERMINATION_GRACE_PERIOD.as_secs().to_string()
+        );
+
+        let mut names: Vec<_> = ANNOTATIONS.iter().map(|a| a.name).collect();
+        names.sort();
+        names.dedup();
+        assert_eq!(names.len(), ANNOTATIONS.len());
+    }
+
+    // The annotations the shim reads are the `*_ANNOTATION` constants of the crate.
+    #[cfg(unix)]
+    #[test]
+    fn test_every_annotation_is_listed() {
+        fn annotations_in(dir: &Path, found: &mut Vec<String>) {
+            for entry in std::fs::read_dir(dir).unwrap() {
+                let path = entry.unwrap().path();
+                if path.is_dir() {
+                    annotations_in(&path, found);
+                    continue;
+                }
+                if path.extension().map_or(true, |ext| ext != "rs") {
+                    continue;
+                }
+                let source = std::fs::read_to_string(&path).unwrap();
+                for line in source.lines() {
+                    let Some((_, value)) = line.split_once("_ANNOTATION: &str = \"") else {
+                        continue;
+                    };
+                    let value = value.split_once('"').unwrap().0;
+                    found.push(value.to_string());
+                }
+            }
+        }
+
+        let mut found = vec![];
+        annotations_in(
+            &Path::new(env!("CARGO_MANIFEST_DIR")).join("src"),
+            &mut found,
+        );
+        assert!(found.contains(&DRAIN_WINDOW_ANNOTATION.to_string()));
+        for annotation in found {
+            assert!(
+                ANNOTATIONS.iter().any(|a| a.name == annotation),
+                "{annotation} is not listed in ANNOTATIONS"
+            );
+        }
+    }
+}
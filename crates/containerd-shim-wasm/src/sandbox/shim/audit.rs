@@ -0,0 +1,209 @@
+//! Audit log of the lifecycle operations on tasks.
+//!
+//! When `audit` is set in the runtime configuration, the create, start, kill, delete and
+//! exec requests on tasks of the configured namespaces are appended to the audit file as
+//! JSON lines, e.g.:
+//!
+//! ```json
+//! {"time":"2024-01-01T00:00:00.000000Z","namespace":"k8s.io","operation":"kill","id":"app","peer":{"pid":1234,"uid":0,"gid":0},"outcome":"ok"}
+//! ```
+//!
+//! The caller is identified by the credentials of the process on the other end of the
+//! shim socket, usually containerd, as reported by the kernel.
+//! The checks the shim does on its own as it creates a task, e.g., the verification of the
+//! provenance of its image, are recorded without a caller, with their details:
+//!
+//! ```json
+//! {"time":"2024-01-01T00:00:00.000000Z","namespace":"k8s.io","operation":"verify-provenance","id":"app","peer":null,"outcome":"ok","details":{"image":"ghcr.io/my-org/app:v1","builder_id":"https://github.com/actions/runner"}}
+//! ```
+//!
+//! The records aren't published as containerd events, which every event subscriber of the
+//! namespace can read. Audit records are best effort: failing to write one is logged, and
+//! doesn't fail the operation.
+
+use std::fs::OpenOptions;
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+
+use chrono::{SecondsFormat, Utc};
+use containerd_shim::TtrpcContext;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::sandbox::config::RuntimeConfig;
+use crate::sandbox::Result;
+
+/// Credentials of the caller of an operation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub(super) struct Peer {
+    pid: i32,
+    uid: u32,
+    gid: u32,
+}
+
+impl Peer {
+    /// Returns the credentials of the process on the other end of the connection of `ctx`.
+    #[cfg(unix)]
+    pub fn of(ctx: &TtrpcContext) -> Option<Self> {
+        use std::os::fd::BorrowedFd;
+
+        use nix::sys::socket::{getsockopt, sockopt};
+
+        // Safety: the connection of the request is open while the request is served
+        let fd = unsafe { BorrowedFd::borrow_raw(ctx.fd) };
+        let creds = getsockopt(&fd, sockopt::PeerCredentials)
+            .inspect_err(|err| log::debug!("failed to get the credentials of the caller: {err}"))
+            .ok()?;
+        Some(Self {
+            pid: creds.pid(),
+            uid: creds.uid(),
+            gid: creds.gid(),
+        })
+    }
+
+    #[cfg(not(unix))]
+    pub fn of(_ctx: &TtrpcContext) -> Option<Self> {
+        None
+    }
+}
+
+#[derive(Serialize)]
+struct Record<'a> {
+    time: String,
+    namespace: &'a str,
+    operation: &'a str,
+    id: &'a str,
+    peer: Option<Peer>,
+    outcome: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(skip_serializing_if = "Value::is_null")]
+    details: Value,
+}
+
+/// Calls `f`, which does `operation` on the task `id`, and records it in the audit log
+/// when it is enabled for `namespace`.
+pub(super) fn call<T>(
+    ctx: &TtrpcContext,
+    namespace: &str,
+    operation: &str,
+    id: &str,
+    f: impl FnOnce() -> Result<T>,
+) -> Result<T> {
+    let config = RuntimeConfig::current();
+    let Some(audit) = config.audit.as_ref().filter(|a| a.applies_to(namespace)) else {
+        return f();
+    };
+
+    let time = Utc::now().to_rfc3339_opts(SecondsFormat::Micros, true);
+    let peer = Peer::of(ctx);
+    let res = f();
+    let record = Record {
+        time,
+        namespace,
+        operation,
+        id,
+        peer,
+        outcome: if res.is_ok() { "ok" } else { "error" },
+        error: res.as_ref().err().map(ToString::to_string),
+        details: Value::Null,
+    };
+    if let Err(err) = append(&audit.path, &record) {
+        log::warn!("failed to record {operation} of task {id} in the audit log: {err}");
+    }
+    res
+}
+
+/// Records `operation`, done by the shim on its own on the task `id`, with its `details`,
+/// when the audit log is enabled for `namespace`.
+pub(crate) fn record(
+    namespace: &str,
+    operation: &str,
+    id: &str,
+    error: Option<String>,
+    details: Value,
+) {
+    let config = RuntimeConfig::current();
+    let Some(audit) = config.audit.as_ref().filter(|a| a.applies_to(namespace)) else {
+        return;
+    };
+    let record = Record {
+        time: Utc::now().to_rfc3339_opts(SecondsFormat::Micros, true),
+        namespace,
+        operation,
+        id,
+        peer: None,
+        outcome: if error.is_none() { "ok" } else { "error" },
+        error,
+        details,
+    };
+    if let Err(err) = append(&audit.path, &record) {
+        log::warn!("failed to record {operation} of task {id} in the audit log: {err}");
+    }
+}
+
+fn append(path: &Path, record: &impl Serialize) -> Result<()> {
+    let mut line = serde_json::to_vec(record)?;
+    line.push(b'\n');
+
+    let mut options = OpenOptions::new();
+    options.append(true).create(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    // a single write, so that the records of concurrent shims don't interleave
+    options.open(path)?.write_all(&line)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_append() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("audit.log");
+        for (operation, error) in [("create", None), ("start", Some("not found: app"))] {
+            let record = Record {
+                time: "2024-01-01T00:00:00.000000Z".to_string(),
+                namespace: "default",
+                operation,
+                id: "app",
+                peer: Some(Peer {
+                    pid: 1,
+                    uid: 0,
+                    gid: 0,
+                }),
+                outcome: if error.is_none() { "ok" } else { "error" },
+                error: error.map(str::to_string),
+                details: Value::Null,
+            };
+            append(&path, &record)?;
+        }
+        let record = Record {
+            time: "2024-01-01T00:00:00.000000Z".to_string(),
+            namespace: "default",
+            operation: "verify-provenance",
+            id: "app",
+            peer: None,
+            outcome: "ok",
+            error: None,
+            details: serde_json::json!({ "image": "app:v1" }),
+        };
+        append(&path, &record)?;
+
+        let log = std::fs::read_to_string(&path)?;
+        let lines: Vec<_> = log.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                r#"{"time":"2024-01-01T00:00:00.000000Z","namespace":"default","operation":"create","id":"app","peer":{"pid":1,"uid":0,"gid":0},"outcome":"ok"}"#,
+                r#"{"time":"2024-01-01T00:00:00.000000Z","namespace":"default","operation":"start","id":"app","peer":{"pid":1,"uid":0,"gid":0},"outcome":"error","error":"not found: app"}"#,
+                r#"{"time":"2024-01-01T00:00:00.000000Z","namespace":"default","operation":"verify-provenance","id":"app","peer":null,"outcome":"ok","details":{"image":"app:v1"}}"#,
+            ]
+        );
+        Ok(())
+    }
+}
@@ -1,6 +1,8 @@
 use std::env::current_dir;
 use std::fmt::Debug;
 use std::sync::Arc;
+#[cfg(unix)]
+use std::sync::Mutex;
 
 use chrono::Utc;
 use containerd_shim::error::Error as ShimError;
@@ -10,9 +12,19 @@ use containerd_shim::{self as shim, api, ExitSignal};
 use oci_spec::runtime::Spec;
 use shim::Flags;
 
+use crate::sandbox::config;
+#[cfg(unix)]
+use crate::sandbox::config::LogFormat;
 use crate::sandbox::instance::Instance;
+use crate::sandbox::panics;
+#[cfg(target_os = "linux")]
+use crate::sandbox::shim::confinement;
 use crate::sandbox::shim::events::{RemoteEventSender, ToTimestamp};
+#[cfg(unix)]
+use crate::sandbox::shim::json_log;
 use crate::sandbox::shim::local::Local;
+#[cfg(unix)]
+use crate::sandbox::shim::manager::ManagerServer;
 
 /// Cli implements the containerd-shim cli interface using `Local<T>` as the task service.
 pub struct Cli<T: Instance + Sync + Send> {
@@ -20,7 +32,9 @@ pub struct Cli<T: Instance + Sync + Send> {
     namespace: String,
     containerd_address: String,
     exit: Arc<ExitSignal>,
-    _id: String,
+    id: String,
+    #[cfg(unix)]
+    manager: Mutex<Option<ManagerServer>>,
 }
 
 impl<I> Debug for Cli<I>
@@ -31,8 +45,8 @@ where
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "Cli {{ namespace: {:?}, containerd_address: {:?}, _id: {:?} }}",
-            self.namespace, self.containerd_address, self._id
+            "Cli {{ namespace: {:?}, containerd_address: {:?}, id: {:?} }}",
+            self.namespace, self.containerd_address, self.id
         )
     }
 }
@@ -46,12 +60,18 @@ where
 
     #[cfg_attr(feature = "tracing", tracing::instrument(level = "Info"))]
     fn new(_runtime_id: &str, args: &Flags, _config: &mut shim::Config) -> Self {
+        #[cfg(unix)]
+        setup_json_logger(args, _config);
+        panics::install_hook();
+
         Cli {
             engine: Default::default(),
             namespace: args.namespace.to_string(),
             containerd_address: args.address.clone(),
             exit: Arc::default(),
-            _id: args.id.to_string(),
+            id: args.id.to_string(),
+            #[cfg(unix)]
+            manager: Mutex::default(),
         }
     }
 
@@ -68,6 +88,13 @@ where
             .as_ref()
             .and_then(|a| a.get("io.kubernetes.cri.sandbox-id"))
             .unwrap_or(&id);
+        // a confined shim can only access the bundle of its first container
+        #[cfg(target_os = "linux")]
+        let grouping = if config::load_from_env().is_some() && confinement::is_enabled() {
+            &id
+        } else {
+            grouping
+        };
 
         let (_child, address) = shim::spawn(opts, grouping, vec![])?;
 
@@ -79,6 +106,10 @@ where
     #[cfg_attr(feature = "tracing", tracing::instrument(level = "Info"))]
     fn wait(&mut self) {
         self.exit.wait();
+        #[cfg(unix)]
+        if let Some(manager) = self.manager.lock().unwrap().take() {
+            manager.shutdown();
+        }
     }
 
     #[cfg_attr(
@@ -86,16 +117,36 @@ where
         tracing::instrument(skip(publisher), level = "Info")
     )]
     fn create_task_service(&self, publisher: RemotePublisher) -> Self::T {
+        let config_path = config::load_from_env();
+        #[cfg(target_os = "linux")]
+        if let Ok(bundle) = current_dir() {
+            confinement::confine(
+                &bundle,
+                &self.namespace,
+                &I::state_roots(),
+                config_path.as_deref(),
+            );
+        }
+        if let Some(path) = config_path {
+            config::watch(path);
+        }
         let events = RemoteEventSender::new(&self.namespace, publisher);
         let exit = self.exit.clone();
         let engine = self.engine.clone();
-        Local::<I>::new(
+        let local = Local::<I>::new(
             engine,
             events,
             exit,
             &self.namespace,
             &self.containerd_address,
-        )
+        );
+        // the socket of the service is in the temp dir, which the confined shim can write to
+        #[cfg(unix)]
+        match ManagerServer::start(&self.namespace, &self.id, &local.instances) {
+            Ok(manager) => *self.manager.lock().unwrap() = Some(manager),
+            Err(err) => log::warn!("failed to start the manager service: {err:#}"),
+        }
+        local
     }
 
     #[cfg_attr(feature = "tracing", tracing::instrument(level = "Info"))]
@@ -107,3 +158,25 @@ where
         })
     }
 }
+
+// Installs the JSON logger if it's selected, instead of the text logger of containerd-shim.
+#[cfg(unix)]
+fn setup_json_logger(args: &Flags, config: &mut shim::Config) {
+    // only the shim serving the task service logs, `start` and `delete` don't
+    if matches!(args.action.as_str(), "start" | "delete")
+        || config.no_setup_logger
+        || LogFormat::from_env() != LogFormat::Json
+    {
+        return;
+    }
+    let level = if args.debug {
+        "debug"
+    } else {
+        config.default_log_level.as_str()
+    };
+    match json_log::init(&args.namespace, &args.id, level) {
+        // keep containerd-shim from installing its own logger
+        Ok(()) => config.no_setup_logger = true,
+        Err(err) => eprintln!("failed to set up JSON logging: {err:#}"),
+    }
+}
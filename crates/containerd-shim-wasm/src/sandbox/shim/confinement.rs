@@ -0,0 +1,332 @@
+//! Landlock confinement of the shim process.
+//!
+//! With `landlock` in the runtime configuration, each container gets a shim of its own,
+//! instead of sharing the shim of its pod, and the serving shim restricts itself with Landlock
+//! once its configuration is loaded, so that a compromised shim can only access:
+//! * the bundle of its container and the state directory of its namespace,
+//! * the temporary directory, where the console sockets are, and the devices it uses, e.g.,
+//!   `/dev/null` and `/dev/ptmx`, but not the other devices of `/dev`,
+//! * `/proc`, `/sys`, `/etc`, the system libraries and the runtime configuration, read-only,
+//! * the directories and files the runtime configuration has the shim write to, e.g.,
+//!   `admission.dir`, `audit.path` or `stdio.spill_dir`, and the files it has the shim read,
+//!   read-only, e.g., the keys of `signatures` and `provenance`, as configured when the
+//!   shim starts,
+//! * the paths of `landlock.read_write_paths` and `landlock.read_only_paths`, e.g., the stdio
+//!   fifos, log files and termination messages of the containers, which are only known once
+//!   they're created.
+//!
+//! Landlock doesn't restrict connecting to the sockets of containerd.
+//!
+//! This limits what a compromised serving shim can read and write directly, but it isn't a
+//! security boundary:
+//! * Landlock only confines the thread that restricts itself and the threads and processes it
+//!   starts afterwards. The threads started before, i.e., the connection of the event
+//!   publisher and the OpenTelemetry exporter, aren't confined.
+//! * The containers are built by zygotes forked when the shim starts, which run as root and
+//!   aren't confined, as they need to mount the rootfs of the containers. A compromised shim
+//!   can have them build any container.
+//! * The state directory of the namespace holds the state of the containers of the other
+//!   shims of the namespace.
+//!
+//! On kernels without Landlock, the shim runs unconfined, with a warning.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::Context as _;
+use landlock::{
+    Access as _, AccessFs, PathBeneath, PathFd, Ruleset, RulesetAttr, RulesetCreatedAttr,
+    RulesetStatus, ABI,
+};
+
+use crate::sandbox::config::{LandlockConfig, RuntimeConfig};
+use crate::sandbox::instance_utils::resolve_rootdir;
+
+// the newest landlock the rules use, older kernels enforce what they support
+const LANDLOCK_ABI: ABI = ABI::V3;
+
+// the directories the shim reads, besides the ones it writes to
+const READ_ONLY_PATHS: &[&str] = &["/proc", "/sys", "/etc", "/usr", "/lib", "/lib64"];
+
+// the devices the shim uses, for the stdio and consoles of the containers
+const DEVICES: &[&str] = &[
+    "/dev/null",
+    "/dev/zero",
+    "/dev/full",
+    "/dev/random",
+    "/dev/urandom",
+    "/dev/ptmx",
+    "/dev/pts",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Access {
+    ReadOnly,
+    ReadWrite,
+}
+
+/// Returns true if the shims are confined, so that each container gets a shim of its own.
+pub fn is_enabled() -> bool {
+    RuntimeConfig::current().landlock.is_some()
+}
+
+/// Restricts the calling thread with the Landlock rules of the runtime configuration, if any.
+/// Called before the shim starts the threads that serve its tasks.
+pub fn confine(
+    bundle: &Path,
+    namespace: &str,
+    state_roots: &[PathBuf],
+    config_path: Option<&Path>,
+) {
+    let config = RuntimeConfig::current();
+    let Some(landlock) = &config.landlock else {
+        return;
+    };
+    // the rules only apply to existing paths
+    create_config_paths(&config);
+    let rules = rules(
+        landlock,
+        &config,
+        bundle,
+        namespace,
+        state_roots,
+        config_path,
+    );
+    match restrict_self(&rules) {
+        Ok(RulesetStatus::FullyEnforced) => log::info!("the shim is confined with landlock"),
+        Ok(RulesetStatus::PartiallyEnforced) => {
+            log::warn!("the shim is partially confined, the kernel has an older landlock")
+        }
+        Ok(RulesetStatus::NotEnforced) => {
+            log::warn!("the shim is not confined, the kernel doesn't support landlock")
+        }
+        Err(err) => log::error!("failed to confine the shim with landlock: {err:#}"),
+    }
+}
+
+fn rules(
+    landlock: &LandlockConfig,
+    config: &RuntimeConfig,
+    bundle: &Path,
+    namespace: &str,
+    state_roots: &[PathBuf],
+    config_path: Option<&Path>,
+) -> Vec<(PathBuf, Access)> {
+    let mut read_write = vec![bundle.to_path_buf(), std::env::temp_dir()];
+    read_write.extend(DEVICES.iter().map(PathBuf::from));
+    // the same state directory as the containers of the shim
+    match resolve_rootdir(bundle, namespace, state_roots) {
+        Ok(rootdir) => read_write.push(rootdir),
+        Err(err) => log::warn!("not allowing the state directory in the landlock rules: {err}"),
+    }
+    read_write.extend(read_write_config_paths(config));
+    read_write.extend(landlock.read_write_paths.iter().cloned());
+
+    let mut read_only: Vec<PathBuf> = READ_ONLY_PATHS.iter().map(PathBuf::from).collect();
+    read_only.extend(config_path.map(Path::to_path_buf));
+    read_only.extend(read_only_config_paths(config));
+    read_only.extend(landlock.read_only_paths.iter().cloned());
+
+    read_write
+        .into_iter()
+        .map(|path| (path, Access::ReadWrite))
+        .chain(read_only.into_iter().map(|path| (path, Access::ReadOnly)))
+        .collect()
+}
+
+// The directories and files the shim writes to as per the runtime configuration.
+// The configuration reloaded afterwards doesn't change them.
+fn read_write_config_paths(config: &RuntimeConfig) -> Vec<PathBuf> {
+    let mut paths = vec![];
+    paths.extend(
+        config
+            .admission
+            .as_ref()
+            .map(|admission| admission.dir.clone()),
+    );
+    paths.extend(config.cpu_boost.as_ref().map(|boost| boost.dir.clone()));
+    paths.extend(config.audit.as_ref().map(|audit| audit.path.clone()));
+    paths.extend(
+        config
+            .capture
+            .as_ref()
+            .and_then(|capture| capture.dir.clone()),
+    );
+    paths.extend(
+        config
+            .stdio
+            .as_ref()
+            .and_then(|stdio| stdio.spill_dir.clone()),
+    );
+    paths.extend(
+        config
+            .file_module_source
+            .as_ref()
+            .map(|file| file.dir.clone()),
+    );
+    paths
+}
+
+// The files the shim reads as per the runtime configuration.
+fn read_only_config_paths(config: &RuntimeConfig) -> Vec<PathBuf> {
+    let mut paths = vec![];
+    if let Some(provenance) = &config.provenance {
+        paths.extend(provenance.keys.iter().cloned());
+    }
+    if let Some(signatures) = &config.signatures {
+        paths.extend(signatures.cosign_keys.iter().cloned());
+        paths.extend(signatures.wasmsign_keys.iter().cloned());
+    }
+    paths.extend(
+        config
+            .module_cache
+            .as_ref()
+            .map(|cache| cache.key_path.clone()),
+    );
+    paths.extend(
+        config
+            .seccomp
+            .as_ref()
+            .and_then(|seccomp| seccomp.profile.clone()),
+    );
+    paths
+}
+
+// Creates the directories and the audit log the shim writes to, which the shim would
+// otherwise create once it is confined, and fail to.
+fn create_config_paths(config: &RuntimeConfig) {
+    let audit = config.audit.as_ref().map(|audit| audit.path.as_path());
+    for path in read_write_config_paths(config) {
+        let created = if Some(path.as_path()) == audit {
+            std::fs::OpenOptions::new()
+                .append(true)
+                .create(true)
+                .open(&path)
+                .map(drop)
+        } else {
+            std::fs::create_dir_all(&path)
+        };
+        if let Err(err) = created {
+            log::warn!("failed to create {path:?} before confining the shim: {err}");
+        }
+    }
+}
+
+fn restrict_self(rules: &[(PathBuf, Access)]) -> anyhow::Result<RulesetStatus> {
+    let mut ruleset = Ruleset::default()
+        .handle_access(AccessFs::from_all(LANDLOCK_ABI))?
+        .create()?;
+    for (path, access) in rules {
+        // paths that don't exist on this host, e.g. `/lib64`, can't be accessed anyway
+        let Ok(fd) = PathFd::new(path) else {
+            log::debug!("not allowing {path:?} in the landlock rules, it doesn't exist");
+            continue;
+        };
+        let access = match access {
+            Access::ReadOnly => AccessFs::from_read(LANDLOCK_ABI),
+            Access::ReadWrite => AccessFs::from_all(LANDLOCK_ABI),
+        };
+        // the rights on the content of directories don't apply to the devices
+        let access = if path.is_dir() {
+            access
+        } else {
+            access & AccessFs::from_file(LANDLOCK_ABI)
+        };
+        ruleset = ruleset
+            .add_rule(PathBeneath::new(fd, access))
+            .with_context(|| format!("failed to allow {path:?}"))?;
+    }
+    Ok(ruleset.restrict_self()?.ruleset)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::sandbox::config::{AuditConfig, SeccompConfig, SignaturePolicy, StdioConfig};
+
+    #[test]
+    fn test_rules() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let bundle = dir.path().join("io.containerd.runtime.v2.task/k8s.io/app");
+        std::fs::create_dir_all(&bundle)?;
+        // the state directory falls back to the runtime directory of the user, as the
+        // containers do, when the default one isn't writable
+        let unwritable = dir.path().join("file");
+        std::fs::write(&unwritable, "")?;
+        let state_root = dir.path().join("containerd/wasmtime");
+
+        let landlock = LandlockConfig {
+            read_write_paths: vec![PathBuf::from("/var/log/pods")],
+            read_only_paths: vec![PathBuf::from("/etc/runwasi/extra")],
+        };
+        let config = RuntimeConfig {
+            audit: Some(AuditConfig {
+                path: PathBuf::from("/var/log/runwasi/audit.log"),
+                ..Default::default()
+            }),
+            stdio: Some(StdioConfig {
+                spill_dir: Some(PathBuf::from("/var/lib/runwasi/spill")),
+                ..Default::default()
+            }),
+            signatures: Some(SignaturePolicy {
+                cosign_keys: vec![PathBuf::from("/etc/runwasi/keys/cosign.pub")],
+                ..Default::default()
+            }),
+            seccomp: Some(SeccompConfig {
+                profile: Some(PathBuf::from("/etc/runwasi/seccomp.json")),
+            }),
+            ..Default::default()
+        };
+        let rules = rules(
+            &landlock,
+            &config,
+            &bundle,
+            "k8s.io",
+            &[unwritable, state_root.clone()],
+            Some(Path::new("/etc/runwasi/config.json")),
+        );
+        let access = |path: &Path| {
+            rules
+                .iter()
+                .find(|(p, _)| p == path)
+                .map(|(_, access)| *access)
+        };
+
+        assert_eq!(access(&bundle), Some(Access::ReadWrite));
+        assert_eq!(access(&state_root.join("k8s.io")), Some(Access::ReadWrite));
+        assert_eq!(access(Path::new("/dev/null")), Some(Access::ReadWrite));
+        assert_eq!(access(Path::new("/var/log/pods")), Some(Access::ReadWrite));
+        assert_eq!(
+            access(Path::new("/var/log/runwasi/audit.log")),
+            Some(Access::ReadWrite)
+        );
+        assert_eq!(
+            access(Path::new("/var/lib/runwasi/spill")),
+            Some(Access::ReadWrite)
+        );
+        assert_eq!(access(Path::new("/proc")), Some(Access::ReadOnly));
+        assert_eq!(
+            access(Path::new("/etc/runwasi/config.json")),
+            Some(Access::ReadOnly)
+        );
+        assert_eq!(
+            access(Path::new("/etc/runwasi/keys/cosign.pub")),
+            Some(Access::ReadOnly)
+        );
+        assert_eq!(
+            access(Path::new("/etc/runwasi/seccomp.json")),
+            Some(Access::ReadOnly)
+        );
+        assert_eq!(
+            access(Path::new("/etc/runwasi/extra")),
+            Some(Access::ReadOnly)
+        );
+
+        // neither the other bundles, the other namespaces nor the devices are allowed
+        assert_eq!(access(bundle.parent().unwrap()), None);
+        assert_eq!(access(&state_root), None);
+        assert_eq!(access(Path::new("/dev")), None);
+        Ok(())
+    }
+}
@@ -0,0 +1,151 @@
+//! Debug dump of the state of the shim on `SIGUSR1`.
+//!
+//! A shim that hangs in production can be asked what it is doing with
+//! `kill -USR1 <shim pid>`. The shim then writes the state of its tasks and of its threads
+//! to a `debug-dump-<timestamp>.txt` file in its working directory, which containerd sets
+//! to the bundle of the task that started the shim, and to its logs.
+//!
+//! Backtraces of the other threads can't be captured from within the process, the name,
+//! state, wait channel and kernel stack (when readable) of each thread are dumped instead.
+//! `gdb -p <shim pid> -batch -ex 'thread apply all bt'` gives their user space backtraces.
+//! The async code of the shim runs on the thread blocked on it, so a hung future shows up
+//! as the thread waiting for it.
+
+use std::fmt::Write as _;
+use std::path::Path;
+use std::sync::{LazyLock, Mutex, Once};
+use std::thread;
+
+use chrono::Utc;
+
+type Section = Box<dyn Fn(&mut String) + Send + Sync>;
+
+static SECTIONS: LazyLock<Mutex<Vec<Section>>> = LazyLock::new(Mutex::default);
+
+/// Registers `f` to write a section of the dump taken on `SIGUSR1`.
+///
+/// The first call starts a background thread in the current process that waits for the signal.
+pub fn on_dump(f: impl Fn(&mut String) + Send + Sync + 'static) {
+    SECTIONS.lock().unwrap().push(Box::new(f));
+
+    static START: Once = Once::new();
+    START.call_once(|| {
+        let res = thread::Builder::new()
+            .name("debug-dump".to_string())
+            .spawn(listen);
+        if let Err(err) = res {
+            log::warn!("failed to start the debug dump listener: {err}");
+        }
+    });
+}
+
+fn listen() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build();
+    let res = runtime.and_then(|runtime| {
+        runtime.block_on(async {
+            use tokio::signal::unix::{signal, SignalKind};
+            let mut sigusr1 = signal(SignalKind::user_defined1())?;
+            while sigusr1.recv().await.is_some() {
+                write_dump(&dump());
+            }
+            Ok::<_, std::io::Error>(())
+        })
+    });
+    if let Err(err) = res {
+        log::warn!("failed to listen for SIGUSR1, debug dumps are disabled: {err}");
+    }
+}
+
+fn dump() -> String {
+    let mut out = format!(
+        "debug dump of shim {} at {}\n",
+        std::process::id(),
+        Utc::now().to_rfc3339()
+    );
+    for section in SECTIONS.lock().unwrap().iter() {
+        out.push('\n');
+        section(&mut out);
+    }
+    out.push('\n');
+    write_threads(&mut out, Path::new("/proc/self/task"));
+    out
+}
+
+fn write_dump(dump: &str) {
+    let path = format!("debug-dump-{}.txt", Utc::now().format("%Y%m%dT%H%M%S%.3f"));
+    match std::fs::write(&path, dump) {
+        Ok(()) => log::warn!("wrote debug dump to {path:?}:\n{dump}"),
+        Err(err) => log::warn!("failed to write debug dump to {path:?}: {err}:\n{dump}"),
+    }
+}
+
+// Writes the threads of the process from `tasks`, the `/proc/<pid>/task` directory.
+fn write_threads(out: &mut String, tasks: &Path) {
+    let _ = writeln!(out, "threads:");
+    let entries = match std::fs::read_dir(tasks) {
+        Ok(entries) => entries,
+        Err(err) => {
+            let _ = writeln!(out, "  failed to list threads: {err}");
+            return;
+        }
+    };
+    let mut tids: Vec<_> = entries.flatten().map(|e| e.file_name()).collect();
+    tids.sort_by_key(|tid| tid.to_string_lossy().parse::<u32>().unwrap_or_default());
+
+    for tid in tids {
+        let dir = tasks.join(&tid);
+        let read = |name: &str| std::fs::read_to_string(dir.join(name)).unwrap_or_default();
+        let comm = read("comm");
+        let stat = read("stat");
+        // the state follows the name of the thread, which can contain spaces
+        let state = stat
+            .rsplit_once(") ")
+            .and_then(|(_, rest)| rest.split(' ').next())
+            .unwrap_or("?");
+        let wchan = read("wchan");
+        let wchan = match wchan.as_str() {
+            "" | "0" => "-",
+            wchan => wchan,
+        };
+        let _ = writeln!(
+            out,
+            "  {} {:?} state={state} wchan={wchan}",
+            tid.to_string_lossy(),
+            comm.trim_end()
+        );
+        for frame in read("stack").lines() {
+            let _ = writeln!(out, "    {frame}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_threads() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let task = dir.path().join("42");
+        std::fs::create_dir(&task)?;
+        std::fs::write(task.join("comm"), "compile pool\n")?;
+        std::fs::write(task.join("stat"), "42 (compile pool) S 1 42 42 0")?;
+        std::fs::write(task.join("wchan"), "futex_wait_queue")?;
+        std::fs::write(task.join("stack"), "[<0>] futex_wait+0x1/0x2\n")?;
+        let task = dir.path().join("7");
+        std::fs::create_dir(&task)?;
+        std::fs::write(task.join("comm"), "shim\n")?;
+        std::fs::write(task.join("stat"), "7 (shim) R 1 7 7 0")?;
+        std::fs::write(task.join("wchan"), "0")?;
+
+        let mut out = String::new();
+        write_threads(&mut out, dir.path());
+        assert_eq!(
+            out,
+            "threads:\n  7 \"shim\" state=R wchan=-\n  42 \"compile pool\" state=S wchan=futex_wait_queue\n    [<0>] futex_wait+0x1/0x2\n"
+        );
+        Ok(())
+    }
+}
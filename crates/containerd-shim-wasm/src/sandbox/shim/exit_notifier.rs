@@ -0,0 +1,235 @@
+//! Notifications of the exit of tasks, for consumers that don't subscribe to containerd events.
+//!
+//! When `exit_notifier` is set in the runtime configuration, a JSON record of the exit of each
+//! task of the configured namespaces is sent to its URL, e.g.:
+//!
+//! ```json
+//! {"id":"app","namespace":"edge","image_digest":"sha256:...","exit_code":0,"exited_at":"2024-01-01T00:00:00.000000Z","timings_ms":{"fetch":12,"compile":null,"build":40,"create":60,"start":3,"first_output":8},"resources":{"cpu_usage_usec":1500,"memory_peak_bytes":4194304}}
+//! ```
+//!
+//! Records are POSTed to `http` and `https` URLs, and written as a line to the socket of
+//! `unix` URLs. A record is sent in the background after the `TaskExit` event, and retried
+//! with backoff up to `max_attempts` times. Notifications are best effort: a record that
+//! can't be delivered is logged and dropped.
+
+use std::thread;
+use std::time::Duration;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::Serialize;
+
+use crate::sandbox::async_utils::AmbientRuntime as _;
+use crate::sandbox::config::{ExitNotifierConfig, RuntimeConfig};
+use crate::sandbox::containerd;
+use crate::sandbox::timings::{TimingRecord, Timings};
+use crate::sys::resources::ResourceSummary;
+
+/// Longest wait between two attempts to deliver a record.
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Serialize)]
+struct ExitRecord {
+    id: String,
+    namespace: String,
+    image_digest: Option<String>,
+    exit_code: u32,
+    exited_at: String,
+    timings_ms: ExitTimings,
+    resources: ResourceSummary,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct ExitTimings {
+    fetch: Option<u64>,
+    compile: Option<u64>,
+    build: Option<u64>,
+    create: Option<u64>,
+    start: Option<u64>,
+    first_output: Option<u64>,
+}
+
+impl From<TimingRecord> for ExitTimings {
+    fn from(timings: TimingRecord) -> Self {
+        let ms = |d: Option<Duration>| d.map(|d| d.as_millis() as u64);
+        Self {
+            fetch: ms(timings.fetch),
+            compile: ms(timings.compile),
+            build: ms(timings.build),
+            create: ms(timings.create),
+            start: ms(timings.start),
+            first_output: ms(timings.first_output),
+        }
+    }
+}
+
+/// The task that exited.
+pub(super) struct Exit<'a> {
+    pub id: &'a str,
+    pub namespace: &'a str,
+    pub containerd_address: &'a str,
+    pub exit_code: u32,
+    pub exited_at: DateTime<Utc>,
+    pub resources: ResourceSummary,
+}
+
+/// Sends the record of `exit` in the background, when notifications are enabled for its
+/// namespace.
+pub(super) fn notify(exit: Exit) {
+    let config = RuntimeConfig::current();
+    let Some(config) = config
+        .exit_notifier
+        .as_ref()
+        .filter(|n| n.applies_to(exit.namespace))
+    else {
+        return;
+    };
+
+    let record = ExitRecord {
+        id: exit.id.to_string(),
+        namespace: exit.namespace.to_string(),
+        image_digest: None,
+        exit_code: exit.exit_code,
+        exited_at: exit.exited_at.to_rfc3339_opts(SecondsFormat::Micros, true),
+        timings_ms: Timings::global()
+            .get(exit.id)
+            .map(ExitTimings::from)
+            .unwrap_or_default(),
+        resources: exit.resources,
+    };
+    let config = config.clone();
+    let containerd_address = exit.containerd_address.to_string();
+    let res = thread::Builder::new()
+        .name(format!("{}-notify", exit.id))
+        .spawn(move || {
+            let mut record = record;
+            record.image_digest = image_digest(&record, &containerd_address);
+            deliver(&record, &config)
+        });
+    if let Err(err) = res {
+        log::warn!("failed to notify the exit of task {}: {err}", exit.id);
+    }
+}
+
+fn deliver(record: &ExitRecord, config: &ExitNotifierConfig) {
+    let body = match serde_json::to_vec(record) {
+        Ok(body) => body,
+        Err(err) => {
+            log::warn!("failed to encode the exit of task {}: {err}", record.id);
+            return;
+        }
+    };
+
+    let timeout = Duration::from_secs(config.timeout_secs);
+    let mut backoff = Duration::from_millis(500);
+    for attempt in 1..=config.max_attempts {
+        match send(&config.url, &body, timeout) {
+            Ok(()) => return,
+            Err(err) if attempt < config.max_attempts => {
+                log::debug!(
+                    "failed to notify the exit of task {} (attempt {attempt}): {err:#}",
+                    record.id
+                );
+                thread::sleep(backoff);
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+            Err(err) => {
+                log::warn!(
+                    "dropping the exit notification of task {} after {attempt} attempts: {err:#}",
+                    record.id
+                );
+            }
+        }
+    }
+}
+
+fn image_digest(record: &ExitRecord, containerd_address: &str) -> Option<String> {
+    let res = async {
+        let client = containerd::Client::shared(containerd_address, &record.namespace);
+        client.image_digest(&record.id).await
+    }
+    .block_on();
+    res.inspect_err(|err| log::debug!("no image digest for task {}: {err}", record.id))
+        .ok()
+}
+
+fn send(url: &str, body: &[u8], timeout: Duration) -> anyhow::Result<()> {
+    let url = url::Url::parse(url)?;
+    if url.scheme() == "unix" {
+        return send_to_socket(url.path(), body, timeout);
+    }
+    async {
+        let client = reqwest::Client::builder().timeout(timeout).build()?;
+        client
+            .post(url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body.to_vec())
+            .send()
+            .await?
+            .error_for_status()?;
+        anyhow::Ok(())
+    }
+    .block_on()
+}
+
+#[cfg(unix)]
+fn send_to_socket(path: &str, body: &[u8], timeout: Duration) -> anyhow::Result<()> {
+    use std::io::Write as _;
+    use std::os::unix::net::UnixStream;
+
+    use anyhow::Context as _;
+
+    let mut stream = UnixStream::connect(path).with_context(|| format!("connecting to {path}"))?;
+    stream.set_write_timeout(Some(timeout))?;
+    stream.write_all(body)?;
+    stream.write_all(b"\n")?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn send_to_socket(_path: &str, _body: &[u8], _timeout: Duration) -> anyhow::Result<()> {
+    anyhow::bail!("unix sockets are not supported on this platform")
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::io::{BufRead as _, BufReader};
+    use std::os::unix::net::UnixListener;
+
+    use super::*;
+
+    #[test]
+    fn test_deliver_to_socket() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let socket = dir.path().join("exits.sock");
+        let listener = UnixListener::bind(&socket)?;
+
+        let record = ExitRecord {
+            id: "app".to_string(),
+            namespace: "edge".to_string(),
+            image_digest: None,
+            exit_code: 3,
+            exited_at: "2024-01-01T00:00:00.000000Z".to_string(),
+            timings_ms: ExitTimings {
+                start: Some(3),
+                ..Default::default()
+            },
+            resources: ResourceSummary::default(),
+        };
+        let config = ExitNotifierConfig {
+            url: format!("unix://{}", socket.display()),
+            max_attempts: 1,
+            ..Default::default()
+        };
+        deliver(&record, &config);
+
+        let (stream, _) = listener.accept()?;
+        let mut line = String::new();
+        BufReader::new(stream).read_line(&mut line)?;
+        let record: serde_json::Value = serde_json::from_str(&line)?;
+        assert_eq!(record["id"], "app");
+        assert_eq!(record["exit_code"], 3);
+        assert_eq!(record["image_digest"], serde_json::Value::Null);
+        assert_eq!(record["timings_ms"]["start"], 3);
+        Ok(())
+    }
+}
@@ -0,0 +1,65 @@
+//! Heap profiles of the shim on `SIGUSR2`, with the `heap-profiling` feature.
+//!
+//! The memory of the shims is what limits the density of wasm nodes, so shims built with the
+//! `heap-profiling` feature sample their allocations with jemalloc, one every 512KiB on
+//! average, and `kill -USR2 <shim pid>` writes a `heap-<timestamp>.prof` profile to the
+//! working directory of the shim, which containerd sets to the bundle of the task that started
+//! it. Profiles are read with `jeprof`.
+
+use std::ffi::CString;
+use std::sync::Once;
+use std::thread;
+
+use anyhow::anyhow;
+use chrono::Utc;
+
+// Read by jemalloc when it starts: a `&[u8]` starts with the pointer to its bytes, which jemalloc
+// reads as a C string.
+#[allow(non_upper_case_globals)]
+#[export_name = "_rjem_malloc_conf"]
+pub static malloc_conf: &[u8] = b"prof:true,prof_active:true,lg_prof_sample:19\0";
+
+/// Starts a background thread writing a heap profile on `SIGUSR2`.
+pub fn start() {
+    static START: Once = Once::new();
+    START.call_once(|| {
+        let res = thread::Builder::new()
+            .name("heap-profile".to_string())
+            .spawn(listen);
+        if let Err(err) = res {
+            log::warn!("failed to start the heap profile listener: {err}");
+        }
+    });
+}
+
+fn listen() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build();
+    let res = runtime.and_then(|runtime| {
+        runtime.block_on(async {
+            use tokio::signal::unix::{signal, SignalKind};
+            let mut sigusr2 = signal(SignalKind::user_defined2())?;
+            while sigusr2.recv().await.is_some() {
+                match dump() {
+                    Ok(path) => log::warn!("wrote heap profile to {path:?}"),
+                    Err(err) => log::warn!("failed to write heap profile: {err}"),
+                }
+            }
+            Ok::<_, std::io::Error>(())
+        })
+    });
+    if let Err(err) = res {
+        log::warn!("failed to listen for SIGUSR2, heap profiles are disabled: {err}");
+    }
+}
+
+fn dump() -> anyhow::Result<String> {
+    let path = format!("heap-{}.prof", Utc::now().format("%Y%m%dT%H%M%S%.3f"));
+    let cpath = CString::new(path.clone())?;
+    // SAFETY: prof.dump takes the path of the profile as a nul terminated string, which
+    // outlives the call
+    unsafe { tikv_jemalloc_ctl::raw::write(b"prof.dump\0", cpath.as_ptr()) }
+        .map_err(|err| anyhow!("{err}"))?;
+    Ok(path)
+}
@@ -31,6 +31,25 @@ impl<T: Instance> InstanceData<T> {
         self.pid.get().copied()
     }
 
+    /// Describes the state of the instance for a debug dump.
+    /// It doesn't block on the locks of the instance, which a hung shim might hold.
+    #[cfg(unix)]
+    pub fn debug_state(&self) -> String {
+        let state = match self.state.try_read() {
+            Ok(state) => format!("{:?}", *state),
+            Err(_) => "<locked>".to_string(),
+        };
+        let pid = match self.pid() {
+            Some(pid) => pid.to_string(),
+            None => "-".to_string(),
+        };
+        let exit = match self.instance.wait_timeout(Duration::ZERO) {
+            Some((code, at)) => format!("{code} at {}", at.to_rfc3339()),
+            None => "pending".to_string(),
+        };
+        format!("state={state} pid={pid} exit={exit}")
+    }
+
     #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Debug"))]
     pub fn config(&self) -> &InstanceConfig {
         &self.cfg
@@ -64,6 +83,16 @@ impl<T: Instance> InstanceData<T> {
         self.instance.kill(signal)
     }
 
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Debug"))]
+    pub fn resize_pty(&self, width: u32, height: u32) -> Result<()> {
+        self.instance.resize_pty(width, height)
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Debug"))]
+    pub fn close_stdin(&self) -> Result<()> {
+        self.instance.close_stdin()
+    }
+
     #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Debug"))]
     pub fn delete(&self) -> Result<()> {
         let mut s = self.state.write().unwrap();
@@ -87,6 +116,13 @@ impl<T: Instance> InstanceData<T> {
         res
     }
 
+    /// Marks the instance as exited when its exit can't be waited for anymore.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Debug"))]
+    pub fn mark_exited(&self) {
+        let mut s = self.state.write().unwrap_or_else(|err| err.into_inner());
+        *s = TaskState::Exited;
+    }
+
     #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Debug"))]
     pub fn wait_timeout(
         &self,
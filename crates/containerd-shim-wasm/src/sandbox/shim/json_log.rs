@@ -0,0 +1,159 @@
+//! Structured JSON logging for the shim.
+//!
+//! By default the shim logs plain text lines to the log fifo read by containerd.
+//! When `log_format` is `json` in the runtime configuration, or `RUNWASI_LOG_FORMAT=json` is set
+//! in the environment of the shim, each line is a JSON object instead, e.g.:
+//!
+//! ```json
+//! {"time":"2024-05-01T10:00:00.000000Z","level":"info","msg":"starting instance","target":"containerd_shim_wasm::sandbox::shim::local","namespace":"k8s.io","shim_id":"7f3a","operation":"start","instance_id":"7f3a"}
+//! ```
+//!
+//! `operation` and `instance_id` are set on the lines logged while handling a task service
+//! request, with the name of the request and the id of the container it targets.
+//!
+//! The log format is read when the shim starts, changing it requires a restart.
+
+use std::cell::RefCell;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use chrono::{SecondsFormat, Utc};
+use log::{LevelFilter, Log, Metadata, Record};
+use serde_json::{json, Value};
+
+// The task service request handled by the current thread.
+struct Operation {
+    method: String,
+    instance_id: Option<String>,
+}
+
+thread_local! {
+    static OPERATION: RefCell<Option<Operation>> = const { RefCell::new(None) };
+}
+
+/// Runs `f` with the lines it logs tagged with the `method` and `instance_id` of a request.
+pub(super) fn with_operation<T>(
+    method: &str,
+    instance_id: Option<String>,
+    f: impl FnOnce() -> T,
+) -> T {
+    let operation = Operation {
+        method: method.to_string(),
+        instance_id,
+    };
+    let previous = OPERATION.replace(Some(operation));
+    let res = f();
+    OPERATION.set(previous);
+    res
+}
+
+/// Installs the JSON logger, writing to the log fifo of the shim in the current directory.
+pub(super) fn init(namespace: &str, shim_id: &str, level: &str) -> anyhow::Result<()> {
+    // containerd opens the fifo for reading before starting the shim
+    let output = OpenOptions::new().write(true).open("log")?;
+    let level = LevelFilter::from_str(level).unwrap_or(LevelFilter::Info);
+    let logger = JsonLogger::new(output, namespace, shim_id);
+    log::set_boxed_logger(Box::new(logger))?;
+    log::set_max_level(level);
+    Ok(())
+}
+
+struct JsonLogger<W> {
+    output: Mutex<W>,
+    namespace: String,
+    shim_id: String,
+}
+
+impl<W: Write> JsonLogger<W> {
+    fn new(output: W, namespace: &str, shim_id: &str) -> Self {
+        Self {
+            output: Mutex::new(output),
+            namespace: namespace.to_string(),
+            shim_id: shim_id.to_string(),
+        }
+    }
+
+    fn format(&self, record: &Record) -> String {
+        let mut entry = json!({
+            "time": Utc::now().to_rfc3339_opts(SecondsFormat::Micros, true),
+            "level": record.level().as_str().to_lowercase(),
+            "msg": record.args().to_string(),
+            "target": record.target(),
+            "namespace": self.namespace,
+            "shim_id": self.shim_id,
+        });
+        OPERATION.with_borrow(|operation| {
+            if let Some(operation) = operation {
+                entry["operation"] = Value::from(operation.method.as_str());
+                if let Some(id) = &operation.instance_id {
+                    entry["instance_id"] = Value::from(id.as_str());
+                }
+            }
+        });
+        let mut line = entry.to_string();
+        line.push('\n');
+        line
+    }
+}
+
+impl<W: Write + Send> Log for JsonLogger<W> {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = self.format(record);
+        // there's nowhere to report a failure to log to
+        let _ = self.output.lock().unwrap().write_all(line.as_bytes());
+    }
+
+    fn flush(&self) {
+        let _ = self.output.lock().unwrap().flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use log::Level;
+
+    use super::*;
+
+    fn format(logger: &JsonLogger<Vec<u8>>, msg: &str) -> Value {
+        let line = logger.format(
+            &Record::builder()
+                .level(Level::Warn)
+                .target("test")
+                .args(format_args!("{msg}"))
+                .build(),
+        );
+        assert!(line.ends_with('\n'));
+        serde_json::from_str(&line).unwrap()
+    }
+
+    #[test]
+    fn test_json_log() {
+        let logger = JsonLogger::new(vec![], "k8s.io", "shim-1");
+
+        let entry = format(&logger, "hello \"world\"");
+        assert_eq!(entry["level"], "warn");
+        assert_eq!(entry["msg"], "hello \"world\"");
+        assert_eq!(entry["target"], "test");
+        assert_eq!(entry["namespace"], "k8s.io");
+        assert_eq!(entry["shim_id"], "shim-1");
+        assert!(entry.get("operation").is_none());
+
+        let entry = with_operation("start", Some("task-1".to_string()), || {
+            format(&logger, "starting")
+        });
+        assert_eq!(entry["operation"], "start");
+        assert_eq!(entry["instance_id"], "task-1");
+
+        // the operation ends with the request
+        assert!(format(&logger, "done").get("operation").is_none());
+    }
+}
@@ -2,45 +2,88 @@ use std::collections::HashMap;
 use std::fs::create_dir_all;
 use std::ops::Not;
 use std::path::Path;
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, Mutex, RwLock, Weak};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use anyhow::Context as AnyhowContext;
+use chrono::{DateTime, Utc};
 use containerd_shim::api::{
-    ConnectRequest, ConnectResponse, CreateTaskRequest, CreateTaskResponse, DeleteRequest, Empty,
-    KillRequest, ShutdownRequest, StartRequest, StartResponse, StateRequest, StateResponse,
+    CloseIORequest, ConnectRequest, ConnectResponse, CreateTaskRequest, CreateTaskResponse,
+    DeleteRequest, Empty, ExecProcessRequest, KillRequest, PidsRequest, PidsResponse,
+    ResizePtyRequest, ShutdownRequest, StartRequest, StartResponse, StateRequest, StateResponse,
     StatsRequest, StatsResponse, WaitRequest, WaitResponse,
 };
 use containerd_shim::error::Error as ShimError;
-use containerd_shim::protos::events::task::{TaskCreate, TaskDelete, TaskExit, TaskIO, TaskStart};
+use containerd_shim::protos::events::task::{
+    TaskCreate, TaskDelete, TaskExit, TaskIO, TaskOOM, TaskPaused, TaskResumed, TaskStart,
+};
 use containerd_shim::protos::shim::shim_ttrpc::Task;
-use containerd_shim::protos::types::task::Status;
+use containerd_shim::protos::types::task::{ProcessInfo, Status};
 use containerd_shim::util::IntoOption;
 use containerd_shim::{DeleteResponse, ExitSignal, TtrpcContext, TtrpcResult};
 use log::debug;
 use oci_spec::runtime::Spec;
+use tokio_util::sync::CancellationToken;
 #[cfg(feature = "opentelemetry")]
 use tracing_opentelemetry::OpenTelemetrySpanExt as _;
 
 #[cfg(feature = "opentelemetry")]
 use super::otel::extract_context;
+use crate::container::EngineMetrics;
+#[cfg(unix)]
+use crate::sandbox::compile_pool::CompilePool;
+use crate::sandbox::config::RuntimeConfig;
 use crate::sandbox::instance::{Instance, InstanceConfig};
+use crate::sandbox::panics;
+use crate::sandbox::shim::audit;
+#[cfg(unix)]
+use crate::sandbox::shim::debug_dump;
 use crate::sandbox::shim::events::{EventSender, RemoteEventSender, ToTimestamp};
+use crate::sandbox::shim::exit_notifier;
 use crate::sandbox::shim::instance_data::InstanceData;
+use crate::sandbox::shim::wire_debug;
+use crate::sandbox::spec_mutator::SpecMutators;
+use crate::sandbox::suspend::{self, Resume};
+use crate::sandbox::sync::WaitableCell;
+use crate::sandbox::timings::{Phase, Timings};
 use crate::sandbox::{oci, Error, Result};
+#[cfg(unix)]
+use crate::sys::container::ZygotePool;
 use crate::sys::metrics::get_metrics;
+use crate::sys::oom::OomMonitor;
+use crate::sys::pids::get_pids;
+use crate::sys::resources::ResourceTracker;
 
 #[cfg(test)]
 mod tests;
 
-type LocalInstances<T> = RwLock<HashMap<String, Arc<InstanceData<T>>>>;
+pub(super) type LocalInstances<T> = RwLock<HashMap<String, Arc<InstanceData<T>>>>;
+
+/// How often the cgroup of a running task is checked for OOM kills.
+const OOM_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+const SIGKILL: u32 = 9;
+
+/// How long the exit of a task is waited for once it is killed, when its exit watcher panicked.
+const FALLBACK_EXIT_TIMEOUT: Duration = Duration::from_secs(5);
+
+// A task that is still being created.
+// `done` is set once the creation completes, whether it succeeded or not.
+#[derive(Default)]
+struct PendingCreate {
+    token: CancellationToken,
+    done: WaitableCell<()>,
+}
+
+type PendingCreates = Mutex<HashMap<String, Arc<PendingCreate>>>;
 
 /// Local implements the Task service for a containerd shim.
 /// It defers all task operations to the `Instance` implementation.
 pub struct Local<T: Instance + Send + Sync, E: EventSender = RemoteEventSender> {
     pub engine: T::Engine,
-    pub(super) instances: LocalInstances<T>,
+    pub(super) instances: Arc<LocalInstances<T>>,
+    creating: Arc<PendingCreates>,
     events: E,
     exit: Arc<ExitSignal>,
     namespace: String,
@@ -60,12 +103,34 @@ impl<T: Instance + Send + Sync, E: EventSender> Local<T, E> {
         namespace: impl AsRef<str> + std::fmt::Debug,
         containerd_address: impl AsRef<str> + std::fmt::Debug,
     ) -> Self {
-        let instances = RwLock::default();
+        let instances = Arc::<LocalInstances<T>>::default();
         let namespace = namespace.as_ref().to_string();
         let containerd_address = containerd_address.as_ref().to_string();
+
+        suspend::on_resume({
+            let instances = Arc::downgrade(&instances);
+            let events = events.clone();
+            move |resume| notify_resume(&instances, &events, resume)
+        });
+
+        let creating = Arc::<PendingCreates>::default();
+        #[cfg(unix)]
+        debug_dump::on_dump({
+            let instances = Arc::downgrade(&instances);
+            let creating = Arc::downgrade(&creating);
+            move |out| dump_tasks(&instances, &creating, out)
+        });
+        #[cfg(unix)]
+        debug_dump::on_dump(CompilePool::dump);
+        #[cfg(unix)]
+        debug_dump::on_dump(ZygotePool::dump);
+        #[cfg(all(unix, feature = "heap-profiling"))]
+        super::heap_profile::start();
+
         Self {
             engine,
             instances,
+            creating,
             events,
             exit,
             namespace,
@@ -84,6 +149,19 @@ impl<T: Instance + Send + Sync, E: EventSender> Local<T, E> {
         self.instances.read().unwrap().contains_key(id)
     }
 
+    // Cancels the creation of task `id` if it is in progress, and waits for it to unwind.
+    // Returns false if the task wasn't being created.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Debug"))]
+    fn cancel_create(&self, id: &str) -> bool {
+        let Some(pending) = self.creating.lock().unwrap().get(id).cloned() else {
+            return false;
+        };
+        log::info!("cancelling the creation of task {id}");
+        pending.token.cancel();
+        pending.done.wait();
+        true
+    }
+
     #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Debug"))]
     fn is_empty(&self) -> bool {
         self.instances.read().unwrap().is_empty()
@@ -95,6 +173,136 @@ impl<T: Instance + Send + Sync, E: EventSender> Local<T, E> {
     }
 }
 
+// Waits for the task to exit, publishing a `TaskOOM` event every time the OOM killer
+// kills one of its processes.
+// The OOM check is done one last time after the task exits, so that the `TaskOOM` event
+// is published before the `TaskExit` event. This lets CRI report the container as `OOMKilled`.
+fn wait_and_watch_oom<T: Instance + Send + Sync>(
+    id: &str,
+    i: &InstanceData<T>,
+    mut oom: OomMonitor,
+    events: &impl EventSender,
+) -> (u32, DateTime<Utc>) {
+    loop {
+        let res = i.wait_timeout(OOM_CHECK_INTERVAL);
+        if oom.check() {
+            log::warn!("a process in task {id} was killed by the OOM killer");
+            events.send(TaskOOM {
+                container_id: id.to_string(),
+                ..Default::default()
+            });
+            if let Some((exit_code, _)) = res {
+                log::info!("task {id} exited with status {exit_code}: OOM killed");
+            }
+        }
+        if let Some(res) = res {
+            return res;
+        }
+    }
+}
+
+// Gives a signaled task `timeout` to stop on its own, and kills it with `SIGKILL` otherwise.
+// This keeps a hung engine from requiring manual intervention.
+fn escalate_after_timeout<T: Instance + Send + Sync>(
+    id: String,
+    i: Arc<InstanceData<T>>,
+    signal: u32,
+    timeout: Duration,
+) -> Result<()> {
+    thread::Builder::new()
+        .name(format!("{id}-stop"))
+        .spawn(move || {
+            if i.wait_timeout(timeout).is_some() {
+                return;
+            }
+            log::warn!(
+                "task {id} did not stop within {timeout:?} after signal {signal}, sending SIGKILL"
+            );
+            if let Err(err) = i.kill(SIGKILL) {
+                log::error!("failed to kill task {id} after stop timeout: {err}");
+            }
+        })
+        .context("could not spawn thread to enforce stop timeout")?;
+    Ok(())
+}
+
+// The host was suspended, so were all the running tasks.
+// Publish a paused/resumed pair for each of them so that platforms can explain the gap in service.
+fn notify_resume<T: Instance + Send + Sync>(
+    instances: &Weak<LocalInstances<T>>,
+    events: &impl EventSender,
+    resume: &Resume,
+) {
+    let Some(instances) = instances.upgrade() else {
+        return;
+    };
+    let running: Vec<String> = instances
+        .read()
+        .unwrap()
+        .iter()
+        .filter(|(_, i)| i.pid().is_some() && i.wait_timeout(Duration::ZERO).is_none())
+        .map(|(id, _)| id.clone())
+        .collect();
+
+    for id in running {
+        log::warn!(
+            "task {id} was suspended for {:?} while the host was suspended",
+            resume.suspended_for
+        );
+        events.send(TaskPaused {
+            container_id: id.clone(),
+            ..Default::default()
+        });
+        events.send(TaskResumed {
+            container_id: id,
+            ..Default::default()
+        });
+    }
+}
+
+// Writes the tasks of the shim to a debug dump.
+// The locks are only tried, a hung shim might be holding them.
+#[cfg(unix)]
+fn dump_tasks<T: Instance + Send + Sync>(
+    instances: &Weak<LocalInstances<T>>,
+    creating: &Weak<PendingCreates>,
+    out: &mut String,
+) {
+    use std::fmt::Write as _;
+
+    let (Some(instances), Some(creating)) = (instances.upgrade(), creating.upgrade()) else {
+        return;
+    };
+    let _ = writeln!(out, "tasks:");
+    match instances.try_read() {
+        Ok(instances) => {
+            let mut ids: Vec<_> = instances.keys().collect();
+            ids.sort();
+            for id in ids {
+                let state = instances[id].debug_state();
+                let timings = Timings::global().get(id).unwrap_or_default();
+                let _ = writeln!(out, "  {id}: {state} timings=[{timings}]");
+            }
+        }
+        Err(_) => {
+            let _ = writeln!(out, "  <locked>");
+        }
+    }
+    match creating.try_lock() {
+        Ok(creating) => {
+            let mut ids: Vec<_> = creating.keys().collect();
+            ids.sort();
+            for id in ids {
+                let timings = Timings::global().get(id).unwrap_or_default();
+                let _ = writeln!(out, "  {id}: creating timings=[{timings}]");
+            }
+        }
+        Err(_) => {
+            let _ = writeln!(out, "  <pending creates locked>");
+        }
+    }
+}
+
 // These are the same functions as in Task, but without the TtrcpContext, which is useful for testing
 impl<T: Instance + Send + Sync, E: EventSender> Local<T, E> {
     #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Debug"))]
@@ -103,19 +311,46 @@ impl<T: Instance + Send + Sync, E: EventSender> Local<T, E> {
             return Err(ShimError::Unimplemented("checkpoint is not supported".to_string()).into());
         }
 
-        if req.terminal {
-            return Err(Error::InvalidArgument(
-                "terminal is not supported".to_string(),
-            ));
-        }
-
         if self.has_instance(&req.id) {
             return Err(Error::AlreadyExists(req.id));
         }
 
+        let pending = Arc::new(PendingCreate::default());
+        {
+            let mut creating = self.creating.lock().unwrap();
+            if creating.contains_key(&req.id) {
+                return Err(Error::AlreadyExists(req.id));
+            }
+            creating.insert(req.id.clone(), pending.clone());
+        }
+        let _done = pending.done.set_guard_with(|| ());
+
+        let id = req.id.clone();
+        Timings::global().reset(&id);
+        let res = self.create_task(req, pending.token.clone());
+        self.creating.lock().unwrap().remove(&id);
+        if res.is_err() {
+            Timings::global().retire(&id);
+        }
+        res
+    }
+
+    // Creates the task, abandoning the creation if `token` is cancelled by a `Kill` or `Delete`.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Debug"))]
+    fn create_task(
+        &self,
+        req: CreateTaskRequest,
+        token: CancellationToken,
+    ) -> Result<CreateTaskResponse> {
         let mut spec = Spec::load(Path::new(&req.bundle).join("config.json"))
             .map_err(|err| Error::InvalidArgument(format!("could not load runtime spec: {err}")))?;
 
+        // The container is built from the spec in the bundle, so rewrites need to be persisted
+        if SpecMutators::global().apply(req.id(), &mut spec)? {
+            spec.save(Path::new(&req.bundle).join("config.json"))
+                .map_err(|err| Error::Others(format!("could not save runtime spec: {err}")))?;
+        }
+
         spec.canonicalize_rootfs(req.bundle()).map_err(|err| {
             ShimError::InvalidArgument(format!("could not canonicalize rootfs: {}", err))
         })?;
@@ -147,16 +382,30 @@ impl<T: Instance + Send + Sync, E: EventSender> Local<T, E> {
         cfg.set_bundle(&req.bundle)
             .set_stdin(&req.stdin)
             .set_stdout(&req.stdout)
-            .set_stderr(&req.stderr);
+            .set_stderr(&req.stderr)
+            .set_terminal(req.terminal)
+            .set_cancellation_token(token.clone());
 
         // Check if this is a cri container
-        let instance = InstanceData::new(req.id(), cfg)?;
+        let creating = Instant::now();
+        let instance = InstanceData::new(req.id(), cfg);
+        Timings::global().record(req.id(), Phase::Create, creating.elapsed());
+        let instance = instance?;
+
+        if token.is_cancelled() {
+            log::info!("the creation of task {} was cancelled", req.id);
+            if let Err(err) = instance.delete() {
+                log::warn!("failed to clean up cancelled task {}: {err}", req.id);
+            }
+            return Err(Error::Cancelled(format!("creation of task {}", req.id)));
+        }
 
         self.instances
             .write()
             .unwrap()
             .insert(req.id().to_string(), Arc::new(instance));
 
+        // the process of the task only exists once it's started, its pid is sent with `TaskStart`
         self.events.send(TaskCreate {
             container_id: req.id,
             bundle: req.bundle,
@@ -190,7 +439,14 @@ impl<T: Instance + Send + Sync, E: EventSender> Local<T, E> {
         }
 
         let i = self.get_instance(req.id())?;
-        let pid = i.start()?;
+        let starting = Instant::now();
+        Timings::global().starting(req.id());
+        let pid = i.start();
+        Timings::global().record(req.id(), Phase::Start, starting.elapsed());
+        let pid = pid?;
+        if let Some(timings) = Timings::global().get(req.id()) {
+            log::info!("task {} started in {timings}", req.id());
+        }
 
         self.events.send(TaskStart {
             container_id: req.id().into(),
@@ -202,18 +458,55 @@ impl<T: Instance + Send + Sync, E: EventSender> Local<T, E> {
 
         let id = req.id().to_string();
 
+        let oom = OomMonitor::new(pid)
+            .inspect_err(|err| log::debug!("not monitoring OOM events for task {id}: {err}"))
+            .ok();
+        let resources = ResourceTracker::new(pid)
+            .inspect_err(|err| log::debug!("not tracking the resources of task {id}: {err}"))
+            .ok();
+        let namespace = self.namespace.clone();
+        let containerd_address = self.containerd_address.clone();
+
+        // the exit of the task belongs to the trace of its start
+        #[cfg(feature = "tracing")]
+        let span = tracing::Span::current();
+
         thread::Builder::new()
             .name(format!("{id}-wait"))
             .spawn(move || {
-                let (exit_code, timestamp) = i.wait();
+                #[cfg(feature = "tracing")]
+                let _enter = span.enter();
+                let context = format!("the exit watcher of task {id}");
+                let exit = panics::guarded(context, || match oom {
+                    Some(oom) => wait_and_watch_oom(&id, &i, oom, &events),
+                    None => i.wait(),
+                });
+                // the task is killed so that it doesn't run unwatched, and its exit is
+                // reported once it is killed, or as failed rather than never exiting
+                let (exit_code, timestamp) = exit.unwrap_or_else(|| {
+                    let _ = i.instance.kill(SIGKILL);
+                    let context = format!("the fallback exit watcher of task {id}");
+                    let exit =
+                        panics::guarded(context, || i.instance.wait_timeout(FALLBACK_EXIT_TIMEOUT));
+                    i.mark_exited();
+                    exit.flatten().unwrap_or_else(|| (137, Utc::now()))
+                });
                 events.send(TaskExit {
                     container_id: id.clone(),
                     exit_status: exit_code,
                     exited_at: Some(timestamp.to_timestamp()).into(),
                     pid,
-                    id,
+                    id: id.clone(),
                     ..Default::default()
                 });
+                exit_notifier::notify(exit_notifier::Exit {
+                    id: &id,
+                    namespace: &namespace,
+                    containerd_address: &containerd_address,
+                    exit_code,
+                    exited_at: timestamp,
+                    resources: resources.map(|r| r.summary()).unwrap_or_default(),
+                });
             })
             .context("could not spawn thread to wait exit")
             .map_err(Error::from)?;
@@ -231,7 +524,19 @@ impl<T: Instance + Send + Sync, E: EventSender> Local<T, E> {
         if !req.exec_id().is_empty() {
             return Err(Error::InvalidArgument("exec is not supported".to_string()));
         }
-        self.get_instance(req.id())?.kill(req.signal())?;
+        // a task killed while it is being created never runs
+        if self.cancel_create(req.id()) && !self.has_instance(req.id()) {
+            return Ok(Empty::new());
+        }
+        let i = self.get_instance(req.id())?;
+        i.kill(req.signal())?;
+
+        if req.signal() != SIGKILL {
+            if let Some(timeout) = RuntimeConfig::current().stop_timeout() {
+                escalate_after_timeout(req.id().to_string(), i, req.signal(), timeout)?;
+            }
+        }
+
         Ok(Empty::new())
     }
 
@@ -241,6 +546,14 @@ impl<T: Instance + Send + Sync, E: EventSender> Local<T, E> {
             return Err(Error::InvalidArgument("exec is not supported".to_string()));
         }
 
+        // no `TaskDelete` event is published, as no `TaskCreate` was
+        if self.cancel_create(req.id()) && !self.has_instance(req.id()) {
+            return Ok(DeleteResponse {
+                exited_at: Some(Utc::now().to_timestamp()).into(),
+                ..Default::default()
+            });
+        }
+
         let i = self.get_instance(req.id())?;
 
         i.delete()?;
@@ -250,9 +563,11 @@ impl<T: Instance + Send + Sync, E: EventSender> Local<T, E> {
         let timestamp = timestamp.map(ToTimestamp::to_timestamp);
 
         self.instances.write().unwrap().remove(req.id());
+        Timings::global().retire(req.id());
 
         self.events.send(TaskDelete {
             container_id: req.id().into(),
+            id: req.id().into(),
             pid,
             exit_status: exit_code.unwrap_or_default(),
             exited_at: timestamp.clone().into(),
@@ -308,6 +623,7 @@ impl<T: Instance + Send + Sync, E: EventSender> Local<T, E> {
             stdin: i.config().get_stdin().to_string_lossy().to_string(),
             stdout: i.config().get_stdout().to_string_lossy().to_string(),
             stderr: i.config().get_stderr().to_string_lossy().to_string(),
+            terminal: i.config().get_terminal(),
             pid: pid.unwrap_or_default(),
             exit_status: exit_code.unwrap_or_default(),
             exited_at: timestamp.into(),
@@ -323,58 +639,146 @@ impl<T: Instance + Send + Sync, E: EventSender> Local<T, E> {
             .pid()
             .ok_or_else(|| Error::InvalidArgument("task is not running".to_string()))?;
 
-        let metrics = get_metrics(pid)?;
+        let mut metrics = get_metrics(pid)?;
+        EngineMetrics::append_to(i.config().get_bundle(), &mut metrics)
+            .map_err(anyhow::Error::from)?;
 
         Ok(StatsResponse {
             stats: Some(metrics).into(),
             ..Default::default()
         })
     }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Debug"))]
+    fn task_resize_pty(&self, req: ResizePtyRequest) -> Result<Empty> {
+        if !req.exec_id().is_empty() {
+            return Err(Error::InvalidArgument("exec is not supported".to_string()));
+        }
+        let i = self.get_instance(req.id())?;
+        if !i.config().get_terminal() {
+            return Err(Error::FailedPrecondition(
+                "task was not created with a terminal".to_string(),
+            ));
+        }
+        i.resize_pty(req.width, req.height)?;
+        Ok(Empty::new())
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Debug"))]
+    fn task_close_io(&self, req: CloseIORequest) -> Result<Empty> {
+        if !req.exec_id().is_empty() {
+            return Err(Error::InvalidArgument("exec is not supported".to_string()));
+        }
+        let i = self.get_instance(req.id())?;
+        if req.stdin {
+            i.close_stdin()?;
+        }
+        Ok(Empty::new())
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Debug"))]
+    fn task_pids(&self, req: PidsRequest) -> Result<PidsResponse> {
+        let i = self.get_instance(req.id())?;
+
+        // A task that hasn't been started yet has no processes
+        let Some(pid) = i.pid() else {
+            return Ok(PidsResponse::default());
+        };
+
+        let pids = get_pids(pid).unwrap_or_else(|err| {
+            log::warn!("failed to list processes for task {}: {err}", req.id());
+            vec![pid]
+        });
+
+        let processes = pids
+            .into_iter()
+            .map(|pid| ProcessInfo {
+                pid,
+                ..Default::default()
+            })
+            .collect();
+
+        Ok(PidsResponse {
+            processes,
+            ..Default::default()
+        })
+    }
 }
 
 impl<T: Instance + Sync + Send, E: EventSender> Task for Local<T, E> {
     #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Info"))]
     fn create(
         &self,
-        _ctx: &TtrpcContext,
+        ctx: &TtrpcContext,
         req: CreateTaskRequest,
     ) -> TtrpcResult<CreateTaskResponse> {
         debug!("create: {:?}", req);
 
         #[cfg(feature = "opentelemetry")]
-        tracing::Span::current().set_parent(extract_context(&_ctx.metadata));
+        tracing::Span::current().set_parent(extract_context(&ctx.metadata));
 
-        Ok(self.task_create(req)?)
+        wire_debug::call("create", req, |req| {
+            let id = req.id().to_string();
+            audit::call(ctx, &self.namespace, "create", &id, || {
+                self.task_create(req)
+            })
+        })
     }
 
     #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Info"))]
-    fn start(&self, _ctx: &TtrpcContext, req: StartRequest) -> TtrpcResult<StartResponse> {
+    fn start(&self, ctx: &TtrpcContext, req: StartRequest) -> TtrpcResult<StartResponse> {
         debug!("start: {:?}", req);
 
         #[cfg(feature = "opentelemetry")]
-        tracing::Span::current().set_parent(extract_context(&_ctx.metadata));
+        tracing::Span::current().set_parent(extract_context(&ctx.metadata));
 
-        Ok(self.task_start(req)?)
+        wire_debug::call("start", req, |req| {
+            let id = req.id().to_string();
+            audit::call(ctx, &self.namespace, "start", &id, || self.task_start(req))
+        })
     }
 
     #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Info"))]
-    fn kill(&self, _ctx: &TtrpcContext, req: KillRequest) -> TtrpcResult<Empty> {
+    fn kill(&self, ctx: &TtrpcContext, req: KillRequest) -> TtrpcResult<Empty> {
         debug!("kill: {:?}", req);
 
         #[cfg(feature = "opentelemetry")]
-        tracing::Span::current().set_parent(extract_context(&_ctx.metadata));
+        tracing::Span::current().set_parent(extract_context(&ctx.metadata));
 
-        Ok(self.task_kill(req)?)
+        wire_debug::call("kill", req, |req| {
+            let id = req.id().to_string();
+            audit::call(ctx, &self.namespace, "kill", &id, || self.task_kill(req))
+        })
     }
 
     #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Info"))]
-    fn delete(&self, _ctx: &TtrpcContext, req: DeleteRequest) -> TtrpcResult<DeleteResponse> {
+    fn delete(&self, ctx: &TtrpcContext, req: DeleteRequest) -> TtrpcResult<DeleteResponse> {
         debug!("delete: {:?}", req);
 
         #[cfg(feature = "opentelemetry")]
-        tracing::Span::current().set_parent(extract_context(&_ctx.metadata));
+        tracing::Span::current().set_parent(extract_context(&ctx.metadata));
 
-        Ok(self.task_delete(req)?)
+        wire_debug::call("delete", req, |req| {
+            let id = req.id().to_string();
+            audit::call(ctx, &self.namespace, "delete", &id, || {
+                self.task_delete(req)
+            })
+        })
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Info"))]
+    fn exec(&self, ctx: &TtrpcContext, req: ExecProcessRequest) -> TtrpcResult<Empty> {
+        debug!("exec: {:?}", req);
+
+        #[cfg(feature = "opentelemetry")]
+        tracing::Span::current().set_parent(extract_context(&ctx.metadata));
+
+        // exec isn't supported, but attempts are still audited
+        wire_debug::call("exec", req, |req| {
+            audit::call(ctx, &self.namespace, "exec", req.id(), || {
+                Err(ShimError::Unimplemented("exec is not supported".to_string()).into())
+            })
+        })
     }
 
     #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Info"))]
@@ -398,14 +802,14 @@ impl<T: Instance + Sync + Send, E: EventSender> Task for Local<T, E> {
                     break;
                 }
             });
-            let result = self.task_wait(req)?;
+            let result = wire_debug::call("wait", req, |req| self.task_wait(req))?;
             tx.send(()).unwrap();
             Ok(result)
         }
 
         #[cfg(not(feature = "opentelemetry"))]
         {
-            Ok(self.task_wait(req)?)
+            wire_debug::call("wait", req, |req| self.task_wait(req))
         }
     }
 
@@ -416,13 +820,15 @@ impl<T: Instance + Sync + Send, E: EventSender> Task for Local<T, E> {
         #[cfg(feature = "opentelemetry")]
         tracing::Span::current().set_parent(extract_context(&_ctx.metadata));
 
-        let i = self.get_instance(req.id())?;
-        let shim_pid = std::process::id();
-        let task_pid = i.pid().unwrap_or_default();
-        Ok(ConnectResponse {
-            shim_pid,
-            task_pid,
-            ..Default::default()
+        wire_debug::call("connect", req, |req| {
+            let i = self.get_instance(req.id())?;
+            let shim_pid = std::process::id();
+            let task_pid = i.pid().unwrap_or_default();
+            Ok(ConnectResponse {
+                shim_pid,
+                task_pid,
+                ..Default::default()
+            })
         })
     }
 
@@ -433,20 +839,22 @@ impl<T: Instance + Sync + Send, E: EventSender> Task for Local<T, E> {
         #[cfg(feature = "opentelemetry")]
         tracing::Span::current().set_parent(extract_context(&_ctx.metadata));
 
-        Ok(self.task_state(req)?)
+        wire_debug::call("state", req, |req| self.task_state(req))
     }
 
     #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Info"))]
-    fn shutdown(&self, _ctx: &TtrpcContext, _: ShutdownRequest) -> TtrpcResult<Empty> {
+    fn shutdown(&self, _ctx: &TtrpcContext, req: ShutdownRequest) -> TtrpcResult<Empty> {
         debug!("shutdown");
 
         #[cfg(feature = "opentelemetry")]
         tracing::Span::current().set_parent(extract_context(&_ctx.metadata));
 
-        if self.is_empty() {
-            self.exit.signal();
-        }
-        Ok(Empty::new())
+        wire_debug::call("shutdown", req, |_| {
+            if self.is_empty() {
+                self.exit.signal();
+            }
+            Ok(Empty::new())
+        })
     }
 
     #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Info"))]
@@ -456,6 +864,36 @@ impl<T: Instance + Sync + Send, E: EventSender> Task for Local<T, E> {
         #[cfg(feature = "opentelemetry")]
         tracing::Span::current().set_parent(extract_context(&_ctx.metadata));
 
-        Ok(self.task_stats(req)?)
+        wire_debug::call("stats", req, |req| self.task_stats(req))
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Info"))]
+    fn pids(&self, _ctx: &TtrpcContext, req: PidsRequest) -> TtrpcResult<PidsResponse> {
+        debug!("pids: {:?}", req);
+
+        #[cfg(feature = "opentelemetry")]
+        tracing::Span::current().set_parent(extract_context(&_ctx.metadata));
+
+        wire_debug::call("pids", req, |req| self.task_pids(req))
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Info"))]
+    fn resize_pty(&self, _ctx: &TtrpcContext, req: ResizePtyRequest) -> TtrpcResult<Empty> {
+        debug!("resize_pty: {:?}", req);
+
+        #[cfg(feature = "opentelemetry")]
+        tracing::Span::current().set_parent(extract_context(&_ctx.metadata));
+
+        wire_debug::call("resize_pty", req, |req| self.task_resize_pty(req))
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Info"))]
+    fn close_io(&self, _ctx: &TtrpcContext, req: CloseIORequest) -> TtrpcResult<Empty> {
+        debug!("close_io: {:?}", req);
+
+        #[cfg(feature = "opentelemetry")]
+        tracing::Span::current().set_parent(extract_context(&_ctx.metadata));
+
+        wire_debug::call("close_io", req, |req| self.task_close_io(req))
     }
 }
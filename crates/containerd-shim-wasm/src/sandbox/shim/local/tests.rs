@@ -44,6 +44,57 @@ impl Instance for InstanceStub {
     }
 }
 
+/// An instance whose creation only completes once it is cancelled.
+struct CancellableInstance(InstanceStub);
+
+impl Instance for CancellableInstance {
+    type Engine = ();
+    fn new(id: String, cfg: &InstanceConfig) -> Result<Self, Error> {
+        while !cfg.get_cancellation_token().is_cancelled() {
+            thread::sleep(Duration::from_millis(10));
+        }
+        InstanceStub::new(id, cfg).map(Self)
+    }
+    fn start(&self) -> Result<u32, Error> {
+        self.0.start()
+    }
+    fn kill(&self, signal: u32) -> Result<(), Error> {
+        self.0.kill(signal)
+    }
+    fn delete(&self) -> Result<(), Error> {
+        self.0.delete()
+    }
+    fn wait_timeout(&self, t: impl Into<Option<Duration>>) -> Option<(u32, DateTime<Utc>)> {
+        self.0.wait_timeout(t)
+    }
+}
+
+/// An instance whose exit can't be waited for until it is killed.
+struct PanickingWaitInstance(InstanceStub);
+
+impl Instance for PanickingWaitInstance {
+    type Engine = ();
+    fn new(id: String, cfg: &InstanceConfig) -> Result<Self, Error> {
+        InstanceStub::new(id, cfg).map(Self)
+    }
+    fn start(&self) -> Result<u32, Error> {
+        self.0.start()
+    }
+    fn kill(&self, signal: u32) -> Result<(), Error> {
+        self.0.kill(signal)
+    }
+    fn delete(&self) -> Result<(), Error> {
+        self.0.delete()
+    }
+    fn wait_timeout(&self, t: impl Into<Option<Duration>>) -> Option<(u32, DateTime<Utc>)> {
+        match self.0.wait_timeout(Duration::ZERO) {
+            Some(exit) => Some(exit),
+            None if t.into() == Some(Duration::ZERO) => None,
+            None => panic!("waiting for the exit failed"),
+        }
+    }
+}
+
 struct LocalWithDestructor<T: Instance + Send + Sync, E: EventSender> {
     local: Arc<Local<T, E>>,
 }
@@ -130,6 +181,105 @@ fn test_delete_after_create() {
         .unwrap();
 }
 
+#[test]
+fn test_delete_cancels_pending_create() -> Result<()> {
+    let dir = tempdir()?;
+    let id = "test-delete-cancels-pending-create";
+    create_bundle(dir.path(), None)?;
+
+    let (tx, rx) = channel();
+    let local = Arc::new(Local::<CancellableInstance, _>::new(
+        (),
+        tx,
+        Arc::new(ExitSignal::default()),
+        "test_namespace",
+        "/test/address",
+    ));
+
+    let create = thread::spawn({
+        let local = local.clone();
+        let bundle = dir.path().to_str().unwrap().to_string();
+        move || {
+            local.task_create(CreateTaskRequest {
+                id: id.to_string(),
+                bundle,
+                ..Default::default()
+            })
+        }
+    });
+
+    // wait for the creation to be in progress
+    while !local.creating.lock().unwrap().contains_key(id) {
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    local.task_delete(DeleteRequest {
+        id: id.to_string(),
+        ..Default::default()
+    })?;
+
+    let res = create.join().unwrap();
+    assert!(matches!(res, Err(Error::Cancelled(_))));
+    assert!(!local.has_instance(id));
+    assert!(local.creating.lock().unwrap().is_empty());
+
+    // neither `TaskCreate` nor `TaskDelete` were published
+    assert!(rx.try_recv().is_err());
+
+    // the task is gone
+    local
+        .task_kill(KillRequest {
+            id: id.to_string(),
+            ..Default::default()
+        })
+        .unwrap_err();
+    Ok(())
+}
+
+#[test]
+fn test_resize_pty_without_terminal() -> Result<()> {
+    let dir = tempdir()?;
+    let id = "test-resize-pty-without-terminal";
+    create_bundle(dir.path(), None)?;
+
+    let (tx, _rx) = channel();
+    let local = Arc::new(Local::<InstanceStub, _>::new(
+        (),
+        tx,
+        Arc::new(ExitSignal::default()),
+        "test_namespace",
+        "/test/address",
+    ));
+    let mut _wrapped = LocalWithDestructor::new(local.clone());
+
+    local.task_create(CreateTaskRequest {
+        id: id.to_string(),
+        bundle: dir.path().to_str().unwrap().to_string(),
+        ..Default::default()
+    })?;
+
+    match local
+        .task_resize_pty(ResizePtyRequest {
+            id: id.to_string(),
+            width: 80,
+            height: 24,
+            ..Default::default()
+        })
+        .unwrap_err()
+    {
+        Error::FailedPrecondition(_) => {}
+        e => return Err(e),
+    }
+
+    local.task_close_io(CloseIORequest {
+        id: id.to_string(),
+        stdin: true,
+        ..Default::default()
+    })?;
+
+    Ok(())
+}
+
 #[test]
 fn test_cri_task() -> Result<()> {
     // Currently the relationship between the "base" container and the "instances" are pretty weak.
@@ -304,7 +454,7 @@ fn test_cri_task() -> Result<()> {
 
 #[test]
 fn test_task_lifecycle() -> Result<()> {
-    let (etx, _erx) = channel(); // TODO: check events
+    let (etx, erx) = channel();
     let exit_signal = Arc::new(ExitSignal::default());
     let local = Arc::new(Local::<InstanceStub, _>::new(
         (),
@@ -356,7 +506,7 @@ fn test_task_lifecycle() -> Result<()> {
 
     assert_eq!(state.status(), Status::CREATED);
 
-    local.task_start(StartRequest {
+    let started = local.task_start(StartRequest {
         id: "test".to_string(),
         ..Default::default()
     })?;
@@ -386,6 +536,12 @@ fn test_task_lifecycle() -> Result<()> {
     })?;
     assert!(res.has_stats());
 
+    let res = local.task_pids(PidsRequest {
+        id: "test".to_string(),
+        ..Default::default()
+    })?;
+    assert!(res.processes.iter().any(|p| p.pid == std::process::id()));
+
     local.task_kill(KillRequest {
         id: "test".to_string(),
         signal: 9,
@@ -416,5 +572,60 @@ fn test_task_lifecycle() -> Result<()> {
         e => return Err(e),
     }
 
+    // the exit event is published from the thread waiting for the task, so it can race
+    // with the delete event
+    let events: Vec<_> = erx
+        .try_iter()
+        .filter(|(topic, _)| topic != "/tasks/exit")
+        .collect();
+    let topics: Vec<&str> = events.iter().map(|(topic, _)| topic.as_str()).collect();
+    assert_eq!(topics, ["/tasks/create", "/tasks/start", "/tasks/delete"]);
+
+    // the process of the task only exists once it is started
+    let create = events[0].1.downcast_ref::<TaskCreate>().unwrap();
+    assert_eq!(create.pid, 0);
+    let start = events[1].1.downcast_ref::<TaskStart>().unwrap();
+    assert_eq!(start.pid, started.pid);
+
+    Ok(())
+}
+
+#[test]
+fn test_exit_watcher_panic() -> Result<()> {
+    let (etx, erx) = channel();
+    let local = Arc::new(Local::<PanickingWaitInstance, _>::new(
+        (),
+        etx,
+        Arc::new(ExitSignal::default()),
+        "test_namespace",
+        "/test/address",
+    ));
+    let mut _wrapped = LocalWithDestructor::new(local.clone());
+
+    let temp = tempdir().unwrap();
+    create_bundle(temp.path(), None)?;
+    local.task_create(CreateTaskRequest {
+        id: "test".to_string(),
+        bundle: temp.path().to_str().unwrap().to_string(),
+        ..Default::default()
+    })?;
+    local.task_start(StartRequest {
+        id: "test".to_string(),
+        ..Default::default()
+    })?;
+
+    // the task is killed, and its exit is reported once it is killed
+    let exit = std::iter::from_fn(|| erx.recv_timeout(Duration::from_secs(10)).ok())
+        .find(|(topic, _)| topic == "/tasks/exit")
+        .map(|(_, event)| event)
+        .unwrap();
+    let exit = exit.downcast_ref::<TaskExit>().unwrap();
+    assert_eq!(exit.exit_status, 1);
+
+    let state = local.task_state(StateRequest {
+        id: "test".to_string(),
+        ..Default::default()
+    })?;
+    assert_eq!(state.status(), Status::STOPPED);
     Ok(())
 }
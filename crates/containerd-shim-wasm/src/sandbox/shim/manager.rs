@@ -0,0 +1,170 @@
+//! Manager service of a running shim.
+//!
+//! `containerd-shim` only serves the Task service on the socket of the shim, so the shim
+//! serves the operations on itself, rather than on its tasks, with a ttrpc service of its own,
+//! `runwasi.services.manager.v1.Manager` (see `protos/manager.proto`), on a second socket.
+//! The socket is `<tmp>/runwasi-manager-<hash>.sock`, where the hash is derived from the
+//! namespace and the id of the shim, i.e., the id of the pod sandbox under CRI, and is only
+//! accessible to the user of the shim.
+//!
+//! `healthcheck -namespace <namespace> -id <id>` queries the `Health` of a running shim, and
+//! [`connect`] returns a client of the service for other tools, e.g., to get the timings of
+//! a slow task with `GetTimings`, or to get debug logs of a running task with `SetLogLevel`.
+
+use std::fs::Permissions;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+use anyhow::Context;
+
+use crate::sandbox::cli::check_config;
+use crate::sandbox::config;
+use crate::sandbox::shim::local::LocalInstances;
+use crate::sandbox::timings::Timings;
+use crate::sandbox::{Error, Instance};
+
+mod protos {
+    include!(concat!(env!("OUT_DIR"), "/protos/mod.rs"));
+}
+
+pub use protos::manager::{
+    GetTimingsRequest, GetTimingsResponse, HealthRequest, HealthResponse, SetLogLevelRequest,
+    SetLogLevelResponse,
+};
+pub use protos::manager_ttrpc::ManagerClient;
+use protos::manager_ttrpc::{create_manager, Manager};
+
+/// How long the requests of the CLI wait for the shim.
+pub(crate) const RPC_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Returns the path of the socket of the manager service of the shim `id` of `namespace`.
+pub fn socket_path(namespace: &str, id: &str) -> PathBuf {
+    // Unix socket paths are limited to ~108 bytes, so the ids are hashed
+    let hash = sha256::digest(format!("{namespace}/{id}"));
+    std::env::temp_dir().join(format!("runwasi-manager-{}.sock", &hash[..16]))
+}
+
+/// Connects to the manager service of the shim `id` of `namespace`.
+pub fn connect(namespace: &str, id: &str) -> anyhow::Result<ManagerClient> {
+    let path = socket_path(namespace, id);
+    let client = ttrpc::Client::connect(&format!("unix://{}", path.display()))
+        .with_context(|| format!("failed to connect to the shim at {}", path.display()))?;
+    Ok(ManagerClient::new(client))
+}
+
+/// Returns the `Health` of the shim `id` of `namespace`.
+pub(crate) fn health(namespace: &str, id: &str) -> anyhow::Result<HealthResponse> {
+    let client = connect(namespace, id)?;
+    let ctx = ttrpc::context::with_timeout(RPC_TIMEOUT.as_nanos() as i64);
+    client
+        .health(ctx, &HealthRequest::default())
+        .context("failed to query the health of the shim")
+}
+
+/// The manager service served by a shim, until it is shut down.
+pub(super) struct ManagerServer {
+    server: ttrpc::Server,
+    path: PathBuf,
+}
+
+impl ManagerServer {
+    pub fn start<T: Instance + Send + Sync>(
+        namespace: &str,
+        id: &str,
+        instances: &Arc<LocalInstances<T>>,
+    ) -> anyhow::Result<Self> {
+        let path = socket_path(namespace, id);
+        // a shim that was killed leaves its socket behind
+        match std::fs::remove_file(&path) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                return Err(err).context("failed to remove the stale manager socket");
+            }
+            _ => {}
+        }
+
+        let service = ManagerService {
+            instances: Arc::downgrade(instances),
+        };
+        let mut server = ttrpc::Server::new()
+            .bind(&format!("unix://{}", path.display()))
+            .with_context(|| format!("failed to bind {}", path.display()))?
+            .register_service(create_manager(Arc::new(service)));
+        std::fs::set_permissions(&path, Permissions::from_mode(0o600))?;
+        server
+            .start()
+            .context("failed to start the manager service")?;
+        log::info!("serving the manager service on {}", path.display());
+
+        Ok(Self { server, path })
+    }
+
+    pub fn shutdown(self) {
+        self.server.shutdown();
+        if let Err(err) = std::fs::remove_file(&self.path) {
+            log::warn!("failed to remove {}: {err}", self.path.display());
+        }
+    }
+}
+
+struct ManagerService<T: Instance> {
+    instances: Weak<LocalInstances<T>>,
+}
+
+impl<T: Instance + Send + Sync> Manager for ManagerService<T> {
+    fn health(
+        &self,
+        _ctx: &ttrpc::TtrpcContext,
+        _req: HealthRequest,
+    ) -> ttrpc::Result<HealthResponse> {
+        let instances = self
+            .instances
+            .upgrade()
+            .map(|instances| instances.read().unwrap().len())
+            .unwrap_or_default();
+        // the engine of a running shim was built as it started
+        let checks = [("config", check_config()), ("engine", Ok(()))];
+        let checks: std::collections::HashMap<_, _> = checks
+            .into_iter()
+            .map(|(name, res)| (name.to_string(), res.err().unwrap_or_else(|| "ok".into())))
+            .collect();
+        Ok(HealthResponse {
+            healthy: checks.values().all(|res| res == "ok"),
+            checks,
+            instances: instances as u32,
+            pid: std::process::id(),
+            ..Default::default()
+        })
+    }
+
+    fn get_timings(
+        &self,
+        _ctx: &ttrpc::TtrpcContext,
+        req: GetTimingsRequest,
+    ) -> ttrpc::Result<GetTimingsResponse> {
+        let timings = Timings::global()
+            .get(&req.id)
+            .ok_or_else(|| Error::NotFound(format!("timings of task {}", req.id)))?;
+        let nanos = |phase: Option<Duration>| phase.map(|d| d.as_nanos() as u64);
+        Ok(GetTimingsResponse {
+            fetch_ns: nanos(timings.fetch),
+            compile_ns: nanos(timings.compile),
+            build_ns: nanos(timings.build),
+            create_ns: nanos(timings.create),
+            start_ns: nanos(timings.start),
+            first_output_ns: nanos(timings.first_output),
+            deleted: timings.deleted,
+            ..Default::default()
+        })
+    }
+
+    fn set_log_level(
+        &self,
+        _ctx: &ttrpc::TtrpcContext,
+        req: SetLogLevelRequest,
+    ) -> ttrpc::Result<SetLogLevelResponse> {
+        config::set_log_level(&req.level)?;
+        Ok(SetLogLevelResponse::default())
+    }
+}
@@ -1,15 +1,43 @@
 //! The shim is the entrypoint for the containerd shim API. It is responsible
 //! for commmuincating with the containerd daemon and managing the lifecycle of
 //! the container/sandbox.
+//!
+//! A shim only lives as long as its tasks: containerd starts it to create a task, and shuts it
+//! down once its last task is deleted. Every task is a container of containerd, with its own
+//! bundle and rootfs, so the shim can't start tasks on its own, e.g., to run periodic jobs.
+//! Periodic wasm jobs are scheduled by the platform instead, e.g., with a systemd timer
+//! running `ctr run --rm` with the runtime of the shim, where each run is a short-lived task
+//! whose history is kept in the journal, and whose exit can be sent to an `exit_notifier`.
+//!
+//! For the same reason, upgrading the shim binary doesn't hand running tasks over to a new
+//! process: the tasks created after the upgrade are served by shims running the new binary,
+//! while the running tasks keep their shim until they are deleted. Their containers are
+//! children of their shim, which reaps them and holds their stdio, so they can't be served by
+//! another process without being restarted.
 
+pub(crate) mod audit;
 mod cli;
+#[cfg(target_os = "linux")]
+mod confinement;
+#[cfg(unix)]
+mod debug_dump;
 mod events;
+mod exit_notifier;
+#[cfg(all(unix, feature = "heap-profiling"))]
+mod heap_profile;
 mod instance_data;
+#[cfg(unix)]
+mod json_log;
 mod local;
+#[cfg(unix)]
+pub mod manager;
 #[cfg(feature = "opentelemetry")]
 mod otel;
 mod task_state;
+mod wire_debug;
 
 pub use cli::Cli;
 #[cfg(feature = "opentelemetry")]
+pub(crate) use otel::trace_context_headers;
+#[cfg(feature = "opentelemetry")]
 pub use otel::{traces_enabled as otel_traces_enabled, Config as OtlpConfig};
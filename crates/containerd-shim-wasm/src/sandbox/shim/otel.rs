@@ -43,6 +43,8 @@ use tracing_opentelemetry::{OpenTelemetrySpanExt as _, OtelData};
 use tracing_subscriber::layer::SubscriberExt as _;
 use tracing_subscriber::{EnvFilter, Layer, Registry};
 
+use crate::sandbox::config::{OtlpExporterConfig, OtlpProtocol};
+
 const OTEL_EXPORTER_OTLP_PROTOCOL_HTTP_JSON: &str = "http/json";
 const OTEL_EXPORTER_OTLP_PROTOCOL_HTTP_PROTOBUF: &str = "http/protobuf";
 const OTEL_EXPORTER_OTLP_PROTOCOL_GRPC: &str = "grpc";
@@ -57,7 +59,8 @@ pub struct Config {
 
 /// Returns `true` if traces are enabled, `false` otherwise.
 ///
-/// Traces are enabled if either `OTEL_EXPORTER_OTLP_TRACES_ENDPOINT` or `OTEL_EXPORTER_OTLP_ENDPOINT` is set and not empty.
+/// Traces are enabled if either `OTEL_EXPORTER_OTLP_TRACES_ENDPOINT` or `OTEL_EXPORTER_OTLP_ENDPOINT` is set and not empty,
+/// or if the runtime configuration has an `otlp` exporter.
 /// `OTEL_SDK_DISABLED` can be set to `true` to disable traces.
 pub fn traces_enabled() -> bool {
     let check_env_var = |var: &str| env::var_os(var).is_some_and(|val| !val.is_empty());
@@ -66,7 +69,10 @@ pub fn traces_enabled() -> bool {
 
     // https://opentelemetry.io/docs/specs/otel/configuration/sdk-environment-variables/#general-sdk-configuration
     let sdk_disabled = env::var_os(OTEL_SDK_DISABLED).is_some_and(|val| val == "true");
-    (traces_endpoint || otlp_endpoint) && !sdk_disabled
+    if sdk_disabled {
+        return false;
+    }
+    traces_endpoint || otlp_endpoint || OtlpExporterConfig::from_env().is_some()
 }
 
 /// Initializes a new OpenTelemetry tracer with the OTLP exporter.
@@ -75,9 +81,21 @@ pub fn traces_enabled() -> bool {
 ///
 /// <https://github.com/open-telemetry/opentelemetry-specification/blob/main/specification/protocol/exporter.md#configuration-options>
 impl Config {
+    /// Builds the configuration from the `OTEL_EXPORTER_OTLP_*` environment variables,
+    /// falling back to the `otlp` exporter of the runtime configuration for the settings they don't set.
     pub fn build_from_env() -> anyhow::Result<Self> {
-        let traces_endpoint = traces_endpoint_from_env()?;
-        let traces_protocol: Protocol = traces_protocol_from_env()?;
+        Self::build(OtlpExporterConfig::from_env())
+    }
+
+    fn build(fallback: Option<OtlpExporterConfig>) -> anyhow::Result<Self> {
+        let traces_endpoint = match (traces_endpoint_from_env(), &fallback) {
+            (Err(_), Some(fallback)) => fallback.endpoint.clone(),
+            (res, _) => res?,
+        };
+        let traces_protocol = match fallback {
+            Some(fallback) if !traces_protocol_in_env() => protocol(fallback.protocol),
+            _ => traces_protocol_from_env()?,
+        };
         Ok(Self {
             traces_endpoint,
             traces_protocol,
@@ -105,13 +123,7 @@ impl Config {
 
     /// Returns the current trace context as a JSON string.
     pub fn get_trace_context() -> anyhow::Result<String> {
-        // propagate the context
-        let mut injector: HashMap<String, String> = HashMap::new();
-        global::get_text_map_propagator(|propagator| {
-            // retrieve the context from `tracing`
-            propagator.inject_context(&Span::current().context(), &mut injector);
-        });
-        Ok(serde_json::to_string(&injector)?)
+        Ok(serde_json::to_string(&trace_context_headers())?)
     }
 
     /// Sets the trace context from a JSON string.
@@ -185,6 +197,19 @@ fn traces_protocol_from_env() -> anyhow::Result<Protocol> {
     Ok(protocol)
 }
 
+fn traces_protocol_in_env() -> bool {
+    env::var_os(OTEL_EXPORTER_OTLP_TRACES_PROTOCOL).is_some()
+        || env::var_os(OTEL_EXPORTER_OTLP_PROTOCOL).is_some()
+}
+
+fn protocol(protocol: OtlpProtocol) -> Protocol {
+    match protocol {
+        OtlpProtocol::HttpProtobuf => Protocol::HttpBinary,
+        OtlpProtocol::HttpJson => Protocol::HttpJson,
+        OtlpProtocol::Grpc => Protocol::Grpc,
+    }
+}
+
 /// A layer that renames spans to include the target in the span name.
 struct SpanNamingLayer;
 
@@ -224,6 +249,17 @@ impl Extractor for MetadataExtractor<'_> {
     }
 }
 
+/// Returns the W3C trace context headers of the current span, e.g. `traceparent`.
+pub(crate) fn trace_context_headers() -> HashMap<String, String> {
+    // propagate the context
+    let mut injector: HashMap<String, String> = HashMap::new();
+    global::get_text_map_propagator(|propagator| {
+        // retrieve the context from `tracing`
+        propagator.inject_context(&Span::current().context(), &mut injector);
+    });
+    injector
+}
+
 /// extract_context extracts the context from the metadata HashMap.
 pub(crate) fn extract_context(metadata: &HashMap<String, Vec<String>>) -> Context {
     let extractor = MetadataExtractor(metadata);
@@ -422,6 +458,32 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_build_with_fallback() {
+        let fallback = OtlpExporterConfig {
+            endpoint: "config_endpoint".to_string(),
+            protocol: OtlpProtocol::Grpc,
+        };
+
+        with_vars::<String, &str, _, _>([], || {
+            let config = Config::build(Some(fallback.clone())).unwrap();
+            assert_eq!(config.traces_endpoint, "config_endpoint");
+            assert_eq!(config.traces_protocol, Protocol::Grpc);
+        });
+
+        with_vars(
+            [
+                (OTEL_EXPORTER_OTLP_ENDPOINT, Some("general_endpoint")),
+                (OTEL_EXPORTER_OTLP_PROTOCOL, Some("http/json")),
+            ],
+            || {
+                let config = Config::build(Some(fallback.clone())).unwrap();
+                assert_eq!(config.traces_endpoint, "general_endpoint");
+                assert_eq!(config.traces_protocol, Protocol::HttpJson);
+            },
+        );
+    }
+
     #[test]
     fn test_metadata_extractor() {
         let mut metadata = HashMap::new();
@@ -0,0 +1,232 @@
+//! Logging of the task service RPCs exchanged with containerd.
+//!
+//! When `wire_debug` is enabled in the runtime configuration, every request received by the
+//! task service and the response sent back are logged under the `runwasi::wire` target.
+//! This helps debugging protocol mismatches between containerd and the shim without
+//! having to capture the traffic on the shim socket.
+//!
+//! Messages are logged in protobuf text format, with the values of fields that can carry
+//! secrets (e.g., environment variables or credentials) redacted, as well as the
+//! `KEY=VALUE` strings redacted by [`redact`](crate::sandbox::redact). Opaque `Any` payloads
+//! are redacted too, as they can embed an OCI process spec.
+//! Each message is capped to [`MAX_MESSAGE_BYTES`].
+
+use std::borrow::Cow;
+
+use containerd_shim::TtrpcResult;
+#[cfg(unix)]
+use protobuf::reflect::ReflectValueRef;
+use protobuf::MessageDyn;
+
+use crate::sandbox::config::RuntimeConfig;
+use crate::sandbox::redact::{self, REDACTED};
+use crate::sandbox::Result;
+
+const TARGET: &str = "runwasi::wire";
+
+/// Maximum size of a logged message, longer messages are truncated.
+const MAX_MESSAGE_BYTES: usize = 4096;
+
+// Fields whose name contains one of these have their value redacted.
+// `value` is the payload of `Any` messages.
+const SENSITIVE_FIELDS: &[&str] = &["env", "secret", "password", "token", "auth", "value"];
+
+/// Calls `f` with `req`, logging the request and its response when wire-debug is enabled.
+/// With JSON logging, the lines logged by `f` are tagged with `method` and the id in `req`.
+pub(super) fn call<Req: MessageDyn, Resp: MessageDyn>(
+    method: &str,
+    req: Req,
+    f: impl FnOnce(Req) -> Result<Resp>,
+) -> TtrpcResult<Resp> {
+    #[cfg(unix)]
+    {
+        let id = request_id(&req);
+        super::json_log::with_operation(method, id, || call_inner(method, req, f))
+    }
+    #[cfg(not(unix))]
+    call_inner(method, req, f)
+}
+
+fn call_inner<Req: MessageDyn, Resp: MessageDyn>(
+    method: &str,
+    req: Req,
+    f: impl FnOnce(Req) -> Result<Resp>,
+) -> TtrpcResult<Resp> {
+    if !RuntimeConfig::current().wire_debug {
+        return f(req).map_err(Into::into);
+    }
+
+    log::info!(target: TARGET, "--> {method} {}", render(&req));
+    let res = f(req);
+    match &res {
+        Ok(resp) => log::info!(target: TARGET, "<-- {method} {}", render(resp)),
+        Err(err) => log::info!(target: TARGET, "<-- {method} error: {err}"),
+    }
+    res.map_err(Into::into)
+}
+
+// Returns the `id` field of a request, which is the id of the container it targets.
+#[cfg(unix)]
+fn request_id(req: &dyn MessageDyn) -> Option<String> {
+    let field = req.descriptor_dyn().field_by_name("id")?;
+    match field.get_singular(req)? {
+        ReflectValueRef::String(id) if !id.is_empty() => Some(id.to_string()),
+        _ => None,
+    }
+}
+
+fn render(msg: &dyn MessageDyn) -> String {
+    let text = protobuf::text_format::print_to_string(msg);
+    cap(redact(&text))
+}
+
+// Redacts the values of sensitive fields in a message in text format.
+// Values are either quoted strings (with `"` escaped) or scalars without spaces.
+fn redact(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(colon) = rest.find(": ") {
+        let (head, tail) = rest.split_at(colon + 2);
+        out.push_str(head);
+
+        let name = head[..colon]
+            .rsplit(|c: char| c.is_whitespace() || c == '{')
+            .next()
+            .unwrap_or_default();
+        let value_len = value_len(tail);
+        let value = &tail[..value_len];
+
+        if is_sensitive(name) {
+            out.push('"');
+            out.push_str(REDACTED);
+            out.push('"');
+        } else {
+            out.push_str(&redact_assignment(value));
+        }
+        rest = &tail[value_len..];
+    }
+
+    out.push_str(rest);
+    out
+}
+
+// Length of the value at the start of `text`.
+fn value_len(text: &str) -> usize {
+    if let Some(quoted) = text.strip_prefix('"') {
+        let mut escaped = false;
+        for (i, c) in quoted.char_indices() {
+            match c {
+                '\\' if !escaped => escaped = true,
+                '"' if !escaped => return i + 2,
+                _ => escaped = false,
+            }
+        }
+        return text.len();
+    }
+    text.find(char::is_whitespace).unwrap_or(text.len())
+}
+
+// Redacts a quoted `"KEY=VALUE"` string if `KEY` is sensitive.
+fn redact_assignment(value: &str) -> String {
+    let Some(inner) = value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) else {
+        return value.to_string();
+    };
+    match redact::assignment(inner) {
+        Cow::Owned(redacted) => format!("\"{redacted}\""),
+        Cow::Borrowed(_) => value.to_string(),
+    }
+}
+
+fn is_sensitive(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    SENSITIVE_FIELDS.iter().any(|p| name.contains(p))
+}
+
+fn cap(mut text: String) -> String {
+    if text.len() <= MAX_MESSAGE_BYTES {
+        return text;
+    }
+    let mut end = MAX_MESSAGE_BYTES;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    let truncated = text.len() - end;
+    text.truncate(end);
+    text.push_str(&format!("... ({truncated} bytes truncated)"));
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use containerd_shim::api::{CreateTaskRequest, StartResponse};
+    use containerd_shim::protos::types::mount::Mount;
+    use protobuf::well_known_types::any::Any;
+
+    use super::*;
+
+    #[test]
+    fn test_redact() {
+        let req = CreateTaskRequest {
+            id: "my-task".to_string(),
+            bundle: "/run/bundle".to_string(),
+            rootfs: vec![Mount {
+                type_: "cifs".to_string(),
+                source: "//server/share".to_string(),
+                options: vec![
+                    "ro".to_string(),
+                    "password=hunter2".to_string(),
+                    "user=\"admin\"".to_string(),
+                ],
+                ..Default::default()
+            }],
+            options: Some(Any {
+                type_url: "runc.v1.Options".to_string(),
+                value: b"SECRET=\"x\" y".to_vec(),
+                ..Default::default()
+            })
+            .into(),
+            ..Default::default()
+        };
+
+        let text = render(&req);
+        assert!(text.contains("my-task"), "{text}");
+        assert!(text.contains("/run/bundle"), "{text}");
+        assert!(text.contains("runc.v1.Options"), "{text}");
+        assert!(text.contains("\"ro\""), "{text}");
+        assert!(text.contains("password=<redacted>"), "{text}");
+        assert!(!text.contains("hunter2"), "{text}");
+        assert!(!text.contains("SECRET"), "{text}");
+    }
+
+    #[test]
+    fn test_render_response() {
+        let resp = StartResponse {
+            pid: 42,
+            ..Default::default()
+        };
+        assert!(render(&resp).contains("pid: 42"));
+    }
+
+    #[test]
+    fn test_cap() {
+        let text = "é".repeat(MAX_MESSAGE_BYTES);
+        let capped = cap(text);
+        assert!(capped.len() < MAX_MESSAGE_BYTES + 64);
+        assert!(capped.ends_with(&format!("({} bytes truncated)", MAX_MESSAGE_BYTES)));
+
+        assert_eq!(cap("short".to_string()), "short");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_request_id() {
+        let req = CreateTaskRequest {
+            id: "my-task".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(request_id(&req).as_deref(), Some("my-task"));
+        assert_eq!(request_id(&CreateTaskRequest::default()), None);
+        assert_eq!(request_id(&StartResponse::default()), None);
+    }
+}
@@ -0,0 +1,93 @@
+//! Stats of wasm processes started outside of the shim.
+//!
+//! Platforms embedding an engine directly, instead of running their workloads as containerd
+//! tasks, can still use the stats of the shim for their processes: an [`AttachedProcess`]
+//! returns the same metrics as the `Stats` RPC of a task, the cgroup metrics of the process
+//! with the counters of its engine appended, and detects when the OOM killer kills it, without
+//! managing its lifecycle.
+//!
+//! The shim has no long-lived daemon to register processes with, so the platform attaches
+//! its processes from its own process, and publishes their events itself:
+//!
+//! ```rust, no_run
+//! use std::path::Path;
+//!
+//! use containerd_shim_wasm::container::EngineMetrics;
+//! use containerd_shim_wasm::sandbox::sidecar::AttachedProcess;
+//!
+//! # fn main() -> anyhow::Result<()> {
+//! # let pid = 42;
+//! let dir = Path::new("/run/my-platform/app");
+//!
+//! // in the process running the engine, periodically
+//! let metrics = EngineMetrics {
+//!     host_calls: Some(7),
+//!     ..Default::default()
+//! };
+//! metrics.write(dir)?;
+//!
+//! // in the platform
+//! let mut process = AttachedProcess::attach(pid)?.with_engine_metrics(dir);
+//! let stats = process.stats()?;
+//! if process.oom_killed() {
+//!     // publish the OOM event of the process
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use protobuf::well_known_types::any::Any;
+
+use crate::container::EngineMetrics;
+use crate::sys::metrics::get_metrics;
+use crate::sys::oom::OomMonitor;
+
+/// A wasm process started outside of the shim, whose stats are collected by the shim.
+pub struct AttachedProcess {
+    pid: u32,
+    engine_metrics: Option<PathBuf>,
+    oom: OomMonitor,
+}
+
+impl AttachedProcess {
+    /// Attaches to the running process `pid`, and to its cgroup.
+    pub fn attach(pid: u32) -> Result<Self> {
+        let oom = OomMonitor::new(pid)
+            .with_context(|| format!("failed to attach to the cgroup of process {pid}"))?;
+        Ok(Self {
+            pid,
+            engine_metrics: None,
+            oom,
+        })
+    }
+
+    /// Appends the counters of the engine of the process, written to `dir` with
+    /// [`EngineMetrics::write`], to its stats.
+    pub fn with_engine_metrics(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.engine_metrics = Some(dir.into());
+        self
+    }
+
+    /// Returns the pid of the process.
+    pub fn pid(&self) -> u32 {
+        self.pid
+    }
+
+    /// Returns the metrics of the process, as returned by the `Stats` RPC of a task.
+    pub fn stats(&self) -> Result<Any> {
+        let mut metrics = get_metrics(self.pid)?;
+        if let Some(dir) = &self.engine_metrics {
+            EngineMetrics::append_to(dir, &mut metrics)?;
+        }
+        Ok(metrics)
+    }
+
+    /// Returns `true` if the OOM killer killed a process in the cgroup of the process
+    /// since the last call.
+    pub fn oom_killed(&mut self) -> bool {
+        self.oom.check()
+    }
+}
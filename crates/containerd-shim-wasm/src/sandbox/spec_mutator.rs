@@ -0,0 +1,244 @@
+//! Rewrites of the OCI spec of a task before its container is built.
+//!
+//! Platforms often need to inject mounts, environment variables or annotations into every
+//! container (e.g., credentials, tracing configuration). Instead of patching the spec on disk
+//! before calling the shim, embedders can register a [`SpecMutator`]:
+//!
+//! ```rust
+//! use containerd_shim_wasm::sandbox::spec_mutator::{register, SpecMutator};
+//! use oci_spec::runtime::Spec;
+//!
+//! struct InjectRegion;
+//!
+//! impl SpecMutator for InjectRegion {
+//!     fn name(&self) -> &str {
+//!         "inject-region"
+//!     }
+//!
+//!     fn mutate(&self, _id: &str, spec: &mut Spec) -> anyhow::Result<()> {
+//!         if let Some(process) = spec.process_mut() {
+//!             let mut env = process.env().clone().unwrap_or_default();
+//!             env.push("REGION=eu-west-1".to_string());
+//!             process.set_env(Some(env));
+//!         }
+//!         Ok(())
+//!     }
+//! }
+//!
+//! register(0, InjectRegion);
+//! ```
+//!
+//! Mutators run in increasing order of priority, and in registration order for equal priorities.
+//! A mutator can override what was in the bundle, but changing or removing an env variable,
+//! a mount or an annotation set by another mutator is a conflict, and fails the task creation.
+
+use std::collections::HashMap;
+use std::sync::{Arc, LazyLock, RwLock};
+
+use oci_spec::runtime::Spec;
+
+use crate::sandbox::error::{Error, Result};
+
+/// Rewrites the OCI spec of a task.
+pub trait SpecMutator: Send + Sync {
+    /// Name of the mutator, used in logs and errors.
+    fn name(&self) -> &str;
+
+    /// Rewrites the `spec` of the task `id`.
+    fn mutate(&self, id: &str, spec: &mut Spec) -> anyhow::Result<()>;
+}
+
+/// Registers `mutator` to rewrite the spec of every task created by the shim.
+pub fn register(priority: i32, mutator: impl SpecMutator + 'static) {
+    SpecMutators::global().register(priority, Arc::new(mutator));
+}
+
+/// An ordered list of spec mutators.
+#[derive(Default)]
+pub(crate) struct SpecMutators {
+    mutators: RwLock<Vec<(i32, Arc<dyn SpecMutator>)>>,
+}
+
+// What an entry of the spec was last set by.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Kind {
+    Env,
+    Mount,
+    Annotation,
+}
+
+impl SpecMutators {
+    pub fn global() -> &'static SpecMutators {
+        static MUTATORS: LazyLock<SpecMutators> = LazyLock::new(SpecMutators::default);
+        &MUTATORS
+    }
+
+    fn register(&self, priority: i32, mutator: Arc<dyn SpecMutator>) {
+        let mut mutators = self.mutators.write().unwrap();
+        // insert after the mutators with the same priority to keep the registration order
+        let pos = mutators.partition_point(|(p, _)| *p <= priority);
+        mutators.insert(pos, (priority, mutator));
+    }
+
+    /// Applies all the mutators to `spec`.
+    /// Returns true if the spec was changed.
+    pub fn apply(&self, id: &str, spec: &mut Spec) -> Result<bool> {
+        let mutators = self.mutators.read().unwrap().clone();
+        if mutators.is_empty() {
+            return Ok(false);
+        }
+
+        let original = spec.clone();
+        let mut owners: HashMap<(Kind, String), String> = HashMap::new();
+
+        for (_, mutator) in mutators {
+            let name = mutator.name();
+            let before = entries(spec);
+            mutator.mutate(id, spec).map_err(|err| {
+                Error::Others(format!("spec mutator {name} failed for task {id}: {err:#}"))
+            })?;
+            let after = entries(spec);
+
+            let changed = before
+                .iter()
+                .filter(|(key, value)| after.get(*key) != Some(value))
+                .map(|(key, _)| key)
+                .chain(after.keys().filter(|key| !before.contains_key(*key)));
+            for key in changed {
+                if let Some(owner) = owners.get(key) {
+                    if owner != name {
+                        return Err(Error::FailedPrecondition(format!(
+                            "spec mutator {name} conflicts with {owner} on {:?} {:?} for task {id}",
+                            key.0, key.1
+                        )));
+                    }
+                }
+                log::debug!("spec mutator {name} set {:?} {:?}", key.0, key.1);
+                owners.insert(key.clone(), name.to_string());
+            }
+        }
+
+        Ok(*spec != original)
+    }
+}
+
+// The entries of the spec that mutators are expected to change, with their value.
+fn entries(spec: &Spec) -> HashMap<(Kind, String), String> {
+    let mut entries = HashMap::new();
+
+    let env = spec.process().as_ref().and_then(|p| p.env().as_ref());
+    for var in env.into_iter().flatten() {
+        let (key, value) = var.split_once('=').unwrap_or((var.as_str(), ""));
+        entries.insert((Kind::Env, key.to_string()), value.to_string());
+    }
+
+    for mount in spec.mounts().iter().flatten() {
+        let key = mount.destination().to_string_lossy().to_string();
+        entries.insert((Kind::Mount, key), format!("{mount:?}"));
+    }
+
+    for (key, value) in spec.annotations().iter().flatten() {
+        entries.insert((Kind::Annotation, key.clone()), value.clone());
+    }
+
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use oci_spec::runtime::{MountBuilder, ProcessBuilder, SpecBuilder};
+
+    use super::*;
+
+    struct SetEnv(&'static str, &'static str);
+
+    impl SpecMutator for SetEnv {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn mutate(&self, _id: &str, spec: &mut Spec) -> anyhow::Result<()> {
+            let process = spec.process_mut().as_mut().unwrap();
+            let mut env = process.env().clone().unwrap_or_default();
+            let key = self.1.split_once('=').unwrap().0;
+            env.retain(|v| !v.starts_with(&format!("{key}=")));
+            env.push(self.1.to_string());
+            process.set_env(Some(env));
+            Ok(())
+        }
+    }
+
+    struct AddMount(&'static str);
+
+    impl SpecMutator for AddMount {
+        fn name(&self) -> &str {
+            "add-mount"
+        }
+
+        fn mutate(&self, _id: &str, spec: &mut Spec) -> anyhow::Result<()> {
+            let mut mounts = spec.mounts().clone().unwrap_or_default();
+            mounts.push(MountBuilder::default().destination(self.0).build()?);
+            spec.set_mounts(Some(mounts));
+            Ok(())
+        }
+    }
+
+    fn spec() -> Spec {
+        SpecBuilder::default()
+            .process(
+                ProcessBuilder::default()
+                    .env(vec!["FOO=bundle".to_string()])
+                    .build()
+                    .unwrap(),
+            )
+            .build()
+            .unwrap()
+    }
+
+    fn env(spec: &Spec) -> Vec<String> {
+        spec.process().as_ref().unwrap().env().clone().unwrap()
+    }
+
+    #[test]
+    fn test_no_mutators() -> Result<()> {
+        let mut spec = spec();
+        assert!(!SpecMutators::default().apply("task", &mut spec)?);
+        assert_eq!(spec, self::spec());
+        Ok(())
+    }
+
+    #[test]
+    fn test_mutators_run_in_priority_order() -> Result<()> {
+        let mutators = SpecMutators::default();
+        mutators.register(10, Arc::new(AddMount("/data")));
+        mutators.register(0, Arc::new(SetEnv("first", "BAR=1")));
+        mutators.register(10, Arc::new(SetEnv("last", "BAZ=1")));
+
+        let mut spec = spec();
+        assert!(mutators.apply("task", &mut spec)?);
+        assert_eq!(env(&spec), ["FOO=bundle", "BAR=1", "BAZ=1"]);
+        assert_eq!(spec.mounts().as_ref().unwrap().len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_mutators_can_override_the_bundle() -> Result<()> {
+        let mutators = SpecMutators::default();
+        mutators.register(0, Arc::new(SetEnv("platform", "FOO=platform")));
+
+        let mut spec = spec();
+        assert!(mutators.apply("task", &mut spec)?);
+        assert_eq!(env(&spec), ["FOO=platform"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_conflicting_mutators() {
+        let mutators = SpecMutators::default();
+        mutators.register(0, Arc::new(SetEnv("a", "BAR=a")));
+        mutators.register(1, Arc::new(SetEnv("b", "BAR=b")));
+
+        let err = mutators.apply("task", &mut spec()).unwrap_err();
+        assert!(err.to_string().contains("conflicts with a"), "{err}");
+    }
+}
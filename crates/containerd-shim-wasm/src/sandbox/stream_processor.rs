@@ -0,0 +1,85 @@
+//! The `precompile` command of the shim, a containerd stream processor precompiling the wasm
+//! layers of images as they are pulled.
+//!
+//! containerd runs a stream processor for each layer of the media types it accepts, as the layer
+//! is unpacked, with the layer on stdin and its media type in `STREAM_PROCESSOR_MEDIATYPE`.
+//! The command precompiles the layer with the engine of the shim, stores the precompiled content
+//! in containerd, and writes an empty tar archive to stdout: the shim loads wasm layers from
+//! the content store, so the snapshot of the layer is kept empty.
+//!
+//! Layers that fail to precompile are reported on stderr, and compiled by the first container
+//! of the image instead, so the command never fails the pull.
+
+use std::io::{Read, Write};
+
+use oci_spec::image::{Descriptor, Digest, MediaType};
+
+use crate::sandbox::oci::WasmLayer;
+use crate::sandbox::Instance;
+
+/// Environment variable with the media type of the layer, set by containerd.
+const MEDIA_TYPE_ENV: &str = "STREAM_PROCESSOR_MEDIATYPE";
+
+const DEFAULT_CONTAINERD_ADDRESS: &str = "/run/containerd/containerd.sock";
+const DEFAULT_NAMESPACE: &str = "k8s.io";
+
+/// Precompiles the layer on stdin, and writes an empty layer to stdout.
+pub(crate) fn run<I: Instance>(containerd_address: &str, namespace: &str) -> anyhow::Result<()> {
+    let containerd_address = Some(containerd_address)
+        .filter(|a| !a.is_empty())
+        .unwrap_or(DEFAULT_CONTAINERD_ADDRESS);
+    let namespace = Some(namespace)
+        .filter(|ns| !ns.is_empty())
+        .unwrap_or(DEFAULT_NAMESPACE);
+    let media_type = std::env::var(MEDIA_TYPE_ENV).unwrap_or_else(|_| "application/wasm".into());
+
+    let layer = read_layer(std::io::stdin().lock(), &media_type)?;
+    let digest = layer.config.digest().clone();
+    // containerd only reports the stderr of stream processors, the shim has no logger here
+    if let Err(err) = I::precompile_pulled_layers(containerd_address, namespace, &[layer]) {
+        eprintln!("failed to precompile layer {digest} as it was pulled: {err}");
+    }
+
+    write_empty_tar(std::io::stdout().lock())
+}
+
+fn read_layer(mut input: impl Read, media_type: &str) -> anyhow::Result<WasmLayer> {
+    let mut layer = vec![];
+    input.read_to_end(&mut layer)?;
+    let digest = Digest::try_from(format!("sha256:{}", sha256::digest(layer.as_slice())))?;
+    let config = Descriptor::new(
+        MediaType::Other(media_type.to_string()),
+        layer.len() as u64,
+        digest,
+    );
+    Ok(WasmLayer { config, layer })
+}
+
+// An empty tar archive is two zeroed 512 bytes blocks.
+fn write_empty_tar(mut out: impl Write) -> anyhow::Result<()> {
+    out.write_all(&[0; 1024])?;
+    out.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_layer() -> anyhow::Result<()> {
+        let module = b"\0asm\x01\0\0\0";
+        let layer = read_layer(&module[..], "application/wasm")?;
+        assert_eq!(layer.layer, module);
+        assert_eq!(layer.config.size(), module.len() as u64);
+        assert_eq!(
+            layer.config.digest().to_string(),
+            format!("sha256:{}", sha256::digest(&module[..]))
+        );
+
+        let mut out = vec![];
+        write_empty_tar(&mut out)?;
+        assert_eq!(out, [0; 1024]);
+        Ok(())
+    }
+}
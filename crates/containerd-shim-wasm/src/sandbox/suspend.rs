@@ -0,0 +1,142 @@
+//! Detection of host suspend/resume cycles.
+//!
+//! Edge devices are often suspended and resumed. While the host is suspended, guests
+//! don't make progress, which confuses monotonic clocks and any deadline derived from them.
+//!
+//! On Linux, `CLOCK_MONOTONIC` doesn't advance while the system is suspended, but
+//! `CLOCK_BOOTTIME` does. A jump in the difference between the two means the host was suspended.
+//!
+//! Engines can use [`on_resume`] to be notified of such jumps and adjust their deadlines
+//! (e.g., epoch or fuel based timeouts) accordingly.
+//!
+//! ```rust
+//! use containerd_shim_wasm::sandbox::suspend::on_resume;
+//!
+//! on_resume(|resume| {
+//!     log::info!("host was suspended for {:?}", resume.suspended_for);
+//! });
+//! ```
+
+use std::sync::{LazyLock, Mutex, Once};
+use std::thread;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+
+/// How often the clocks are sampled.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Minimum gap between the clocks to be considered a suspension.
+const THRESHOLD: Duration = Duration::from_secs(2);
+
+/// Information about a detected suspend/resume cycle.
+#[derive(Clone, Debug)]
+pub struct Resume {
+    /// How long the host was suspended for.
+    pub suspended_for: Duration,
+    /// When the resume was detected.
+    pub resumed_at: DateTime<Utc>,
+}
+
+type Callback = Box<dyn Fn(&Resume) + Send + Sync>;
+
+static CALLBACKS: LazyLock<Mutex<Vec<Callback>>> = LazyLock::new(Mutex::default);
+
+/// Registers `f` to be called every time the host resumes from a suspension.
+///
+/// The first call starts a background thread in the current process that monitors the clocks.
+pub fn on_resume(f: impl Fn(&Resume) + Send + Sync + 'static) {
+    CALLBACKS.lock().unwrap().push(Box::new(f));
+
+    static START: Once = Once::new();
+    START.call_once(|| {
+        let res = thread::Builder::new()
+            .name("suspend-monitor".to_string())
+            .spawn(monitor);
+        if let Err(err) = res {
+            log::warn!("failed to start suspend monitor: {err}");
+        }
+    });
+}
+
+fn monitor() {
+    let Some(mut prev) = clock_offset() else {
+        log::debug!("suspend detection is not supported on this platform");
+        return;
+    };
+
+    loop {
+        thread::sleep(POLL_INTERVAL);
+        let Some(offset) = clock_offset() else {
+            return;
+        };
+
+        if let Some(suspended_for) = detect_suspension(prev, offset) {
+            let resume = Resume {
+                suspended_for,
+                resumed_at: Utc::now(),
+            };
+            log::warn!("host resumed after being suspended for {suspended_for:?}");
+            for f in CALLBACKS.lock().unwrap().iter() {
+                f(&resume);
+            }
+        }
+
+        prev = offset;
+    }
+}
+
+// Returns how long the host was suspended, if the offset between
+// the clocks increased by more than `THRESHOLD` since the last sample.
+fn detect_suspension(prev: Duration, offset: Duration) -> Option<Duration> {
+    let gap = offset.saturating_sub(prev);
+    (gap >= THRESHOLD).then_some(gap)
+}
+
+// Difference between `CLOCK_BOOTTIME` and `CLOCK_MONOTONIC`,
+// i.e., the total time the host spent suspended since boot.
+#[cfg(target_os = "linux")]
+fn clock_offset() -> Option<Duration> {
+    fn now(clock: libc::clockid_t) -> Option<Duration> {
+        let mut ts = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        let res = unsafe { libc::clock_gettime(clock, &mut ts) };
+        (res == 0).then(|| Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32))
+    }
+
+    let boottime = now(libc::CLOCK_BOOTTIME)?;
+    let monotonic = now(libc::CLOCK_MONOTONIC)?;
+    Some(boottime.saturating_sub(monotonic))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn clock_offset() -> Option<Duration> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_suspension() {
+        let prev = Duration::from_secs(10);
+        assert!(detect_suspension(prev, prev).is_none());
+        assert!(detect_suspension(prev, prev + Duration::from_millis(10)).is_none());
+        assert_eq!(
+            detect_suspension(prev, prev + Duration::from_secs(60)),
+            Some(Duration::from_secs(60))
+        );
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_clock_offset() {
+        let a = clock_offset().unwrap();
+        let b = clock_offset().unwrap();
+        // the offset only grows while the host is suspended
+        assert!(detect_suspension(a, b).is_none());
+    }
+}
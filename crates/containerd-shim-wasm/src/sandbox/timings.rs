@@ -0,0 +1,224 @@
+//! Per-phase timings of the creation and start of the tasks of the shim.
+//!
+//! Each phase is recorded where it runs, keyed by the id of the task, so that
+//! "why was this task slow to start" can be answered from a single record instead of
+//! correlating log lines. The records of deleted tasks are kept for a while, since a slow
+//! task is often only looked at once it has exited. They are listed in the debug dump of the
+//! shim, served by the `GetTimings` RPC of its [`manager`](crate::sandbox::shim::manager)
+//! service, logged when a task starts, and sent with the exit notifications of the tasks.
+//!
+//! The first output of a task is only seen by the shim when it buffers the output, with
+//! `stdio` in the runtime configuration, otherwise the container writes to containerd
+//! directly and the phase isn't recorded.
+
+#![cfg_attr(windows, allow(dead_code))] // fetching and building are only timed on linux
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt::{Display, Formatter};
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+/// Deleted tasks whose timings are kept.
+const RETAINED_DELETED: usize = 32;
+
+/// A phase of the creation or start of a task.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Phase {
+    /// Fetching the modules of the task, including precompiling them.
+    Fetch,
+    /// Precompiling the modules of the task, when they weren't precompiled yet.
+    Compile,
+    /// Building the container of the task.
+    Build,
+    /// The whole creation of the task.
+    Create,
+    /// Starting the task.
+    Start,
+    /// From the start of the task until its first output.
+    FirstOutput,
+}
+
+/// The timings recorded for a task.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TimingRecord {
+    pub fetch: Option<Duration>,
+    pub compile: Option<Duration>,
+    pub build: Option<Duration>,
+    pub create: Option<Duration>,
+    pub start: Option<Duration>,
+    pub first_output: Option<Duration>,
+    /// Whether the task was deleted.
+    pub deleted: bool,
+}
+
+impl TimingRecord {
+    fn add(&mut self, phase: Phase, duration: Duration) {
+        let slot = match phase {
+            Phase::Fetch => &mut self.fetch,
+            Phase::Compile => &mut self.compile,
+            Phase::Build => &mut self.build,
+            Phase::Create => &mut self.create,
+            Phase::Start => &mut self.start,
+            Phase::FirstOutput => &mut self.first_output,
+        };
+        // a phase can run more than once, e.g., compiling several images
+        *slot = Some(slot.unwrap_or_default() + duration);
+    }
+}
+
+impl Display for TimingRecord {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let phases = [
+            ("fetch", self.fetch),
+            ("compile", self.compile),
+            ("build", self.build),
+            ("create", self.create),
+            ("start", self.start),
+            ("first_output", self.first_output),
+        ];
+        let mut sep = "";
+        for (name, duration) in phases {
+            if let Some(duration) = duration {
+                write!(f, "{sep}{name}={duration:?}")?;
+                sep = " ";
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The timings of the tasks of the shim.
+#[derive(Default)]
+pub(crate) struct Timings {
+    inner: Mutex<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    records: HashMap<String, TimingRecord>,
+    deleted: VecDeque<String>,
+    // when the tasks were started, until their first output
+    started: HashMap<String, Instant>,
+}
+
+impl Timings {
+    /// Returns the timings shared by all the tasks in this process.
+    pub fn global() -> &'static Timings {
+        static TIMINGS: LazyLock<Timings> = LazyLock::new(Timings::default);
+        &TIMINGS
+    }
+
+    /// Records that `phase` of the task `id` took `duration`.
+    pub fn record(&self, id: &str, phase: Phase, duration: Duration) {
+        let mut inner = self.inner.lock().unwrap();
+        inner
+            .records
+            .entry(id.to_string())
+            .or_default()
+            .add(phase, duration);
+    }
+
+    /// Records that the task `id` is being started, for the time to its first output.
+    pub fn starting(&self, id: &str) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.started.insert(id.to_string(), Instant::now());
+    }
+
+    /// Records the first output of the task `id`, if it was started.
+    pub fn first_output(&self, id: &str) {
+        let mut inner = self.inner.lock().unwrap();
+        let Some(started) = inner.started.remove(id) else {
+            return;
+        };
+        inner
+            .records
+            .entry(id.to_string())
+            .or_default()
+            .add(Phase::FirstOutput, started.elapsed());
+    }
+
+    /// Returns the timings of the task `id`, if it is live or was recently deleted.
+    pub fn get(&self, id: &str) -> Option<TimingRecord> {
+        self.inner.lock().unwrap().records.get(id).cloned()
+    }
+
+    /// Marks the task `id` as deleted. Only the last `RETAINED_DELETED` deleted tasks are kept.
+    pub fn retire(&self, id: &str) {
+        let mut inner = self.inner.lock().unwrap();
+        let Some(record) = inner.records.get_mut(id) else {
+            return;
+        };
+        if record.deleted {
+            return;
+        }
+        record.deleted = true;
+        inner.started.remove(id);
+        inner.deleted.push_back(id.to_string());
+        while inner.deleted.len() > RETAINED_DELETED {
+            if let Some(oldest) = inner.deleted.pop_front() {
+                inner.records.remove(&oldest);
+            }
+        }
+    }
+
+    /// Forgets the task `id`, e.g., when a task with the same id is created again.
+    pub fn reset(&self, id: &str) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.records.remove(id);
+        inner.deleted.retain(|deleted| deleted != id);
+        inner.started.remove(id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timings() {
+        let timings = Timings::default();
+        timings.record("a", Phase::Fetch, Duration::from_millis(10));
+        timings.record("a", Phase::Compile, Duration::from_millis(20));
+        timings.record("a", Phase::Compile, Duration::from_millis(5));
+        timings.record("a", Phase::Create, Duration::from_millis(40));
+
+        let record = timings.get("a").unwrap();
+        assert_eq!(record.compile, Some(Duration::from_millis(25)));
+        assert_eq!(record.start, None);
+        assert_eq!(record.to_string(), "fetch=10ms compile=25ms create=40ms");
+        assert!(timings.get("b").is_none());
+    }
+
+    #[test]
+    fn test_first_output() {
+        let timings = Timings::default();
+        // not started yet
+        timings.first_output("a");
+        assert!(timings.get("a").is_none());
+
+        timings.starting("a");
+        timings.first_output("a");
+        let first_output = timings.get("a").unwrap().first_output;
+        assert!(first_output.is_some());
+
+        // only the first output is recorded
+        timings.first_output("a");
+        assert_eq!(timings.get("a").unwrap().first_output, first_output);
+    }
+
+    #[test]
+    fn test_deleted_timings_are_bounded() {
+        let timings = Timings::default();
+        for n in 0..=RETAINED_DELETED {
+            let id = n.to_string();
+            timings.record(&id, Phase::Create, Duration::from_millis(1));
+            timings.retire(&id);
+        }
+        // the oldest deleted task is forgotten
+        assert!(timings.get("0").is_none());
+        assert!(timings.get("1").unwrap().deleted);
+
+        timings.reset("1");
+        assert!(timings.get("1").is_none());
+    }
+}
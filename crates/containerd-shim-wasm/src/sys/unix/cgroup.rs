@@ -0,0 +1,50 @@
+use std::fs::read_to_string;
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+/// The directory of a cgroup in the cgroup filesystem.
+pub enum CgroupDir {
+    /// A cgroup in the v1 hierarchy of a specific controller.
+    V1(PathBuf),
+    /// A cgroup in the v2 unified hierarchy.
+    V2(PathBuf),
+}
+
+impl CgroupDir {
+    pub fn path(&self) -> &PathBuf {
+        match self {
+            Self::V1(p) | Self::V2(p) => p,
+        }
+    }
+}
+
+/// Resolve the cgroup directory of `pid`.
+/// On cgroup v2 there is a single unified hierarchy (`0::/path`).
+/// On cgroup v1 we use the hierarchy of `v1_controller`.
+pub fn cgroup_dir(pid: u32, v1_controller: &str) -> Result<CgroupDir> {
+    let content = read_to_string(format!("/proc/{pid}/cgroup"))
+        .with_context(|| format!("failed to read cgroup of process {pid}"))?;
+
+    let mut v1_path = None;
+    for line in content.lines() {
+        let mut parts = line.splitn(3, ':');
+        let (Some(id), Some(controllers), Some(path)) = (parts.next(), parts.next(), parts.next())
+        else {
+            continue;
+        };
+        let path = path.trim_start_matches('/');
+        if id == "0" && controllers.is_empty() {
+            return Ok(CgroupDir::V2(PathBuf::from(CGROUP_ROOT).join(path)));
+        }
+        if controllers.split(',').any(|c| c == v1_controller) {
+            v1_path = Some(PathBuf::from(CGROUP_ROOT).join(v1_controller).join(path));
+        }
+    }
+
+    v1_path
+        .map(CgroupDir::V1)
+        .with_context(|| format!("failed to find cgroup of process {pid}"))
+}
@@ -0,0 +1,105 @@
+//! Admission of containers against a limit on the wasm instances of the node.
+//!
+//! Every shim runs its own containers, so a limit across the node is kept in a directory shared
+//! by all the shims: each of the `max_instances` slots is a lock file there, and a container
+//! holds an exclusive `flock` on one from its creation until it is deleted. When every slot is
+//! locked, the creation is rejected with `RESOURCE_EXHAUSTED`.
+//!
+//! The kernel releases the locks of a shim that dies, so slots are never leaked.
+
+use std::fs::{File, OpenOptions};
+use std::path::Path;
+
+use nix::errno::Errno;
+use nix::fcntl::{Flock, FlockArg};
+
+use crate::sandbox::config::AdmissionConfig;
+use crate::sandbox::Error;
+
+/// A slot of the node, held until it's dropped.
+pub struct Slot {
+    _lock: Flock<File>,
+}
+
+impl Slot {
+    /// Takes a free slot of the node for the container `id`.
+    pub fn acquire(id: &str, cfg: &AdmissionConfig) -> Result<Self, Error> {
+        std::fs::create_dir_all(&cfg.dir)?;
+        for index in 0..cfg.max_instances {
+            let file = open_slot(&cfg.dir, index)?;
+            match Flock::lock(file, FlockArg::LockExclusiveNonblock) {
+                Ok(lock) => {
+                    log::info!(
+                        "container {id} admitted in slot {index} of {}",
+                        cfg.max_instances
+                    );
+                    if log::log_enabled!(log::Level::Debug) {
+                        log::debug!(
+                            "{} of {} wasm instances running on the node",
+                            in_use(cfg),
+                            cfg.max_instances
+                        );
+                    }
+                    return Ok(Self { _lock: lock });
+                }
+                Err((_, Errno::EWOULDBLOCK)) => continue,
+                Err((_, err)) => return Err(err.into()),
+            }
+        }
+        Err(Error::ResourceExhausted(format!(
+            "container {id} rejected, the node already runs {} wasm instances",
+            cfg.max_instances
+        )))
+    }
+}
+
+/// Returns the number of slots of the node held by containers.
+pub fn in_use(cfg: &AdmissionConfig) -> usize {
+    (0..cfg.max_instances)
+        .filter(|&index| {
+            let Ok(file) = open_slot(&cfg.dir, index) else {
+                return false;
+            };
+            matches!(
+                Flock::lock(file, FlockArg::LockSharedNonblock),
+                Err((_, Errno::EWOULDBLOCK))
+            )
+        })
+        .count()
+}
+
+fn open_slot(dir: &Path, index: usize) -> std::io::Result<File> {
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(dir.join(format!("slot-{index}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_admission() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let cfg = AdmissionConfig {
+            max_instances: 2,
+            dir: dir.path().to_path_buf(),
+        };
+
+        let first = Slot::acquire("first", &cfg)?;
+        let _second = Slot::acquire("second", &cfg)?;
+        assert_eq!(in_use(&cfg), 2);
+        assert!(matches!(
+            Slot::acquire("third", &cfg),
+            Err(Error::ResourceExhausted(_))
+        ));
+
+        drop(first);
+        assert_eq!(in_use(&cfg), 1);
+        let _third = Slot::acquire("third", &cfg)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,186 @@
+//! The artifact cache of the node, where the precompiled layers and the wasm modules of the
+//! containers are shared. See [`PrecompiledArtifact`] and [`WasmLayer::bytes`].
+
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+use memmap2::Mmap;
+
+use crate::container::{share_layers, Engine, PrecompiledArtifact, WasmBinaryType};
+use crate::sandbox::oci::WasmLayer;
+
+// the directory of the wasm modules, next to the ones of the engines, named `{name}-{version}`
+const MODULES_DIR: &str = "wasm";
+
+/// Maps the precompiled layers of `layers` from the artifact cache in `rootdir`, adding the ones
+/// that aren't cached yet. Layers that aren't precompiled, or fail to be cached, are skipped.
+pub fn map<E: Engine>(
+    engine: &E,
+    rootdir: &Path,
+    layers: &[WasmLayer],
+) -> Vec<PrecompiledArtifact> {
+    let Some(version) = engine.can_precompile() else {
+        return vec![];
+    };
+    // artifacts compiled by other engines, or other versions of the engine, are kept apart
+    let dir = artifacts_dir(rootdir).join(format!("{}-{version}", E::name()).replace('/', "_"));
+
+    layers
+        .iter()
+        .filter(|layer| {
+            !layer.layer.is_empty() && WasmBinaryType::from_bytes(&layer.layer).is_none()
+        })
+        .filter_map(|layer| {
+            let digest = layer.config.digest().to_string();
+            map_layer(&dir, &digest, &layer.layer)
+                .inspect_err(|err| log::warn!("not sharing the precompiled layer {digest}: {err}"))
+                .ok()
+        })
+        .collect()
+}
+
+/// Maps the wasm modules of `layers` from the artifact cache in `rootdir`, adding the ones that
+/// aren't cached yet, and drops their bytes from `layers`, so that [`WasmLayer::bytes`] returns
+/// the mappings. Modules that fail to be cached keep their bytes.
+pub fn share_modules(rootdir: &Path, layers: &mut [WasmLayer]) {
+    let dir = artifacts_dir(rootdir).join(MODULES_DIR);
+    let mut mapped = HashMap::new();
+    for layer in layers.iter() {
+        if WasmBinaryType::from_bytes(&layer.layer).is_none() {
+            continue;
+        }
+        let digest = layer.config.digest().to_string();
+        match map_module(&dir, &digest, &layer.layer) {
+            Ok(mmap) => {
+                mapped.insert(digest, mmap);
+            }
+            Err(err) => log::warn!("not sharing the module {digest}: {err}"),
+        }
+    }
+    let digests: HashSet<String> = mapped.keys().cloned().collect();
+    // the mappings are inherited by the container process, forked from this one
+    if digests.is_empty() || !share_layers(mapped) {
+        return;
+    }
+    for layer in layers {
+        if digests.contains(layer.config.digest()) {
+            layer.layer = vec![];
+        }
+    }
+}
+
+/// Removes the precompiled layer or the wasm module `digest` from the artifact cache in
+/// `rootdir`, for all engines, once it's deleted from containerd. The containers mapping it keep
+/// their mapping.
+pub fn evict(rootdir: &Path, digest: &str) {
+    let Ok(dirs) = fs::read_dir(artifacts_dir(rootdir)) else {
+        return;
+    };
+    for dir in dirs.flatten() {
+        let path = dir.path().join(digest.replace(':', "_"));
+        match fs::remove_file(&path) {
+            Ok(()) => log::info!("evicted the precompiled layer {digest} from {path:?}"),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => log::warn!("failed to evict the precompiled layer {path:?}: {err}"),
+        }
+    }
+}
+
+fn artifacts_dir(rootdir: &Path) -> PathBuf {
+    // container ids can't start with a dot, so this is never the state of a container
+    rootdir.join(".artifacts")
+}
+
+fn map_layer(dir: &Path, digest: &str, content: &[u8]) -> std::io::Result<PrecompiledArtifact> {
+    let artifact = PrecompiledArtifact::open(digest, &cache(dir, digest, content)?)?;
+    // like cached modules, the cached layer might not have been verified by its writer
+    if *artifact != *content {
+        return Err(std::io::Error::other(format!(
+            "the cached precompiled layer {digest} doesn't match the layer"
+        )));
+    }
+    Ok(artifact)
+}
+
+fn map_module(dir: &Path, digest: &str, content: &[u8]) -> std::io::Result<Mmap> {
+    let file = File::open(cache(dir, digest, content)?)?;
+    // SAFETY: cached modules are written to a temporary file and renamed, so the file is never
+    // modified once it's in the cache, which the containers can't access, and the mapping is
+    // read-only.
+    let mmap = unsafe { Mmap::map(&file)? };
+    // the cached module was written by another container of the node, which might not have
+    // verified the digest of its layer
+    if mmap[..] != *content {
+        return Err(std::io::Error::other(format!(
+            "the cached module {digest} doesn't match the layer"
+        )));
+    }
+    Ok(mmap)
+}
+
+// Writes `content` to the cache in `dir`, unless it's already there, and returns its path.
+fn cache(dir: &Path, digest: &str, content: &[u8]) -> std::io::Result<PathBuf> {
+    let path = dir.join(digest.replace(':', "_"));
+    if !path.exists() {
+        fs::create_dir_all(dir)?;
+        // other containers of the image might be caching it concurrently
+        let tmp = dir.join(format!(
+            ".{}.{}",
+            digest.replace(':', "_"),
+            std::process::id()
+        ));
+        fs::write(&tmp, content)?;
+        fs::rename(&tmp, &path)?;
+    }
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_map_layer() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let artifact = map_layer(dir.path(), "sha256:abc", b"precompiled")?;
+        assert_eq!(artifact.digest(), "sha256:abc");
+        assert_eq!(&*artifact, b"precompiled");
+
+        // the cached artifact is reused
+        let artifact = map_layer(dir.path(), "sha256:abc", b"precompiled")?;
+        assert_eq!(&*artifact, b"precompiled");
+        assert!(dir.path().join("sha256_abc").exists());
+
+        // a layer that doesn't match the cached artifact isn't shared
+        map_layer(dir.path(), "sha256:abc", b"tampered").unwrap_err();
+        map_layer(dir.path(), "sha256:abc", b"").unwrap_err();
+        Ok(())
+    }
+
+    #[test]
+    fn test_map_module() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let module = b"\0asm\x01\0\0\0";
+        let mmap = map_module(dir.path(), "sha256:abc", module)?;
+        assert_eq!(&mmap[..], module);
+        assert_eq!(&map_module(dir.path(), "sha256:abc", module)?[..], module);
+
+        // a layer that doesn't match the cached module isn't shared
+        map_module(dir.path(), "sha256:abc", b"\0asm\x02\0\0\0").unwrap_err();
+        Ok(())
+    }
+
+    #[test]
+    fn test_evict() -> anyhow::Result<()> {
+        let rootdir = tempfile::tempdir()?;
+        let engine_dir = artifacts_dir(rootdir.path()).join("wasmtime-1");
+        map_layer(&engine_dir, "sha256:abc", b"precompiled")?;
+        map_layer(&engine_dir, "sha256:def", b"precompiled")?;
+
+        evict(rootdir.path(), "sha256:abc");
+        assert!(!engine_dir.join("sha256_abc").exists());
+        assert!(engine_dir.join("sha256_def").exists());
+        Ok(())
+    }
+}
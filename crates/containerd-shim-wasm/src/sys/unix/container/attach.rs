@@ -0,0 +1,237 @@
+//! Detaching and re-attaching the readers of the output of a container.
+//!
+//! containerd clients read the output of a task from fifos, and a client that goes away
+//! leaves the fifos without a reader: writing to them then fails, or blocks once their
+//! buffer is full. When the `runwasi.io/attachable` annotation is `"true"`, the container
+//! writes to an intermediate fifo instead, and the shim copies it to the fifo of containerd:
+//! * when the reader goes away, the shim keeps reading the output of the container,
+//!   and keeps the last `BACKLOG_BYTES` of it.
+//! * when a new reader opens the fifo of containerd, e.g., with `ctr task attach`, it gets
+//!   the backlog, and then the output of the container as it is written.
+//!
+//! The guest never sees the reader going away.
+
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::{FileTypeExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::thread;
+
+use anyhow::{Context, Result};
+use nix::fcntl::{fcntl, FcntlArg, OFlag};
+use nix::sys::stat::Mode;
+use nix::unistd::mkfifo;
+use oci_spec::runtime::Spec;
+
+/// Annotation to keep the output of the container while no reader is attached.
+pub const ATTACHABLE_ANNOTATION: &str = "runwasi.io/attachable";
+
+/// Output kept while no reader is attached, replayed to the next reader.
+const BACKLOG_BYTES: usize = 64 * 1024;
+
+const BUFFER_SIZE: usize = 32 * 1024;
+
+/// Returns true if readers can detach from and re-attach to the output of the container.
+pub fn is_enabled(spec: &Spec) -> bool {
+    spec.annotations()
+        .as_ref()
+        .and_then(|a| a.get(ATTACHABLE_ANNOTATION))
+        .is_some_and(|v| v == "true")
+}
+
+/// Returns true if `output` is a fifo readers can detach from.
+/// Output to files, e.g., with a `file://` log URI, is always written.
+pub fn is_fifo(output: &Path) -> bool {
+    std::fs::metadata(output).is_ok_and(|m| m.file_type().is_fifo())
+}
+
+/// Creates the fifo `attach-{stream}` for the container in `dir`, and starts copying what is
+/// written to it into the fifo `output`, whenever it has a reader.
+/// Returns the path of the fifo.
+pub fn start(
+    id: &str,
+    stream: &str,
+    output: impl AsRef<Path>,
+    dir: impl AsRef<Path>,
+) -> Result<PathBuf> {
+    let output = output.as_ref().to_path_buf();
+    // containerd has a reader on the fifo when the container is created
+    let writer = open_writer(&output)
+        .with_context(|| format!("failed to open output {output:?}"))?
+        .with_context(|| format!("output {output:?} has no reader"))?;
+
+    let path = dir.as_ref().join(format!("attach-{stream}"));
+    let _ = std::fs::remove_file(&path);
+    mkfifo(&path, Mode::S_IRUSR | Mode::S_IWUSR)
+        .with_context(|| format!("failed to create fifo {path:?}"))?;
+
+    let stream = format!("{stream} of container {id}");
+    thread::Builder::new().name("attach".to_string()).spawn({
+        let path = path.clone();
+        move || {
+            // this blocks until the container opens the fifo for writing
+            let res = File::open(&path).and_then(|input| {
+                let mut output = Output::new(output, writer, &stream);
+                copy(input, &mut output)
+            });
+            if let Err(err) = res {
+                log::error!("error reading {path:?}: {err}");
+            }
+            let _ = std::fs::remove_file(&path);
+        }
+    })?;
+
+    Ok(path)
+}
+
+// Copies `input` into `output` until the container closes it.
+fn copy(mut input: impl Read, output: &mut Output) -> std::io::Result<()> {
+    let mut buf = vec![0; BUFFER_SIZE];
+    loop {
+        let n = match input.read(&mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(n) => n,
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        output.write(&buf[..n]);
+    }
+}
+
+// Opens the fifo at `path` for writing, if it has a reader.
+fn open_writer(path: &Path) -> std::io::Result<Option<File>> {
+    // opening a fifo without a reader fails with ENXIO instead of blocking with O_NONBLOCK
+    let res = OpenOptions::new()
+        .write(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(path);
+    let file = match res {
+        Ok(file) => file,
+        Err(err) if err.raw_os_error() == Some(libc::ENXIO) => return Ok(None),
+        Err(err) => return Err(err),
+    };
+    // writes to the reader block as they would without the shim
+    fcntl(file.as_raw_fd(), FcntlArg::F_SETFL(OFlag::empty()))?;
+    Ok(Some(file))
+}
+
+// The fifo of containerd, with the output written while it has no reader.
+struct Output {
+    path: PathBuf,
+    stream: String,
+    writer: Option<File>,
+    backlog: VecDeque<u8>,
+}
+
+impl Output {
+    fn new(path: PathBuf, writer: File, stream: &str) -> Self {
+        Self {
+            path,
+            stream: stream.to_string(),
+            writer: Some(writer),
+            backlog: VecDeque::new(),
+        }
+    }
+
+    fn write(&mut self, chunk: &[u8]) {
+        if self.writer.is_none() {
+            self.attach();
+        }
+        let Some(writer) = &mut self.writer else {
+            self.keep(chunk);
+            return;
+        };
+        // the backlog is only written to a new reader
+        let (front, back) = self.backlog.as_slices();
+        let res = writer
+            .write_all(front)
+            .and_then(|_| writer.write_all(back))
+            .and_then(|_| writer.write_all(chunk));
+        match res {
+            Ok(()) => self.backlog.clear(),
+            Err(err) => {
+                log::info!("reader of {} detached: {err}", self.stream);
+                self.writer = None;
+                // part of it might have been written, but the next reader won't have seen it
+                self.keep(chunk);
+            }
+        }
+    }
+
+    fn attach(&mut self) {
+        match open_writer(&self.path) {
+            Ok(Some(writer)) => {
+                log::info!(
+                    "reader of {} attached, replaying {} bytes",
+                    self.stream,
+                    self.backlog.len()
+                );
+                self.writer = Some(writer);
+            }
+            Ok(None) => {}
+            Err(err) => log::warn!("failed to reopen {:?}: {err}", self.path),
+        }
+    }
+
+    fn keep(&mut self, chunk: &[u8]) {
+        let chunk = &chunk[chunk.len().saturating_sub(BACKLOG_BYTES)..];
+        let excess = (self.backlog.len() + chunk.len()).saturating_sub(BACKLOG_BYTES);
+        self.backlog.drain(..excess);
+        self.backlog.extend(chunk);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reattach() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let fifo = dir.path().join("stdout");
+        mkfifo(&fifo, Mode::S_IRUSR | Mode::S_IWUSR)?;
+        assert!(is_fifo(&fifo));
+
+        let reader = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(&fifo)?;
+        let writer = open_writer(&fifo)?.unwrap();
+        let mut output = Output::new(fifo.clone(), writer, "stdout");
+
+        // the reader goes away
+        drop(reader);
+        output.write(b"hello ");
+        assert!(output.writer.is_none());
+        output.write(b"world");
+        assert_eq!(output.backlog.len(), 11);
+
+        // a new reader gets the backlog with the next output
+        let mut reader = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(&fifo)?;
+        output.write(b"!");
+        let mut received = vec![0; 64];
+        let n = reader.read(&mut received)?;
+        assert_eq!(&received[..n], b"hello world!");
+        Ok(())
+    }
+
+    #[test]
+    fn test_backlog_is_bounded() {
+        let mut output = Output {
+            path: PathBuf::new(),
+            stream: "stdout".to_string(),
+            writer: None,
+            backlog: VecDeque::new(),
+        };
+        output.keep(&vec![1; BACKLOG_BYTES]);
+        output.keep(&[2; 10]);
+        assert_eq!(output.backlog.len(), BACKLOG_BYTES);
+        assert_eq!(output.backlog.back(), Some(&2));
+        assert_eq!(output.backlog.front(), Some(&1));
+    }
+}
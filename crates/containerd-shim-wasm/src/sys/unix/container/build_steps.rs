@@ -0,0 +1,96 @@
+//! Timing of the steps of the creation of a container.
+//!
+//! The steps run by the shim, like resolving the state directory and fetching the modules,
+//! are spans of their own within the `new` span of the instance.
+//!
+//! The container itself is built in the zygote, which is forked before the tracing of the
+//! shim is set up and can't export spans. The zygote times its steps instead, and returns
+//! them with the container. The shim logs them, and with the `opentelemetry` feature, adds
+//! them to the trace as children of the span of the build, with their actual start and end.
+
+use std::time::{Duration, SystemTime};
+
+use serde::{Deserialize, Serialize};
+
+/// Runs the step `name` of the creation of a container in the shim, in a span of its own.
+pub fn in_span<T>(name: &'static str, f: impl FnOnce() -> T) -> T {
+    #[cfg(feature = "tracing")]
+    let _span = tracing::info_span!("create_step", step = name).entered();
+    #[cfg(not(feature = "tracing"))]
+    let _ = name;
+    f()
+}
+
+/// A step of the build of a container, timed in the zygote.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BuildStep {
+    pub name: String,
+    pub start: SystemTime,
+    pub duration: Duration,
+}
+
+/// The steps of the build of a container, timed in the zygote.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct BuildSteps(Vec<BuildStep>);
+
+impl BuildSteps {
+    /// Runs the step `name`, and records how long it took.
+    pub fn time<T>(&mut self, name: &str, f: impl FnOnce() -> T) -> T {
+        let start = SystemTime::now();
+        let res = f();
+        let duration = start.elapsed().unwrap_or_default();
+        self.0.push(BuildStep {
+            name: name.to_string(),
+            start,
+            duration,
+        });
+        res
+    }
+
+    /// Reports the steps of the build of the container `id`, in the current span.
+    pub fn report(&self, id: &str) {
+        for step in &self.0 {
+            log::debug!(
+                "building container {id}: {} took {:?}",
+                step.name,
+                step.duration
+            );
+        }
+        #[cfg(feature = "opentelemetry")]
+        self.export();
+    }
+
+    #[cfg(feature = "opentelemetry")]
+    fn export(&self) {
+        use opentelemetry::trace::{Span as _, Tracer as _};
+        use tracing_opentelemetry::OpenTelemetrySpanExt as _;
+
+        let parent = tracing::Span::current().context();
+        let tracer = opentelemetry::global::tracer("containerd-shim-wasm");
+        for step in &self.0 {
+            let mut span = tracer
+                .span_builder(format!("zygote {}", step.name))
+                .with_start_time(step.start)
+                .start_with_context(&tracer, &parent);
+            span.end_with_timestamp(step.start + step.duration);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_time() {
+        let mut steps = BuildSteps::default();
+        let res = steps.time("sleep", || {
+            std::thread::sleep(Duration::from_millis(10));
+            42
+        });
+        assert_eq!(res, 42);
+        assert_eq!(steps.0.len(), 1);
+        assert_eq!(steps.0[0].name, "sleep");
+        assert!(steps.0[0].duration >= Duration::from_millis(10));
+    }
+}
@@ -0,0 +1,86 @@
+//! Cleanup of the artifacts a previous container left in its bundle.
+//!
+//! The shim creates fifos in the bundle of a container to copy its output, and removes them
+//! once the container closes them. A shim that is killed leaves them behind, and a container
+//! created again with the same bundle then fails to create them, or blocks opening a fifo
+//! without a reader. They are removed before the output of the container is opened.
+
+use std::io::ErrorKind;
+use std::os::unix::fs::FileTypeExt;
+use std::path::Path;
+
+/// The fifos the shim creates in the bundle of a container.
+const ARTIFACTS: &[&str] = &[
+    "log-stdout",
+    "log-stderr",
+    "journal-stdout",
+    "journal-stderr",
+    "attach-stdout",
+    "attach-stderr",
+    "cri-stdout",
+    "cri-stderr",
+    "tee-stdout",
+    "tee-stderr",
+    "pumped-stdout",
+    "pumped-stderr",
+    "combined-stdout",
+    "combined-stderr",
+    "startup-boost",
+];
+
+/// Removes the fifos and sockets a previous container of `id` left in `bundle`.
+/// Only the files the shim creates are removed, and only if they are fifos or sockets,
+/// so that files of the image or of the user are never removed.
+pub fn clean_stale_artifacts(id: &str, bundle: &Path) {
+    for name in ARTIFACTS {
+        let path = bundle.join(name);
+        // don't follow symlinks, the link itself isn't one of ours
+        let file_type = match std::fs::symlink_metadata(&path) {
+            Ok(metadata) => metadata.file_type(),
+            Err(err) if err.kind() == ErrorKind::NotFound => continue,
+            Err(err) => {
+                log::warn!("failed to inspect {path:?} in the bundle of container {id}: {err}");
+                continue;
+            }
+        };
+        if !file_type.is_fifo() && !file_type.is_socket() {
+            log::warn!("not removing {path:?} of container {id}, it is not a fifo or a socket");
+            continue;
+        }
+        match std::fs::remove_file(&path) {
+            Ok(()) => log::info!("removed stale {path:?} from the bundle of container {id}"),
+            Err(err) if err.kind() == ErrorKind::NotFound => {}
+            Err(err) => log::warn!("failed to remove stale {path:?} of container {id}: {err}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nix::sys::stat::Mode;
+    use nix::unistd::mkfifo;
+
+    use super::*;
+
+    #[test]
+    fn test_clean_stale_artifacts() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let fifo = dir.path().join("log-stdout");
+        mkfifo(&fifo, Mode::S_IRUSR | Mode::S_IWUSR)?;
+        let socket = dir.path().join("tee-stderr");
+        let _listener = std::os::unix::net::UnixListener::bind(&socket)?;
+        // a file of the same name that isn't a fifo is kept
+        let file = dir.path().join("cri-stdout");
+        std::fs::write(&file, "not ours")?;
+        let other = dir.path().join("app-fifo");
+        mkfifo(&other, Mode::S_IRUSR | Mode::S_IWUSR)?;
+
+        clean_stale_artifacts("test", dir.path());
+
+        assert!(!fifo.exists());
+        assert!(std::fs::symlink_metadata(&socket).is_err());
+        assert_eq!(std::fs::read_to_string(&file)?, "not ours");
+        assert!(other.exists());
+        Ok(())
+    }
+}
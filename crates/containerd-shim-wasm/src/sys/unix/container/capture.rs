@@ -0,0 +1,136 @@
+//! Capture of the output of containers to files, for audit and forensics.
+//!
+//! When `capture` is set in the runtime configuration, the stdout and stderr of the containers
+//! in its namespaces, and of the containers with the `runwasi.io/capture-output` annotation,
+//! are copied to `{stream}.log` files in a directory of their own, rotated as per its
+//! `rotation`. Unlike the output collected by containerd, which the logging of the node might
+//! drop, the captured files are written by the shim itself. They are kept once the container
+//! is deleted if they are captured to the `dir` of the configuration, and removed with the
+//! container if they are captured to its state directory, which is on tmpfs.
+//!
+//! A container whose output must be captured isn't created if its capture files can't be
+//! opened, or if it has a console, whose output isn't captured.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use oci_spec::runtime::Spec;
+
+use super::pump::tee::Sink;
+use super::rotate::{RotatingFile, Rotation};
+use crate::sandbox::config::CaptureConfig;
+
+/// Annotation asking for the output of the container to be captured.
+pub const CAPTURE_OUTPUT_ANNOTATION: &str = "runwasi.io/capture-output";
+
+/// Where the output of a container is captured.
+pub struct Capture {
+    dir: PathBuf,
+    rotation: Rotation,
+    // whether the files are kept once the container is deleted
+    keep: bool,
+}
+
+impl Capture {
+    /// Returns the capture of the output of the container, if it is captured.
+    /// `rootdir` is the state directory of the containers of the namespace.
+    pub fn for_container(
+        spec: &Spec,
+        id: &str,
+        namespace: &str,
+        rootdir: &Path,
+        cfg: Option<&CaptureConfig>,
+    ) -> Option<Self> {
+        let cfg = cfg?;
+        let requested = spec
+            .annotations()
+            .as_ref()
+            .and_then(|a| a.get(CAPTURE_OUTPUT_ANNOTATION))
+            .is_some_and(|v| v == "true");
+        if !requested && !cfg.applies_to(namespace) {
+            return None;
+        }
+
+        let dir = match &cfg.dir {
+            Some(dir) => dir.join(namespace).join(id),
+            // container ids can't start with a dot, so this is never the state of a container
+            None => rootdir.join(".capture").join(id),
+        };
+        Some(Self {
+            dir,
+            rotation: Rotation {
+                max_size: cfg.rotation.max_size_bytes,
+                max_files: cfg.rotation.max_files,
+            },
+            keep: cfg.dir.is_some(),
+        })
+    }
+
+    /// Opens the capture file of `stream`, as a sink of the output of the container.
+    pub fn sink(&self, stream: &str) -> Result<Sink> {
+        std::fs::create_dir_all(&self.dir)
+            .with_context(|| format!("failed to create capture directory {:?}", self.dir))?;
+        let path = self.dir.join(format!("{stream}.log"));
+        let file = RotatingFile::open(&path, self.rotation)
+            .with_context(|| format!("failed to open capture file {path:?}"))?;
+        Ok(Sink::new(format!("file://{}", path.display()), file))
+    }
+}
+
+// Removes the captured files once the container is deleted, or fails to be created, unless
+// they are kept.
+impl Drop for Capture {
+    fn drop(&mut self) {
+        if self.keep {
+            return;
+        }
+        match std::fs::remove_dir_all(&self.dir) {
+            Ok(()) => {}
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => log::warn!("failed to remove capture directory {:?}: {err}", self.dir),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::io::Write as _;
+
+    use oci_spec::runtime::SpecBuilder;
+
+    use super::*;
+
+    #[test]
+    fn test_capture() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let cfg = CaptureConfig {
+            namespaces: vec!["payments".to_string()],
+            ..Default::default()
+        };
+
+        let spec = SpecBuilder::default().build()?;
+        assert!(Capture::for_container(&spec, "app", "default", dir.path(), Some(&cfg)).is_none());
+        assert!(Capture::for_container(&spec, "app", "payments", dir.path(), None).is_none());
+
+        let spec = SpecBuilder::default()
+            .annotations(HashMap::from([(
+                CAPTURE_OUTPUT_ANNOTATION.to_string(),
+                "true".to_string(),
+            )]))
+            .build()?;
+        let capture =
+            Capture::for_container(&spec, "app", "default", dir.path(), Some(&cfg)).unwrap();
+        let mut sink = capture.sink("stdout")?;
+        sink.writer.write_all(b"hello\n")?;
+
+        let file = dir.path().join(".capture/app/stdout.log");
+        assert_eq!(std::fs::read(&file)?, b"hello\n");
+
+        // the files captured to the state directory are removed with the container
+        drop(sink);
+        drop(capture);
+        assert!(!file.exists());
+        Ok(())
+    }
+}
@@ -0,0 +1,132 @@
+use std::fs::File;
+use std::io::{IoSliceMut, Write as _};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::net::UnixListener;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use std::thread;
+
+use anyhow::{bail, Context, Result};
+use nix::errno::Errno;
+use nix::sys::socket::{recvmsg, ControlMessageOwned, MsgFlags};
+
+use crate::sys::stdio::open;
+
+// End-of-transmission, sent to the terminal when stdin is closed
+const EOT: u8 = 0x04;
+
+/// Console holds the master end of the pseudo terminal of a container
+/// created with `terminal: true`.
+///
+/// libcontainer creates the pseudo terminal inside the container and sends
+/// the master end over the console socket.
+pub struct Console {
+    socket: PathBuf,
+    listener: UnixListener,
+    master: OnceLock<File>,
+}
+
+impl Console {
+    pub fn new(socket: impl AsRef<Path>) -> Result<Arc<Self>> {
+        let socket = socket.as_ref().to_path_buf();
+        let _ = std::fs::remove_file(&socket);
+        let listener = UnixListener::bind(&socket)
+            .with_context(|| format!("failed to bind console socket {socket:?}"))?;
+        Ok(Arc::new(Self {
+            socket,
+            listener,
+            master: OnceLock::new(),
+        }))
+    }
+
+    pub fn socket(&self) -> &Path {
+        &self.socket
+    }
+
+    /// Waits in the background for the pseudo terminal master, and
+    /// then copies data between it and the stdin/stdout pipes.
+    pub fn start(
+        self: &Arc<Self>,
+        stdin: impl AsRef<Path>,
+        stdout: impl AsRef<Path>,
+    ) -> Result<()> {
+        let this = self.clone();
+        let stdin = stdin.as_ref().to_path_buf();
+        let stdout = stdout.as_ref().to_path_buf();
+        thread::Builder::new()
+            .name("console".to_string())
+            .spawn(move || {
+                if let Err(err) = this.accept_and_copy(&stdin, &stdout) {
+                    log::error!("console error: {err}");
+                }
+            })?;
+        Ok(())
+    }
+
+    fn accept_and_copy(&self, stdin: &Path, stdout: &Path) -> Result<()> {
+        let (stream, _) = self.listener.accept()?;
+        let master = File::from(recv_fd(stream.as_raw_fd())?);
+
+        if let Ok(mut stdin) = open(stdin) {
+            let mut master = master.try_clone()?;
+            thread::spawn(move || std::io::copy(&mut stdin, &mut master));
+        }
+        if let Ok(mut stdout) = open(stdout) {
+            let mut master = master.try_clone()?;
+            thread::spawn(move || std::io::copy(&mut master, &mut stdout));
+        }
+
+        let _ = self.master.set(master);
+        Ok(())
+    }
+
+    /// Sets the window size of the terminal.
+    pub fn resize(&self, width: u32, height: u32) -> Result<()> {
+        let Some(master) = self.master.get() else {
+            bail!("console is not ready");
+        };
+        let size = libc::winsize {
+            ws_row: height as u16,
+            ws_col: width as u16,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+        let res = unsafe { libc::ioctl(master.as_raw_fd(), libc::TIOCSWINSZ, &size) };
+        Errno::result(res).context("failed to resize terminal")?;
+        Ok(())
+    }
+
+    /// Signals the end of input to the terminal.
+    pub fn close_stdin(&self) -> Result<()> {
+        if let Some(mut master) = self.master.get() {
+            master.write_all(&[EOT])?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Console {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.socket);
+    }
+}
+
+fn recv_fd(socket: i32) -> Result<OwnedFd> {
+    let mut buf = [0u8; 4096];
+    let mut iov = [IoSliceMut::new(&mut buf)];
+    let mut cmsg = nix::cmsg_space!([i32; 1]);
+    let msg = recvmsg::<()>(
+        socket,
+        &mut iov,
+        Some(&mut cmsg),
+        MsgFlags::MSG_CMSG_CLOEXEC,
+    )?;
+    for cmsg in msg.cmsgs()? {
+        if let ControlMessageOwned::ScmRights(fds) = cmsg {
+            if let Some(fd) = fds.first() {
+                return Ok(unsafe { OwnedFd::from_raw_fd(*fd) });
+            }
+        }
+    }
+    bail!("no file descriptor received on console socket")
+}
@@ -9,6 +9,8 @@ use serde::de::DeserializeOwned;
 use serde::Serialize;
 use zygote::{WireError, Zygote};
 
+use super::ZygotePool;
+
 thread_local! {
     // The youki's Container will live in a static inside the zygote process.
     // Reserve some space for it here.
@@ -20,15 +22,20 @@ pub struct Container(Zygote);
 
 // Constructor methods
 impl Container {
-    pub fn build<Arg: Serialize + DeserializeOwned + 'static>(
-        f: fn(Arg) -> anyhow::Result<YoukiContainer>,
+    /// Builds the container with `f` in a new zygote, from the [`ZygotePool`].
+    /// `f` returns the container, and a value to return to the caller along with it.
+    pub fn build<
+        Arg: Serialize + DeserializeOwned + 'static,
+        T: Serialize + DeserializeOwned + 'static,
+    >(
+        f: fn(Arg) -> anyhow::Result<(YoukiContainer, T)>,
         arg: Arg,
-    ) -> anyhow::Result<Self> {
-        let zygote = Zygote::global().spawn();
+    ) -> anyhow::Result<(Self, T)> {
+        let zygote = ZygotePool::global().take();
         let container = Container(zygote);
-        container.run_init(f, arg)?;
+        let res = container.run_init(f, arg)?;
 
-        Ok(container)
+        Ok((container, res))
     }
 }
 
@@ -79,16 +86,20 @@ impl Container {
             .map_err(|e| anyhow!(e))
     }
 
-    fn run_init<Arg: Serialize + DeserializeOwned + 'static>(
+    fn run_init<
+        Arg: Serialize + DeserializeOwned + 'static,
+        T: Serialize + DeserializeOwned + 'static,
+    >(
         &self,
-        f: fn(Arg) -> anyhow::Result<YoukiContainer>,
+        f: fn(Arg) -> anyhow::Result<(YoukiContainer, T)>,
         arg: Arg,
-    ) -> anyhow::Result<()> {
+    ) -> anyhow::Result<T> {
         self.run_impl(
-            |c: &mut Option<YoukiContainer>, (f, arg): (usize, Arg)| -> anyhow::Result<()> {
-                let f: fn(Arg) -> anyhow::Result<YoukiContainer> = unsafe { transmute(f) };
-                *c = Some(f(arg)?);
-                Ok(())
+            |c: &mut Option<YoukiContainer>, (f, arg): (usize, Arg)| -> anyhow::Result<T> {
+                let f: fn(Arg) -> anyhow::Result<(YoukiContainer, T)> = unsafe { transmute(f) };
+                let (container, res) = f(arg)?;
+                *c = Some(container);
+                Ok(res)
             },
             (f as usize, arg),
         )
@@ -0,0 +1,343 @@
+//! Startup CPU boost of a container.
+//!
+//! Compiling and instantiating a module is CPU intensive, and slow in pods with a low CPU
+//! limit. When `cpu_boost` is set in the runtime configuration, containers with the
+//! `runwasi.io/startup-cpu-boost: "true"` annotation have their CPU quota raised to
+//! `cpu_boost.cpus` from their creation until their guest starts running, and restored to
+//! the limit of the runtime spec afterwards, so that steady state quotas are never exceeded.
+//!
+//! The quota is raised on the cgroup of the container and on its parent (the pod), as the
+//! quota of a cgroup is bounded by the quota of its parent. The containers of a pod can start
+//! at the same time, in different shims, so the boosts of each pod are counted in a file of
+//! `cpu_boost.dir`, locked while it's updated: the first boost raises the quota of the pod,
+//! and the last one to end restores it.
+//! Engines tell the shim the guest is about to run with
+//! [`RuntimeContext::startup_complete`](crate::container::RuntimeContext::startup_complete),
+//! through a fifo in the bundle. The boost also ends when the container process exits or
+//! execs, or after `cpu_boost.max_duration_secs`.
+
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, Write};
+use std::os::fd::AsFd;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use nix::fcntl::{Flock, FlockArg};
+use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
+use nix::sys::stat::Mode;
+use nix::unistd::mkfifo;
+use oci_spec::runtime::Spec;
+
+use crate::sandbox::config::CpuBoostConfig;
+use crate::sys::cgroup::{cgroup_dir, CgroupDir};
+
+/// Annotation to boost the CPU quota of the container while it starts.
+pub const CPU_BOOST_ANNOTATION: &str = "runwasi.io/startup-cpu-boost";
+
+const DEFAULT_PERIOD_US: u64 = 100_000;
+
+/// Returns true if the container asks for a startup CPU boost.
+pub fn is_requested(spec: &Spec) -> bool {
+    spec.annotations()
+        .as_ref()
+        .and_then(|a| a.get(CPU_BOOST_ANNOTATION))
+        .is_some_and(|v| v == "true")
+}
+
+/// A startup CPU boost, between the creation of the container and the start of its guest.
+pub struct CpuBoost {
+    fifo: PathBuf,
+    // keeps the fifo open for reading, so that the container can open it without blocking
+    reader: File,
+}
+
+impl CpuBoost {
+    /// Creates the fifo the container signals the end of its startup on, in `dir`.
+    pub fn new(dir: &Path) -> Result<Self> {
+        let fifo = dir.join("startup-boost");
+        let _ = std::fs::remove_file(&fifo);
+        mkfifo(&fifo, Mode::S_IRUSR | Mode::S_IWUSR)
+            .with_context(|| format!("failed to create fifo {fifo:?}"))?;
+        let reader = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(&fifo)?;
+        Ok(Self { fifo, reader })
+    }
+
+    /// The path of the fifo, opened for writing by the container process.
+    pub fn fifo(&self) -> &Path {
+        &self.fifo
+    }
+
+    /// Raises the CPU quota of the cgroup of `pid`, until the container signals the end of
+    /// its startup.
+    pub fn start(self, id: &str, pid: u32, cfg: &CpuBoostConfig) -> Result<()> {
+        let cgroup = cgroup_dir(pid, "cpu")?;
+        let parent = cgroup.path().parent().map(|dir| match cgroup {
+            CgroupDir::V1(_) => CgroupDir::V1(dir.to_path_buf()),
+            CgroupDir::V2(_) => CgroupDir::V2(dir.to_path_buf()),
+        });
+        let container = match boost(&cgroup, cfg.cpus) {
+            Ok(original) => original.map(|original| (cgroup, original)),
+            Err(err) => {
+                log::warn!("failed to boost {:?}: {err:#}", cgroup.path());
+                None
+            }
+        };
+        let pod = parent.and_then(|parent| {
+            let path = parent.path().clone();
+            PodBoost::start(&cfg.dir, parent, cfg.cpus)
+                .inspect_err(|err| log::warn!("failed to boost {path:?}: {err:#}"))
+                .ok()
+                .flatten()
+        });
+        let boosted = container.is_some() || pod.is_some();
+        if boosted {
+            log::info!(
+                "boosted the CPU quota of container {id} to {} CPUs",
+                cfg.cpus
+            );
+        } else {
+            log::debug!("no startup CPU boost for container {id}, its quota is large enough");
+        }
+
+        // keep reading the fifo even without a boost, so that the container can signal it
+        let max_duration = Duration::from_secs(cfg.max_duration_secs);
+        let id = id.to_string();
+        thread::Builder::new()
+            .name("cpu-boost".to_string())
+            .spawn(move || {
+                let start = Instant::now();
+                self.wait(max_duration);
+                if !boosted {
+                    return;
+                }
+                if let Some((cgroup, original)) = container {
+                    if let Err(err) = restore(&cgroup, &original) {
+                        log::error!(
+                            "failed to restore the CPU quota of {:?}: {err:#}",
+                            cgroup.path()
+                        );
+                    }
+                }
+                if let Some(pod) = pod {
+                    let path = pod.cgroup.path().clone();
+                    if let Err(err) = pod.end() {
+                        log::error!("failed to restore the CPU quota of {path:?}: {err:#}");
+                    }
+                }
+                log::info!(
+                    "restored the CPU quota of container {id} after {:?}",
+                    start.elapsed()
+                );
+            })?;
+        Ok(())
+    }
+
+    // Waits for the container to write to the fifo or close it, at most `max_duration`.
+    fn wait(&self, max_duration: Duration) {
+        let timeout = PollTimeout::try_from(max_duration).unwrap_or(PollTimeout::MAX);
+        let mut fds = [PollFd::new(self.reader.as_fd(), PollFlags::POLLIN)];
+        loop {
+            match poll(&mut fds, timeout) {
+                Err(nix::errno::Errno::EINTR) => continue,
+                Ok(0) => log::warn!("startup CPU boost ended after {max_duration:?}"),
+                Ok(_) => {}
+                Err(err) => log::warn!("failed to wait for the end of the startup: {err}"),
+            }
+            break;
+        }
+        let _ = std::fs::remove_file(&self.fifo);
+    }
+}
+
+// Raises the quota of `cgroup` to `cpus`, if it is lower.
+// Returns the original quota, or None if it's unchanged.
+fn boost(cgroup: &CgroupDir, cpus: f64) -> Result<Option<String>> {
+    let (file, original) = read_quota(cgroup)?;
+    let (quota, period) = match cgroup {
+        CgroupDir::V2(_) => {
+            let mut parts = original.split_whitespace();
+            let quota = parts.next().unwrap_or("max");
+            let period = parts.next().and_then(|p| p.parse().ok());
+            (
+                quota.parse::<u64>().ok(),
+                period.unwrap_or(DEFAULT_PERIOD_US),
+            )
+        }
+        CgroupDir::V1(dir) => {
+            let period = std::fs::read_to_string(dir.join("cpu.cfs_period_us"))?;
+            let period = period.trim().parse().unwrap_or(DEFAULT_PERIOD_US);
+            (original.parse::<u64>().ok(), period)
+        }
+    };
+    // unlimited
+    let Some(quota) = quota else {
+        return Ok(None);
+    };
+    let boosted = (cpus * period as f64) as u64;
+    if boosted <= quota {
+        return Ok(None);
+    }
+    let value = match cgroup {
+        CgroupDir::V2(_) => format!("{boosted} {period}"),
+        CgroupDir::V1(_) => boosted.to_string(),
+    };
+    std::fs::write(&file, value).with_context(|| format!("failed to write {file:?}"))?;
+    Ok(Some(original))
+}
+
+// A boost of the quota of a pod, counted with the other boosts of the pod in flight.
+struct PodBoost {
+    cgroup: CgroupDir,
+    // the count of the boosts of the pod and its original quota, e.g. `2 50000 100000`
+    state: PathBuf,
+}
+
+impl PodBoost {
+    // Raises the quota of the pod `cgroup` to `cpus` if it's the first boost of the pod,
+    // counting the boosts of the pods in `dir`. Returns None if the quota is large enough.
+    fn start(dir: &Path, cgroup: CgroupDir, cpus: f64) -> Result<Option<Self>> {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("failed to create directory {dir:?}"))?;
+        let state = dir.join(sha256::digest(cgroup.path().as_os_str().as_bytes()));
+        let mut lock = lock_state(&state)?;
+        let (count, original) = match read_state(&mut lock)? {
+            (0, _) => match boost(&cgroup, cpus)? {
+                Some(original) => (0, original),
+                None => return Ok(None),
+            },
+            state => state,
+        };
+        write_state(&mut lock, count + 1, &original)?;
+        Ok(Some(Self { cgroup, state }))
+    }
+
+    // Ends the boost, restoring the original quota of the pod if it's the last one.
+    fn end(self) -> Result<()> {
+        let mut lock = lock_state(&self.state)?;
+        let (count, original) = read_state(&mut lock)?;
+        if count > 1 {
+            return write_state(&mut lock, count - 1, &original);
+        }
+        restore(&self.cgroup, &original)?;
+        write_state(&mut lock, 0, "")
+    }
+}
+
+fn lock_state(path: &Path) -> Result<Flock<File>> {
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)
+        .with_context(|| format!("failed to open {path:?}"))?;
+    Flock::lock(file, FlockArg::LockExclusive)
+        .map_err(|(_, err)| err)
+        .with_context(|| format!("failed to lock {path:?}"))
+}
+
+fn read_state(file: &mut File) -> Result<(u64, String)> {
+    let mut state = String::new();
+    file.rewind()?;
+    file.read_to_string(&mut state)?;
+    let Some((count, original)) = state.trim().split_once(' ') else {
+        return Ok((0, String::new()));
+    };
+    Ok((count.parse().unwrap_or(0), original.to_string()))
+}
+
+fn write_state(file: &mut File, count: u64, original: &str) -> Result<()> {
+    file.set_len(0)?;
+    file.rewind()?;
+    file.write_all(format!("{count} {original}").as_bytes())?;
+    Ok(())
+}
+
+fn restore(cgroup: &CgroupDir, original: &str) -> Result<()> {
+    let (file, _) = read_quota(cgroup)?;
+    std::fs::write(&file, original).with_context(|| format!("failed to write {file:?}"))?;
+    Ok(())
+}
+
+fn read_quota(cgroup: &CgroupDir) -> Result<(PathBuf, String)> {
+    let file = match cgroup {
+        CgroupDir::V2(dir) => dir.join("cpu.max"),
+        CgroupDir::V1(dir) => dir.join("cpu.cfs_quota_us"),
+    };
+    let quota =
+        std::fs::read_to_string(&file).with_context(|| format!("failed to read {file:?}"))?;
+    Ok((file, quota.trim().to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_boost_v2() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let cgroup = CgroupDir::V2(dir.path().to_path_buf());
+        let cpu_max = dir.path().join("cpu.max");
+
+        std::fs::write(&cpu_max, "50000 100000\n")?;
+        let original = boost(&cgroup, 2.0)?;
+        assert_eq!(original.as_deref(), Some("50000 100000"));
+        assert_eq!(std::fs::read_to_string(&cpu_max)?, "200000 100000");
+        restore(&cgroup, &original.unwrap())?;
+        assert_eq!(std::fs::read_to_string(&cpu_max)?, "50000 100000");
+
+        // the quota is already larger than the boost
+        std::fs::write(&cpu_max, "400000 100000\n")?;
+        assert_eq!(boost(&cgroup, 2.0)?, None);
+
+        std::fs::write(&cpu_max, "max 100000\n")?;
+        assert_eq!(boost(&cgroup, 2.0)?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_overlapping_pod_boosts() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let pod = dir.path().join("pod");
+        std::fs::create_dir(&pod)?;
+        let cpu_max = pod.join("cpu.max");
+        let state = dir.path().join("state");
+        std::fs::write(&cpu_max, "50000 100000\n")?;
+
+        let first = PodBoost::start(&state, CgroupDir::V2(pod.clone()), 2.0)?.unwrap();
+        let second = PodBoost::start(&state, CgroupDir::V2(pod.clone()), 2.0)?.unwrap();
+        assert_eq!(std::fs::read_to_string(&cpu_max)?, "200000 100000");
+
+        // the second boost still runs
+        first.end()?;
+        assert_eq!(std::fs::read_to_string(&cpu_max)?, "200000 100000");
+
+        second.end()?;
+        assert_eq!(std::fs::read_to_string(&cpu_max)?, "50000 100000");
+
+        // the quota is already larger than the boost
+        std::fs::write(&cpu_max, "400000 100000\n")?;
+        assert!(PodBoost::start(&state, CgroupDir::V2(pod), 2.0)?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_wait_for_startup() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let boost = CpuBoost::new(dir.path())?;
+        let mut writer = OpenOptions::new().write(true).open(boost.fifo())?;
+
+        let start = Instant::now();
+        writer.write_all(&[1])?;
+        boost.wait(Duration::from_secs(10));
+        assert!(start.elapsed() < Duration::from_secs(10));
+        Ok(())
+    }
+}
@@ -0,0 +1,87 @@
+//! The request the shim sends to the zygote to build a container.
+//!
+//! The zygote is forked from the shim, but a shim upgraded on disk still sends its requests to
+//! zygotes forked from the previous binary until they are recycled. The request is versioned,
+//! and the zygote refuses a request of another version with an error naming both versions,
+//! instead of misreading its fields.
+//!
+//! [`CREATE_REQUEST_VERSION`] must be bumped whenever a field is added, removed or changes type.
+
+use std::path::PathBuf;
+
+use oci_spec::image::Platform;
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::container::{Capabilities, ComponentLimits};
+use crate::sandbox::config::RedactionConfig;
+use crate::sandbox::oci::WasmLayer;
+use crate::sandbox::InstanceConfig;
+use crate::sys::stdio::StdioOpen;
+
+/// Version of the format of [`CreateRequest`].
+pub(crate) const CREATE_REQUEST_VERSION: u32 = 1;
+
+/// The version of a [`CreateRequest`].
+///
+/// It fails to deserialize unless it's [`CREATE_REQUEST_VERSION`], and it's the first field
+/// of the request, so the rest of a request of another version is never read.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct Version(u32);
+
+impl Version {
+    pub const CURRENT: Self = Self(CREATE_REQUEST_VERSION);
+}
+
+impl<'de> Deserialize<'de> for Version {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let version = u32::deserialize(deserializer)?;
+        if version != CREATE_REQUEST_VERSION {
+            return Err(D::Error::custom(format!(
+                "create request version {version} is not supported by this zygote, which expects version {CREATE_REQUEST_VERSION}: the shim was likely upgraded, recycle its zygotes"
+            )));
+        }
+        Ok(Self(version))
+    }
+}
+
+/// What the zygote needs to build a container.
+///
+/// The container process can't read the runtime config, so everything derived from it is
+/// resolved by the shim and sent here.
+#[derive(Serialize, Deserialize)]
+pub struct CreateRequest {
+    /// Always [`Version::CURRENT`] when sent.
+    pub version: Version,
+    pub id: String,
+    pub cfg: InstanceConfig,
+    pub modules: Vec<WasmLayer>,
+    pub platform: Platform,
+    pub console_socket: Option<PathBuf>,
+    pub capabilities: Capabilities,
+    pub component_limits: ComponentLimits,
+    /// The fifo the container signals the end of its startup on, with a CPU boost.
+    pub boost_fifo: Option<PathBuf>,
+    /// The socket the container receives its inherited file descriptors from.
+    pub inherit_fds_socket: Option<PathBuf>,
+    pub rootdir: PathBuf,
+    pub stdio_open: StdioOpen,
+    pub redaction: RedactionConfig,
+    /// Whether the container may run the module embedded in the shim.
+    pub embedded_module: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_version() {
+        let version: Version = serde_json::from_str(&CREATE_REQUEST_VERSION.to_string()).unwrap();
+        assert_eq!(version, Version::CURRENT);
+
+        let next = (CREATE_REQUEST_VERSION + 1).to_string();
+        let err = serde_json::from_str::<Version>(&next).unwrap_err();
+        assert!(err.to_string().contains("not supported by this zygote"));
+    }
+}
@@ -0,0 +1,170 @@
+//! CRI log format for the stdout and stderr of a container.
+//!
+//! The kubelet reads the logs of containers from files where each line is prefixed with its
+//! timestamp, its stream, and whether it is a full or a partial line, e.g.:
+//!
+//! ```text
+//! 2024-01-01T00:00:00.000000000Z stdout F hello world
+//! ```
+//!
+//! When the `runwasi.io/log-line-format` annotation is `cri`, the output the container writes
+//! to `file://` log URIs is written in this format, so that the shim can write logs the kubelet
+//! reads directly. The other streams are left as is, e.g., the fifos of containerd, whose CRI
+//! logger adds the prefix itself. Lines longer than 16KiB are split into partial lines (`P`),
+//! as the container runtimes of the kubelet do.
+
+use std::io::{Result as IoResult, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use chrono::{SecondsFormat, Utc};
+use oci_spec::runtime::Spec;
+
+use super::log_uri::copy_from_fifo;
+use crate::sys::stdio::open;
+
+/// Annotation with the format of the lines of the output of the container, `raw` or `cri`.
+pub const LOG_LINE_FORMAT_ANNOTATION: &str = "runwasi.io/log-line-format";
+
+/// Lines longer than this are split in several partial lines.
+const MAX_LINE_BYTES: usize = 16 * 1024;
+
+/// Returns true if the output of the container is written in the CRI log format.
+pub fn is_enabled(spec: &Spec) -> Result<bool> {
+    let format = spec
+        .annotations()
+        .as_ref()
+        .and_then(|a| a.get(LOG_LINE_FORMAT_ANNOTATION));
+    match format.map(String::as_str) {
+        None | Some("raw") => Ok(false),
+        Some("cri") => Ok(true),
+        Some(format) => bail!("invalid {LOG_LINE_FORMAT_ANNOTATION} {format:?}"),
+    }
+}
+
+/// Creates the fifo `cri-{stream}` in `dir`, and starts copying what is written to it into
+/// `output` in the CRI log format.
+/// Returns the path of the fifo.
+pub fn start(stream: &str, output: impl AsRef<Path>, dir: impl AsRef<Path>) -> Result<PathBuf> {
+    let output = open(output.as_ref())
+        .with_context(|| format!("failed to open output {:?}", output.as_ref()))?;
+    let path = dir.as_ref().join(format!("cri-{stream}"));
+    copy_from_fifo(&path, CriWriter::new(output, stream))?;
+    Ok(path)
+}
+
+/// Writes each line written to it with the CRI log prefix.
+struct CriWriter<W: Write> {
+    output: W,
+    stream: String,
+    line: Vec<u8>,
+}
+
+impl<W: Write> CriWriter<W> {
+    fn new(output: W, stream: &str) -> Self {
+        Self {
+            output,
+            stream: stream.to_string(),
+            line: vec![],
+        }
+    }
+
+    fn write_line(&mut self, tag: &str) -> IoResult<()> {
+        let timestamp = Utc::now().to_rfc3339_opts(SecondsFormat::Nanos, true);
+        let mut entry = format!("{timestamp} {} {tag} ", self.stream).into_bytes();
+        entry.append(&mut self.line);
+        entry.push(b'\n');
+        self.output.write_all(&entry)
+    }
+}
+
+impl<W: Write> Write for CriWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        for chunk in buf.split_inclusive(|b| *b == b'\n') {
+            let (line, complete) = match chunk.strip_suffix(b"\n") {
+                Some(line) => (line, true),
+                None => (chunk, false),
+            };
+            for part in line.chunks(MAX_LINE_BYTES) {
+                if self.line.len() + part.len() > MAX_LINE_BYTES {
+                    self.write_line("P")?;
+                }
+                self.line.extend_from_slice(part);
+            }
+            if complete {
+                self.write_line("F")?;
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> IoResult<()> {
+        self.output.flush()
+    }
+}
+
+impl<W: Write> Drop for CriWriter<W> {
+    fn drop(&mut self) {
+        // the last line of the output might not end with a new line
+        if !self.line.is_empty() {
+            let _ = self.write_line("F");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(output: &[u8]) -> Vec<(String, String, String)> {
+        String::from_utf8_lossy(output)
+            .lines()
+            .map(|line| {
+                let mut parts = line.splitn(4, ' ');
+                let timestamp = parts.next().unwrap();
+                chrono::DateTime::parse_from_rfc3339(timestamp).unwrap();
+                let stream = parts.next().unwrap().to_string();
+                let tag = parts.next().unwrap().to_string();
+                (stream, tag, parts.next().unwrap_or_default().to_string())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_cri_writer() -> IoResult<()> {
+        let mut output = vec![];
+        let mut writer = CriWriter::new(&mut output, "stderr");
+        writer.write_all(b"hello\nwor")?;
+        writer.write_all(b"ld\n\nbye")?;
+        drop(writer);
+
+        let entry = |tag: &str, msg: &str| ("stderr".to_string(), tag.to_string(), msg.to_string());
+        assert_eq!(
+            lines(&output),
+            [
+                entry("F", "hello"),
+                entry("F", "world"),
+                entry("F", ""),
+                entry("F", "bye"),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_cri_writer_long_line() -> IoResult<()> {
+        let mut output = vec![];
+        let mut writer = CriWriter::new(&mut output, "stdout");
+        writer.write_all(&[b'a'; MAX_LINE_BYTES + 1])?;
+        writer.write_all(b"\n")?;
+        drop(writer);
+
+        let lines = lines(&output);
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].1, "P");
+        assert_eq!(lines[0].2.len(), MAX_LINE_BYTES);
+        assert_eq!(lines[1].1, "F");
+        assert_eq!(lines[1].2, "a");
+        Ok(())
+    }
+}
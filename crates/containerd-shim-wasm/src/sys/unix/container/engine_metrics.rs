@@ -0,0 +1,70 @@
+//! Reporting of the counters of the engine from the container process to the shim.
+
+use std::fs::{File, OpenOptions};
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::container::{drain_stats, Engine, EngineMetrics, ENGINE_METRICS_FILE};
+
+/// How often the counters of the engine are written.
+const REPORT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Opens the file in `bundle` the counters of the engine of a container are written to.
+/// It is opened by the shim, as the bundle isn't visible from the container.
+pub fn open(bundle: &Path) -> std::io::Result<File> {
+    OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(bundle.join(ENGINE_METRICS_FILE))
+}
+
+/// Writes the counters of `engine` to `file` periodically, until the container exits.
+pub fn report(engine: impl Engine, file: Arc<File>) {
+    let res = thread::Builder::new()
+        .name("engine-metrics".to_string())
+        .spawn(move || loop {
+            if let Some(metrics) = collect(&engine) {
+                if let Err(err) = write(&file, &metrics) {
+                    log::warn!("failed to write the engine metrics, not reporting them: {err}");
+                    return;
+                }
+            }
+            thread::sleep(REPORT_INTERVAL);
+        });
+    if let Err(err) = res {
+        log::warn!("failed to start reporting the engine metrics: {err}");
+    }
+}
+
+/// Writes the last counters of `engine` to `file`, before the container exits.
+pub fn flush(engine: &impl Engine, file: &File) {
+    if let Some(metrics) = collect(engine) {
+        if let Err(err) = write(file, &metrics) {
+            log::warn!("failed to write the last engine metrics: {err}");
+        }
+    }
+}
+
+// The counters of the engine, with the background tasks of the guest the process drained.
+fn collect(engine: &impl Engine) -> Option<EngineMetrics> {
+    let metrics = engine.metrics();
+    let Some((drained, dropped)) = drain_stats() else {
+        return metrics;
+    };
+    Some(EngineMetrics {
+        drained_tasks: Some(drained),
+        dropped_tasks: Some(dropped),
+        ..metrics.unwrap_or_default()
+    })
+}
+
+// The file is overwritten in place, a reader that sees a partial write ignores it.
+fn write(file: &File, metrics: &EngineMetrics) -> std::io::Result<()> {
+    let data = serde_json::to_vec(metrics)?;
+    file.write_all_at(&data, 0)?;
+    file.set_len(data.len() as u64)
+}
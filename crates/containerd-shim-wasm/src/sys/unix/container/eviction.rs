@@ -0,0 +1,187 @@
+//! Eviction of the state kept for the images deleted from containerd.
+//!
+//! The shim keeps state derived from the images of its containers outside of containerd: the
+//! markers of the images without wasm layers, and the precompiled layers and wasm modules of
+//! the artifact cache of the root directory. It outlives the images it's derived from.
+//!
+//! When `image_eviction` is set in the runtime configuration, the shim records the digests of
+//! the wasm layers of the image of each container as it loads them, by the name CRI annotates
+//! the container with. It subscribes to the deletions of images and content in the namespace
+//! of its first container, and drops:
+//! * the image marker of a deleted image, so an image pulled again under its name is looked up,
+//! * the cached layers recorded for a deleted image. containerd only publishes the deletions of
+//!   content deleted through its content service, not of the content garbage collected once
+//!   the images referencing it are deleted,
+//! * the cached layers of deleted content.
+//!
+//! The artifact cache is keyed by the digests of the layers, so evicting the layers of an image
+//! that other images share only makes their next container cache them again. The modules
+//! pulled with `runwasi.io/pull-modules` aren't recorded, and are only evicted with their
+//! content.
+//!
+//! Nothing else needs to be invalidated:
+//! * the zygotes of the zygote pool are forked before any image is loaded, and hold no layers,
+//! * the engines shared by the containers of a shim hold no modules,
+//! * the containers already running keep the layers they mapped from the artifact cache, whose
+//!   pages are freed once the last of them exits, as the files are removed.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{LazyLock, Mutex};
+
+use oci_spec::runtime::Spec;
+
+use super::artifact_cache;
+use super::image_marker::{self, ImageMarker};
+use crate::sandbox::config::RuntimeConfig;
+use crate::sandbox::containerd::{Client, Deleted};
+
+// the root directories of the containers of each watched namespace
+static WATCHED: LazyLock<Mutex<HashMap<String, HashSet<PathBuf>>>> =
+    LazyLock::new(Default::default);
+
+/// Evicts the state kept in `rootdir` for the images deleted from the namespace of `client`,
+/// subscribing to the deletions of the namespace on first use.
+pub fn watch(client: &Client, namespace: &str, rootdir: &Path) {
+    let mut watched = WATCHED.lock().unwrap();
+    if let Some(rootdirs) = watched.get_mut(namespace) {
+        rootdirs.insert(rootdir.to_path_buf());
+        return;
+    }
+    watched.insert(
+        namespace.to_string(),
+        HashSet::from([rootdir.to_path_buf()]),
+    );
+
+    log::info!("watching the images deleted from namespace {namespace}");
+    let namespace = namespace.to_string();
+    client.watch_deletions(move |deleted| {
+        if !RuntimeConfig::current().image_eviction {
+            return;
+        }
+        let rootdirs = WATCHED
+            .lock()
+            .unwrap()
+            .get(&namespace)
+            .cloned()
+            .unwrap_or_default();
+        evict(&rootdirs, &deleted);
+    });
+}
+
+/// Records the `digests` of the wasm layers of the image of the container of `spec` in
+/// `rootdir`, so that they are evicted from the artifact cache once the image is deleted.
+pub fn record_layers(spec: &Spec, rootdir: &Path, digests: impl IntoIterator<Item = String>) {
+    let Some(image) = image_marker::image_name(spec) else {
+        return;
+    };
+    let path = layers_path(rootdir, image);
+    let mut recorded = read_layers(&path);
+    let len = recorded.len();
+    recorded.extend(digests);
+    if recorded.len() == len {
+        return;
+    }
+
+    let content: String = recorded
+        .iter()
+        .map(|digest| format!("{digest}\n"))
+        .collect();
+    // other containers of the image might be recording it concurrently
+    let tmp = path.with_extension(format!("{}.tmp", std::process::id()));
+    let res = path
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|()| fs::write(&tmp, content))
+        .and_then(|()| fs::rename(&tmp, &path));
+    if let Err(err) = res {
+        log::debug!("failed to record the layers of image {image} in {path:?}: {err}");
+    }
+}
+
+fn layers_path(rootdir: &Path, image: &str) -> PathBuf {
+    // container ids can't start with a dot, so this is never the state of a container
+    rootdir.join(".image-layers").join(sha256::digest(image))
+}
+
+fn read_layers(path: &Path) -> BTreeSet<String> {
+    fs::read_to_string(path)
+        .map(|content| content.lines().map(str::to_string).collect())
+        .unwrap_or_default()
+}
+
+fn evict(rootdirs: &HashSet<PathBuf>, deleted: &Deleted) {
+    match deleted {
+        Deleted::Image(name) => {
+            for rootdir in rootdirs {
+                ImageMarker::forget(rootdir, name);
+                let path = layers_path(rootdir, name);
+                for digest in read_layers(&path) {
+                    artifact_cache::evict(rootdir, &digest);
+                }
+                let _ = fs::remove_file(&path);
+            }
+        }
+        Deleted::Content(digest) => {
+            for rootdir in rootdirs {
+                artifact_cache::evict(rootdir, digest);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use oci_spec::runtime::SpecBuilder;
+
+    use super::*;
+
+    #[test]
+    fn test_evict() -> anyhow::Result<()> {
+        let rootdir = tempfile::tempdir()?;
+        let rootdirs = HashSet::from([rootdir.path().to_path_buf()]);
+
+        let spec = SpecBuilder::default()
+            .annotations(HashMap::from([(
+                "io.kubernetes.cri.image-name".to_string(),
+                "docker.io/library/app:latest".to_string(),
+            )]))
+            .build()?;
+        let marker =
+            ImageMarker::for_container(&spec, rootdir.path(), Duration::from_secs(60)).unwrap();
+        marker.record(false);
+
+        let artifact = rootdir.path().join(".artifacts/wasmtime-1/sha256_abc");
+        std::fs::create_dir_all(artifact.parent().unwrap())?;
+        std::fs::write(&artifact, b"precompiled")?;
+
+        evict(&rootdirs, &Deleted::Content("sha256:abc".to_string()));
+        assert!(!artifact.exists());
+        assert!(marker.has_no_wasm_layers());
+
+        let artifacts = ["sha256_def", "sha256_ghi"].map(|name| {
+            let artifact = rootdir.path().join(".artifacts/wasm").join(name);
+            std::fs::create_dir_all(artifact.parent().unwrap()).unwrap();
+            std::fs::write(&artifact, b"\0asm\x01\0\0\0").unwrap();
+            artifact
+        });
+        record_layers(&spec, rootdir.path(), ["sha256:def".to_string()]);
+        record_layers(&spec, rootdir.path(), ["sha256:def".to_string()]);
+
+        evict(
+            &rootdirs,
+            &Deleted::Image("docker.io/library/app:latest".to_string()),
+        );
+        assert!(!marker.has_no_wasm_layers());
+        // only the layers of the deleted image are evicted
+        assert!(!artifacts[0].exists());
+        assert!(artifacts[1].exists());
+        assert!(
+            read_layers(&layers_path(rootdir.path(), "docker.io/library/app:latest")).is_empty()
+        );
+        Ok(())
+    }
+}
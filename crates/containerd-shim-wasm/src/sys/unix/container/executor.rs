@@ -1,11 +1,14 @@
 use std::cell::OnceCell;
 use std::collections::HashMap;
 use std::fs::File;
-use std::io::Read;
+use std::io::{Read, Seek, Write};
+use std::os::fd::{AsRawFd, FromRawFd};
 use std::os::unix::prelude::PermissionsExt;
 use std::path::PathBuf;
+use std::sync::Arc;
 
 use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
 use libcontainer::workload::default::DefaultExecutor;
 use libcontainer::workload::{
     Executor as LibcontainerExecutor, ExecutorError as LibcontainerExecutorError,
@@ -14,12 +17,26 @@ use libcontainer::workload::{
 use oci_spec::image::Platform;
 use oci_spec::runtime::Spec;
 
-use crate::container::{Engine, PathResolve, RuntimeContext, Source, WasiContext};
+use super::engine_metrics;
+use super::security_label;
+use crate::container::{
+    Capabilities, ComponentLimits, Engine, InheritedFd, InstanceInfo, PathResolve,
+    PrecompiledArtifact, RuntimeContext, Source, StartupSignal, Terminator, WasiContext,
+};
 use crate::sandbox::oci::WasmLayer;
+#[cfg(feature = "tracing")]
+use crate::sandbox::redact::Redacted;
+
+/// Annotation with the path of a file, inside the container, to use as the stdin of the guest.
+pub const STDIN_FILE_ANNOTATION: &str = "io.containerd.wasm.stdin-file";
+/// Annotation with the literal data to use as the stdin of the guest.
+pub const STDIN_DATA_ANNOTATION: &str = "io.containerd.wasm.stdin-data";
 
 #[derive(Clone)]
 enum InnerExecutor {
     Wasm,
+    // the image has no wasm entrypoint, the module embedded in the shim runs instead
+    Embedded,
     Linux,
     CantHandle,
 }
@@ -30,10 +47,24 @@ pub(crate) struct Executor<E: Engine> {
     inner: OnceCell<InnerExecutor>,
     wasm_layers: Vec<WasmLayer>,
     platform: Platform,
+    id: String,
+    state_dir: PathBuf,
+    capabilities: Capabilities,
+    component_limits: ComponentLimits,
+    precompiled_artifacts: Arc<[PrecompiledArtifact]>,
+    startup_signal: StartupSignal,
+    inherited_fds: Arc<[InheritedFd]>,
+    metrics_file: Option<Arc<File>>,
+    exit_report_file: Option<Arc<File>>,
+    started_at: OnceCell<DateTime<Utc>>,
+    embedded: Option<WasmLayer>,
 }
 
 impl<E: Engine> LibcontainerExecutor for Executor<E> {
-    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Debug"))]
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip(self, spec), fields(spec = ?Redacted(spec)), level = "Debug")
+    )]
     fn validate(&self, spec: &Spec) -> Result<(), ExecutorValidationError> {
         // We can handle linux container. We delegate wasm container to the engine.
         match self.inner(spec) {
@@ -42,7 +73,10 @@ impl<E: Engine> LibcontainerExecutor for Executor<E> {
         }
     }
 
-    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Debug"))]
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip(self, spec), fields(spec = ?Redacted(spec)), level = "Debug")
+    )]
     fn exec(&self, spec: &Spec) -> Result<(), LibcontainerExecutorError> {
         // If it looks like a linux container, run it as a linux container.
         // Otherwise, run it as a wasm container
@@ -52,10 +86,33 @@ impl<E: Engine> LibcontainerExecutor for Executor<E> {
                 log::info!("executing linux container");
                 DefaultExecutor {}.exec(spec)
             }
-            InnerExecutor::Wasm => {
+            inner @ (InnerExecutor::Wasm | InnerExecutor::Embedded) => {
+                if let Err(err) = redirect_stdin(spec) {
+                    log::error!("error setting up stdin: {err:#}");
+                    std::process::exit(137)
+                }
+                if let Err(err) = security_label::apply(spec) {
+                    log::error!("error applying the security labels: {err:#}");
+                    std::process::exit(137)
+                }
+                self.started_at.get_or_init(Utc::now);
+                if let Some(file) = &self.metrics_file {
+                    engine_metrics::report(self.engine.clone(), file.clone());
+                }
+                let ctx = match (inner, &self.embedded) {
+                    (InnerExecutor::Embedded, Some(layer)) => {
+                        self.ctx_with_layers(spec, std::slice::from_ref(layer))
+                    }
+                    _ => self.ctx(spec),
+                };
                 log::info!("calling start function");
-                match self.engine.run_wasi(&self.ctx(spec)) {
-                    Ok(code) => std::process::exit(code),
+                match self.engine.run_wasi(&ctx) {
+                    Ok(code) => {
+                        if let Some(file) = &self.metrics_file {
+                            engine_metrics::flush(&self.engine, file);
+                        }
+                        std::process::exit(code)
+                    }
                     Err(err) => {
                         log::info!("error running start function: {err}");
                         std::process::exit(137)
@@ -82,22 +139,78 @@ impl<E: Engine> LibcontainerExecutor for Executor<E> {
 }
 
 impl<E: Engine> Executor<E> {
-    pub fn new(engine: E, wasm_layers: Vec<WasmLayer>, platform: Platform) -> Self {
+    pub fn new(
+        engine: E,
+        wasm_layers: Vec<WasmLayer>,
+        platform: Platform,
+        id: String,
+        state_dir: PathBuf,
+        capabilities: Capabilities,
+        component_limits: ComponentLimits,
+        precompiled_artifacts: Vec<PrecompiledArtifact>,
+        startup_signal: StartupSignal,
+        inherited_fds: Vec<InheritedFd>,
+        metrics_file: Option<File>,
+        exit_report_file: Option<File>,
+        embedded: Option<WasmLayer>,
+    ) -> Self {
         Self {
             engine,
             inner: Default::default(),
             wasm_layers,
             platform,
+            id,
+            state_dir,
+            capabilities,
+            component_limits,
+            precompiled_artifacts: precompiled_artifacts.into(),
+            startup_signal,
+            inherited_fds: inherited_fds.into(),
+            metrics_file: metrics_file.map(Arc::new),
+            exit_report_file: exit_report_file.map(Arc::new),
+            started_at: Default::default(),
+            embedded,
         }
     }
 
     fn ctx<'a>(&'a self, spec: &'a Spec) -> WasiContext<'a> {
-        let wasm_layers = &self.wasm_layers;
+        self.ctx_with_layers(spec, &self.wasm_layers)
+    }
+
+    fn ctx_with_layers<'a>(
+        &'a self,
+        spec: &'a Spec,
+        wasm_layers: &'a [WasmLayer],
+    ) -> WasiContext<'a> {
         let platform = &self.platform;
+        let instance_info = self.instance_info(spec);
         WasiContext {
             spec,
             wasm_layers,
             platform,
+            instance_info,
+            capabilities: self.capabilities,
+            component_limits: self.component_limits,
+            precompiled_artifacts: &self.precompiled_artifacts,
+            startup_signal: Some(&self.startup_signal),
+            inherited_fds: &self.inherited_fds,
+            terminator: Terminator::new(self.exit_report_file.clone()),
+        }
+    }
+
+    // This runs in the container process, so the pid is the one of the container.
+    fn instance_info(&self, spec: &Spec) -> InstanceInfo {
+        let cgroup_path = spec
+            .linux()
+            .as_ref()
+            .and_then(|linux| linux.cgroups_path().clone());
+        InstanceInfo {
+            id: self.id.clone(),
+            pid: std::process::id(),
+            cgroup_path,
+            state_dir: self.state_dir.clone(),
+            // the guest isn't started yet when validating the container
+            started_at: self.started_at.get().copied().unwrap_or_else(Utc::now),
         }
     }
 
@@ -110,6 +223,10 @@ impl<E: Engine> Executor<E> {
                     log::debug!("error checking if linux container: {err}. Fallback to wasm container");
                     match self.engine.can_handle(ctx) {
                         Ok(_) => InnerExecutor::Wasm,
+                        Err(err) if self.can_handle_embedded(spec) => {
+                            log::info!("no wasm entrypoint in container {}: {err}. Running the embedded module", self.id);
+                            InnerExecutor::Embedded
+                        }
                         Err(err) => {
                             // log an error and return
                             log::error!("error checking if wasm container: {err}. Note: arg0 must be a path to a Wasm file");
@@ -120,6 +237,56 @@ impl<E: Engine> Executor<E> {
             }
         })
     }
+
+    // The embedded module only replaces images without wasm layers.
+    fn can_handle_embedded(&self, spec: &Spec) -> bool {
+        let Some(layer) = &self.embedded else {
+            return false;
+        };
+        if !self.wasm_layers.is_empty() {
+            return false;
+        }
+        let ctx = self.ctx_with_layers(spec, std::slice::from_ref(layer));
+        self.engine
+            .can_handle(&ctx)
+            .inspect_err(|err| log::error!("the engine can't run the embedded module: {err}"))
+            .is_ok()
+    }
+}
+
+// Replaces the stdin of the process with the file or data from the stdin annotations, if any.
+// This runs inside the container, so paths are resolved in the container rootfs and mounts.
+fn redirect_stdin(spec: &Spec) -> Result<()> {
+    let Some(annotations) = spec.annotations() else {
+        return Ok(());
+    };
+
+    let file = match (
+        annotations.get(STDIN_FILE_ANNOTATION),
+        annotations.get(STDIN_DATA_ANNOTATION),
+    ) {
+        (None, None) => return Ok(()),
+        (Some(_), Some(_)) => {
+            bail!("only one of {STDIN_FILE_ANNOTATION} and {STDIN_DATA_ANNOTATION} can be set")
+        }
+        (Some(path), None) => {
+            log::info!("using {path:?} as stdin");
+            File::open(path).with_context(|| format!("failed to open stdin file {path:?}"))?
+        }
+        (None, Some(data)) => {
+            // the rootfs might be read-only, so keep the data in memory
+            let fd = unsafe { libc::memfd_create(c"stdin".as_ptr(), libc::MFD_CLOEXEC) };
+            let fd = nix::errno::Errno::result(fd).context("failed to create stdin memfd")?;
+            let mut file = unsafe { File::from_raw_fd(fd) };
+            file.write_all(data.as_bytes())?;
+            file.rewind()?;
+            file
+        }
+    };
+
+    let res = unsafe { libc::dup2(file.as_raw_fd(), libc::STDIN_FILENO) };
+    nix::errno::Errno::result(res).context("failed to replace stdin")?;
+    Ok(())
 }
 
 fn is_linux_container(ctx: &impl RuntimeContext) -> Result<()> {
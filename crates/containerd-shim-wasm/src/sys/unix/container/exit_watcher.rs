@@ -0,0 +1,176 @@
+//! Watches the exit of the containers of the shim.
+//!
+//! A single reactor thread waits on the pidfds of all the containers with epoll, and reaps
+//! them as they exit, instead of a thread per container blocking in `waitid`, which at high
+//! density costs a stack and a scheduler entry per container.
+//! On kernels without pidfds (before 5.3), each container is watched by a thread of its own.
+
+use std::collections::HashMap;
+use std::os::fd::{AsFd, FromRawFd, OwnedFd};
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+
+use nix::errno::Errno;
+use nix::sys::epoll::{Epoll, EpollCreateFlags, EpollEvent, EpollFlags, EpollTimeout};
+use nix::sys::wait::{waitid, Id as WaitID, WaitPidFlag, WaitStatus};
+use nix::unistd::Pid;
+
+use crate::sandbox::panics;
+
+type OnExit = Box<dyn FnOnce(u32) + Send>;
+
+struct Reactor {
+    epoll: Epoll,
+    // None once the reactor thread died
+    watched: Mutex<Option<HashMap<i32, (OwnedFd, OnExit)>>>,
+}
+
+/// Calls `on_exit` with the exit status of the child process `pid` once it exits.
+/// If the reactor thread dies, the `on_exit` of the processes it watched are dropped without
+/// being called.
+pub(crate) fn watch(pid: i32, on_exit: impl FnOnce(u32) + Send + 'static) {
+    let on_exit: OnExit = Box::new(on_exit);
+    let on_exit = match reactor() {
+        Some(reactor) => match reactor.watch(pid, on_exit) {
+            Ok(()) => return,
+            Err((err, on_exit)) => {
+                log::warn!("failed to watch process {pid} with a pidfd: {err}");
+                on_exit
+            }
+        },
+        None => on_exit,
+    };
+
+    let context = format!("the exit watcher of process {pid}");
+    thread::spawn(move || {
+        panics::guarded(context, || on_exit(wait(pid)));
+    });
+}
+
+fn reactor() -> Option<&'static Reactor> {
+    static REACTOR: OnceLock<Option<Reactor>> = OnceLock::new();
+    REACTOR
+        .get_or_init(|| {
+            Reactor::start()
+                .inspect_err(|err| {
+                    log::info!("watching the exit of containers with threads: {err}")
+                })
+                .ok()
+        })
+        .as_ref()
+}
+
+impl Reactor {
+    fn start() -> nix::Result<Self> {
+        // probe for pidfd support with the shim itself
+        pidfd_open(std::process::id() as i32)?;
+
+        let reactor = Self {
+            epoll: Epoll::new(EpollCreateFlags::EPOLL_CLOEXEC)?,
+            watched: Mutex::new(Some(HashMap::new())),
+        };
+        thread::Builder::new()
+            .name("exit-watcher".to_string())
+            .spawn(|| {
+                let reactor = reactor().expect("the reactor is started");
+                panics::guarded("the exit watcher", || reactor.run());
+                reactor.watched.lock().unwrap().take();
+            })
+            .map_err(|err| Errno::from_raw(err.raw_os_error().unwrap_or(libc::EAGAIN)))?;
+        Ok(reactor)
+    }
+
+    fn watch(&self, pid: i32, on_exit: OnExit) -> Result<(), (Errno, OnExit)> {
+        let pidfd = match pidfd_open(pid) {
+            Ok(pidfd) => pidfd,
+            Err(err) => return Err((err, on_exit)),
+        };
+        // the reactor might see the exit before it's inserted, it waits for the lock
+        let mut watched = self.watched.lock().unwrap();
+        let Some(watched) = watched.as_mut() else {
+            return Err((Errno::ESRCH, on_exit));
+        };
+        let event = EpollEvent::new(EpollFlags::EPOLLIN, pid as u64);
+        if let Err(err) = self.epoll.add(pidfd.as_fd(), event) {
+            return Err((err, on_exit));
+        }
+        watched.insert(pid, (pidfd, on_exit));
+        Ok(())
+    }
+
+    fn run(&self) {
+        let mut events = [EpollEvent::empty(); 64];
+        loop {
+            let n = match self.epoll.wait(&mut events, EpollTimeout::NONE) {
+                Ok(n) => n,
+                Err(Errno::EINTR) => continue,
+                Err(err) => {
+                    log::error!("failed to wait for the exit of containers: {err}");
+                    return;
+                }
+            };
+            for event in &events[..n] {
+                let pid = event.data() as i32;
+                let watch = self.watched.lock().unwrap().as_mut().unwrap().remove(&pid);
+                let Some((pidfd, on_exit)) = watch else {
+                    continue;
+                };
+                let _ = self.epoll.delete(pidfd.as_fd());
+                let context = format!("the exit watcher of process {pid}");
+                panics::guarded(context, || on_exit(wait(pid)));
+            }
+        }
+    }
+}
+
+fn pidfd_open(pid: i32) -> nix::Result<OwnedFd> {
+    // SAFETY: pidfd_open has no side effects, and returns a new fd on success
+    let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid, 0) };
+    Errno::result(fd).map(|fd| unsafe { OwnedFd::from_raw_fd(fd as i32) })
+}
+
+// Reaps the process `pid` and returns its exit status, the number of the signal that killed
+// it, or 137 if it can't be waited on.
+fn wait(pid: i32) -> u32 {
+    (match waitid(WaitID::Pid(Pid::from_raw(pid)), WaitPidFlag::WEXITED) {
+        Ok(WaitStatus::Exited(_, status)) => status,
+        Ok(WaitStatus::Signaled(_, sig, _)) => sig as i32,
+        Ok(_) => 0,
+        Err(Errno::ECHILD) => {
+            log::info!("no child process");
+            0
+        }
+        Err(e) => {
+            log::error!("waitpid failed: {e}");
+            137
+        }
+    }) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_watch() -> anyhow::Result<()> {
+        let (tx, rx) = mpsc::channel();
+        for code in [0, 3] {
+            let child = Command::new("sh")
+                .args(["-c", &format!("sleep 0.1; exit {code}")])
+                .spawn()?;
+            let tx = tx.clone();
+            watch(child.id() as i32, move |status| {
+                tx.send((code, status)).unwrap()
+            });
+        }
+        for _ in 0..2 {
+            let (code, status) = rx.recv_timeout(Duration::from_secs(10))?;
+            assert_eq!(code, status);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,130 @@
+//! Markers of the images without wasm layers, to skip looking them up in containerd.
+//!
+//! Creating a container of an image without wasm layers, e.g., a wasm module in the rootfs of
+//! a regular image, looks the image up in containerd only to find no wasm layers. When
+//! `image_marker_ttl_secs` is set in the runtime configuration, the shim records the images
+//! without wasm layers in the root directory, by the name CRI annotates containers with, and
+//! the following containers of the image go straight to their rootfs.
+//!
+//! Markers are only trusted for `image_marker_ttl_secs`: an image name retagged to an image
+//! with wasm layers within that time still runs from its rootfs.
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use oci_spec::runtime::Spec;
+
+/// Annotation CRI sets with the name of the image of a container.
+const IMAGE_NAME_ANNOTATION: &str = "io.kubernetes.cri.image-name";
+
+/// Returns the name of the image of the container, if CRI annotated it with it.
+pub fn image_name(spec: &Spec) -> Option<&str> {
+    spec.annotations()
+        .as_ref()?
+        .get(IMAGE_NAME_ANNOTATION)
+        .map(String::as_str)
+}
+
+/// The marker of the image of a container.
+pub struct ImageMarker {
+    path: PathBuf,
+    ttl: Duration,
+}
+
+impl ImageMarker {
+    /// Returns the marker of the image of the container, if the container is annotated with
+    /// the name of its image.
+    pub fn for_container(spec: &Spec, rootdir: &Path, ttl: Duration) -> Option<Self> {
+        let image = image_name(spec)?;
+        Some(Self {
+            path: Self::path(rootdir, image),
+            ttl,
+        })
+    }
+
+    /// Removes the marker of `image`, once the image is deleted from containerd: an image
+    /// pulled again under its name might have wasm layers.
+    pub fn forget(rootdir: &Path, image: &str) {
+        let path = Self::path(rootdir, image);
+        match std::fs::remove_file(&path) {
+            Ok(()) => log::info!("forgot the image marker of deleted image {image}"),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => log::debug!("failed to remove the image marker {path:?}: {err}"),
+        }
+    }
+
+    fn path(rootdir: &Path, image: &str) -> PathBuf {
+        // container ids can't start with a dot, so this is never the state of a container
+        rootdir.join(".images").join(sha256::digest(image))
+    }
+
+    /// Returns true if the image was recorded without wasm layers less than the TTL ago.
+    pub fn has_no_wasm_layers(&self) -> bool {
+        let Ok(modified) = self.path.metadata().and_then(|m| m.modified()) else {
+            return false;
+        };
+        SystemTime::now()
+            .duration_since(modified)
+            .is_ok_and(|age| age < self.ttl)
+    }
+
+    /// Records whether the image has wasm layers.
+    pub fn record(&self, has_wasm_layers: bool) {
+        let res = if has_wasm_layers {
+            std::fs::remove_file(&self.path).or_else(|err| match err.kind() {
+                std::io::ErrorKind::NotFound => Ok(()),
+                _ => Err(err),
+            })
+        } else {
+            self.path
+                .parent()
+                .map_or(Ok(()), std::fs::create_dir_all)
+                .and_then(|()| std::fs::write(&self.path, b""))
+        };
+        if let Err(err) = res {
+            log::debug!("failed to update the image marker {:?}: {err}", self.path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use oci_spec::runtime::SpecBuilder;
+
+    use super::*;
+
+    #[test]
+    fn test_image_marker() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let ttl = Duration::from_secs(60);
+
+        let spec = SpecBuilder::default().build()?;
+        assert!(ImageMarker::for_container(&spec, dir.path(), ttl).is_none());
+
+        let spec = SpecBuilder::default()
+            .annotations(HashMap::from([(
+                IMAGE_NAME_ANNOTATION.to_string(),
+                "docker.io/library/app:latest".to_string(),
+            )]))
+            .build()?;
+        let marker = ImageMarker::for_container(&spec, dir.path(), ttl).unwrap();
+        assert!(!marker.has_no_wasm_layers());
+
+        marker.record(false);
+        assert!(marker.has_no_wasm_layers());
+
+        marker.record(true);
+        assert!(!marker.has_no_wasm_layers());
+
+        marker.record(false);
+        ImageMarker::forget(dir.path(), "docker.io/library/app:latest");
+        assert!(!marker.has_no_wasm_layers());
+
+        let marker = ImageMarker::for_container(&spec, dir.path(), Duration::ZERO).unwrap();
+        marker.record(false);
+        assert!(!marker.has_no_wasm_layers());
+        Ok(())
+    }
+}
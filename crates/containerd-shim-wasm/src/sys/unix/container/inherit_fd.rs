@@ -0,0 +1,252 @@
+//! Reception of the file descriptors passed by the platform with the `runwasi.io/inherit-fds`
+//! annotation.
+//!
+//! The file descriptors are received by the zygote, so that the container process inherits
+//! them when it is forked. The zygote builds every container of the shim, so the socket must
+//! be in the bundle, and the platform must send the file descriptors within
+//! [`RECEIVE_TIMEOUT`] of the connection.
+
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::IoSliceMut;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::net::UnixStream;
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{bail, ensure, Context, Result};
+use nix::errno::Errno;
+use nix::sys::socket::{recvmsg, ControlMessageOwned, MsgFlags};
+use oci_spec::runtime::Spec;
+
+use crate::container::{FdDescriptor, InheritedFd, INHERIT_FDS_ANNOTATION};
+use crate::sandbox::Error as SandboxError;
+
+/// At most this many file descriptors are received.
+const MAX_FDS: usize = 64;
+
+const MAX_PAYLOAD_BYTES: usize = 64 * 1024;
+
+/// How long the platform has to send the file descriptors once connected.
+pub const RECEIVE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Returns the path of the socket the platform sends file descriptors on, if any.
+/// The path of the annotation must be relative, and resolve to a file of `bundle`.
+pub fn socket_path(spec: &Spec, bundle: &Path) -> Result<Option<PathBuf>, SandboxError> {
+    let Some(path) = spec
+        .annotations()
+        .as_ref()
+        .and_then(|a| a.get(INHERIT_FDS_ANNOTATION))
+    else {
+        return Ok(None);
+    };
+    let relative = Path::new(path);
+    if !relative
+        .components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+    {
+        return Err(SandboxError::InvalidArgument(format!(
+            "{INHERIT_FDS_ANNOTATION} must be a relative path without `..`, got {path:?}"
+        )));
+    }
+    let bundle = bundle.canonicalize()?;
+    let socket = bundle.join(relative).canonicalize().map_err(|err| {
+        SandboxError::InvalidArgument(format!(
+            "{INHERIT_FDS_ANNOTATION} socket {path:?} not found in the bundle: {err}"
+        ))
+    })?;
+    // symlinks in the bundle could still point out of it
+    if !socket.starts_with(&bundle) {
+        return Err(SandboxError::InvalidArgument(format!(
+            "{INHERIT_FDS_ANNOTATION} socket {path:?} is outside of the bundle"
+        )));
+    }
+    Ok(Some(socket))
+}
+
+/// Receives the file descriptors the platform sends on the socket at `path`, failing if
+/// they aren't sent within [`RECEIVE_TIMEOUT`].
+pub fn receive(path: &Path) -> Result<Vec<InheritedFd>> {
+    let socket = UnixStream::connect(path).with_context(|| {
+        format!("failed to connect to {INHERIT_FDS_ANNOTATION} socket {path:?}")
+    })?;
+    socket.set_read_timeout(Some(RECEIVE_TIMEOUT))?;
+
+    let mut payload = vec![0u8; MAX_PAYLOAD_BYTES];
+    let mut iov = [IoSliceMut::new(&mut payload)];
+    let mut cmsg = nix::cmsg_space!([i32; MAX_FDS]);
+    let msg = recvmsg::<()>(
+        socket.as_raw_fd(),
+        &mut iov,
+        Some(&mut cmsg),
+        MsgFlags::MSG_CMSG_CLOEXEC,
+    )
+    .map_err(|err| match err {
+        Errno::EAGAIN => {
+            anyhow::anyhow!("no file descriptors received on {path:?} within {RECEIVE_TIMEOUT:?}")
+        }
+        err => err.into(),
+    })?;
+    let mut fds = vec![];
+    for cmsg in msg.cmsgs()? {
+        if let ControlMessageOwned::ScmRights(received) = cmsg {
+            // take ownership first, so that they are closed on errors
+            fds.extend(
+                received
+                    .into_iter()
+                    .map(|fd| unsafe { OwnedFd::from_raw_fd(fd) }),
+            );
+        }
+    }
+    let truncated = msg
+        .flags
+        .intersects(MsgFlags::MSG_TRUNC | MsgFlags::MSG_CTRUNC);
+    let len = msg.bytes;
+    ensure!(
+        !truncated,
+        "message on {path:?} is too large, expected at most {MAX_FDS} file descriptors"
+    );
+
+    let descriptors: Vec<FdDescriptor> = serde_json::from_slice(&payload[..len])
+        .with_context(|| format!("invalid file descriptors description on {path:?}"))?;
+    if descriptors.len() != fds.len() {
+        bail!(
+            "received {} file descriptors on {path:?}, but {} descriptions",
+            fds.len(),
+            descriptors.len()
+        );
+    }
+    let mut names = HashSet::new();
+    for desc in &descriptors {
+        ensure!(
+            names.insert(desc.name.as_str()),
+            "duplicate file descriptor name {:?}",
+            desc.name
+        );
+    }
+
+    Ok(descriptors
+        .into_iter()
+        .zip(fds)
+        .map(|(desc, fd)| InheritedFd::new(desc.name, desc.role, File::from(fd)))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::io::{IoSlice, Read, Write};
+    use std::os::unix::net::UnixListener;
+    use std::thread;
+
+    use nix::sys::socket::{sendmsg, ControlMessage};
+    use oci_spec::runtime::SpecBuilder;
+
+    use super::*;
+    use crate::container::FdRole;
+
+    fn send(path: &Path, payload: &'static [u8], fds: Vec<OwnedFd>) -> Result<()> {
+        let listener = UnixListener::bind(path)?;
+        thread::spawn(move || {
+            let (conn, _) = listener.accept().unwrap();
+            let raw: Vec<i32> = fds.iter().map(|fd| fd.as_raw_fd()).collect();
+            sendmsg::<()>(
+                conn.as_raw_fd(),
+                &[IoSlice::new(payload)],
+                &[ControlMessage::ScmRights(&raw)],
+                MsgFlags::empty(),
+                None,
+            )
+            .unwrap();
+        });
+        Ok(())
+    }
+
+    #[test]
+    fn test_receive() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let socket = dir.path().join("fds.sock");
+        let (mut reader, writer) = UnixStream::pair()?;
+        send(
+            &socket,
+            br#"[{"name": "audit", "role": "log"}]"#,
+            vec![OwnedFd::from(writer)],
+        )?;
+
+        let fds = receive(&socket)?;
+        assert_eq!(fds.len(), 1);
+        assert_eq!(fds[0].name(), "audit");
+        assert_eq!(fds[0].role(), FdRole::Log);
+
+        fds[0].file().write_all(b"hello")?;
+        drop(fds);
+        let mut received = String::new();
+        reader.read_to_string(&mut received)?;
+        assert_eq!(received, "hello");
+        Ok(())
+    }
+
+    #[test]
+    fn test_receive_timeout() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let socket = dir.path().join("fds.sock");
+        // connections are queued, but nothing is ever sent
+        let _listener = UnixListener::bind(&socket)?;
+        let err = receive(&socket).unwrap_err();
+        assert!(err.to_string().contains("within"), "{err:#}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_socket_path() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let bundle = dir.path().join("bundle");
+        std::fs::create_dir(&bundle)?;
+        let _listener = UnixListener::bind(bundle.join("fds.sock"))?;
+        let _outside = UnixListener::bind(dir.path().join("host.sock"))?;
+        std::os::unix::fs::symlink(dir.path().join("host.sock"), bundle.join("link.sock"))?;
+        let spec = |path: &str| {
+            SpecBuilder::default()
+                .annotations(HashMap::from([(
+                    INHERIT_FDS_ANNOTATION.to_string(),
+                    path.to_string(),
+                )]))
+                .build()
+                .unwrap()
+        };
+
+        assert_eq!(
+            socket_path(&spec("fds.sock"), &bundle)?,
+            Some(bundle.canonicalize()?.join("fds.sock"))
+        );
+        assert_eq!(
+            socket_path(&SpecBuilder::default().build()?, &bundle)?,
+            None
+        );
+        for path in [
+            dir.path().join("host.sock").to_str().unwrap(),
+            "../host.sock",
+            "link.sock",
+            "missing.sock",
+        ] {
+            assert!(matches!(
+                socket_path(&spec(path), &bundle),
+                Err(SandboxError::InvalidArgument(_))
+            ));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_receive_mismatch() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let socket = dir.path().join("fds.sock");
+        send(
+            &socket,
+            br#"[{"name": "http", "role": "listener"}]"#,
+            vec![],
+        )?;
+        receive(&socket).unwrap_err();
+        Ok(())
+    }
+}
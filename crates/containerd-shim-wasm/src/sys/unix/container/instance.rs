@@ -1,34 +1,79 @@
-use std::marker::PhantomData;
-use std::path::Path;
+use std::fs::OpenOptions;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::Duration;
 
 use chrono::{DateTime, Utc};
 use libcontainer::container::builder::ContainerBuilder;
 use libcontainer::syscall::syscall::SyscallType;
-use nix::errno::Errno;
-use nix::sys::wait::{waitid, Id as WaitID, WaitPidFlag, WaitStatus};
-use nix::unistd::Pid;
 use oci_spec::image::Platform;
+use oci_spec::runtime::Spec;
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
 
+use super::admission::Slot;
+use super::artifact_cache;
+use super::build_steps::{self, BuildSteps};
+use super::capture::Capture;
+use super::console::Console;
 use super::container::Container;
-use crate::container::Engine;
+use super::cpu_boost::{self, CpuBoost};
+use super::create_request::{CreateRequest, Version};
+use super::eviction;
+use super::exit_watcher;
+use super::image_marker::ImageMarker;
+use super::inherit_fd;
+use super::mount_options;
+use super::multiplex::Multiplexer;
+use super::rotate::Rotation;
+use super::seccomp;
+use super::security_label;
+use super::shared_engine::{self, SharedEngine};
+#[cfg(feature = "opentelemetry")]
+use super::trace_context;
+use super::{
+    attach, bundle, cri_log, engine_metrics, journald, log_uri, multiplex, pump, revision,
+};
+use crate::container::{Capabilities, Engine, ExitReport, StartupSignal};
 use crate::sandbox::async_utils::AmbientRuntime as _;
-use crate::sandbox::instance_utils::determine_rootdir;
+use crate::sandbox::config::RuntimeConfig;
+use crate::sandbox::containerd::ContainerdFetcher;
+use crate::sandbox::embedded;
+use crate::sandbox::fetcher::{self, FetchRequest, ModuleFetcher as _};
+use crate::sandbox::instance_utils::resolve_rootdir;
+use crate::sandbox::log_sampling::sampled;
+use crate::sandbox::oci::WasmLayer;
+use crate::sandbox::panics;
+use crate::sandbox::redact;
 use crate::sandbox::sync::WaitableCell;
+use crate::sandbox::timings::{Phase, Timings};
 use crate::sandbox::{
     containerd, Error as SandboxError, Instance as SandboxInstance, InstanceConfig,
 };
 use crate::sys::container::executor::Executor;
-use crate::sys::stdio::open;
+use crate::sys::stdio::{StdioOpen, DEFAULT_OPEN_TIMEOUT};
 
-const DEFAULT_CONTAINER_ROOT_DIR: &str = "/run/containerd";
+pub(crate) const DEFAULT_CONTAINER_ROOT_DIR: &str = "/run/containerd";
 
 pub struct Instance<E: Engine> {
     exit_code: WaitableCell<(u32, DateTime<Utc>)>,
     container: Container,
+    console: Option<Arc<Console>>,
+    // joined once the instance is deleted
+    multiplexer: Mutex<Option<Multiplexer>>,
     id: String,
-    _phantom: PhantomData<E>,
+    bundle: PathBuf,
+    termination_log: Option<PathBuf>,
+    containerd_address: String,
+    namespace: String,
+    // released once the instance is deleted
+    _admission: Option<Slot>,
+    // removed once the instance is deleted
+    _capture: Option<Capture>,
+    // released once the last instance is deleted
+    _engine: SharedEngine<E>,
 }
 
 impl<E: Engine + Default> SandboxInstance for Instance<E> {
@@ -36,54 +81,415 @@ impl<E: Engine + Default> SandboxInstance for Instance<E> {
 
     #[cfg_attr(feature = "tracing", tracing::instrument(level = "Info"))]
     fn new(id: String, cfg: &InstanceConfig) -> Result<Self, SandboxError> {
-        // check if container is OCI image with wasm layers and attempt to read the module
-        let (modules, platform) = containerd::Client::connect(cfg.get_containerd_address(), &cfg.get_namespace()).block_on()?
-            .load_modules(&id, &E::default())
-            .block_on()
-            .unwrap_or_else(|e| {
-                log::warn!("Error obtaining wasm layers for container {id}.  Will attempt to use files inside container image. Error: {e}");
-                (vec![], Platform::default())
-            });
-
-        let container = Container::build(
-            |(id, cfg, modules, platform)| {
-                let namespace = cfg.get_namespace();
-
-                let bundle = cfg.get_bundle().to_path_buf();
-                let rootdir = Path::new(DEFAULT_CONTAINER_ROOT_DIR).join(E::name());
-                let rootdir = determine_rootdir(&bundle, &namespace, rootdir)?;
-                let engine = E::default();
-
-                let mut builder = ContainerBuilder::new(id.clone(), SyscallType::Linux)
-                    .with_executor(Executor::new(engine, modules, platform))
-                    .with_root_path(rootdir.clone())?;
-
-                if let Ok(f) = open(cfg.get_stdin()) {
-                    builder = builder.with_stdin(f);
+        let cancelled = || SandboxError::Cancelled(format!("creation of container {id}"));
+        let token = cfg.get_cancellation_token();
+        let timeout = RuntimeConfig::current().create_timeout();
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let timed_out = |phase: &str| {
+            let timeout = timeout.unwrap_or_default();
+            SandboxError::TimedOut(format!(
+                "creation of container {id} overran its {timeout:?} deadline while {phase}"
+            ))
+        };
+        let interrupted = |phase: &'static str| {
+            move |interruption| match interruption {
+                Interruption::Cancelled => cancelled(),
+                Interruption::TimedOut => timed_out(phase),
+            }
+        };
+
+        let mut spec = Spec::load(cfg.get_bundle().join("config.json"))?;
+        mount_options::check(&id, &spec)?;
+        security_label::check(&spec)?;
+        let termination_log = ExitReport::termination_log(&spec);
+
+        // fail before fetching anything if the state of the container can't be kept
+        let rootdir = build_steps::in_span("resolve_rootdir", || {
+            resolve_rootdir(
+                cfg.get_bundle(),
+                &cfg.get_namespace(),
+                &state_roots::<E>(&RuntimeConfig::current()),
+            )
+        })?;
+
+        let admission = RuntimeConfig::current()
+            .admission
+            .as_ref()
+            .map(|admission| Slot::acquire(&id, admission))
+            .transpose()?;
+
+        // containerd is only dialed once the container needs it
+        let client = containerd::Client::shared(cfg.get_containerd_address(), cfg.get_namespace());
+        // the content fetched for the container is leased until it is deleted
+        let lease = LeaseGuard::new(&id, &client);
+        if RuntimeConfig::current().image_eviction {
+            eviction::watch(&client, &cfg.get_namespace(), &rootdir);
+        }
+        let engine = shared_engine::acquire::<E>();
+        let containerd = ContainerdFetcher::new(client, E::clone(&engine));
+
+        let image_marker = RuntimeConfig::current()
+            .image_marker_ttl_secs
+            .and_then(|ttl| ImageMarker::for_container(&spec, &rootdir, Duration::from_secs(ttl)));
+        let signatures_required = containerd::signatures_required(&cfg.get_namespace());
+        let fetching = Instant::now();
+        let (mut modules, platform) = build_steps::in_span("fetch_modules", || {
+            Ok::<_, SandboxError>(match fetcher::module_source(&spec) {
+                Some(source) => {
+                    log::info!("fetching the modules of container {id} from {source}");
+                    let fetcher = fetcher::fetcher_for(source)?;
+                    let req = FetchRequest {
+                        id: id.clone(),
+                        source: source.to_string(),
+                    };
+                    let (modules, platform) =
+                        run_until_interrupted(fetcher.fetch(&req), token, deadline)
+                            .map_err(interrupted("fetching its modules"))?
+                            .map_err(|e| {
+                                // keep the code of the sources rejected by the fetcher
+                                e.downcast::<SandboxError>()
+                                    .unwrap_or_else(SandboxError::Any)
+                            })?;
+                    containerd::verify_fetched(&cfg.get_namespace(), source, &modules)?;
+                    (modules, platform)
+                }
+                None if !signatures_required
+                    && image_marker
+                        .as_ref()
+                        .is_some_and(ImageMarker::has_no_wasm_layers) =>
+                {
+                    sampled!(log::Level::Info, "the image of container {id} has no wasm layers, using files inside container image");
+                    (vec![], Platform::default())
+                }
+                None => {
+                    // check if container is OCI image with wasm layers and attempt to read the module
+                    let req = FetchRequest {
+                        id: id.clone(),
+                        source: String::new(),
+                    };
+                    match run_until_interrupted(containerd.fetch(&req), token, deadline)
+                        .map_err(interrupted("fetching its modules"))?
+                    {
+                        Ok((modules, platform)) => {
+                            if let Some(marker) = &image_marker {
+                                marker.record(!modules.is_empty());
+                            }
+                            if RuntimeConfig::current().image_eviction {
+                                let digests = modules.iter().map(|m| m.config.digest().to_string());
+                                eviction::record_layers(&spec, &rootdir, digests);
+                            }
+                            (modules, platform)
+                        }
+                        // the files of the rootfs can't be verified
+                        Err(e) if signatures_required => {
+                            return Err(match e.downcast::<SandboxError>() {
+                                Ok(err @ SandboxError::PermissionDenied(_)) => err,
+                                Ok(err) => SandboxError::PermissionDenied(format!(
+                                    "the modules of container {id} can't be verified: {err}"
+                                )),
+                                Err(err) => SandboxError::PermissionDenied(format!(
+                                    "the modules of container {id} can't be verified: {err:#}"
+                                )),
+                            });
+                        }
+                        Err(e) => match e.downcast::<SandboxError>() {
+                            // the files of the rootfs mustn't run instead of rejected modules,
+                            // or of wasm layers that can't be read
+                            Ok(
+                                err @ (SandboxError::PermissionDenied(_)
+                                | SandboxError::FailedPrecondition(_)),
+                            ) => return Err(err),
+                            e => {
+                                let e = e.map_or_else(|e| format!("{e:#}"), |e| e.to_string());
+                                sampled!(log::Level::Warn, "Error obtaining wasm layers for container {id}.  Will attempt to use files inside container image. Error: {e}");
+                                (vec![], Platform::default())
+                            }
+                        },
+                    }
                 }
-                if let Ok(f) = open(cfg.get_stdout()) {
-                    builder = builder.with_stdout(f);
+            })
+        })?;
+        let client = containerd.client();
+
+        let pull_modules = spec
+            .annotations()
+            .as_ref()
+            .and_then(|a| a.get(containerd::PULL_MODULES_ANNOTATION));
+        if let Some(pull_modules) = pull_modules {
+            for (name, reference) in containerd::parse_pull_modules(pull_modules)? {
+                log::info!("pulling module {name} of container {id} from {reference}");
+                let layers = run_until_interrupted(
+                    client.pull_module::<E>(&id, &name, &reference),
+                    token,
+                    deadline,
+                )
+                .map_err(interrupted("pulling its modules"))??;
+                modules.extend(layers);
+            }
+        }
+        Timings::global().record(&id, Phase::Fetch, fetching.elapsed());
+
+        // don't start building the container if the task was deleted while fetching the modules
+        if token.is_cancelled() {
+            return Err(cancelled());
+        }
+
+        match client.image_revision(&id).block_on() {
+            Ok(Some(revision)) => {
+                log::info!("container {id} runs image revision {revision}");
+                #[cfg(feature = "opentelemetry")]
+                {
+                    use tracing_opentelemetry::OpenTelemetrySpanExt as _;
+                    tracing::Span::current()
+                        .set_attribute(revision::REVISION_ATTRIBUTE, revision.clone());
                 }
-                if let Ok(f) = open(cfg.get_stderr()) {
-                    builder = builder.with_stderr(f);
+                if revision::inject(&mut spec, &revision) {
+                    spec.save(cfg.get_bundle().join("config.json"))?;
                 }
+            }
+            Ok(None) => {}
+            Err(err) => log::debug!("no image revision for container {id}: {err}"),
+        }
+
+        #[cfg(feature = "opentelemetry")]
+        if trace_context::inject(&mut spec, &crate::sandbox::shim::trace_context_headers()) {
+            spec.save(cfg.get_bundle().join("config.json"))?;
+        }
+
+        if seccomp::inject_default(&mut spec)? {
+            spec.save(cfg.get_bundle().join("config.json"))?;
+        }
 
-                let container = builder
-                    .as_init(&bundle)
-                    .as_sibling(true)
-                    .with_systemd(false)
-                    .build()?;
+        // a previous container with the same bundle might have left its fifos behind
+        bundle::clean_stale_artifacts(&id, cfg.get_bundle());
 
-                Ok(container)
-            },
-            (id.clone(), cfg.clone(), modules, platform),
+        let mut cfg = cfg.clone();
+        // the CRI logger of containerd prefixes the lines of its fifos itself
+        let cri_stdout = log_uri::is_file(cfg.get_stdout());
+        let cri_stderr = log_uri::is_file(cfg.get_stderr());
+        let (stdout, stderr) = log_uri::resolve(
+            &id,
+            &cfg.get_namespace(),
+            cfg.get_stdout(),
+            cfg.get_stderr(),
+            cfg.get_bundle(),
+            Rotation::from_spec(&spec)?,
         )?;
+        cfg.set_stdout(stdout).set_stderr(stderr);
+
+        let console = if cfg.get_terminal() {
+            let console = Console::new(console_socket_path::<E>(&id))?;
+            console.start(cfg.get_stdin(), cfg.get_stdout())?;
+            Some(console)
+        } else {
+            None
+        };
+        let console_socket = console.as_ref().map(|c| c.socket().to_path_buf());
+
+        let containerd_address = cfg.get_containerd_address();
+        let namespace = cfg.get_namespace();
+        let journald = console.is_none() && journald::is_enabled(&spec)?;
+        if journald {
+            let (stdout, stderr) = journald::start(&id, &namespace, cfg.get_bundle())?;
+            cfg.set_stdout(stdout).set_stderr(stderr);
+        } else if console.is_none() && attach::is_enabled(&spec) {
+            let bundle = cfg.get_bundle().to_path_buf();
+            if attach::is_fifo(cfg.get_stdout()) {
+                let stdout = attach::start(&id, "stdout", cfg.get_stdout(), &bundle)?;
+                cfg.set_stdout(stdout);
+            }
+            if attach::is_fifo(cfg.get_stderr()) {
+                let stderr = attach::start(&id, "stderr", cfg.get_stderr(), &bundle)?;
+                cfg.set_stderr(stderr);
+            }
+        }
+        let combined_output = multiplex::is_enabled(&spec);
+        // journald has its own fields for the timestamp and the stream of a line
+        if console.is_none() && !journald && cri_log::is_enabled(&spec)? {
+            if combined_output {
+                return Err(SandboxError::InvalidArgument(
+                    "the CRI log format can't be used with the combined output".to_string(),
+                ));
+            }
+            if !cri_stdout && !cri_stderr {
+                log::warn!("container {id} has no file:// log URI to write in the CRI log format");
+            }
+            let bundle = cfg.get_bundle().to_path_buf();
+            if cri_stdout {
+                let stdout = cri_log::start("stdout", cfg.get_stdout(), &bundle)?;
+                cfg.set_stdout(stdout);
+            }
+            if cri_stderr {
+                let stderr = cri_log::start("stderr", cfg.get_stderr(), &bundle)?;
+                cfg.set_stderr(stderr);
+            }
+        }
+        let runtime_config = RuntimeConfig::current();
+        // the container process doesn't see the runtime config, so the policy is applied here
+        let capabilities = match &runtime_config.strict_wasi {
+            Some(policy) if policy.applies_to(&namespace) => {
+                log::info!("container {id} runs in strict WASI mode");
+                Capabilities::strict()
+            }
+            _ => Capabilities::all(),
+        };
+        let capabilities = match &runtime_config.throttled_clock {
+            Some(policy) if policy.applies_to(&namespace) => {
+                log::info!("container {id} reads throttled clocks");
+                capabilities.with_clock_throttle(policy.throttle())
+            }
+            _ => capabilities,
+        };
+        let tee = runtime_config
+            .tee
+            .as_ref()
+            .filter(|t| t.applies_to(&namespace));
+        let capture = Capture::for_container(
+            &spec,
+            &id,
+            &namespace,
+            &rootdir,
+            runtime_config.capture.as_ref(),
+        );
+        if console.is_some() && capture.is_some() {
+            return Err(SandboxError::FailedPrecondition(format!(
+                "the output of container {id} must be captured, but it has a console"
+            )));
+        }
+        if console.is_none() && (tee.is_some() || capture.is_some()) {
+            let bundle = cfg.get_bundle().to_path_buf();
+            let sinks = |stream: &str| -> anyhow::Result<_> {
+                let mut sinks = tee.map_or_else(Vec::new, |tee| {
+                    pump::tee::sinks(&id, &namespace, stream, tee)
+                });
+                if let Some(capture) = &capture {
+                    sinks.push(capture.sink(stream)?);
+                }
+                Ok(sinks)
+            };
+            if !cfg.get_stdout().as_os_str().is_empty() {
+                let stdout =
+                    pump::tee::start("stdout", cfg.get_stdout(), &bundle, sinks("stdout")?)?;
+                cfg.set_stdout(stdout);
+            }
+            // the stderr of containerd is unused with the combined output
+            if !cfg.get_stderr().as_os_str().is_empty() && !combined_output {
+                let stderr =
+                    pump::tee::start("stderr", cfg.get_stderr(), &bundle, sinks("stderr")?)?;
+                cfg.set_stderr(stderr);
+            }
+        }
+        if let (None, Some(stdio)) = (&console, &runtime_config.stdio) {
+            let bundle = cfg.get_bundle().to_path_buf();
+            if !cfg.get_stdout().as_os_str().is_empty() {
+                let stdout = pump::start(&id, "stdout", cfg.get_stdout(), &bundle, stdio)?;
+                cfg.set_stdout(stdout);
+            }
+            // the stderr of containerd is unused with the combined output
+            if !cfg.get_stderr().as_os_str().is_empty() && !combined_output {
+                let stderr = pump::start(&id, "stderr", cfg.get_stderr(), &bundle, stdio)?;
+                cfg.set_stderr(stderr);
+            }
+        }
+        let multiplexer =
+            if console.is_none() && combined_output && !cfg.get_stdout().as_os_str().is_empty() {
+                let multiplexer = multiplex::start(cfg.get_stdout(), cfg.get_bundle())?;
+                let (stdout, stderr) = multiplexer.fifos();
+                cfg.set_stdout(stdout).set_stderr(stderr);
+                Some(multiplexer)
+            } else {
+                None
+            };
+
+        let cpu_boost = match &runtime_config.cpu_boost {
+            Some(_) if cpu_boost::is_requested(&spec) => Some(CpuBoost::new(cfg.get_bundle())?),
+            _ => None,
+        };
+        let boost_fifo = cpu_boost.as_ref().map(|b| b.fifo().to_path_buf());
+        let inherit_fds_socket = inherit_fd::socket_path(&spec, cfg.get_bundle())?;
 
+        // the container process can't read the runtime config, so this is resolved here
+        let stdio_open = StdioOpen {
+            timeout: runtime_config
+                .stdio_open_timeout_secs
+                .map_or(DEFAULT_OPEN_TIMEOUT, Duration::from_secs),
+            strict: runtime_config.strict_stdio,
+        };
+        let embedded_module = runtime_config
+            .embedded_module
+            .as_ref()
+            .is_some_and(|policy| policy.applies_to(&namespace));
+
+        let bundle = cfg.get_bundle().to_path_buf();
+        let request = CreateRequest {
+            version: Version::CURRENT,
+            id: id.clone(),
+            cfg,
+            modules,
+            platform,
+            console_socket,
+            capabilities,
+            component_limits: runtime_config.component_limits,
+            boost_fifo,
+            inherit_fds_socket,
+            rootdir,
+            stdio_open,
+            redaction: runtime_config.redaction.clone(),
+            embedded_module,
+        };
+        let building = Instant::now();
+        let container = build_steps::in_span("build", || {
+            // the build runs on a thread of its own, so that the creation can be interrupted
+            // while the zygote builds the container, which is then deleted once built
+            let (tx, rx) = tokio::sync::oneshot::channel();
+            let build_id = id.clone();
+            thread::Builder::new()
+                .name("build".to_string())
+                .spawn(move || {
+                    shared_engine::prepare_zygote::<E>();
+                    let res = Container::build(build_container::<E>, request).map(
+                        |(container, steps)| {
+                            steps.report(&build_id);
+                            container
+                        },
+                    );
+                    if let Err(Ok(container)) = tx.send(res) {
+                        log::info!("deleting container {build_id}, its creation was interrupted");
+                        if let Err(err) = container.delete() {
+                            log::warn!("failed to delete container {build_id}: {err}");
+                        }
+                    }
+                })?;
+            run_until_interrupted(rx, token, deadline)
+                .map_err(interrupted("building it"))?
+                .map_err(|_| SandboxError::Others(format!("the build of container {id} panicked")))?
+                .map_err(SandboxError::from)
+        })?;
+        Timings::global().record(&id, Phase::Build, building.elapsed());
+
+        if let (Some(boost), Some(boost_cfg)) = (cpu_boost, &runtime_config.cpu_boost) {
+            let res = container
+                .pid()
+                .and_then(|pid| boost.start(&id, pid as u32, boost_cfg));
+            if let Err(err) = res {
+                log::warn!("no startup CPU boost for container {id}: {err:#}");
+            }
+        }
+
+        lease.keep();
         Ok(Self {
             id,
+            bundle,
+            termination_log,
             exit_code: WaitableCell::new(),
             container,
-            _phantom: Default::default(),
+            console,
+            multiplexer: Mutex::new(multiplexer),
+            containerd_address,
+            namespace,
+            _admission: admission,
+            _capture: capture,
+            _engine: engine,
         })
     }
 
@@ -100,23 +506,25 @@ impl<E: Engine + Default> SandboxInstance for Instance<E> {
         self.container.start()?;
 
         let exit_code = self.exit_code.clone();
-        thread::spawn(move || {
-            // move the exit code guard into this thread
+        let id = self.id.clone();
+        let bundle = self.bundle.clone();
+        let termination_log = self.termination_log.clone();
+        exit_watcher::watch(pid, move |status| {
+            // the exit code guard reports a watcher that never ran as exit code 137
             let _guard = guard;
-
-            let status = match waitid(WaitID::Pid(Pid::from_raw(pid)), WaitPidFlag::WEXITED) {
-                Ok(WaitStatus::Exited(_, status)) => status,
-                Ok(WaitStatus::Signaled(_, sig, _)) => sig as i32,
-                Ok(_) => 0,
-                Err(Errno::ECHILD) => {
-                    log::info!("no child process");
-                    0
-                }
-                Err(e) => {
-                    log::error!("waitpid failed: {e}");
-                    137
+            if let Some(report) = ExitReport::read(&bundle) {
+                log::warn!(
+                    "container {id} was terminated by its engine with exit code {status}: {}",
+                    report.message()
+                );
+                // the exit of the task only carries its exit code, the kubelet reports the
+                // termination message along with it
+                if let Some(path) = &termination_log {
+                    if let Err(err) = report.write_termination_message(path) {
+                        log::warn!("failed to write the termination message of {id}: {err}");
+                    }
                 }
-            } as u32;
+            }
             let _ = exit_code.set((status, Utc::now()));
         });
 
@@ -131,15 +539,67 @@ impl<E: Engine + Default> SandboxInstance for Instance<E> {
         Ok(())
     }
 
+    /// Resize the pseudo terminal of the instance
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Info"))]
+    fn resize_pty(&self, width: u32, height: u32) -> Result<(), SandboxError> {
+        let console = self.console.as_ref().ok_or_else(|| {
+            SandboxError::FailedPrecondition("instance has no terminal".to_string())
+        })?;
+        console.resize(width, height)?;
+        Ok(())
+    }
+
+    /// Close the stdin of the instance
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Info"))]
+    fn close_stdin(&self) -> Result<(), SandboxError> {
+        if let Some(console) = &self.console {
+            console.close_stdin()?;
+        }
+        Ok(())
+    }
+
     /// Delete any reference to the instance
     /// This is called after the instance has exited.
     #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), level = "Info"))]
     fn delete(&self) -> Result<(), SandboxError> {
         log::info!("deleting instance: {}", self.id);
-        self.container.delete()?;
+        let deleted = self.container.delete();
+        // the content isn't needed to delete the container again, so a failed deletion
+        // doesn't keep the lease
+        let client = containerd::Client::shared(&self.containerd_address, &self.namespace);
+        release_lease(&self.id, &client);
+        deleted?;
+        if let Some(multiplexer) = self.multiplexer.lock().unwrap().take() {
+            multiplexer.join();
+        }
         Ok(())
     }
 
+    fn try_engine() -> Result<E, SandboxError> {
+        E::try_default().map_err(|err| {
+            SandboxError::FailedPrecondition(format!(
+                "failed to build the {} engine: {err:#}",
+                E::name()
+            ))
+        })
+    }
+
+    fn state_roots() -> Vec<PathBuf> {
+        state_roots::<E>(&RuntimeConfig::current())
+    }
+
+    /// Precompiles the wasm layers of an image as it is pulled
+    fn precompile_pulled_layers(
+        containerd_address: &str,
+        namespace: &str,
+        layers: &[WasmLayer],
+    ) -> Result<(), SandboxError> {
+        let client = containerd::Client::connect(containerd_address, namespace).block_on()?;
+        client
+            .precompile_pulled_layers(&shared_engine::acquire::<E>(), layers)
+            .block_on()
+    }
+
     /// Waits for the instance to finish and returns its exit code
     /// Returns None if the timeout is reached before the instance has finished.
     /// This is a blocking call.
@@ -151,3 +611,189 @@ impl<E: Engine + Default> SandboxInstance for Instance<E> {
         self.exit_code.wait_timeout(t).copied()
     }
 }
+
+// Builds the container of `request` in its zygote.
+fn build_container<E: Engine>(
+    request: CreateRequest,
+) -> anyhow::Result<(libcontainer::container::Container, BuildSteps)> {
+    // every field is named, so that a new field can't be left unused here
+    let CreateRequest {
+        version: _,
+        id,
+        cfg,
+        mut modules,
+        platform,
+        console_socket,
+        capabilities,
+        component_limits,
+        boost_fifo,
+        inherit_fds_socket,
+        rootdir,
+        stdio_open,
+        redaction,
+        embedded_module,
+    } = request;
+    redact::set_process_config(redaction);
+    let bundle = cfg.get_bundle().to_path_buf();
+    let mut steps = BuildSteps::default();
+
+    let executor = steps.time("prepare_executor", || -> anyhow::Result<_> {
+        let engine = shared_engine::for_container::<E>();
+        let state_dir = rootdir.join(&id);
+        // the container also opens the fifo for reading, so that signaling the end
+        // of its startup never fails with EPIPE once the shim stopped waiting for it
+        let startup_signal = match boost_fifo {
+            Some(fifo) => StartupSignal::new(OpenOptions::new().read(true).write(true).open(fifo)?),
+            None => StartupSignal::default(),
+        };
+        // the container process inherits the file descriptors of the zygote
+        let inherited_fds = match inherit_fds_socket {
+            Some(socket) => inherit_fd::receive(&socket)?,
+            None => vec![],
+        };
+        let metrics_file = engine_metrics::open(&bundle)
+            .inspect_err(|err| log::warn!("not reporting the engine metrics of {id}: {err}"))
+            .ok();
+        let exit_report_file = ExitReport::open(&bundle)
+            .inspect_err(|err| log::warn!("not reporting the exit reason of {id}: {err}"))
+            .ok();
+        // mapped in the zygote, as the artifact cache isn't visible in the container
+        let precompiled_artifacts = artifact_cache::map(&engine, &rootdir, &modules);
+        artifact_cache::share_modules(&rootdir, &mut modules);
+        // the embedded module is in the binary of the zygote too
+        let embedded = embedded_module.then(embedded::layer).flatten();
+        let executor = Executor::new(
+            engine,
+            modules,
+            platform,
+            id.clone(),
+            state_dir,
+            capabilities,
+            component_limits,
+            precompiled_artifacts,
+            startup_signal,
+            inherited_fds,
+            metrics_file,
+            exit_report_file,
+            embedded,
+        );
+        Ok(executor)
+    })?;
+
+    let builder = ContainerBuilder::new(id.clone(), SyscallType::Linux)
+        .with_executor(executor)
+        .with_root_path(rootdir.clone())?;
+
+    let builder = steps.time("open_stdio", || -> anyhow::Result<_> {
+        let mut builder = builder;
+        if console_socket.is_some() {
+            // stdio is connected to the pseudo terminal by libcontainer
+            builder = builder.with_console_socket(console_socket);
+        } else {
+            if let Some(f) = stdio_open.open(&id, "stdin", cfg.get_stdin())? {
+                builder = builder.with_stdin(f);
+            }
+            if let Some(f) = stdio_open.open(&id, "stdout", cfg.get_stdout())? {
+                builder = builder.with_stdout(f);
+            }
+            if let Some(f) = stdio_open.open(&id, "stderr", cfg.get_stderr())? {
+                builder = builder.with_stderr(f);
+            }
+        }
+        Ok(builder)
+    })?;
+
+    let container = steps.time("build_container", || {
+        builder
+            .as_init(&bundle)
+            .as_sibling(true)
+            .with_systemd(false)
+            .build()
+    })?;
+
+    Ok((container, steps))
+}
+
+// Why a step of the creation of a container was interrupted.
+enum Interruption {
+    // the task was deleted
+    Cancelled,
+    // the creation overran its deadline
+    TimedOut,
+}
+
+// Runs `fut` until it completes, or the creation of the container is interrupted.
+fn run_until_interrupted<T>(
+    fut: impl Future<Output = T>,
+    token: &CancellationToken,
+    deadline: Option<Instant>,
+) -> Result<T, Interruption> {
+    async {
+        let fut = token.run_until_cancelled(fut);
+        let res = match deadline {
+            Some(deadline) => tokio::time::timeout_at(deadline, fut)
+                .await
+                .map_err(|_| Interruption::TimedOut)?,
+            None => fut.await,
+        };
+        res.ok_or(Interruption::Cancelled)
+    }
+    .block_on()
+}
+
+// Releases the lease of the content of a container when dropped, unless its creation succeeded.
+struct LeaseGuard {
+    id: String,
+    client: Option<containerd::Client>,
+}
+
+impl LeaseGuard {
+    fn new(id: &str, client: &containerd::Client) -> Self {
+        Self {
+            id: id.to_string(),
+            client: Some(client.clone()),
+        }
+    }
+
+    // Keeps the lease until the instance is deleted.
+    fn keep(mut self) {
+        self.client = None;
+    }
+}
+
+impl Drop for LeaseGuard {
+    fn drop(&mut self) {
+        if let Some(client) = self.client.take() {
+            release_lease(&self.id, &client);
+        }
+    }
+}
+
+// The content used by the instance is leased until the instance is deleted.
+// Failing to release the lease only delays the garbage collection of the content.
+fn release_lease(id: &str, client: &containerd::Client) {
+    if let Err(err) = client.release_instance_lease(id).block_on() {
+        log::warn!("failed to release the lease of {id}: {err}");
+    }
+}
+
+// The directories the state of containers can be kept in, in order of preference.
+// Without a configured `state_root`, this falls back to the runtime directory of the user,
+// e.g., with rootless containerd or on hosts where `/run/containerd` is read-only.
+fn state_roots<E: Engine>(config: &RuntimeConfig) -> Vec<PathBuf> {
+    if let Some(root) = &config.state_root {
+        return vec![root.clone()];
+    }
+    let mut roots = vec![Path::new(DEFAULT_CONTAINER_ROOT_DIR).join(E::name())];
+    if let Some(dir) = std::env::var_os("XDG_RUNTIME_DIR").filter(|dir| !dir.is_empty()) {
+        roots.push(PathBuf::from(dir).join("containerd").join(E::name()));
+    }
+    roots
+}
+
+// Unix socket paths are limited to ~108 bytes, so we can't place the
+// console socket in the bundle directory, which can be arbitrarily long.
+fn console_socket_path<E: Engine>(id: &str) -> PathBuf {
+    let hash = sha256::digest(id);
+    std::env::temp_dir().join(format!("{}-console-{}.sock", E::name(), &hash[..16]))
+}
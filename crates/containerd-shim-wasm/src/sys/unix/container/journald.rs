@@ -0,0 +1,209 @@
+//! journald log driver for the stdout and stderr of a container.
+//!
+//! On hosts that centralize logs through systemd-journald, the output of a container can be
+//! sent to the journal instead of the fifos of containerd, by setting `log_driver` to
+//! `journald` in the runtime configuration, or per container with the
+//! `runwasi.io/log-driver: journald` annotation.
+//!
+//! Each line is a journal entry, with the container id as `SYSLOG_IDENTIFIER` and
+//! `CONTAINER_ID`, and its namespace as `CONTAINER_NAMESPACE`. Lines from stdout are logged
+//! with the `info` priority, and lines from stderr with the `err` priority, e.g.:
+//!
+//! ```text
+//! journalctl SYSLOG_IDENTIFIER=<container id>
+//! ```
+//!
+//! Entries are sent with the native journal protocol, over its datagram socket.
+
+use std::io::{Result as IoResult, Write};
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use oci_spec::runtime::Spec;
+
+use super::log_uri::copy_from_fifo;
+use crate::sandbox::config::{LogDriver, RuntimeConfig};
+
+/// Annotation with the log driver of the container, `containerd` or `journald`.
+pub const LOG_DRIVER_ANNOTATION: &str = "runwasi.io/log-driver";
+
+const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// Lines longer than this are split in several entries.
+const MAX_LINE_BYTES: usize = 16 * 1024;
+
+// syslog priorities
+const PRIORITY_ERR: &str = "3";
+const PRIORITY_INFO: &str = "6";
+
+/// Returns true if the output of the container is sent to journald.
+pub fn is_enabled(spec: &Spec) -> Result<bool> {
+    let annotation = spec
+        .annotations()
+        .as_ref()
+        .and_then(|a| a.get(LOG_DRIVER_ANNOTATION));
+    let driver = match annotation {
+        Some(driver) => driver
+            .parse()
+            .with_context(|| format!("invalid {LOG_DRIVER_ANNOTATION} {driver:?}"))?,
+        None => RuntimeConfig::current().log_driver,
+    };
+    Ok(driver == LogDriver::Journald)
+}
+
+/// Starts sending the output of the container `id` to journald.
+/// Returns the paths of the fifos in `dir` the container writes its stdout and stderr to.
+pub fn start(id: &str, namespace: &str, dir: &Path) -> Result<(PathBuf, PathBuf)> {
+    start_with_socket(id, namespace, dir, Path::new(JOURNAL_SOCKET))
+}
+
+fn start_with_socket(
+    id: &str,
+    namespace: &str,
+    dir: &Path,
+    socket: &Path,
+) -> Result<(PathBuf, PathBuf)> {
+    if !socket.exists() {
+        bail!("journald is not running, {socket:?} doesn't exist");
+    }
+    let fields = |priority| {
+        [
+            ("SYSLOG_IDENTIFIER", id),
+            ("CONTAINER_ID", id),
+            ("CONTAINER_NAMESPACE", namespace),
+            ("PRIORITY", priority),
+        ]
+    };
+
+    let stdout = dir.join("journal-stdout");
+    let stderr = dir.join("journal-stderr");
+    copy_from_fifo(&stdout, JournalWriter::new(socket, &fields(PRIORITY_INFO))?)?;
+    copy_from_fifo(&stderr, JournalWriter::new(socket, &fields(PRIORITY_ERR))?)?;
+    Ok((stdout, stderr))
+}
+
+/// Sends each line written to it as a journal entry.
+struct JournalWriter {
+    socket: UnixDatagram,
+    path: PathBuf,
+    // the fields common to all the entries, serialized
+    fields: Vec<u8>,
+    line: Vec<u8>,
+}
+
+impl JournalWriter {
+    fn new(path: &Path, fields: &[(&str, &str)]) -> IoResult<Self> {
+        let mut serialized = vec![];
+        for (key, value) in fields {
+            append_field(&mut serialized, key, value.as_bytes());
+        }
+        Ok(Self {
+            socket: UnixDatagram::unbound()?,
+            path: path.to_path_buf(),
+            fields: serialized,
+            line: vec![],
+        })
+    }
+
+    fn send_line(&mut self) -> IoResult<()> {
+        let mut entry = self.fields.clone();
+        append_field(&mut entry, "MESSAGE", &self.line);
+        self.line.clear();
+        self.socket.send_to(&entry, &self.path)?;
+        Ok(())
+    }
+}
+
+impl Write for JournalWriter {
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        for chunk in buf.split_inclusive(|b| *b == b'\n') {
+            let (line, complete) = match chunk.strip_suffix(b"\n") {
+                Some(line) => (line, true),
+                None => (chunk, false),
+            };
+            for part in line.chunks(MAX_LINE_BYTES) {
+                if self.line.len() + part.len() > MAX_LINE_BYTES {
+                    self.send_line()?;
+                }
+                self.line.extend_from_slice(part);
+            }
+            if complete {
+                self.send_line()?;
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> IoResult<()> {
+        Ok(())
+    }
+}
+
+impl Drop for JournalWriter {
+    fn drop(&mut self) {
+        // the last line of the output might not end with a new line
+        if !self.line.is_empty() {
+            let _ = self.send_line();
+        }
+    }
+}
+
+// Serializes a field in the native journal protocol.
+// Values with new lines are prefixed with their length instead of separated with `=`.
+fn append_field(entry: &mut Vec<u8>, key: &str, value: &[u8]) {
+    entry.extend_from_slice(key.as_bytes());
+    if value.contains(&b'\n') {
+        entry.push(b'\n');
+        entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        entry.push(b'=');
+    }
+    entry.extend_from_slice(value);
+    entry.push(b'\n');
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::OpenOptions;
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_append_field() {
+        let mut entry = vec![];
+        append_field(&mut entry, "MESSAGE", b"hello");
+        append_field(&mut entry, "MESSAGE", b"a\nb");
+        let mut expected = b"MESSAGE=hello\nMESSAGE\n".to_vec();
+        expected.extend_from_slice(&3u64.to_le_bytes());
+        expected.extend_from_slice(b"a\nb\n");
+        assert_eq!(entry, expected);
+    }
+
+    #[test]
+    fn test_journald() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let socket = dir.path().join("journal");
+        let journal = UnixDatagram::bind(&socket)?;
+        journal.set_read_timeout(Some(Duration::from_secs(1)))?;
+
+        let (stdout, _) = start_with_socket("test", "default", dir.path(), &socket)?;
+        let mut fifo = OpenOptions::new().write(true).open(stdout)?;
+        fifo.write_all(b"hello\nwor")?;
+        fifo.write_all(b"ld")?;
+        drop(fifo);
+
+        let mut buf = vec![0; 1024];
+        let mut entries = vec![];
+        for _ in 0..2 {
+            let n = journal.recv(&mut buf)?;
+            entries.push(String::from_utf8(buf[..n].to_vec())?);
+        }
+        let fields =
+            "SYSLOG_IDENTIFIER=test\nCONTAINER_ID=test\nCONTAINER_NAMESPACE=default\nPRIORITY=6\n";
+        assert_eq!(entries[0], format!("{fields}MESSAGE=hello\n"));
+        assert_eq!(entries[1], format!("{fields}MESSAGE=world\n"));
+        Ok(())
+    }
+}
@@ -0,0 +1,291 @@
+//! containerd log URIs for the stdout and stderr of a container.
+//!
+//! containerd passes the stdio of a task as fifo paths, or as URIs when the task is created
+//! with a log URI (e.g., `ctr run --log-uri`):
+//! * `fifo:///path` is a fifo, as a plain path.
+//! * `file:///path` is a file the output is appended to, and rotated if configured
+//!   (see [`super::rotate`]). Plain paths to regular files are rotated too.
+//! * `binary:///path/to/logger?key=value` is a logging binary, started with the query as
+//!   arguments (`key value`), and `CONTAINER_ID` / `CONTAINER_NAMESPACE` in its environment.
+//!   It reads stdout from fd 3 and stderr from fd 4, and closes fd 5 once it is ready.
+//!
+//! The container writes to fifos in the bundle, which are copied to the file or the binary
+//! by the shim.
+
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::os::fd::{AsRawFd, OwnedFd, RawFd};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread;
+
+use anyhow::{bail, Context, Result};
+use nix::fcntl::{fcntl, FcntlArg, OFlag};
+use nix::sys::stat::Mode;
+use nix::unistd::{dup2, mkfifo, pipe2};
+use url::Url;
+
+use super::rotate::{RotatingFile, Rotation};
+
+/// Where the output of a stream goes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum LogTarget {
+    Path(PathBuf),
+    File(PathBuf),
+    Binary(Url),
+}
+
+fn parse(stream: &Path) -> Result<LogTarget> {
+    let Some(uri) = stream.to_str().filter(|s| s.contains("://")) else {
+        return Ok(LogTarget::Path(stream.to_path_buf()));
+    };
+    let url = Url::parse(uri).with_context(|| format!("invalid log URI {uri:?}"))?;
+    let path = PathBuf::from(url.path());
+    if path.as_os_str().is_empty() {
+        bail!("log URI {uri:?} has no path");
+    }
+    match url.scheme() {
+        "fifo" => Ok(LogTarget::Path(path)),
+        "file" => Ok(LogTarget::File(path)),
+        "binary" => Ok(LogTarget::Binary(url)),
+        scheme => bail!("unsupported log URI scheme {scheme:?}"),
+    }
+}
+
+/// Returns true if `stream` is a `file://` log URI.
+pub fn is_file(stream: &Path) -> bool {
+    matches!(parse(stream), Ok(LogTarget::File(_)))
+}
+
+/// Connects the `stdout` and `stderr` of the container `id` to their log URIs.
+/// Returns the paths the container writes its stdout and stderr to, creating fifos in `dir`
+/// for the URIs that aren't paths.
+pub fn resolve(
+    id: &str,
+    namespace: &str,
+    stdout: &Path,
+    stderr: &Path,
+    dir: &Path,
+    rotation: Option<Rotation>,
+) -> Result<(PathBuf, PathBuf)> {
+    let stdout_target = parse(stdout)?;
+    let stderr_target = parse(stderr)?;
+
+    // containerd passes the same logging binary for both streams
+    if let LogTarget::Binary(url) = &stdout_target {
+        let stdout = dir.join("log-stdout");
+        let stderr = dir.join("log-stderr");
+        let (stdout_pipe, stderr_pipe) = start_binary(url, id, namespace)?;
+        copy_from_fifo(&stdout, File::from(stdout_pipe))?;
+        if stderr_target == stdout_target {
+            copy_from_fifo(&stderr, File::from(stderr_pipe))?;
+            return Ok((stdout, stderr));
+        }
+        let stderr = resolve_stream(stderr_target, &stderr, rotation)?;
+        return Ok((stdout, stderr));
+    }
+
+    let stdout = resolve_stream(stdout_target, &dir.join("log-stdout"), rotation)?;
+    let stderr = resolve_stream(stderr_target, &dir.join("log-stderr"), rotation)?;
+    Ok((stdout, stderr))
+}
+
+fn resolve_stream(target: LogTarget, fifo: &Path, rotation: Option<Rotation>) -> Result<PathBuf> {
+    match target {
+        // the container writes to regular files directly, unless they are rotated
+        LogTarget::Path(path) if rotation.is_none() || !path.is_file() => Ok(path),
+        LogTarget::Path(path) | LogTarget::File(path) => {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            let context = || format!("failed to open log file {path:?}");
+            match rotation {
+                Some(rotation) => {
+                    let file = RotatingFile::open(&path, rotation).with_context(context)?;
+                    copy_from_fifo(fifo, file)?;
+                }
+                None => {
+                    let file = OpenOptions::new()
+                        .create(true)
+                        .append(true)
+                        .open(&path)
+                        .with_context(context)?;
+                    copy_from_fifo(fifo, file)?;
+                }
+            }
+            Ok(fifo.to_path_buf())
+        }
+        LogTarget::Binary(url) => bail!("logging binary {url} can only be used for stdout"),
+    }
+}
+
+// Creates the fifo `path`, and starts copying what is written to it into `output`.
+pub(super) fn copy_from_fifo(path: &Path, mut output: impl Write + Send + 'static) -> Result<()> {
+    let _ = std::fs::remove_file(path);
+    mkfifo(path, Mode::S_IRUSR | Mode::S_IWUSR)
+        .with_context(|| format!("failed to create fifo {path:?}"))?;
+
+    let path = path.to_path_buf();
+    thread::Builder::new()
+        .name("log-copy".to_string())
+        .spawn(move || {
+            // this blocks until the container opens the fifo for writing
+            let res = File::open(&path).and_then(|mut input| copy(&mut input, &mut output));
+            if let Err(err) = res {
+                log::error!("error copying {path:?} to its log: {err}");
+            }
+            let _ = std::fs::remove_file(&path);
+        })?;
+    Ok(())
+}
+
+fn copy(input: &mut impl Read, output: &mut impl Write) -> std::io::Result<()> {
+    loop {
+        match std::io::copy(input, output) {
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+            res => return res.map(|_| ()),
+        }
+    }
+}
+
+// Starts the logging binary, and waits for it to be ready.
+// Returns the write ends of its stdout and stderr pipes.
+fn start_binary(url: &Url, id: &str, namespace: &str) -> Result<(OwnedFd, OwnedFd)> {
+    // the pipes are moved to their fds in the binary, and not inherited otherwise
+    let (stdout_r, stdout_w) = pipe2(OFlag::O_CLOEXEC)?;
+    let (stderr_r, stderr_w) = pipe2(OFlag::O_CLOEXEC)?;
+    let (ready_r, ready_w) = pipe2(OFlag::O_CLOEXEC)?;
+
+    let args = url
+        .query_pairs()
+        .flat_map(|(key, value)| [key.to_string(), value.to_string()])
+        .filter(|arg| !arg.is_empty());
+
+    let mut cmd = Command::new(url.path());
+    cmd.args(args)
+        .env("CONTAINER_ID", id)
+        .env("CONTAINER_NAMESPACE", namespace);
+
+    let fds = [
+        stdout_r.as_raw_fd(),
+        stderr_r.as_raw_fd(),
+        ready_w.as_raw_fd(),
+    ];
+    // SAFETY: only async-signal-safe functions are called between fork and exec
+    unsafe {
+        cmd.pre_exec(move || pass_fds(&fds).map_err(std::io::Error::from));
+    }
+    let mut child = cmd
+        .spawn()
+        .with_context(|| format!("failed to start logging binary {url}"))?;
+
+    // only the binary keeps these
+    drop((stdout_r, stderr_r, ready_w));
+
+    // the binary closes the ready pipe once it is ready, or exits
+    let mut ready = File::from(ready_r);
+    let _ = ready.read(&mut [0]);
+
+    thread::Builder::new()
+        .name("log-binary".to_string())
+        .spawn(move || match child.wait() {
+            Ok(status) if status.success() => {}
+            Ok(status) => log::warn!("logging binary exited with {status}"),
+            Err(err) => log::error!("failed to wait for logging binary: {err}"),
+        })?;
+
+    Ok((stdout_w, stderr_w))
+}
+
+// Moves `fds` to the file descriptors 3, 4 and 5 in the child process.
+// This runs between fork and exec, so it must not allocate.
+fn pass_fds(fds: &[RawFd; 3]) -> nix::Result<()> {
+    // first move them out of the way, in case one of them is already one of the targets
+    let mut moved = [0; 3];
+    for (fd, moved) in fds.iter().zip(&mut moved) {
+        *moved = fcntl(*fd, FcntlArg::F_DUPFD_CLOEXEC(10))?;
+    }
+    for (target, fd) in (3..).zip(moved) {
+        // dup2 clears FD_CLOEXEC on the new descriptor
+        dup2(fd, target)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() -> Result<()> {
+        assert_eq!(
+            parse(Path::new("/run/fifo"))?,
+            LogTarget::Path("/run/fifo".into())
+        );
+        assert_eq!(
+            parse(Path::new("fifo:///run/fifo"))?,
+            LogTarget::Path("/run/fifo".into())
+        );
+        assert_eq!(
+            parse(Path::new("file:///var/log/app.log"))?,
+            LogTarget::File("/var/log/app.log".into())
+        );
+        assert!(matches!(
+            parse(Path::new("binary:///usr/bin/logger?tag=app"))?,
+            LogTarget::Binary(_)
+        ));
+        assert!(parse(Path::new("syslog://localhost")).is_err());
+
+        assert!(is_file(Path::new("file:///var/log/app.log")));
+        assert!(!is_file(Path::new("/run/fifo")));
+        assert!(!is_file(Path::new("fifo:///run/fifo")));
+        Ok(())
+    }
+
+    #[test]
+    fn test_file_log() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let log = dir.path().join("app.log");
+        std::fs::write(&log, "previous\n")?;
+
+        let uri = PathBuf::from(format!("file://{}", log.display()));
+        let (stdout, _) = resolve("test", "default", &uri, Path::new(""), dir.path(), None)?;
+
+        let mut fifo = OpenOptions::new().write(true).open(stdout)?;
+        fifo.write_all(b"hello\n")?;
+        drop(fifo);
+        thread::sleep(std::time::Duration::from_millis(100));
+
+        assert_eq!(std::fs::read_to_string(log)?, "previous\nhello\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_binary_log() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let log = dir.path().join("app.log");
+
+        // a logging binary that copies its stdout pipe to the file passed as argument
+        let logger = dir.path().join("logger");
+        std::fs::write(&logger, "#!/bin/sh\nexec 5>&-\ncat <&3 > \"$2\"\n")?;
+        std::fs::set_permissions(&logger, std::os::unix::fs::PermissionsExt::from_mode(0o755))?;
+
+        let uri = PathBuf::from(format!(
+            "binary://{}?log={}",
+            logger.display(),
+            log.display()
+        ));
+        let (stdout, stderr) = resolve("test", "default", &uri, &uri, dir.path(), None)?;
+        assert_ne!(stdout, stderr);
+
+        let mut fifo = OpenOptions::new().write(true).open(stdout)?;
+        fifo.write_all(b"hello\n")?;
+        drop(fifo);
+        let _ = OpenOptions::new().write(true).open(stderr)?;
+        thread::sleep(std::time::Duration::from_millis(200));
+
+        assert_eq!(std::fs::read_to_string(log)?, "hello\n");
+        Ok(())
+    }
+}
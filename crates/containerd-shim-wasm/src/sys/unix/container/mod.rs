@@ -1,5 +1,44 @@
 #[allow(clippy::module_inception)]
 mod container;
 
+mod admission;
+mod artifact_cache;
+mod attach;
+mod build_steps;
+mod bundle;
+mod capture;
+mod console;
+mod cpu_boost;
+mod create_request;
+mod cri_log;
+mod engine_metrics;
+mod eviction;
 mod executor;
+mod exit_watcher;
+mod image_marker;
+mod inherit_fd;
 pub mod instance;
+mod journald;
+mod log_uri;
+mod mount_options;
+mod multiplex;
+mod pump;
+mod revision;
+mod rotate;
+mod seccomp;
+mod security_label;
+mod shared_engine;
+#[cfg(feature = "opentelemetry")]
+mod trace_context;
+mod zygote_pool;
+
+pub(crate) use attach::ATTACHABLE_ANNOTATION;
+pub(crate) use capture::CAPTURE_OUTPUT_ANNOTATION;
+pub(crate) use cpu_boost::CPU_BOOST_ANNOTATION;
+pub(crate) use cri_log::LOG_LINE_FORMAT_ANNOTATION;
+pub(crate) use executor::{STDIN_DATA_ANNOTATION, STDIN_FILE_ANNOTATION};
+pub(crate) use instance::DEFAULT_CONTAINER_ROOT_DIR;
+pub(crate) use journald::LOG_DRIVER_ANNOTATION;
+pub(crate) use multiplex::COMBINED_OUTPUT_ANNOTATION;
+pub(crate) use rotate::{LOG_MAX_FILES_ANNOTATION, LOG_MAX_SIZE_ANNOTATION};
+pub(crate) use zygote_pool::ZygotePool;
@@ -0,0 +1,265 @@
+//! Checks of the mount options of a container, before it's built.
+//!
+//! The mounts of the spec are applied by libcontainer as runc does: `ro`, `nosuid`, `nodev`
+//! and `noexec` of bind mounts with a remount of the bind mount, propagation flags with a
+//! mount of their own once mounted, and the recursive flags, e.g. `rro`, with
+//! `mount_setattr(2)`. Options it doesn't know of are passed to the filesystem as data, which
+//! bind mounts ignore.
+//!
+//! The shim checks the options of every mount before the container is built, and reports the
+//! ones that won't apply in the container:
+//! * a security-relevant recursive flag, i.e. `rro`, `rnosuid`, `rnodev`, `rnoexec` or
+//!   `rnosymfollow`, on a kernel without `mount_setattr(2)`, fails the creation of the
+//!   container, as its submounts would be writable or executable,
+//! * other recursive flags on such a kernel, and unknown options of bind mounts, are logged
+//!   and ignored,
+//! * an invalid `rootfsPropagation` fails the creation of the container.
+
+use std::sync::OnceLock;
+
+use oci_spec::runtime::{Mount, Spec};
+
+use crate::sandbox::Error as SandboxError;
+
+// the options libcontainer turns into mount flags, as runc does
+const FLAGS: &[&str] = &[
+    "defaults",
+    "ro",
+    "rw",
+    "suid",
+    "nosuid",
+    "dev",
+    "nodev",
+    "exec",
+    "noexec",
+    "sync",
+    "async",
+    "dirsync",
+    "remount",
+    "mand",
+    "nomand",
+    "atime",
+    "noatime",
+    "diratime",
+    "nodiratime",
+    "relatime",
+    "norelatime",
+    "strictatime",
+    "nostrictatime",
+    "bind",
+    "rbind",
+    "idmap",
+    "ridmap",
+    "tmpcopyup",
+];
+
+const PROPAGATION: &[&str] = &[
+    "private",
+    "rprivate",
+    "shared",
+    "rshared",
+    "slave",
+    "rslave",
+    "unbindable",
+    "runbindable",
+];
+
+// the recursive flags applied with mount_setattr, and whether they restrict the mount
+const RECURSIVE: &[(&str, bool)] = &[
+    ("rro", true),
+    ("rnosuid", true),
+    ("rnodev", true),
+    ("rnoexec", true),
+    ("rnosymfollow", true),
+    ("rrw", false),
+    ("rsuid", false),
+    ("rdev", false),
+    ("rexec", false),
+    ("rsymfollow", false),
+    ("ratime", false),
+    ("rnoatime", false),
+    ("rdiratime", false),
+    ("rnodiratime", false),
+    ("rrelatime", false),
+    ("rnorelatime", false),
+    ("rstrictatime", false),
+    ("rnostrictatime", false),
+];
+
+/// An option of a mount that won't apply in the container.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Unapplied {
+    pub destination: String,
+    pub option: String,
+    pub reason: &'static str,
+}
+
+/// Checks the mount options of the spec of the container `id`, logging the ones that won't
+/// apply. Fails if a security-relevant one won't.
+pub fn check(id: &str, spec: &Spec) -> Result<(), SandboxError> {
+    let unapplied = unapplied(spec, mount_setattr_supported())?;
+    for u in &unapplied {
+        log::warn!(
+            "mount option {:?} of {} in container {id} is ignored: {}",
+            u.option,
+            u.destination,
+            u.reason
+        );
+    }
+    Ok(())
+}
+
+fn unapplied(spec: &Spec, mount_setattr: bool) -> Result<Vec<Unapplied>, SandboxError> {
+    if let Some(propagation) = spec
+        .linux()
+        .as_ref()
+        .and_then(|linux| linux.rootfs_propagation().as_ref())
+    {
+        if !PROPAGATION.contains(&propagation.as_str()) {
+            return Err(SandboxError::InvalidArgument(format!(
+                "invalid rootfs propagation {propagation:?}"
+            )));
+        }
+    }
+
+    let mut unapplied = vec![];
+    for mount in spec.mounts().iter().flatten() {
+        let destination = mount.destination().to_string_lossy().to_string();
+        let bind = is_bind(mount);
+        for option in mount.options().iter().flatten() {
+            let option = option.as_str();
+            if FLAGS.contains(&option) || PROPAGATION.contains(&option) {
+                continue;
+            }
+            let reason = match RECURSIVE.iter().find(|(name, _)| *name == option) {
+                Some(_) if mount_setattr => continue,
+                Some((_, true)) => {
+                    return Err(SandboxError::FailedPrecondition(format!(
+                        "mount option {option:?} of {destination} needs mount_setattr, which the kernel doesn't support"
+                    )));
+                }
+                Some((_, false)) => "the kernel doesn't support mount_setattr",
+                None if bind => "bind mounts don't take filesystem options",
+                None => continue,
+            };
+            unapplied.push(Unapplied {
+                destination: destination.clone(),
+                option: option.to_string(),
+                reason,
+            });
+        }
+    }
+    Ok(unapplied)
+}
+
+fn is_bind(mount: &Mount) -> bool {
+    mount.typ().as_deref() == Some("bind")
+        || mount
+            .options()
+            .iter()
+            .flatten()
+            .any(|o| o == "bind" || o == "rbind")
+}
+
+fn mount_setattr_supported() -> bool {
+    static SUPPORTED: OnceLock<bool> = OnceLock::new();
+    *SUPPORTED.get_or_init(|| {
+        // SAFETY: mount_setattr with an invalid fd and no attributes changes nothing, and only
+        // fails with ENOSYS on kernels without it
+        let res = unsafe {
+            libc::syscall(
+                libc::SYS_mount_setattr,
+                -1,
+                std::ptr::null::<libc::c_char>(),
+                0,
+                std::ptr::null::<libc::c_void>(),
+                0,
+            )
+        };
+        !(res == -1 && std::io::Error::last_os_error().raw_os_error() == Some(libc::ENOSYS))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use oci_spec::runtime::{LinuxBuilder, MountBuilder, SpecBuilder};
+
+    use super::*;
+
+    fn spec(mounts: &[(&str, &str, &[&str])]) -> Spec {
+        let mounts = mounts
+            .iter()
+            .map(|(destination, typ, options)| {
+                MountBuilder::default()
+                    .destination(destination)
+                    .typ(typ)
+                    .options(options.iter().map(|o| o.to_string()).collect::<Vec<_>>())
+                    .build()
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+        SpecBuilder::default().mounts(mounts).build().unwrap()
+    }
+
+    #[test]
+    fn test_unapplied_mount_options() {
+        let spec = spec(&[
+            (
+                "/data",
+                "bind",
+                &["rbind", "ro", "nosuid", "nodev", "noexec", "rslave"],
+            ),
+            ("/tmp", "tmpfs", &["nosuid", "size=65536k", "mode=755"]),
+            ("/cache", "none", &["bind", "size=1k", "rnoatime"]),
+        ]);
+
+        assert_eq!(
+            unapplied(&spec, true).unwrap(),
+            vec![Unapplied {
+                destination: "/cache".to_string(),
+                option: "size=1k".to_string(),
+                reason: "bind mounts don't take filesystem options",
+            }]
+        );
+
+        let unapplied_options = unapplied(&spec, false)
+            .unwrap()
+            .into_iter()
+            .map(|u| u.option)
+            .collect::<Vec<_>>();
+        assert_eq!(unapplied_options, ["size=1k", "rnoatime"]);
+    }
+
+    #[test]
+    fn test_security_mount_options_fail() {
+        let spec = spec(&[("/data", "bind", &["rbind", "rro"])]);
+        assert!(unapplied(&spec, true).unwrap().is_empty());
+        assert!(matches!(
+            unapplied(&spec, false),
+            Err(SandboxError::FailedPrecondition(_))
+        ));
+    }
+
+    #[test]
+    fn test_rootfs_propagation() {
+        let mut spec = spec(&[]);
+        spec.set_linux(Some(
+            LinuxBuilder::default()
+                .rootfs_propagation("rslave")
+                .build()
+                .unwrap(),
+        ));
+        assert!(unapplied(&spec, false).is_ok());
+
+        spec.set_linux(Some(
+            LinuxBuilder::default()
+                .rootfs_propagation("everywhere")
+                .build()
+                .unwrap(),
+        ));
+        assert!(matches!(
+            unapplied(&spec, false),
+            Err(SandboxError::InvalidArgument(_))
+        ));
+    }
+}
@@ -0,0 +1,230 @@
+//! Multiplexing of the stdout and stderr of a container into a single stream.
+//!
+//! When the `runwasi.io/combined-output` annotation is `"true"`, the stdout and stderr of the
+//! container are written to the stdout fifo of the task using docker's stream framing:
+//! each chunk is prefixed by an 8 bytes header `[stream, 0, 0, 0, size]`, where `stream` is
+//! `1` for stdout and `2` for stderr, and `size` is the length of the chunk as a big endian `u32`.
+//!
+//! The container writes to two intermediate fifos, read by a thread each. The chunks of a
+//! stream are framed in the order they're written, and frames are never interleaved, but the
+//! chunks written to stdout and stderr in a short time can be framed in any order, as the two
+//! streams are read concurrently, as with docker.
+
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::os::unix::fs::OpenOptionsExt as _;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use nix::sys::stat::Mode;
+use nix::unistd::mkfifo;
+use oci_spec::runtime::Spec;
+
+use crate::sys::stdio::open;
+
+/// Annotation to write stdout and stderr to a single stream, using docker's stream framing.
+pub const COMBINED_OUTPUT_ANNOTATION: &str = "runwasi.io/combined-output";
+
+const STDOUT_STREAM: u8 = 1;
+const STDERR_STREAM: u8 = 2;
+
+const BUFFER_SIZE: usize = 32 * 1024;
+
+const JOIN_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Returns true if the combined output is requested for the container.
+pub fn is_enabled(spec: &Spec) -> bool {
+    spec.annotations()
+        .as_ref()
+        .and_then(|a| a.get(COMBINED_OUTPUT_ANNOTATION))
+        .is_some_and(|v| v == "true")
+}
+
+/// The threads copying the stdout and stderr of a container into its combined output.
+pub struct Multiplexer {
+    stdout: PathBuf,
+    stderr: PathBuf,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl Multiplexer {
+    /// The paths of the stdout and stderr fifos of the container.
+    pub fn fifos(&self) -> (PathBuf, PathBuf) {
+        (self.stdout.clone(), self.stderr.clone())
+    }
+
+    /// Waits for the streams to be copied, once the container exited.
+    /// The threads of the streams the container never opened are stopped.
+    pub fn join(mut self) {
+        let fifos = [self.stdout.clone(), self.stderr.clone()];
+        for (path, thread) in fifos.iter().zip(self.threads.drain(..)) {
+            while !thread.is_finished() {
+                stop_waiting(path);
+                thread::sleep(JOIN_POLL_INTERVAL);
+            }
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for Multiplexer {
+    // the threads of a container that wasn't created would wait for it forever
+    fn drop(&mut self) {
+        for (path, thread) in [&self.stdout, &self.stderr].into_iter().zip(&self.threads) {
+            if !thread.is_finished() {
+                stop_waiting(path);
+            }
+        }
+    }
+}
+
+// A thread still waiting for the container to open its fifo reads EOF once the fifo is
+// opened and closed here.
+fn stop_waiting(fifo: &Path) {
+    let _ = OpenOptions::new()
+        .write(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(fifo);
+}
+
+/// Creates the stdout and stderr fifos of the container in `dir`, and starts copying
+/// what is written to them into `output`.
+pub fn start(output: impl AsRef<Path>, dir: impl AsRef<Path>) -> Result<Multiplexer> {
+    let output = open(output.as_ref())
+        .with_context(|| format!("failed to open combined output {:?}", output.as_ref()))?;
+    let output = Arc::new(Mutex::new(output));
+
+    let stdout = dir.as_ref().join("combined-stdout");
+    let stderr = dir.as_ref().join("combined-stderr");
+    let mut threads = Vec::with_capacity(2);
+    for (stream, path) in [(STDOUT_STREAM, &stdout), (STDERR_STREAM, &stderr)] {
+        let _ = std::fs::remove_file(path);
+        mkfifo(path, Mode::S_IRUSR | Mode::S_IWUSR)
+            .with_context(|| format!("failed to create fifo {path:?}"))?;
+
+        let path = path.clone();
+        let output = output.clone();
+        let thread = thread::Builder::new()
+            .name(format!("combined-output-{stream}"))
+            .spawn(move || {
+                // this blocks until the container opens the fifo for writing
+                let res = File::open(&path).and_then(|input| copy(input, stream, &output));
+                if let Err(err) = res {
+                    log::error!("error multiplexing {path:?}: {err}");
+                }
+                let _ = std::fs::remove_file(&path);
+            })?;
+        threads.push(thread);
+    }
+
+    Ok(Multiplexer {
+        stdout,
+        stderr,
+        threads,
+    })
+}
+
+// Copies `input` into `output` until the container closes it, framing each chunk.
+fn copy(mut input: impl Read, stream: u8, output: &Mutex<impl Write>) -> std::io::Result<()> {
+    let mut buf = vec![0; BUFFER_SIZE];
+    loop {
+        let n = match input.read(&mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(n) => n,
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        write_frame(&mut *output.lock().unwrap(), stream, &buf[..n])?;
+    }
+}
+
+fn write_frame(output: &mut impl Write, stream: u8, data: &[u8]) -> std::io::Result<()> {
+    let size = u32::try_from(data.len()).expect("chunks are smaller than the buffer");
+    let mut frame = Vec::with_capacity(8 + data.len());
+    frame.extend([stream, 0, 0, 0]);
+    frame.extend(size.to_be_bytes());
+    frame.extend(data);
+    output.write_all(&frame)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use oci_spec::runtime::SpecBuilder;
+
+    use super::*;
+
+    #[test]
+    fn test_is_enabled() -> Result<()> {
+        let spec = SpecBuilder::default().build()?;
+        assert!(!is_enabled(&spec));
+
+        let spec = SpecBuilder::default()
+            .annotations(HashMap::from([(
+                COMBINED_OUTPUT_ANNOTATION.to_string(),
+                "true".to_string(),
+            )]))
+            .build()?;
+        assert!(is_enabled(&spec));
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_frame() -> Result<()> {
+        let mut output = vec![];
+        write_frame(&mut output, STDERR_STREAM, b"oops\n")?;
+        assert_eq!(output, b"\x02\0\0\0\0\0\0\x05oops\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_combined_output() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let output_path = dir.path().join("output");
+        File::create(&output_path)?;
+
+        let multiplexer = start(&output_path, dir.path())?;
+        let (stdout, stderr) = multiplexer.fifos();
+
+        let mut stdout = OpenOptions::new().write(true).open(stdout)?;
+        let mut stderr = OpenOptions::new().write(true).open(stderr)?;
+        stdout.write_all(b"hello")?;
+        stderr.write_all(b"world")?;
+        drop((stdout, stderr));
+        multiplexer.join();
+
+        // the streams are read concurrently, so their frames can come in any order
+        let mut output = std::fs::read(output_path)?;
+        let mut frames = vec![];
+        while !output.is_empty() {
+            let size = u32::from_be_bytes(output[4..8].try_into()?) as usize;
+            frames.push((output[0], output[8..8 + size].to_vec()));
+            output.drain(..8 + size);
+        }
+        frames.sort();
+        assert_eq!(
+            frames,
+            [
+                (STDOUT_STREAM, b"hello".to_vec()),
+                (STDERR_STREAM, b"world".to_vec())
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_join_without_writers() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let output_path = dir.path().join("output");
+        File::create(&output_path)?;
+
+        // the container never opened its fifos
+        start(&output_path, dir.path())?.join();
+        assert!(std::fs::read(output_path)?.is_empty());
+        Ok(())
+    }
+}
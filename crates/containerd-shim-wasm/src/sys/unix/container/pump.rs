@@ -0,0 +1,467 @@
+//! Buffering of the output of a container between the container and the fifos of containerd.
+//!
+//! By default, the container writes directly to the fifos of containerd, and a burst of output
+//! blocks the engine as soon as the pipe buffer is full and containerd is slow to read it.
+//! When the `stdio` section of the runtime configuration is set, the container writes to an
+//! intermediate fifo instead, and a pump in the shim copies it to containerd:
+//! * output is buffered in memory up to `max_buffer_bytes`, then to a file in `spill_dir`,
+//!   up to `max_spill_bytes`.
+//! * once the buffers are full, the container blocks, or with `on_full: drop`, its writes
+//!   never block and the output that doesn't fit is discarded. Dropped bytes are counted.
+//! * the buffers of both pipes are resized to `pipe_size_bytes` with `F_SETPIPE_SZ`.
+//! * the time spent blocked writing to containerd is logged when the stream is closed,
+//!   and slow writes are logged as they happen.
+//! * the time from the start of the container to its first output is recorded in its
+//!   [`Timings`].
+//!
+//! The output can also be copied to more sinks than containerd, see [`tee`].
+
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use nix::fcntl::{fcntl, FcntlArg};
+use nix::sys::stat::Mode;
+use nix::unistd::mkfifo;
+
+use crate::sandbox::config::{OverflowPolicy, StdioConfig};
+use crate::sandbox::panics;
+use crate::sandbox::timings::Timings;
+use crate::sys::stdio::open;
+
+pub mod tee;
+
+const BUFFER_SIZE: usize = 32 * 1024;
+
+/// Writes to containerd blocking longer than this are logged.
+const SLOW_WRITE: Duration = Duration::from_secs(1);
+
+/// What happened to the output of a stream.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PumpStats {
+    /// Bytes copied to containerd.
+    pub bytes: u64,
+    /// Bytes that were buffered on disk.
+    pub spilled_bytes: u64,
+    /// Bytes that were discarded because the buffers were full.
+    pub dropped_bytes: u64,
+    /// Total time spent blocked writing to containerd.
+    pub blocked: Duration,
+    /// Longest single write to containerd.
+    pub max_blocked: Duration,
+}
+
+/// Creates the fifo `name` for the container in `dir`, and starts pumping what is written
+/// to it into `output`.
+/// Returns the path of the fifo.
+pub fn start(
+    id: &str,
+    name: &str,
+    output: impl AsRef<Path>,
+    dir: impl AsRef<Path>,
+    cfg: &StdioConfig,
+) -> Result<PathBuf> {
+    let output = open(output.as_ref())
+        .with_context(|| format!("failed to open output {:?}", output.as_ref()))?;
+    if let Some(size) = cfg.pipe_size_bytes {
+        set_pipe_size(&output, size);
+    }
+
+    let spill = match &cfg.spill_dir {
+        Some(spill_dir) => Some(spill_file(spill_dir, &format!("{id}-{name}"))?),
+        None => None,
+    };
+    let buffer = Arc::new(
+        Buffer::new(cfg.max_buffer_bytes, spill, cfg.on_full).with_spill_limit(cfg.max_spill_bytes),
+    );
+
+    let path = dir.as_ref().join(format!("pumped-{name}"));
+    let _ = std::fs::remove_file(&path);
+    mkfifo(&path, Mode::S_IRUSR | Mode::S_IWUSR)
+        .with_context(|| format!("failed to create fifo {path:?}"))?;
+
+    let pipe_size = cfg.pipe_size_bytes;
+    thread::Builder::new()
+        .name(format!("{name}-pump-read"))
+        .spawn({
+            let path = path.clone();
+            let buffer = buffer.clone();
+            let stream = format!("{name} of container {id}");
+            let id = id.to_string();
+            move || {
+                // this blocks until the container opens the fifo for writing
+                let res = panics::guarded(format!("the reader of the {stream}"), || {
+                    File::open(&path).and_then(|input| {
+                        if let Some(size) = pipe_size {
+                            set_pipe_size(&input, size);
+                        }
+                        fill(input, &buffer, &stream, || {
+                            Timings::global().first_output(&id)
+                        })
+                    })
+                });
+                if let Some(Err(err)) = res {
+                    log::error!("error reading {path:?}: {err}");
+                }
+                buffer.close();
+                let _ = std::fs::remove_file(&path);
+            }
+        })?;
+
+    let id = id.to_string();
+    let name = name.to_string();
+    thread::Builder::new()
+        .name(format!("{name}-pump-write"))
+        .spawn(move || {
+            let stream = format!("{name} of container {id}");
+            let context = format!("the writer of the {stream}");
+            let Some(stats) = panics::guarded(context, || drain(&buffer, output, &stream)) else {
+                // discard the rest of the output, so that the container isn't blocked writing it
+                drain(&buffer, std::io::sink(), &stream);
+                return;
+            };
+            log::info!(
+                "{name} of container {id}: {} bytes written, {} bytes spilled to disk, {} bytes dropped, blocked for {:?} (longest write {:?})",
+                stats.bytes,
+                stats.spilled_bytes,
+                stats.dropped_bytes,
+                stats.blocked,
+                stats.max_blocked,
+            );
+        })?;
+
+    Ok(path)
+}
+
+fn set_pipe_size(pipe: &File, size: u32) {
+    let size = i32::try_from(size).unwrap_or(i32::MAX);
+    if let Err(err) = fcntl(pipe.as_raw_fd(), FcntlArg::F_SETPIPE_SZ(size)) {
+        log::warn!("failed to set the pipe buffer size to {size}: {err}");
+    }
+}
+
+// Creates an anonymous file in `dir` to spill output to.
+fn spill_file(dir: &Path, name: &str) -> Result<File> {
+    std::fs::create_dir_all(dir).with_context(|| format!("failed to create {dir:?}"))?;
+    let path = dir.join(format!("{name}.spill"));
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&path)
+        .with_context(|| format!("failed to create spill file {path:?}"))?;
+    // unlink the file, so that it is freed once the pump closes it
+    std::fs::remove_file(&path)?;
+    Ok(file)
+}
+
+// Reads `input` into `buffer` until the container closes it.
+// Pushes what is read from `input` to `buffer`, calling `first_output` once something is read.
+fn fill(
+    mut input: impl Read,
+    buffer: &Buffer,
+    stream: &str,
+    first_output: impl FnOnce(),
+) -> std::io::Result<()> {
+    let mut buf = vec![0; BUFFER_SIZE];
+    let mut dropping = false;
+    let mut first_output = Some(first_output);
+    loop {
+        let n = match input.read(&mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(n) => n,
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        if let Some(first_output) = first_output.take() {
+            first_output();
+        }
+        // only log when the buffer starts and stops overflowing, not for every chunk
+        let dropped = !buffer.push(&buf[..n])?;
+        if dropped && !dropping {
+            log::warn!("the buffers of {stream} are full, dropping output");
+        } else if !dropped && dropping {
+            let total = buffer.state.lock().unwrap().dropped_bytes;
+            log::warn!("stopped dropping {stream}, {total} bytes dropped so far");
+        }
+        dropping = dropped;
+    }
+}
+
+// Writes what is pushed to `buffer` into `output` until it is closed and empty.
+fn drain(buffer: &Buffer, mut output: impl Write, stream: &str) -> PumpStats {
+    let mut stats = PumpStats::default();
+    while let Some(chunk) = buffer.pop() {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(err) => {
+                log::error!("error reading the spilled {stream}: {err}");
+                break;
+            }
+        };
+        let start = Instant::now();
+        if let Err(err) = output.write_all(&chunk) {
+            log::error!("error writing {stream}, discarding the rest of it: {err}");
+            // keep the container from blocking on a full buffer
+            while buffer.pop().is_some() {}
+            break;
+        }
+        let blocked = start.elapsed();
+        if blocked > SLOW_WRITE {
+            log::warn!(
+                "writing {} bytes of {stream} blocked for {blocked:?}",
+                chunk.len()
+            );
+        }
+        stats.bytes += chunk.len() as u64;
+        stats.blocked += blocked;
+        stats.max_blocked = stats.max_blocked.max(blocked);
+    }
+    let state = buffer.state.lock().unwrap();
+    stats.spilled_bytes = state.spilled_bytes;
+    stats.dropped_bytes = state.dropped_bytes;
+    stats
+}
+
+// A FIFO of output chunks, held in memory up to a limit, and then in a spill file if any,
+// up to another limit.
+struct Buffer {
+    limit: usize,
+    spill_limit: u64,
+    on_full: OverflowPolicy,
+    state: Mutex<BufferState>,
+    changed: Condvar,
+}
+
+#[derive(Default)]
+struct BufferState {
+    memory: VecDeque<Vec<u8>>,
+    memory_len: usize,
+    spill: Option<File>,
+    // the unread data of the spill file is `spill_read..spill_write`
+    spill_read: u64,
+    spill_write: u64,
+    spilled_bytes: u64,
+    dropped_bytes: u64,
+    closed: bool,
+}
+
+impl Buffer {
+    fn new(limit: usize, spill: Option<File>, on_full: OverflowPolicy) -> Self {
+        Self {
+            limit,
+            spill_limit: u64::MAX,
+            on_full,
+            state: Mutex::new(BufferState {
+                spill,
+                ..Default::default()
+            }),
+            changed: Condvar::new(),
+        }
+    }
+
+    // Limits the size of the spill file to `spill_limit` bytes.
+    fn with_spill_limit(mut self, spill_limit: u64) -> Self {
+        self.spill_limit = spill_limit;
+        self
+    }
+
+    // Appends a chunk, blocking while the buffers are full, unless full buffers drop output.
+    // Returns false if the chunk was dropped.
+    fn push(&self, chunk: &[u8]) -> std::io::Result<bool> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            let spilling = state.spill_write > state.spill_read;
+            let fits = state.memory_len + chunk.len() <= self.limit || state.memory.is_empty();
+            if !spilling && fits {
+                state.memory_len += chunk.len();
+                state.memory.push_back(chunk.to_vec());
+                break;
+            }
+            // keep the chunks in order: once spilling, everything goes to the spill file
+            // until it is drained
+            let spill_fits = state.spill_write + chunk.len() as u64 <= self.spill_limit;
+            if let (Some(spill), true) = (&state.spill, spill_fits) {
+                spill.write_all_at(chunk, state.spill_write)?;
+                state.spill_write += chunk.len() as u64;
+                state.spilled_bytes += chunk.len() as u64;
+                break;
+            }
+            if self.on_full == OverflowPolicy::Drop {
+                state.dropped_bytes += chunk.len() as u64;
+                return Ok(false);
+            }
+            state = self.changed.wait(state).unwrap();
+        }
+        self.changed.notify_all();
+        Ok(true)
+    }
+
+    // Returns the next chunk, or None once the buffer is closed and empty.
+    fn pop(&self) -> Option<std::io::Result<Vec<u8>>> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(chunk) = state.memory.pop_front() {
+                state.memory_len -= chunk.len();
+                self.changed.notify_all();
+                return Some(Ok(chunk));
+            }
+            if state.spill_write > state.spill_read {
+                let chunk = read_spilled(&mut state);
+                self.changed.notify_all();
+                return Some(chunk);
+            }
+            if state.closed {
+                return None;
+            }
+            state = self.changed.wait(state).unwrap();
+        }
+    }
+
+    fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.changed.notify_all();
+    }
+}
+
+fn read_spilled(state: &mut BufferState) -> std::io::Result<Vec<u8>> {
+    let spill = state
+        .spill
+        .as_ref()
+        .expect("only spill files have spilled data");
+    let len = (state.spill_write - state.spill_read).min(BUFFER_SIZE as u64) as usize;
+    let mut chunk = vec![0; len];
+    spill.read_exact_at(&mut chunk, state.spill_read)?;
+    state.spill_read += len as u64;
+    if state.spill_read == state.spill_write {
+        // reuse the file from the start for the next spill
+        spill.set_len(0)?;
+        state.spill_read = 0;
+        state.spill_write = 0;
+    }
+    Ok(chunk)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn drain_all(buffer: &Buffer) -> Vec<u8> {
+        buffer.close();
+        let mut output = vec![];
+        drain(buffer, &mut output, "test");
+        output
+    }
+
+    #[test]
+    fn test_buffer_in_memory() -> Result<()> {
+        let buffer = Buffer::new(1024, None, OverflowPolicy::Block);
+        buffer.push(b"hello ")?;
+        buffer.push(b"world")?;
+        assert_eq!(drain_all(&buffer), b"hello world");
+        Ok(())
+    }
+
+    #[test]
+    fn test_buffer_spills_in_order() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let buffer = Buffer::new(
+            4,
+            Some(spill_file(dir.path(), "test")?),
+            OverflowPolicy::Block,
+        );
+        for chunk in [&b"ab"[..], b"cd", b"ef", b"gh"] {
+            buffer.push(chunk)?;
+        }
+        assert_eq!(buffer.state.lock().unwrap().spilled_bytes, 4);
+        // the spill file is anonymous
+        assert_eq!(std::fs::read_dir(dir.path())?.count(), 0);
+
+        assert_eq!(buffer.pop().unwrap()?, b"ab");
+        // new output goes after the spilled output
+        buffer.push(b"ij")?;
+        assert_eq!(drain_all(&buffer), b"cdefghij");
+        Ok(())
+    }
+
+    #[test]
+    fn test_buffer_blocks_without_spill() -> Result<()> {
+        let buffer = Arc::new(Buffer::new(4, None, OverflowPolicy::Block));
+        buffer.push(b"abcd")?;
+
+        let writer = thread::spawn({
+            let buffer = buffer.clone();
+            move || buffer.push(b"ef")
+        });
+        thread::sleep(Duration::from_millis(100));
+        assert!(!writer.is_finished());
+
+        assert_eq!(buffer.pop().unwrap()?, b"abcd");
+        writer.join().unwrap()?;
+        assert_eq!(drain_all(&buffer), b"ef");
+        Ok(())
+    }
+
+    #[test]
+    fn test_buffer_drops_when_full() -> Result<()> {
+        let buffer = Buffer::new(4, None, OverflowPolicy::Drop);
+        assert!(buffer.push(b"abcd")?);
+        assert!(!buffer.push(b"ef")?);
+        assert_eq!(buffer.state.lock().unwrap().dropped_bytes, 2);
+
+        assert_eq!(buffer.pop().unwrap()?, b"abcd");
+        assert!(buffer.push(b"gh")?);
+        assert_eq!(drain_all(&buffer), b"gh");
+        Ok(())
+    }
+
+    #[test]
+    fn test_buffer_spill_is_bounded() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let buffer = Buffer::new(
+            4,
+            Some(spill_file(dir.path(), "test")?),
+            OverflowPolicy::Drop,
+        )
+        .with_spill_limit(4);
+        assert!(buffer.push(b"abcd")?);
+        assert!(buffer.push(b"ef")?);
+        assert!(buffer.push(b"gh")?);
+        // the spill file is full
+        assert!(!buffer.push(b"ij")?);
+        assert_eq!(buffer.state.lock().unwrap().spilled_bytes, 4);
+        assert_eq!(buffer.state.lock().unwrap().dropped_bytes, 2);
+
+        assert_eq!(drain_all(&buffer), b"abcdefgh");
+        Ok(())
+    }
+
+    #[test]
+    fn test_pump() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let output = dir.path().join("output");
+        File::create(&output)?;
+
+        let cfg = StdioConfig {
+            spill_dir: Some(dir.path().join("spill")),
+            ..Default::default()
+        };
+        let fifo = start("test", "stdout", &output, dir.path(), &cfg)?;
+
+        let mut input = OpenOptions::new().write(true).open(fifo)?;
+        input.write_all(b"hello")?;
+        drop(input);
+        thread::sleep(Duration::from_millis(100));
+
+        assert_eq!(std::fs::read(output)?, b"hello");
+        Ok(())
+    }
+}
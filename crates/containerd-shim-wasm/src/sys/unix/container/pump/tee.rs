@@ -0,0 +1,311 @@
+//! Duplication of the output of a container to additional sinks.
+//!
+//! When `tee` is set in the runtime configuration, the stdout and stderr of the containers in
+//! its namespaces are copied to its sinks on top of the fifos of containerd, e.g., to debug
+//! a container without disturbing the collection of its logs by the kubelet.
+//! Sinks are URIs, where `{namespace}`, `{id}` and `{stream}` (`stdout` or `stderr`) are
+//! replaced by the namespace and id of the container, and the name of the stream:
+//! * `file:///var/log/wasm/{id}-{stream}.log` appends the output to a file.
+//! * `unix:///run/wasm-debug.sock` connects to a unix stream socket and writes the output to it.
+//!
+//! containerd always gets the whole output. Each sink is written by its own thread, from a
+//! buffer of the log pump of up to 1MiB: the output that doesn't fit while a sink is slow is
+//! dropped for that sink, so that it never blocks containerd or the other sinks. A sink that
+//! fails is logged and dropped, without affecting the other sinks.
+//!
+//! The output captured as per `capture` is copied to its files the same way, see
+//! [`capture`](super::super::capture).
+
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+use anyhow::{bail, Context, Result};
+use nix::sys::stat::Mode;
+use nix::unistd::mkfifo;
+use url::Url;
+
+use super::{drain, Buffer, PumpStats, BUFFER_SIZE};
+use crate::sandbox::config::{OverflowPolicy, TeeConfig};
+use crate::sys::stdio::open;
+
+/// Output buffered for a sink while it is slow, before it is dropped.
+const SINK_BUFFER_SIZE: usize = 1024 * 1024;
+
+/// Opens the sinks of `cfg` for the `stream` of the container.
+pub fn sinks(id: &str, namespace: &str, stream: &str, cfg: &TeeConfig) -> Vec<Sink> {
+    let mut sinks = vec![];
+    for sink in &cfg.sinks {
+        let uri = sink
+            .replace("{namespace}", namespace)
+            .replace("{id}", id)
+            .replace("{stream}", stream);
+        // a sink that isn't available doesn't prevent the container from starting
+        match Sink::open(&uri) {
+            Ok(sink) => sinks.push(sink),
+            Err(err) => log::warn!("not copying {stream} of container {id} to {uri}: {err:#}"),
+        }
+    }
+    sinks
+}
+
+/// Creates the fifo `tee-{stream}` for the container in `dir`, and starts copying what is
+/// written to it into `output` and `sinks`.
+/// Returns the path of the fifo.
+pub fn start(
+    stream: &str,
+    output: impl AsRef<Path>,
+    dir: impl AsRef<Path>,
+    sinks: Vec<Sink>,
+) -> Result<PathBuf> {
+    let output = open(output.as_ref())
+        .with_context(|| format!("failed to open output {:?}", output.as_ref()))?;
+
+    let path = dir.as_ref().join(format!("tee-{stream}"));
+    let _ = std::fs::remove_file(&path);
+    mkfifo(&path, Mode::S_IRUSR | Mode::S_IWUSR)
+        .with_context(|| format!("failed to create fifo {path:?}"))?;
+
+    let stream = stream.to_string();
+    thread::Builder::new()
+        .name(format!("{stream}-tee"))
+        .spawn({
+            let path = path.clone();
+            move || {
+                let mut queues: Vec<_> = sinks
+                    .into_iter()
+                    .filter_map(|sink| {
+                        let uri = sink.uri.clone();
+                        SinkQueue::start(sink, SINK_BUFFER_SIZE)
+                            .inspect_err(|err| log::warn!("not copying {stream} to {uri}: {err}"))
+                            .ok()
+                    })
+                    .collect();
+                // this blocks until the container opens the fifo for writing
+                let res = File::open(&path).and_then(|input| copy(input, output, &mut queues));
+                if let Err(err) = res {
+                    log::error!("error copying {stream}: {err}");
+                }
+                let _ = std::fs::remove_file(&path);
+                for queue in queues {
+                    queue.finish();
+                }
+            }
+        })?;
+
+    Ok(path)
+}
+
+// Copies `input` into `output` and the queues of the sinks until the container closes it.
+fn copy(
+    mut input: impl Read,
+    mut output: impl Write,
+    queues: &mut [SinkQueue],
+) -> std::io::Result<()> {
+    let mut buf = vec![0; BUFFER_SIZE];
+    loop {
+        let n = match input.read(&mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(n) => n,
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        output.write_all(&buf[..n])?;
+        for queue in queues.iter_mut() {
+            queue.push(&buf[..n]);
+        }
+    }
+}
+
+// The output waiting to be written to a sink by its thread.
+struct SinkQueue {
+    uri: String,
+    buffer: Arc<Buffer>,
+    thread: JoinHandle<PumpStats>,
+    dropping: bool,
+}
+
+impl SinkQueue {
+    fn start(sink: Sink, limit: usize) -> std::io::Result<Self> {
+        let Sink { uri, writer } = sink;
+        let buffer = Arc::new(Buffer::new(limit, None, OverflowPolicy::Drop));
+        let thread = thread::Builder::new().name("tee-sink".to_string()).spawn({
+            let buffer = buffer.clone();
+            let uri = uri.clone();
+            move || drain(&buffer, writer, &uri)
+        })?;
+        Ok(Self {
+            uri,
+            buffer,
+            thread,
+            dropping: false,
+        })
+    }
+
+    // Queues a chunk for the sink, dropping it if the sink is too far behind.
+    fn push(&mut self, chunk: &[u8]) {
+        // without a spill file, pushing never fails
+        let dropped = !self.buffer.push(chunk).unwrap_or(false);
+        // only log when the sink starts falling behind, not for every chunk
+        if dropped && !self.dropping {
+            log::warn!("{} is too slow, dropping output", self.uri);
+        }
+        self.dropping = dropped;
+    }
+
+    // Waits for the queued output to be written to the sink.
+    fn finish(self) -> PumpStats {
+        self.buffer.close();
+        let stats = self.thread.join().unwrap_or_default();
+        if stats.dropped_bytes > 0 {
+            log::warn!("{} bytes dropped for {}", stats.dropped_bytes, self.uri);
+        }
+        stats
+    }
+}
+
+/// A sink the output of a container is copied to.
+pub struct Sink {
+    uri: String,
+    pub(crate) writer: Box<dyn Write + Send>,
+}
+
+impl Sink {
+    /// Returns a sink writing to `writer`, named `uri` in the logs.
+    pub fn new(uri: impl Into<String>, writer: impl Write + Send + 'static) -> Self {
+        Self {
+            uri: uri.into(),
+            writer: Box::new(writer),
+        }
+    }
+
+    fn open(uri: &str) -> Result<Self> {
+        let url = Url::parse(uri).with_context(|| format!("invalid tee sink {uri:?}"))?;
+        let path = PathBuf::from(url.path());
+        let writer: Box<dyn Write + Send> = match url.scheme() {
+            "file" => {
+                if let Some(parent) = path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                Box::new(OpenOptions::new().create(true).append(true).open(&path)?)
+            }
+            "unix" => Box::new(UnixStream::connect(&path)?),
+            scheme => bail!("unsupported tee sink scheme {scheme:?}"),
+        };
+        Ok(Self {
+            uri: uri.to_string(),
+            writer,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::io::BufRead;
+    use std::os::unix::net::UnixListener;
+    use std::sync::mpsc::{channel, Receiver};
+    use std::sync::Mutex;
+
+    use super::*;
+
+    // Reads the chunks one at a time.
+    struct Chunks(VecDeque<&'static [u8]>);
+
+    impl Read for Chunks {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let Some(chunk) = self.0.pop_front() else {
+                return Ok(0);
+            };
+            buf[..chunk.len()].copy_from_slice(chunk);
+            Ok(chunk.len())
+        }
+    }
+
+    // A sink that blocks until it is released.
+    struct BlockedSink {
+        release: Receiver<()>,
+        written: Arc<Mutex<Vec<u8>>>,
+    }
+
+    impl Write for BlockedSink {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            let _ = self.release.recv();
+            self.written.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_tee() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let socket = dir.path().join("debug.sock");
+        let listener = UnixListener::bind(&socket)?;
+
+        let cfg = TeeConfig {
+            sinks: vec![
+                format!(
+                    "file://{}/{{namespace}}/{{id}}-{{stream}}.log",
+                    dir.path().display()
+                ),
+                format!("unix://{}", socket.display()),
+                "unix:///nonexistent.sock".to_string(),
+            ],
+            ..Default::default()
+        };
+        let sinks = sinks("test", "default", "stdout", &cfg);
+        assert_eq!(sinks.len(), 2);
+        let mut queues = sinks
+            .into_iter()
+            .map(|sink| SinkQueue::start(sink, SINK_BUFFER_SIZE))
+            .collect::<std::io::Result<Vec<_>>>()?;
+
+        let mut output = vec![];
+        copy(&b"hello\n"[..], &mut output, &mut queues)?;
+        for queue in queues {
+            assert_eq!(queue.finish().bytes, 6);
+        }
+
+        assert_eq!(output, b"hello\n");
+        let file = dir.path().join("default/test-stdout.log");
+        assert_eq!(std::fs::read(file)?, b"hello\n");
+
+        let (conn, _) = listener.accept()?;
+        let mut line = String::new();
+        std::io::BufReader::new(conn).read_line(&mut line)?;
+        assert_eq!(line, "hello\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_slow_sink_drops_output() -> Result<()> {
+        let (release, blocked) = channel();
+        let written = Arc::new(Mutex::new(vec![]));
+        let sink = BlockedSink {
+            release: blocked,
+            written: written.clone(),
+        };
+        let mut queues = vec![SinkQueue::start(Sink::new("blocked", sink), 4)?];
+
+        // the sink holds at most a chunk being written and a chunk in its buffer,
+        // and the output is never blocked by it
+        let input = Chunks(VecDeque::from([&b"abcd"[..], b"efgh", b"ijkl", b"mnop"]));
+        let mut output = vec![];
+        copy(input, &mut output, &mut queues)?;
+        assert_eq!(output, b"abcdefghijklmnop");
+
+        drop(release);
+        let stats = queues.pop().unwrap().finish();
+        assert!(stats.dropped_bytes >= 8, "{stats:?}");
+        assert_eq!(stats.bytes + stats.dropped_bytes, 16);
+        assert_eq!(written.lock().unwrap().len() as u64, stats.bytes);
+        Ok(())
+    }
+}
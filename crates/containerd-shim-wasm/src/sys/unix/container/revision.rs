@@ -0,0 +1,105 @@
+//! Injection of the revision of the image into the environment of the guest.
+//!
+//! The `org.opencontainers.image.revision` of the image is exposed to the guest as the
+//! `RUNWASI_IMAGE_REVISION` env var, and as the `vcs.ref.head.revision` resource attribute
+//! in `OTEL_RESOURCE_ATTRIBUTES`, so that the logs and traces of the guest and of the shim
+//! agree on the build that is running. Values already set in the spec are kept.
+
+use oci_spec::runtime::Spec;
+
+/// Env var with the revision of the image.
+pub const IMAGE_REVISION_ENV: &str = "RUNWASI_IMAGE_REVISION";
+
+/// OpenTelemetry resource attribute with the revision of the image.
+pub const REVISION_ATTRIBUTE: &str = "vcs.ref.head.revision";
+
+const OTEL_RESOURCE_ATTRIBUTES: &str = "OTEL_RESOURCE_ATTRIBUTES";
+
+/// Adds `revision` to the env of `spec`.
+/// Returns true if the spec was changed.
+pub fn inject(spec: &mut Spec, revision: &str) -> bool {
+    let Some(process) = spec.process_mut() else {
+        return false;
+    };
+    let mut env = process.env().clone().unwrap_or_default();
+    let mut changed = false;
+
+    let revision_prefix = format!("{IMAGE_REVISION_ENV}=");
+    if !env.iter().any(|e| e.starts_with(&revision_prefix)) {
+        env.push(format!("{revision_prefix}{revision}"));
+        changed = true;
+    }
+
+    let attribute = format!("{REVISION_ATTRIBUTE}={revision}");
+    let attributes_prefix = format!("{OTEL_RESOURCE_ATTRIBUTES}=");
+    match env.iter_mut().find(|e| e.starts_with(&attributes_prefix)) {
+        Some(attributes) if attributes.contains(&format!("{REVISION_ATTRIBUTE}=")) => {}
+        Some(attributes) if attributes.len() == attributes_prefix.len() => {
+            attributes.push_str(&attribute);
+            changed = true;
+        }
+        Some(attributes) => {
+            attributes.push(',');
+            attributes.push_str(&attribute);
+            changed = true;
+        }
+        None => {
+            env.push(format!("{attributes_prefix}{attribute}"));
+            changed = true;
+        }
+    }
+
+    if changed {
+        process.set_env(Some(env));
+    }
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use oci_spec::runtime::{ProcessBuilder, SpecBuilder};
+
+    use super::*;
+
+    fn spec(env: &[&str]) -> Spec {
+        let env = env.iter().map(ToString::to_string).collect::<Vec<_>>();
+        SpecBuilder::default()
+            .process(ProcessBuilder::default().env(env).build().unwrap())
+            .build()
+            .unwrap()
+    }
+
+    fn env(spec: &Spec) -> Vec<String> {
+        spec.process().as_ref().unwrap().env().clone().unwrap()
+    }
+
+    #[test]
+    fn test_inject() {
+        let mut spec = spec(&["PATH=/bin"]);
+        assert!(inject(&mut spec, "abc123"));
+        assert_eq!(
+            env(&spec),
+            [
+                "PATH=/bin",
+                "RUNWASI_IMAGE_REVISION=abc123",
+                "OTEL_RESOURCE_ATTRIBUTES=vcs.ref.head.revision=abc123"
+            ]
+        );
+
+        // already injected
+        assert!(!inject(&mut spec, "abc123"));
+    }
+
+    #[test]
+    fn test_inject_keeps_existing_attributes() {
+        let mut spec = spec(&["OTEL_RESOURCE_ATTRIBUTES=service.name=app"]);
+        assert!(inject(&mut spec, "abc123"));
+        assert_eq!(
+            env(&spec),
+            [
+                "OTEL_RESOURCE_ATTRIBUTES=service.name=app,vcs.ref.head.revision=abc123",
+                "RUNWASI_IMAGE_REVISION=abc123",
+            ]
+        );
+    }
+}
@@ -0,0 +1,180 @@
+//! Size based rotation of the log files of a container.
+//!
+//! When the stdout or stderr of a container is a file, it is rotated once it reaches
+//! `max_size_bytes`: `app.log` is renamed to `app.log.1`, `app.log.1` to `app.log.2`, and so on,
+//! keeping at most `max_files` files including the current one.
+//!
+//! Rotation is configured with `log_rotation` in the runtime configuration, and can be
+//! overridden per container with the `runwasi.io/log-max-size` and `runwasi.io/log-max-files`
+//! annotations.
+
+use std::fs::{File, OpenOptions};
+use std::io::{Result as IoResult, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use oci_spec::runtime::Spec;
+
+use crate::sandbox::config::{LogRotationConfig, RuntimeConfig};
+
+/// Annotation with the size in bytes a log file is rotated at.
+pub const LOG_MAX_SIZE_ANNOTATION: &str = "runwasi.io/log-max-size";
+/// Annotation with the number of log files to keep, including the current one.
+pub const LOG_MAX_FILES_ANNOTATION: &str = "runwasi.io/log-max-files";
+
+/// How log files are rotated.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rotation {
+    pub max_size: u64,
+    pub max_files: usize,
+}
+
+impl Rotation {
+    /// Returns the rotation of the log files of the container, if they are rotated.
+    pub fn from_spec(spec: &Spec) -> Result<Option<Self>> {
+        let config = RuntimeConfig::current().log_rotation.clone();
+        let annotation = |key: &str| {
+            spec.annotations()
+                .as_ref()
+                .and_then(|a| a.get(key))
+                .map(|v| v.parse().with_context(|| format!("invalid {key} {v:?}")))
+                .transpose()
+        };
+
+        let max_size =
+            annotation(LOG_MAX_SIZE_ANNOTATION)?.or(config.as_ref().map(|c| c.max_size_bytes));
+        let Some(max_size) = max_size else {
+            return Ok(None);
+        };
+        let max_files = annotation(LOG_MAX_FILES_ANNOTATION)?
+            .map(|v: u64| v as usize)
+            .or(config.as_ref().map(|c| c.max_files))
+            .unwrap_or(LogRotationConfig::default().max_files);
+
+        if max_size == 0 || max_files == 0 {
+            bail!("log files must be rotated at a non-zero size, keeping at least one file");
+        }
+        Ok(Some(Self {
+            max_size,
+            max_files,
+        }))
+    }
+}
+
+/// A log file, appended to, and rotated once it reaches its maximum size.
+pub struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    rotation: Rotation,
+}
+
+impl RotatingFile {
+    pub fn open(path: impl AsRef<Path>, rotation: Rotation) -> IoResult<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = append(&path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path,
+            file,
+            size,
+            rotation,
+        })
+    }
+
+    fn rotate(&mut self) -> IoResult<()> {
+        let rotated = |i: usize| PathBuf::from(format!("{}.{i}", self.path.display()));
+        let oldest = self.rotation.max_files - 1;
+        if oldest == 0 {
+            std::fs::remove_file(&self.path)?;
+        } else {
+            let _ = std::fs::remove_file(rotated(oldest));
+            for i in (1..oldest).rev() {
+                let _ = std::fs::rename(rotated(i), rotated(i + 1));
+            }
+            std::fs::rename(&self.path, rotated(1))?;
+        }
+        self.file = append(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        if self.size > 0 && self.size + buf.len() as u64 > self.rotation.max_size {
+            self.rotate()?;
+        }
+        let n = self.file.write(buf)?;
+        self.size += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> IoResult<()> {
+        self.file.flush()
+    }
+}
+
+fn append(path: &Path) -> IoResult<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use oci_spec::runtime::SpecBuilder;
+
+    use super::*;
+
+    #[test]
+    fn test_rotation_from_annotations() -> Result<()> {
+        let spec = SpecBuilder::default().build()?;
+        assert_eq!(Rotation::from_spec(&spec)?, None);
+
+        let spec = SpecBuilder::default()
+            .annotations(HashMap::from([(
+                LOG_MAX_SIZE_ANNOTATION.to_string(),
+                "1024".to_string(),
+            )]))
+            .build()?;
+        assert_eq!(
+            Rotation::from_spec(&spec)?,
+            Some(Rotation {
+                max_size: 1024,
+                max_files: LogRotationConfig::default().max_files
+            })
+        );
+
+        let spec = SpecBuilder::default()
+            .annotations(HashMap::from([(
+                LOG_MAX_SIZE_ANNOTATION.to_string(),
+                "1k".to_string(),
+            )]))
+            .build()?;
+        assert!(Rotation::from_spec(&spec).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_rotating_file() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("app.log");
+        let rotation = Rotation {
+            max_size: 4,
+            max_files: 3,
+        };
+
+        let mut file = RotatingFile::open(&path, rotation)?;
+        for line in ["a\n", "b\n", "c\n", "d\n", "e\n", "f\n", "g\n"] {
+            file.write_all(line.as_bytes())?;
+        }
+
+        let read = |suffix: &str| std::fs::read_to_string(format!("{}{suffix}", path.display()));
+        assert_eq!(read("")?, "g\n");
+        assert_eq!(read(".1")?, "e\nf\n");
+        assert_eq!(read(".2")?, "c\nd\n");
+        assert!(read(".3").is_err());
+        Ok(())
+    }
+}
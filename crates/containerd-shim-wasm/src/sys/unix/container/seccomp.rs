@@ -0,0 +1,170 @@
+//! Default seccomp profile of the containers.
+//!
+//! libcontainer applies the `linux.seccomp` profile of the spec in the init of the container,
+//! before the executor hands control to the engine. Specs often have none, e.g. when the pod
+//! doesn't set a `seccompProfile`, and the engine then runs with the whole syscall surface of
+//! the host, which a compromised engine could use.
+//!
+//! With `seccomp` in the runtime configuration, containers whose spec has no profile get the
+//! profile of `seccomp.profile`, or the default profile of the shim. A wasm engine needs no
+//! more than memory management, threads, files and sockets, so the default profile denies the
+//! syscalls that manage the system, namespaces, mounts, kernel modules and keys, or inspect
+//! other processes, with `EPERM`.
+
+use anyhow::Context as _;
+use oci_spec::runtime::{
+    LinuxBuilder, LinuxSeccomp, LinuxSeccompAction, LinuxSeccompBuilder, LinuxSyscallBuilder, Spec,
+};
+
+use crate::sandbox::config::{RuntimeConfig, SeccompConfig};
+
+// the syscalls denied by the default profile
+const DENIED_SYSCALLS: &[&str] = &[
+    "acct",
+    "add_key",
+    "adjtimex",
+    "bpf",
+    "chroot",
+    "clock_adjtime",
+    "clock_settime",
+    "create_module",
+    "delete_module",
+    "finit_module",
+    "fsconfig",
+    "fsmount",
+    "fsopen",
+    "fspick",
+    "get_kernel_syms",
+    "get_mempolicy",
+    "init_module",
+    "io_uring_enter",
+    "io_uring_register",
+    "io_uring_setup",
+    "ioperm",
+    "iopl",
+    "kcmp",
+    "kexec_file_load",
+    "kexec_load",
+    "keyctl",
+    "lookup_dcookie",
+    "mbind",
+    "mount",
+    "mount_setattr",
+    "move_mount",
+    "move_pages",
+    "name_to_handle_at",
+    "nfsservctl",
+    "open_by_handle_at",
+    "open_tree",
+    "perf_event_open",
+    "personality",
+    "pivot_root",
+    "process_vm_readv",
+    "process_vm_writev",
+    "ptrace",
+    "query_module",
+    "quotactl",
+    "reboot",
+    "request_key",
+    "set_mempolicy",
+    "setns",
+    "settimeofday",
+    "swapoff",
+    "swapon",
+    "syslog",
+    "umount",
+    "umount2",
+    "unshare",
+    "uselib",
+    "userfaultfd",
+    "ustat",
+    "vhangup",
+];
+
+/// Sets the seccomp profile of `spec` to the one of the runtime configuration, if it has none.
+/// Returns true if the spec was changed.
+pub fn inject_default(spec: &mut Spec) -> anyhow::Result<bool> {
+    match &RuntimeConfig::current().seccomp {
+        Some(config) => inject(spec, config),
+        None => Ok(false),
+    }
+}
+
+fn inject(spec: &mut Spec, config: &SeccompConfig) -> anyhow::Result<bool> {
+    let mut linux = match spec.linux() {
+        Some(linux) => linux.clone(),
+        None => LinuxBuilder::default().build()?,
+    };
+    if linux.seccomp().is_some() {
+        return Ok(false);
+    }
+
+    let profile = match &config.profile {
+        Some(path) => {
+            let profile = std::fs::read(path)
+                .with_context(|| format!("failed to read seccomp profile {path:?}"))?;
+            serde_json::from_slice(&profile)
+                .with_context(|| format!("invalid seccomp profile {path:?}"))?
+        }
+        None => default_profile()?,
+    };
+    linux.set_seccomp(Some(profile));
+    spec.set_linux(Some(linux));
+    Ok(true)
+}
+
+fn default_profile() -> anyhow::Result<LinuxSeccomp> {
+    let denied = LinuxSyscallBuilder::default()
+        .names(
+            DENIED_SYSCALLS
+                .iter()
+                .map(|s| s.to_string())
+                .collect::<Vec<_>>(),
+        )
+        .action(LinuxSeccompAction::ScmpActErrno)
+        .errno_ret(libc::EPERM as u32)
+        .build()?;
+    Ok(LinuxSeccompBuilder::default()
+        .default_action(LinuxSeccompAction::ScmpActAllow)
+        .syscalls(vec![denied])
+        .build()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use oci_spec::runtime::SpecBuilder;
+
+    use super::*;
+
+    #[test]
+    fn test_inject_default_profile() -> anyhow::Result<()> {
+        let mut spec = SpecBuilder::default().build()?;
+        assert!(inject(&mut spec, &SeccompConfig::default())?);
+
+        let linux = spec.linux().as_ref().unwrap();
+        let seccomp = linux.seccomp().as_ref().unwrap();
+        assert_eq!(seccomp.default_action(), LinuxSeccompAction::ScmpActAllow);
+        let syscalls = seccomp.syscalls().as_ref().unwrap();
+        assert!(syscalls[0].names().contains(&"mount".to_string()));
+
+        // the profile of the spec is kept
+        assert!(!inject(&mut spec, &SeccompConfig::default())?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_inject_profile_file() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("seccomp.json");
+        std::fs::write(&path, r#"{ "defaultAction": "SCMP_ACT_ERRNO" }"#)?;
+        let config = SeccompConfig {
+            profile: Some(path),
+        };
+
+        let mut spec = SpecBuilder::default().build()?;
+        assert!(inject(&mut spec, &config)?);
+        let seccomp = spec.linux().as_ref().unwrap().seccomp().clone().unwrap();
+        assert_eq!(seccomp.default_action(), LinuxSeccompAction::ScmpActErrno);
+        Ok(())
+    }
+}
@@ -0,0 +1,175 @@
+//! The SELinux label and AppArmor profile of the containers.
+//!
+//! libcontainer sets the `process.selinuxLabel` and `process.apparmorProfile` of the spec up
+//! for the next `execve(2)` of the init of the container, as runc does. The wasm executor
+//! never execs, it runs the engine in the init, so the labels would never apply and the guest
+//! would run unconfined. Instead, the shim:
+//! * checks, before building the container, that the host enforces the labels of the spec, and
+//!   that the AppArmor profile is loaded, failing the creation of the container otherwise,
+//! * switches the init to the labels right before the engine runs, with the `changeprofile`
+//!   command of AppArmor, and a dynamic transition of SELinux, which the policy of the host
+//!   must allow from the label of the shim.
+//!
+//! Linux containers run by the executor exec their entrypoint, and get their labels from
+//! libcontainer.
+
+use std::path::Path;
+
+use anyhow::Context as _;
+use oci_spec::runtime::Spec;
+
+use crate::sandbox::Error as SandboxError;
+
+const APPARMOR_ENABLED: &str = "/sys/module/apparmor/parameters/enabled";
+const APPARMOR_PROFILES: &str = "/sys/kernel/security/apparmor/profiles";
+const SELINUX_FS: &str = "/sys/fs/selinux";
+
+/// The MAC systems the host enforces.
+struct Host {
+    apparmor: bool,
+    // the loaded AppArmor profiles, if they can be listed
+    apparmor_profiles: Option<String>,
+    selinux: bool,
+}
+
+impl Host {
+    fn current() -> Self {
+        Self {
+            apparmor: std::fs::read_to_string(APPARMOR_ENABLED).is_ok_and(|v| v.starts_with('Y')),
+            apparmor_profiles: std::fs::read_to_string(APPARMOR_PROFILES).ok(),
+            selinux: Path::new(SELINUX_FS).join("enforce").exists(),
+        }
+    }
+
+    fn has_apparmor_profile(&self, profile: &str) -> bool {
+        self.apparmor_profiles.as_ref().map_or(true, |profiles| {
+            profiles
+                .lines()
+                .any(|line| line.rsplit_once(" (").map_or(line, |(name, _)| name) == profile)
+        })
+    }
+}
+
+/// Fails if the host doesn't enforce the SELinux label or AppArmor profile of the spec.
+pub fn check(spec: &Spec) -> Result<(), SandboxError> {
+    check_on(&Host::current(), spec)
+}
+
+fn check_on(host: &Host, spec: &Spec) -> Result<(), SandboxError> {
+    if let Some(profile) = apparmor_profile(spec) {
+        if !host.apparmor {
+            return Err(SandboxError::FailedPrecondition(format!(
+                "apparmor profile {profile:?} is specified, but AppArmor is not enabled on the host"
+            )));
+        }
+        if !host.has_apparmor_profile(profile) {
+            return Err(SandboxError::FailedPrecondition(format!(
+                "apparmor profile {profile:?} is not loaded"
+            )));
+        }
+    }
+    if let Some(label) = selinux_label(spec) {
+        if !host.selinux {
+            return Err(SandboxError::FailedPrecondition(format!(
+                "selinux label {label:?} is specified, but SELinux is not enabled on the host"
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Switches the calling thread to the SELinux label and AppArmor profile of the spec. Called
+/// in the init of the container, before it starts any thread, so that they all inherit them.
+pub fn apply(spec: &Spec) -> anyhow::Result<()> {
+    if let Some(label) = selinux_label(spec) {
+        write_attr("current", label)
+            .with_context(|| format!("failed to switch to selinux label {label:?}"))?;
+    }
+    if let Some(profile) = apparmor_profile(spec) {
+        let attr = if Path::new("/proc/thread-self/attr/apparmor").exists() {
+            "apparmor/current"
+        } else {
+            "current"
+        };
+        write_attr(attr, &format!("changeprofile {profile}"))
+            .with_context(|| format!("failed to switch to apparmor profile {profile:?}"))?;
+    }
+    Ok(())
+}
+
+fn write_attr(attr: &str, value: &str) -> std::io::Result<()> {
+    // the attribute is only changed by a single write of the whole value
+    std::fs::write(Path::new("/proc/thread-self/attr").join(attr), value)
+}
+
+fn apparmor_profile(spec: &Spec) -> Option<&str> {
+    let profile = spec.process().as_ref()?.apparmor_profile().as_deref()?;
+    (!profile.is_empty() && profile != "unconfined").then_some(profile)
+}
+
+fn selinux_label(spec: &Spec) -> Option<&str> {
+    let label = spec.process().as_ref()?.selinux_label().as_deref()?;
+    (!label.is_empty()).then_some(label)
+}
+
+#[cfg(test)]
+mod tests {
+    use oci_spec::runtime::{ProcessBuilder, SpecBuilder};
+
+    use super::*;
+
+    fn spec(apparmor_profile: &str, selinux_label: &str) -> Spec {
+        let process = ProcessBuilder::default()
+            .apparmor_profile(apparmor_profile)
+            .selinux_label(selinux_label)
+            .build()
+            .unwrap();
+        SpecBuilder::default().process(process).build().unwrap()
+    }
+
+    #[test]
+    fn test_check_apparmor() {
+        let host = Host {
+            apparmor: true,
+            apparmor_profiles: Some("cri-containerd.apparmor.d (enforce)\n".to_string()),
+            selinux: false,
+        };
+        assert!(check_on(&host, &spec("cri-containerd.apparmor.d", "")).is_ok());
+        assert!(check_on(&host, &spec("unconfined", "")).is_ok());
+        assert!(matches!(
+            check_on(&host, &spec("wasm-workloads", "")),
+            Err(SandboxError::FailedPrecondition(_))
+        ));
+
+        let host = Host {
+            apparmor: false,
+            apparmor_profiles: None,
+            selinux: false,
+        };
+        assert!(matches!(
+            check_on(&host, &spec("cri-containerd.apparmor.d", "")),
+            Err(SandboxError::FailedPrecondition(_))
+        ));
+    }
+
+    #[test]
+    fn test_check_selinux() {
+        let label = "system_u:system_r:container_t:s0:c1,c2";
+        let host = Host {
+            apparmor: false,
+            apparmor_profiles: None,
+            selinux: true,
+        };
+        assert!(check_on(&host, &spec("", label)).is_ok());
+
+        let host = Host {
+            selinux: false,
+            ..host
+        };
+        assert!(check_on(&host, &spec("", "")).is_ok());
+        assert!(matches!(
+            check_on(&host, &spec("", label)),
+            Err(SandboxError::FailedPrecondition(_))
+        ));
+    }
+}
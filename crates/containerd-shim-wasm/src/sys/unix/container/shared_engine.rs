@@ -0,0 +1,186 @@
+//! A single instance of each engine, shared by the containers of a shim.
+//!
+//! Building an engine can be expensive, e.g., setting up its compiler, its code caches and
+//! the pooling allocator of its memories, and each container used to build its own.
+//!
+//! The shim builds the engine once, and uses it to fetch and precompile the modules of
+//! all its containers, releasing it once the last of them is deleted, e.g., so that the memory
+//! reserved by a pooling allocator isn't kept by an idle shim. Engines that opt in with
+//! [`Engine::shared`] are also built once in the zygote, before the container processes are
+//! forked from it, so that the containers start with a ready engine and share its memory
+//! copy-on-write.
+
+use std::any::{Any, TypeId};
+use std::collections::{HashMap, HashSet};
+use std::ops::Deref;
+use std::sync::{LazyLock, Mutex};
+
+use zygote::Zygote;
+
+use crate::container::Engine;
+
+// the engines of this process, with the number of their users, which aren't counted for the
+// engines kept for the life of the process
+static ENGINES: LazyLock<Mutex<HashMap<TypeId, (usize, Box<dyn Any + Send>)>>> =
+    LazyLock::new(Default::default);
+
+fn cached<E: Engine + Default>(users: usize) -> E {
+    let mut engines = ENGINES.lock().unwrap();
+    let (count, engine) = engines
+        .entry(TypeId::of::<E>())
+        .or_insert_with(|| (0, Box::new(E::default())));
+    *count += users;
+    engine
+        .downcast_ref::<E>()
+        .expect("engines are keyed by their type")
+        .clone()
+}
+
+/// Returns the engine `E` of this process, building it the first time, and keeping it for
+/// the life of the process.
+pub fn get<E: Engine + Default>() -> E {
+    cached::<E>(0)
+}
+
+/// Returns the engine `E` of this process, building it if it has no other user, and releasing
+/// it once the last [`SharedEngine`] is dropped.
+pub fn acquire<E: Engine + Default>() -> SharedEngine<E> {
+    SharedEngine {
+        engine: cached::<E>(1),
+    }
+}
+
+/// A user of the engine `E` of this process.
+pub struct SharedEngine<E: Engine> {
+    engine: E,
+}
+
+impl<E: Engine> Deref for SharedEngine<E> {
+    type Target = E;
+
+    fn deref(&self) -> &E {
+        &self.engine
+    }
+}
+
+impl<E: Engine> Drop for SharedEngine<E> {
+    fn drop(&mut self) {
+        let mut engines = ENGINES.lock().unwrap();
+        let Some((count, _)) = engines.get_mut(&TypeId::of::<E>()) else {
+            return;
+        };
+        *count -= 1;
+        if *count == 0 {
+            log::info!(
+                "releasing the {} engine, it has no container left",
+                E::name()
+            );
+            engines.remove(&TypeId::of::<E>());
+        }
+    }
+}
+
+/// Builds the engine `E` in the zygote, if it opts in with [`Engine::shared`], so that the
+/// container processes forked from the zygote inherit it.
+pub fn prepare_zygote<E: Engine + Default>() {
+    static PREPARED: LazyLock<Mutex<HashSet<TypeId>>> = LazyLock::new(Default::default);
+
+    if !E::shared() || !PREPARED.lock().unwrap().insert(TypeId::of::<E>()) {
+        return;
+    }
+    log::info!("building the shared {} engine in the zygote", E::name());
+    Zygote::global().run(
+        |_| {
+            get::<E>();
+        },
+        (),
+    );
+}
+
+/// Returns the engine a container process runs its guest with.
+pub fn for_container<E: Engine + Default>() -> E {
+    if E::shared() {
+        get::<E>()
+    } else {
+        E::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::container::RuntimeContext;
+
+    static BUILT: AtomicUsize = AtomicUsize::new(0);
+
+    #[derive(Clone)]
+    struct CountingEngine;
+
+    impl Default for CountingEngine {
+        fn default() -> Self {
+            BUILT.fetch_add(1, Ordering::SeqCst);
+            Self
+        }
+    }
+
+    impl Engine for CountingEngine {
+        fn name() -> &'static str {
+            "counting"
+        }
+
+        fn run_wasi(&self, _ctx: &impl RuntimeContext) -> anyhow::Result<i32> {
+            Ok(0)
+        }
+    }
+
+    #[test]
+    fn test_get_builds_once() {
+        get::<CountingEngine>();
+        get::<CountingEngine>();
+        assert_eq!(BUILT.load(Ordering::SeqCst), 1);
+
+        // engines that don't opt in are built for each container
+        for_container::<CountingEngine>();
+        assert_eq!(BUILT.load(Ordering::SeqCst), 2);
+    }
+
+    static ACQUIRED: AtomicUsize = AtomicUsize::new(0);
+
+    #[derive(Clone)]
+    struct AcquiredEngine;
+
+    impl Default for AcquiredEngine {
+        fn default() -> Self {
+            ACQUIRED.fetch_add(1, Ordering::SeqCst);
+            Self
+        }
+    }
+
+    impl Engine for AcquiredEngine {
+        fn name() -> &'static str {
+            "acquired"
+        }
+
+        fn run_wasi(&self, _ctx: &impl RuntimeContext) -> anyhow::Result<i32> {
+            Ok(0)
+        }
+    }
+
+    #[test]
+    fn test_acquire_releases_after_last_user() {
+        let first = acquire::<AcquiredEngine>();
+        let second = acquire::<AcquiredEngine>();
+        assert_eq!(ACQUIRED.load(Ordering::SeqCst), 1);
+
+        drop(first);
+        let third = acquire::<AcquiredEngine>();
+        assert_eq!(ACQUIRED.load(Ordering::SeqCst), 1);
+
+        drop(second);
+        drop(third);
+        let _fourth = acquire::<AcquiredEngine>();
+        assert_eq!(ACQUIRED.load(Ordering::SeqCst), 2);
+    }
+}
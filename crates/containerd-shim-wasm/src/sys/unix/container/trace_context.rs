@@ -0,0 +1,90 @@
+//! Propagation of the trace context of the shim to the guest.
+//!
+//! The shim continues the traces of containerd from the `traceparent` of its requests.
+//! The trace context of the creation of a container is passed on to the guest as the
+//! `TRACEPARENT` and `TRACESTATE` env vars, as OpenTelemetry SDKs read them, so that the
+//! spans of the guest belong to the same trace. Values already set in the spec are kept.
+
+use std::collections::HashMap;
+
+use oci_spec::runtime::Spec;
+
+/// W3C trace context headers, and the env vars they are passed to the guest as.
+const HEADERS: [(&str, &str); 2] = [("traceparent", "TRACEPARENT"), ("tracestate", "TRACESTATE")];
+
+/// Adds the trace context `headers` to the env of `spec`.
+/// Returns true if the spec was changed.
+pub fn inject(spec: &mut Spec, headers: &HashMap<String, String>) -> bool {
+    let Some(process) = spec.process_mut() else {
+        return false;
+    };
+    let mut env = process.env().clone().unwrap_or_default();
+    let mut changed = false;
+
+    for (header, var) in HEADERS {
+        let Some(value) = headers.get(header).filter(|v| !v.is_empty()) else {
+            continue;
+        };
+        let prefix = format!("{var}=");
+        if !env.iter().any(|e| e.starts_with(&prefix)) {
+            env.push(format!("{prefix}{value}"));
+            changed = true;
+        }
+    }
+
+    if changed {
+        process.set_env(Some(env));
+    }
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use oci_spec::runtime::{ProcessBuilder, SpecBuilder};
+
+    use super::*;
+
+    const TRACEPARENT: &str = "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01";
+
+    fn spec(env: &[&str]) -> Spec {
+        let env = env.iter().map(ToString::to_string).collect::<Vec<_>>();
+        SpecBuilder::default()
+            .process(ProcessBuilder::default().env(env).build().unwrap())
+            .build()
+            .unwrap()
+    }
+
+    fn env(spec: &Spec) -> Vec<String> {
+        spec.process().as_ref().unwrap().env().clone().unwrap()
+    }
+
+    #[test]
+    fn test_inject() {
+        let headers = HashMap::from([("traceparent".to_string(), TRACEPARENT.to_string())]);
+        let mut spec = spec(&["PATH=/bin"]);
+        assert!(inject(&mut spec, &headers));
+        assert_eq!(
+            env(&spec),
+            [
+                "PATH=/bin".to_string(),
+                format!("TRACEPARENT={TRACEPARENT}")
+            ]
+        );
+        // the guest isn't part of any trace without a trace context
+        assert!(!inject(&mut spec, &HashMap::new()));
+    }
+
+    #[test]
+    fn test_inject_keeps_existing() {
+        let headers = HashMap::from([
+            ("traceparent".to_string(), TRACEPARENT.to_string()),
+            ("tracestate".to_string(), "vendor=value".to_string()),
+        ]);
+        let mut spec = spec(&["TRACEPARENT=00-set-by-user"]);
+        assert!(inject(&mut spec, &headers));
+        assert_eq!(
+            env(&spec),
+            ["TRACEPARENT=00-set-by-user", "TRACESTATE=vendor=value"]
+        );
+    }
+}
@@ -0,0 +1,130 @@
+//! Zygotes spawned ahead of time, for faster cold starts.
+//!
+//! Each container is built in a zygote of its own, spawned from the global zygote of the shim.
+//! The global zygote spawns one zygote at a time, so under a burst of creations the builds
+//! wait for each other's zygote. With `zygote_pool_size` set in the runtime configuration,
+//! that many zygotes are kept ready in the background, and a build takes one of them instead.
+//!
+//! The pool is started by the first build with a non-zero `zygote_pool_size`, once the shared
+//! engine, if any, is built in the global zygote, so that the zygotes of the pool inherit it.
+//! It stops once the size is set back to 0.
+//! How long builds waited for a zygote is included in the debug dump of the shim.
+
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Condvar, LazyLock, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use zygote::Zygote;
+
+use crate::sandbox::config::RuntimeConfig;
+
+/// How often the pool checks whether its configured size changed.
+const RESIZE_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Default)]
+pub(crate) struct ZygotePool {
+    ready: Mutex<Vec<Zygote>>,
+    taken: Condvar,
+    // whether the thread refilling the pool runs
+    started: AtomicBool,
+    stats: Mutex<PoolStats>,
+}
+
+#[derive(Default)]
+struct PoolStats {
+    hits: u64,
+    misses: u64,
+    total_wait: Duration,
+    max_wait: Duration,
+}
+
+impl ZygotePool {
+    pub fn global() -> &'static ZygotePool {
+        static POOL: LazyLock<ZygotePool> = LazyLock::new(ZygotePool::default);
+        &POOL
+    }
+
+    /// Returns a zygote to build a container in, from the pool if it has one ready.
+    pub fn take(&'static self) -> Zygote {
+        self.start();
+
+        let waiting = Instant::now();
+        let ready = self.ready.lock().unwrap().pop();
+        self.taken.notify_one();
+        let hit = ready.is_some();
+        let zygote = ready.unwrap_or_else(|| Zygote::global().spawn());
+        let wait = waiting.elapsed();
+
+        let mut stats = self.stats.lock().unwrap();
+        if hit {
+            stats.hits += 1;
+        } else {
+            stats.misses += 1;
+        }
+        stats.total_wait += wait;
+        stats.max_wait = stats.max_wait.max(wait);
+        log::debug!("waited {wait:?} for a zygote");
+        zygote
+    }
+
+    fn start(&'static self) {
+        if RuntimeConfig::current().zygote_pool_size == 0
+            || self.started.swap(true, Ordering::SeqCst)
+        {
+            return;
+        }
+        let res = thread::Builder::new()
+            .name("zygote-pool".to_string())
+            .spawn(|| self.refill());
+        if let Err(err) = res {
+            log::warn!("failed to start the zygote pool: {err}");
+            self.started.store(false, Ordering::SeqCst);
+        }
+    }
+
+    // Keeps `zygote_pool_size` zygotes ready, until the size is set to 0.
+    fn refill(&self) {
+        let mut ready = self.ready.lock().unwrap();
+        loop {
+            let size = RuntimeConfig::current().zygote_pool_size;
+            if size == 0 {
+                ready.clear();
+                self.started.store(false, Ordering::SeqCst);
+                return;
+            }
+            if ready.len() < size {
+                // don't hold the lock while spawning, so that builds can take the ready ones
+                drop(ready);
+                let zygote = Zygote::global().spawn();
+                ready = self.ready.lock().unwrap();
+                ready.push(zygote);
+                continue;
+            }
+            // the zygotes over the size are dropped, if the size was lowered
+            ready.truncate(size);
+            ready = self.taken.wait_timeout(ready, RESIZE_INTERVAL).unwrap().0;
+        }
+    }
+
+    /// Writes the counters of the pool to `out`.
+    pub fn dump(out: &mut String) {
+        let pool = Self::global();
+        let ready = pool.ready.try_lock().map(|ready| ready.len());
+        let Ok(stats) = pool.stats.try_lock() else {
+            let _ = writeln!(out, "zygote pool: <locked>");
+            return;
+        };
+        let builds = stats.hits + stats.misses;
+        let mean_wait = stats.total_wait / builds.max(1) as u32;
+        let _ = writeln!(
+            out,
+            "zygote pool: ready={} hits={} misses={} mean_wait={mean_wait:?} max_wait={:?}",
+            ready.map_or("<locked>".to_string(), |n| n.to_string()),
+            stats.hits,
+            stats.misses,
+            stats.max_wait,
+        );
+    }
+}
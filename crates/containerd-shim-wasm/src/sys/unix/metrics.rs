@@ -1,3 +1,10 @@
+//! Metrics of the tasks, returned by the `Stats` RPC.
+//!
+//! The shim doesn't serve metrics itself: it has no long-lived daemon mode, and each shim
+//! process only lives as long as its containers. containerd exports the metrics returned
+//! here for every task on its own Prometheus endpoint, enabled with `[metrics] address`
+//! in its configuration, which is where wasm workloads are monitored per node.
+
 use anyhow::Result;
 use containerd_shim::cgroup::collect_metrics;
 use containerd_shim::util::convert_to_any;
@@ -1,3 +1,7 @@
+mod cgroup;
 pub mod container;
 pub mod metrics;
+pub mod oom;
+pub mod pids;
+pub mod resources;
 pub mod stdio;
@@ -0,0 +1,72 @@
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+use super::cgroup::{cgroup_dir, CgroupDir};
+
+/// Tracks the number of OOM kills in the cgroup of a process.
+///
+/// The cgroup is resolved when the monitor is created, so that it can
+/// still be checked once the process has exited.
+pub struct OomMonitor {
+    events: PathBuf,
+    kills: u64,
+}
+
+impl OomMonitor {
+    pub fn new(pid: u32) -> Result<Self> {
+        let events = match cgroup_dir(pid, "memory")? {
+            CgroupDir::V2(dir) => dir.join("memory.events"),
+            CgroupDir::V1(dir) => dir.join("memory.oom_control"),
+        };
+        let kills = oom_kills(&events)?;
+        Ok(Self { events, kills })
+    }
+
+    /// Returns `true` if the OOM killer killed a process in the cgroup since the last check.
+    pub fn check(&mut self) -> bool {
+        let Ok(kills) = oom_kills(&self.events) else {
+            // the cgroup might have been removed already
+            return false;
+        };
+        let killed = kills > self.kills;
+        self.kills = kills;
+        killed
+    }
+}
+
+fn oom_kills(events: &Path) -> Result<u64> {
+    let content = read_to_string(events).with_context(|| format!("failed to read {events:?}"))?;
+    Ok(parse_oom_kills(&content).unwrap_or_default())
+}
+
+// Both `memory.events` (v2) and `memory.oom_control` (v1) contain an `oom_kill <count>` line
+fn parse_oom_kills(content: &str) -> Option<u64> {
+    content
+        .lines()
+        .find_map(|line| line.strip_prefix("oom_kill "))
+        .and_then(|count| count.trim().parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_oom_kills_v2() {
+        let content = "low 0\nhigh 0\nmax 12\noom 3\noom_kill 2\noom_group_kill 0\n";
+        assert_eq!(parse_oom_kills(content), Some(2));
+    }
+
+    #[test]
+    fn test_parse_oom_kills_v1() {
+        let content = "oom_kill_disable 0\nunder_oom 0\noom_kill 1\n";
+        assert_eq!(parse_oom_kills(content), Some(1));
+    }
+
+    #[test]
+    fn test_parse_oom_kills_missing() {
+        assert_eq!(parse_oom_kills("low 0\nhigh 0\n"), None);
+    }
+}
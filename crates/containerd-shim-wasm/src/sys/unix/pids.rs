@@ -0,0 +1,26 @@
+use std::fs::read_to_string;
+
+use anyhow::{Context, Result};
+
+use super::cgroup::cgroup_dir;
+
+/// Returns the pids of all the processes in the same cgroup as `pid`.
+/// This includes the container's init process, as well as any helper
+/// process the engine might have spawned.
+#[cfg_attr(feature = "tracing", tracing::instrument(level = "Debug"))]
+pub fn get_pids(pid: u32) -> Result<Vec<u32>> {
+    // On cgroup v1 the `pids` controller is the one that tracks process membership
+    let procs = cgroup_dir(pid, "pids")?.path().join("cgroup.procs");
+    let content = read_to_string(&procs).with_context(|| format!("failed to read {procs:?}"))?;
+
+    let mut pids: Vec<u32> = content
+        .lines()
+        .filter_map(|line| line.trim().parse().ok())
+        .collect();
+
+    if !pids.contains(&pid) {
+        pids.insert(0, pid);
+    }
+
+    Ok(pids)
+}
@@ -0,0 +1,92 @@
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use serde::Serialize;
+
+use super::cgroup::{cgroup_dir, CgroupDir};
+
+/// Resources used by a task over its lifetime.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct ResourceSummary {
+    /// CPU time used by the task, in microseconds.
+    pub cpu_usage_usec: Option<u64>,
+    /// Peak memory usage of the task, in bytes.
+    pub memory_peak_bytes: Option<u64>,
+}
+
+/// Tracks the resources used by the cgroup of a process.
+///
+/// The cgroup is resolved when the tracker is created, so that it can still be read
+/// once the process has exited, until the task is deleted.
+pub struct ResourceTracker {
+    cpu: PathBuf,
+    memory_peak: PathBuf,
+}
+
+impl ResourceTracker {
+    pub fn new(pid: u32) -> Result<Self> {
+        let cpu = match cgroup_dir(pid, "cpuacct")? {
+            CgroupDir::V2(dir) => dir.join("cpu.stat"),
+            CgroupDir::V1(dir) => dir.join("cpuacct.usage"),
+        };
+        let memory_peak = match cgroup_dir(pid, "memory")? {
+            CgroupDir::V2(dir) => dir.join("memory.peak"),
+            CgroupDir::V1(dir) => dir.join("memory.max_usage_in_bytes"),
+        };
+        Ok(Self { cpu, memory_peak })
+    }
+
+    /// Returns the resources used so far. Counters that can't be read are left as None.
+    pub fn summary(&self) -> ResourceSummary {
+        ResourceSummary {
+            cpu_usage_usec: cpu_usage_usec(&self.cpu),
+            memory_peak_bytes: read_u64(&self.memory_peak),
+        }
+    }
+}
+
+fn cpu_usage_usec(path: &Path) -> Option<u64> {
+    if path.ends_with("cpuacct.usage") {
+        // cgroup v1 reports nanoseconds
+        return read_u64(path).map(|ns| ns / 1000);
+    }
+    let content = read_to_string(path).ok()?;
+    content
+        .lines()
+        .find_map(|line| line.strip_prefix("usage_usec "))
+        .and_then(|usec| usec.trim().parse().ok())
+}
+
+fn read_u64(path: &Path) -> Option<u64> {
+    read_to_string(path).ok()?.trim().parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summary() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let tracker = ResourceTracker {
+            cpu: dir.path().join("cpu.stat"),
+            memory_peak: dir.path().join("memory.peak"),
+        };
+        assert_eq!(tracker.summary(), ResourceSummary::default());
+
+        std::fs::write(
+            &tracker.cpu,
+            "usage_usec 1500\nuser_usec 1000\nsystem_usec 500\n",
+        )?;
+        std::fs::write(&tracker.memory_peak, "4096\n")?;
+        assert_eq!(
+            tracker.summary(),
+            ResourceSummary {
+                cpu_usage_usec: Some(1500),
+                memory_peak_bytes: Some(4096),
+            }
+        );
+        Ok(())
+    }
+}
@@ -1,7 +1,122 @@
 use std::fs::{File, OpenOptions};
-use std::io::Result;
+use std::io::{ErrorKind, Result};
 use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+/// How long to wait for containerd to create the stdio fifos of a container, by default.
+/// Waiting delays the creation of every container whose stdio is missing, so it's opt-in.
+pub const DEFAULT_OPEN_TIMEOUT: Duration = Duration::ZERO;
+
+/// Longest wait between two attempts to open a stdio path that doesn't exist yet.
+const MAX_BACKOFF: Duration = Duration::from_millis(500);
 
 pub fn open(path: impl AsRef<Path>) -> Result<File> {
     OpenOptions::new().read(true).write(true).open(path)
 }
+
+/// How the stdio of a container is opened.
+///
+/// containerd occasionally creates the stdio fifos of a container after asking the shim to
+/// create it, so paths that don't exist yet can be waited for, with backoff, up to `timeout`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StdioOpen {
+    pub timeout: Duration,
+    /// Fails if a path can't be opened, instead of running the container without it.
+    pub strict: bool,
+}
+
+impl Default for StdioOpen {
+    fn default() -> Self {
+        Self {
+            timeout: DEFAULT_OPEN_TIMEOUT,
+            strict: false,
+        }
+    }
+}
+
+impl StdioOpen {
+    /// Opens the `stream` of the container `id` at `path`.
+    /// Returns None if the container has no such stream, or if it can't be opened and
+    /// `strict` isn't set.
+    pub fn open(&self, id: &str, stream: &str, path: &Path) -> anyhow::Result<Option<File>> {
+        if path.as_os_str().is_empty() {
+            return Ok(None);
+        }
+        match open_with_retry(path, self.timeout) {
+            Ok(file) => Ok(Some(file)),
+            Err(err) if self.strict => {
+                anyhow::bail!("failed to open the {stream} of container {id} at {path:?}: {err}")
+            }
+            Err(err) => {
+                log::warn!(
+                    "running container {id} without its {stream}, failed to open {path:?}: {err}"
+                );
+                Ok(None)
+            }
+        }
+    }
+}
+
+// Opens `path`, waiting for it to be created up to `timeout`.
+fn open_with_retry(path: &Path, timeout: Duration) -> Result<File> {
+    let deadline = Instant::now() + timeout;
+    let mut backoff = Duration::from_millis(10);
+    loop {
+        let err = match open(path) {
+            Err(err) if err.kind() == ErrorKind::NotFound => err,
+            res => return res,
+        };
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(err);
+        }
+        thread::sleep(backoff.min(remaining));
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_open_waits_for_path() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("stdout");
+        let creator = thread::spawn({
+            let path = path.clone();
+            move || {
+                thread::sleep(Duration::from_millis(50));
+                std::fs::write(path, "")
+            }
+        });
+        let stdio = StdioOpen {
+            timeout: Duration::from_secs(5),
+            strict: false,
+        };
+        assert!(stdio.open("test", "stdout", &path)?.is_some());
+        creator.join().unwrap()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_open_missing_path() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("stdout");
+        let mut stdio = StdioOpen {
+            timeout: Duration::from_millis(20),
+            strict: false,
+        };
+        assert!(stdio.open("test", "stdout", &path)?.is_none());
+        assert!(stdio.open("test", "stdout", Path::new(""))?.is_none());
+
+        stdio.strict = true;
+        stdio.open("test", "stdout", &path).unwrap_err();
+        // a container without the stream is fine in strict mode too
+        assert!(stdio.open("test", "stdout", Path::new(""))?.is_none());
+        Ok(())
+    }
+}
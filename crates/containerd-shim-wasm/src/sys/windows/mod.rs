@@ -1,3 +1,6 @@
 pub mod container;
 pub mod metrics;
+pub mod oom;
+pub mod pids;
+pub mod resources;
 pub mod stdio;
@@ -0,0 +1,13 @@
+use anyhow::{bail, Result};
+
+pub struct OomMonitor;
+
+impl OomMonitor {
+    pub fn new(_pid: u32) -> Result<Self> {
+        bail!("OOM monitoring is not supported on Windows")
+    }
+
+    pub fn check(&mut self) -> bool {
+        false
+    }
+}
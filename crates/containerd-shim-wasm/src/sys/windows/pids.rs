@@ -0,0 +1,6 @@
+use anyhow::Result;
+
+pub fn get_pids(pid: u32) -> Result<Vec<u32>> {
+    // Windows doesn't have cgroups, only report the task process for now
+    Ok(vec![pid])
+}
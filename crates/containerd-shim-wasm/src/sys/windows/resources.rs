@@ -0,0 +1,23 @@
+use anyhow::{bail, Result};
+use serde::Serialize;
+
+/// Resources used by a task over its lifetime.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct ResourceSummary {
+    /// CPU time used by the task, in microseconds.
+    pub cpu_usage_usec: Option<u64>,
+    /// Peak memory usage of the task, in bytes.
+    pub memory_peak_bytes: Option<u64>,
+}
+
+pub struct ResourceTracker;
+
+impl ResourceTracker {
+    pub fn new(_pid: u32) -> Result<Self> {
+        bail!("resource tracking is not supported on Windows")
+    }
+
+    pub fn summary(&self) -> ResourceSummary {
+        ResourceSummary::default()
+    }
+}
@@ -1,16 +1,67 @@
 use std::fs::{File, OpenOptions};
-use std::io::Result;
+use std::io::{ErrorKind, Result};
+use std::os::windows::ffi::OsStrExt as _;
 use std::os::windows::fs::OpenOptionsExt as _;
 use std::path::Path;
+use std::time::{Duration, Instant};
 
+use windows_sys::Win32::Foundation::ERROR_PIPE_BUSY;
 use windows_sys::Win32::Storage::FileSystem::FILE_FLAG_OVERLAPPED;
+use windows_sys::Win32::System::Pipes::WaitNamedPipeW;
+
+/// How long to wait for containerd to accept a connection to a named pipe.
+const PIPE_BUSY_TIMEOUT: Duration = Duration::from_secs(5);
 
 pub fn open(path: impl AsRef<Path>) -> Result<File> {
+    let path = path.as_ref();
     // Containerd always passes a named pipe for stdin, stdout, and stderr so we can check if it is a pipe and open with overlapped IO
     let mut options = OpenOptions::new();
     options.read(true).write(true);
-    if path.as_ref().starts_with(r"\\.\pipe\") {
-        options.custom_flags(FILE_FLAG_OVERLAPPED);
+    if !is_named_pipe(path) {
+        return options.open(path);
+    }
+    options.custom_flags(FILE_FLAG_OVERLAPPED);
+    open_pipe(path, &options)
+}
+
+fn is_named_pipe(path: &Path) -> bool {
+    path.starts_with(r"\\.\pipe\")
+}
+
+// Connects to the named pipe at `path`, created by containerd.
+// All the instances of the pipe are busy until containerd accepts the previous connection,
+// so this waits for an instance to be available, up to `PIPE_BUSY_TIMEOUT`.
+fn open_pipe(path: &Path, options: &OpenOptions) -> Result<File> {
+    let deadline = Instant::now() + PIPE_BUSY_TIMEOUT;
+    let name: Vec<u16> = path.as_os_str().encode_wide().chain([0]).collect();
+    loop {
+        let err = match options.open(path) {
+            Err(err) if err.raw_os_error() == Some(ERROR_PIPE_BUSY as i32) => err,
+            res => return res,
+        };
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(std::io::Error::new(
+                ErrorKind::TimedOut,
+                format!("named pipe {path:?} is busy: {err}"),
+            ));
+        }
+        // this returns early if the pipe doesn't exist anymore, which the next open reports
+        let timeout = u32::try_from(remaining.as_millis()).unwrap_or(u32::MAX);
+        unsafe { WaitNamedPipeW(name.as_ptr(), timeout) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_named_pipe() {
+        assert!(is_named_pipe(Path::new(
+            r"\\.\pipe\containerd-shim-1-stdout"
+        )));
+        assert!(!is_named_pipe(Path::new(r"C:\logs\stdout.log")));
+        assert!(!is_named_pipe(Path::new("")));
     }
-    options.open(path)
 }
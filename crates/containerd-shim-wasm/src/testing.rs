@@ -29,6 +29,7 @@ where
     WasiInstance::Engine: Default + Send + Sync + Clone,
 {
     container_name: String,
+    containerd_address: String,
     start_fn: String,
     namespaces: Vec<LinuxNamespace>,
     tempdir: tempfile::TempDir,
@@ -73,6 +74,7 @@ where
         let builder = Self {
             tempdir,
             container_name: "test".to_string(),
+            containerd_address: "/run/containerd/containerd.sock".to_string(),
             start_fn: "".to_string(),
             namespaces: get_default_namespaces(),
             _phantom: Default::default(),
@@ -88,6 +90,11 @@ where
         self
     }
 
+    pub fn with_containerd_address(mut self, address: impl Into<String>) -> Self {
+        self.containerd_address = address.into();
+        self
+    }
+
     pub fn with_host_network(mut self) -> Self {
         // Removing the `network` namespace results in the binding to the host's socket.
         // This allows for direct communication with the host's networking interface.
@@ -214,7 +221,7 @@ where
 
         log::info!("building wasi test: {}", dir.display());
 
-        let mut cfg = InstanceConfig::new(TEST_NAMESPACE, "/run/containerd/containerd.sock");
+        let mut cfg = InstanceConfig::new(TEST_NAMESPACE, self.containerd_address);
         cfg.set_bundle(dir)
             .set_stdout(dir.join("stdout"))
             .set_stderr(dir.join("stderr"))
@@ -331,9 +338,21 @@ pub mod oci_helpers {
             log::debug!("dropping OCIGuard");
             clean_container(self.container_name.clone()).unwrap();
             clean_image(self.image_name.clone()).unwrap();
+            clean_instance_lease(&self.container_name);
         }
     }
 
+    // The lease is only created if the modules of the container were loaded
+    fn clean_instance_lease(container_name: &str) {
+        let _ = Command::new("ctr")
+            .arg("-n")
+            .arg(TEST_NAMESPACE)
+            .arg("leases")
+            .arg("rm")
+            .arg(format!("runwasi-instance-{container_name}"))
+            .output();
+    }
+
     pub fn clean_container(container_name: String) -> Result<()> {
         log::debug!("deleting container '{}'", container_name);
         let success = Command::new("ctr")
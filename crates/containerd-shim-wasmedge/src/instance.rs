@@ -1,7 +1,9 @@
 use std::collections::HashMap;
 
-use anyhow::{Context, Result};
-use containerd_shim_wasm::container::{Engine, Entrypoint, Instance, RuntimeContext};
+use anyhow::{bail, Context, Result};
+use containerd_shim_wasm::container::{
+    Engine, Entrypoint, Instance, RuntimeContext, WRITE_ALLOW_ANNOTATION,
+};
 use wasmedge_sdk::config::{CommonConfigOptions, Config, ConfigBuilder};
 use wasmedge_sdk::wasi::WasiModule;
 use wasmedge_sdk::{Module, Store, Vm};
@@ -15,10 +17,16 @@ pub struct WasmEdgeEngine {
 
 impl Default for WasmEdgeEngine {
     fn default() -> Self {
+        Self::new().expect("failed to create config")
+    }
+}
+
+impl WasmEdgeEngine {
+    fn new() -> Result<Self> {
         let config = ConfigBuilder::new(CommonConfigOptions::default())
             .build()
-            .expect("failed to create config");
-        Self { config }
+            .map_err(|err| anyhow::anyhow!("failed to create config: {err}"))?;
+        Ok(Self { config })
     }
 }
 
@@ -27,7 +35,21 @@ impl Engine for WasmEdgeEngine {
         "wasmedge"
     }
 
+    fn try_default() -> Result<Self> {
+        Self::new()
+    }
+
     fn run_wasi(&self, ctx: &impl RuntimeContext) -> Result<i32> {
+        if ctx.capabilities().is_strict() {
+            bail!("strict WASI mode is not supported by wasmedge");
+        }
+        if ctx.capabilities().clock_throttle().is_some() {
+            bail!("throttled clocks are not supported by wasmedge");
+        }
+        if !ctx.write_policy()?.is_unrestricted() {
+            bail!("the {WRITE_ALLOW_ANNOTATION} annotation is not supported by wasmedge");
+        }
+
         let args = ctx.args();
         let envs = ctx.envs();
         let Entrypoint {
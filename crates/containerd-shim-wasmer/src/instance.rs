@@ -1,5 +1,8 @@
-use anyhow::Result;
-use containerd_shim_wasm::container::{Engine, Entrypoint, Instance, RuntimeContext};
+use anyhow::{bail, Result};
+use containerd_shim_wasm::container::{
+    Engine, Entrypoint, Instance, RuntimeContext, WRITE_ALLOW_ANNOTATION,
+};
+use containerd_shim_wasm::sandbox::redact::Redacted;
 use tokio::runtime::Handle;
 use wasmer::{Module, Store};
 use wasmer_wasix::virtual_fs::host_fs::FileSystem;
@@ -18,6 +21,16 @@ impl Engine for WasmerEngine {
     }
 
     fn run_wasi(&self, ctx: &impl RuntimeContext) -> Result<i32> {
+        if ctx.capabilities().is_strict() {
+            bail!("strict WASI mode is not supported by wasmer");
+        }
+        if ctx.capabilities().clock_throttle().is_some() {
+            bail!("throttled clocks are not supported by wasmer");
+        }
+        if !ctx.write_policy()?.is_unrestricted() {
+            bail!("the {WRITE_ALLOW_ANNOTATION} annotation is not supported by wasmer");
+        }
+
         let args = ctx.args();
         let envs = ctx
             .envs()
@@ -47,7 +60,10 @@ impl Engine for WasmerEngine {
             .build()?;
         let _guard = runtime.enter();
 
-        log::info!("Creating `WasiEnv`...: args {args:?}, envs: {envs:?}");
+        log::info!(
+            "Creating `WasiEnv`...: args {args:?}, envs: {:?}",
+            Redacted(ctx.envs())
+        );
         let fs = FileSystem::new(Handle::current(), "/")?;
         let (instance, wasi_env) = WasiEnv::builder(mod_name)
             .args(&args[1..])
@@ -0,0 +1,68 @@
+//! The `wasi:clocks` of guests whose clocks are throttled.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use containerd_shim_wasm::container::{ClockThrottle, ThrottledClock};
+use wasmtime_wasi::{HostMonotonicClock, HostWallClock, WasiCtxBuilder};
+
+/// The throttled clocks of a guest, shared by all its stores so that the rate limit of the
+/// throttle applies to the guest as a whole, e.g., across the requests of a `wasi:http` proxy.
+#[derive(Clone)]
+pub struct GuestClocks {
+    wall: ThrottledWallClock,
+    monotonic: ThrottledMonotonicClock,
+}
+
+impl GuestClocks {
+    pub fn new(throttle: ClockThrottle) -> Self {
+        Self {
+            wall: ThrottledWallClock(Arc::new(ThrottledClock::new(throttle))),
+            monotonic: ThrottledMonotonicClock {
+                clock: Arc::new(ThrottledClock::new(throttle)),
+                start: Instant::now(),
+            },
+        }
+    }
+
+    /// Makes the guest of `builder` read these clocks.
+    pub fn apply(&self, builder: &mut WasiCtxBuilder) {
+        builder
+            .wall_clock(self.wall.clone())
+            .monotonic_clock(self.monotonic.clone());
+    }
+}
+
+#[derive(Clone)]
+struct ThrottledWallClock(Arc<ThrottledClock>);
+
+impl HostWallClock for ThrottledWallClock {
+    fn resolution(&self) -> Duration {
+        self.0.resolution()
+    }
+
+    fn now(&self) -> Duration {
+        self.0.read(|| {
+            SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+        })
+    }
+}
+
+// in nanoseconds since the guest started
+#[derive(Clone)]
+struct ThrottledMonotonicClock {
+    clock: Arc<ThrottledClock>,
+    start: Instant,
+}
+
+impl HostMonotonicClock for ThrottledMonotonicClock {
+    fn resolution(&self) -> u64 {
+        self.clock.resolution().as_nanos() as u64
+    }
+
+    fn now(&self) -> u64 {
+        self.clock.read(|| self.start.elapsed()).as_nanos() as u64
+    }
+}
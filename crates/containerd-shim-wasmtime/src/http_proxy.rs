@@ -8,7 +8,11 @@ use std::sync::Arc;
 use std::time::Duration;
 
 use anyhow::{bail, Result};
-use containerd_shim_wasm::container::RuntimeContext;
+use containerd_shim_wasm::container::{
+    ComponentLimits, FdRole, GuestLogger, RuntimeContext, TerminationDeadline,
+};
+use http_body_util::BodyExt;
+use hyper::body::{Body, Bytes};
 use hyper::server::conn::http1;
 use tokio::net::{TcpListener, TcpStream};
 use tokio_util::sync::CancellationToken;
@@ -21,7 +25,9 @@ use wasmtime_wasi_http::body::HyperOutgoingBody;
 use wasmtime_wasi_http::io::TokioIo;
 use wasmtime_wasi_http::{WasiHttpCtx, WasiHttpView};
 
-use crate::instance::{envs_from_ctx, WasiPreview2Ctx};
+use crate::clock::GuestClocks;
+use crate::instance::{envs_from_ctx, limit_resource_handles, store_limits, WasiPreview2Ctx};
+use crate::shadow::{tee_request, ShadowProxy, SHADOW_COMPONENT_ENV};
 
 const DEFAULT_ADDR: SocketAddr =
     SocketAddr::new(IpAddr::V4(std::net::Ipv4Addr::new(0, 0, 0, 0)), 8080);
@@ -80,30 +86,33 @@ pub(crate) async fn serve_conn(
         .remove("WASMTIME_HTTP_BACKLOG")
         .and_then(|v| v.parse().ok())
         .unwrap_or(DEFAULT_BACKLOG);
-
-    let socket = match addr {
-        SocketAddr::V4(_) => tokio::net::TcpSocket::new_v4()?,
-        SocketAddr::V6(_) => tokio::net::TcpSocket::new_v6()?,
+    let shadow = env
+        .remove(SHADOW_COMPONENT_ENV)
+        .map(|path| ShadowProxy::load(instance.engine(), &path))
+        .transpose()?
+        .map(Arc::new);
+
+    let listener = match inherited_listener(ctx)? {
+        Some(listener) => listener,
+        None => bind(addr, backlog)?,
     };
-
-    // Conditionally enable `SO_REUSEADDR` depending on the current
-    // platform. On Unix we want this to be able to rebind an address in
-    // the `TIME_WAIT` state which can happen then a server is killed with
-    // active TCP connections and then restarted. On Windows though if
-    // `SO_REUSEADDR` is specified then it enables multiple applications to
-    // bind the port at the same time which is not something we want. Hence
-    // this is conditionally set based on the platform (and deviates from
-    // Tokio's default from always-on).
-    socket.set_reuseaddr(!cfg!(windows))?;
-    socket.bind(addr)?;
-
-    let listener = socket.listen(backlog)?;
     let tracker = TaskTracker::new();
 
     log::info!("Serving HTTP on http://{}/", listener.local_addr()?);
 
     let env = env.into_iter().collect();
-    let handler = Arc::new(ProxyHandler::new(instance, env, tracker.clone()));
+    let handler = Arc::new(
+        ProxyHandler::new(
+            instance,
+            shadow.clone(),
+            env,
+            ctx.termination_deadline(),
+            ctx.guest_logger(),
+            ctx.component_limits(),
+            tracker.clone(),
+        )
+        .with_clocks(ctx.capabilities().clock_throttle().map(GuestClocks::new)),
+    );
 
     loop {
         let stream = tokio::select! {
@@ -136,56 +145,178 @@ pub(crate) async fn serve_conn(
     }
 
     tracker.close();
-    tracker.wait().await;
+    let drain = ctx.drain_window();
+    if drain.window().is_some() {
+        drain.drain(|| tracker.len()).await;
+    } else {
+        tracker.wait().await;
+    }
+
+    if let Some(shadow) = shadow {
+        shadow.log_summary();
+    }
 
     Ok(())
 }
 
+fn bind(addr: SocketAddr, backlog: u32) -> Result<TcpListener> {
+    let socket = match addr {
+        SocketAddr::V4(_) => tokio::net::TcpSocket::new_v4()?,
+        SocketAddr::V6(_) => tokio::net::TcpSocket::new_v6()?,
+    };
+
+    // Conditionally enable `SO_REUSEADDR` depending on the current
+    // platform. On Unix we want this to be able to rebind an address in
+    // the `TIME_WAIT` state which can happen then a server is killed with
+    // active TCP connections and then restarted. On Windows though if
+    // `SO_REUSEADDR` is specified then it enables multiple applications to
+    // bind the port at the same time which is not something we want. Hence
+    // this is conditionally set based on the platform (and deviates from
+    // Tokio's default from always-on).
+    socket.set_reuseaddr(!cfg!(windows))?;
+    socket.bind(addr)?;
+
+    Ok(socket.listen(backlog)?)
+}
+
+// The first listening socket passed by the platform with `runwasi.io/inherit-fds`, if any.
+#[cfg(unix)]
+fn inherited_listener(ctx: &impl RuntimeContext) -> Result<Option<TcpListener>> {
+    use std::os::fd::OwnedFd;
+
+    let mut listeners = ctx
+        .inherited_fds()
+        .iter()
+        .filter(|fd| fd.role() == FdRole::Listener);
+    let Some(fd) = listeners.next() else {
+        return Ok(None);
+    };
+    if listeners.next().is_some() {
+        log::warn!("serving HTTP on listener {:?} only", fd.name());
+    }
+    let listener = std::net::TcpListener::from(OwnedFd::from(fd.file().try_clone()?));
+    listener.set_nonblocking(true)?;
+    Ok(Some(TcpListener::from_std(listener)?))
+}
+
+#[cfg(not(unix))]
+fn inherited_listener(_ctx: &impl RuntimeContext) -> Result<Option<TcpListener>> {
+    Ok(None)
+}
+
 struct ProxyHandler {
     instance_pre: ProxyPre<WasiPreview2Ctx>,
+    shadow: Option<Arc<ShadowProxy>>,
     next_id: AtomicU64,
     env: Vec<(String, String)>,
+    termination: TerminationDeadline,
+    logger: GuestLogger,
+    limits: ComponentLimits,
+    clocks: Option<GuestClocks>,
     tracker: TaskTracker,
 }
 
 impl ProxyHandler {
     fn new(
         instance_pre: ProxyPre<WasiPreview2Ctx>,
+        shadow: Option<Arc<ShadowProxy>>,
         env: Vec<(String, String)>,
+        termination: TerminationDeadline,
+        logger: GuestLogger,
+        limits: ComponentLimits,
         tracker: TaskTracker,
     ) -> Self {
         ProxyHandler {
             instance_pre,
+            shadow,
             env,
+            termination,
+            logger,
+            limits,
+            clocks: None,
             tracker,
             next_id: AtomicU64::from(0),
         }
     }
 
+    // the requests share the clocks of the guest, for their rate limit to apply to the guest
+    fn with_clocks(mut self, clocks: Option<GuestClocks>) -> Self {
+        self.clocks = clocks;
+        self
+    }
+
     fn wasi_store_for_request(&self, req_id: u64) -> Store<WasiPreview2Ctx> {
         let engine = self.instance_pre.engine();
         let mut builder = wasmtime_wasi::WasiCtxBuilder::new();
 
         builder.envs(&self.env);
         builder.env("REQUEST_ID", req_id.to_string());
+        if let Some(clocks) = &self.clocks {
+            clocks.apply(&mut builder);
+        }
 
         let ctx = WasiPreview2Ctx {
             wasi_ctx: builder.build(),
             wasi_http: WasiHttpCtx::new(),
             resource_table: ResourceTable::default(),
+            termination: self.termination.clone(),
+            logger: self.logger.clone(),
+            limits: store_limits(self.limits),
+            max_resource_handles: self.limits.max_resource_handles,
         };
 
-        Store::new(engine, ctx)
+        let mut store = Store::new(engine, ctx);
+        store.limiter(|ctx| &mut ctx.limits);
+        limit_resource_handles(&mut store, self.limits.max_resource_handles);
+        store
     }
 
     async fn handle_request(
         self: Arc<Self>,
         req: Request,
     ) -> Result<hyper::Response<HyperOutgoingBody>> {
-        let (sender, receiver) = tokio::sync::oneshot::channel();
-
         let req_id = self.next_req_id();
 
+        let Some(shadow) = self.shadow.clone() else {
+            return self.call(&self.instance_pre, req_id, req).await;
+        };
+
+        let (req, copy) = tee_request(req).await?;
+        let resp = self.call(&self.instance_pre, req_id, req).await;
+        let status = resp.as_ref().ok().map(|resp| resp.status());
+
+        // The shadow response is discarded, but its body is still consumed
+        // so that the shadow component runs to completion.
+        let this = self.clone();
+        self.tracker.spawn(async move {
+            let res = match this.call(shadow.instance_pre(), req_id, copy).await {
+                Ok(resp) => {
+                    let status = resp.status();
+                    resp.into_body()
+                        .collect()
+                        .await
+                        .map(|_| status)
+                        .map_err(|e| anyhow::anyhow!("{e:?}"))
+                }
+                Err(err) => Err(err),
+            };
+            shadow.record(req_id, status, res);
+        });
+
+        resp
+    }
+
+    async fn call<B>(
+        &self,
+        instance_pre: &ProxyPre<WasiPreview2Ctx>,
+        req_id: u64,
+        req: hyper::Request<B>,
+    ) -> Result<hyper::Response<HyperOutgoingBody>>
+    where
+        B: Body<Data = Bytes, Error = hyper::Error> + Send + Sync + 'static,
+    {
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+
         log::trace!(
             "Request {req_id} handling {} to {}",
             req.method(),
@@ -196,7 +327,7 @@ impl ProxyHandler {
 
         let req = store.data_mut().new_incoming_request(Scheme::Http, req)?;
         let out = store.data_mut().new_response_outparam(sender)?;
-        let proxy = self.instance_pre.instantiate_async(&mut store).await?;
+        let proxy = instance_pre.instantiate_async(&mut store).await?;
 
         let task = self.tracker.spawn(async move {
             if let Err(e) = proxy
@@ -4,7 +4,8 @@ use std::sync::LazyLock;
 
 use anyhow::{bail, Context, Result};
 use containerd_shim_wasm::container::{
-    Engine, Entrypoint, Instance, RuntimeContext, WasmBinaryType,
+    ComponentLimits, Engine, Entrypoint, GuestLogger, Instance, PrecompiledArtifact,
+    RuntimeContext, TerminationDeadline, WasmBinaryType,
 };
 use containerd_shim_wasm::sandbox::WasmLayer;
 use tokio_util::sync::CancellationToken;
@@ -12,13 +13,19 @@ use wasi_preview1::WasiP1Ctx;
 use wasi_preview2::bindings::Command;
 use wasmtime::component::types::ComponentItem;
 use wasmtime::component::{self, Component, ResourceTable};
-use wasmtime::{Config, Module, Precompiled, Store};
+use wasmtime::{CallHook, Config, Module, Precompiled, Store, StoreLimits, StoreLimitsBuilder};
 use wasmtime_wasi::preview1::{self as wasi_preview1};
 use wasmtime_wasi::{self as wasi_preview2};
 use wasmtime_wasi_http::bindings::ProxyPre;
-use wasmtime_wasi_http::{WasiHttpCtx, WasiHttpView};
+use wasmtime_wasi_http::body::HyperOutgoingBody;
+use wasmtime_wasi_http::types::{
+    default_send_request, HostFutureIncomingResponse, OutgoingRequestConfig,
+};
+use wasmtime_wasi_http::{HttpResult, WasiHttpCtx, WasiHttpView};
 
+use crate::clock::GuestClocks;
 use crate::http_proxy::serve_conn;
+use crate::logging;
 
 pub type WasmtimeInstance = Instance<WasmtimeEngine>;
 
@@ -53,8 +60,42 @@ impl<'a> ComponentTarget<'a> {
     }
 }
 
-#[derive(Clone, Default, Debug)]
-pub struct WasmtimeEngine;
+/// The wasmtime engine of the containers.
+///
+/// It is built once in the zygote of the shim and shared by the containers forked from it, see
+/// [`Engine::shared`]. Building it doesn't start any thread: the threads of the parallel
+/// compilation are only started when a module is compiled, in the container process.
+#[derive(Clone)]
+pub struct WasmtimeEngine {
+    engine: wasmtime::Engine,
+}
+
+impl Default for WasmtimeEngine {
+    fn default() -> Self {
+        Self::new().unwrap()
+    }
+}
+
+impl WasmtimeEngine {
+    fn new() -> Result<Self> {
+        let mut config = wasmtime::Config::new();
+
+        // Disable Wasmtime parallel compilation for the tests
+        // see https://github.com/containerd/runwasi/pull/405#issuecomment-1928468714 for details
+        config.parallel_compilation(!cfg!(test));
+        config.wasm_component_model(true); // enable component linking
+        config.async_support(true); // must be on
+
+        if use_pooling_allocator_by_default() {
+            let cfg = wasmtime::PoolingAllocationConfig::default();
+            config.allocation_strategy(wasmtime::InstanceAllocationStrategy::Pooling(cfg));
+        }
+
+        Ok(Self {
+            engine: wasmtime::Engine::new(&config).context("failed to create wasmtime engine")?,
+        })
+    }
+}
 
 static PRECOMPILER: LazyLock<wasmtime::Engine> = LazyLock::new(|| {
     let mut config = wasmtime::Config::new();
@@ -74,25 +115,10 @@ pub struct WasmtimeEngineImpl {
     cancel: CancellationToken,
 }
 
-impl Default for WasmtimeEngineImpl {
-    fn default() -> Self {
-        let mut config = wasmtime::Config::new();
-
-        // Disable Wasmtime parallel compilation for the tests
-        // see https://github.com/containerd/runwasi/pull/405#issuecomment-1928468714 for details
-        config.parallel_compilation(!cfg!(test));
-        config.wasm_component_model(true); // enable component linking
-        config.async_support(true); // must be on
-
-        if use_pooling_allocator_by_default() {
-            let cfg = wasmtime::PoolingAllocationConfig::default();
-            config.allocation_strategy(wasmtime::InstanceAllocationStrategy::Pooling(cfg));
-        }
-
+impl WasmtimeEngineImpl {
+    fn new(engine: &WasmtimeEngine) -> Self {
         Self {
-            engine: wasmtime::Engine::new(&config)
-                .context("failed to create wasmtime engine")
-                .unwrap(),
+            engine: engine.engine.clone(),
             cancel: CancellationToken::new(),
         }
     }
@@ -102,6 +128,10 @@ pub struct WasiPreview2Ctx {
     pub(crate) wasi_ctx: wasi_preview2::WasiCtx,
     pub(crate) wasi_http: WasiHttpCtx,
     pub(crate) resource_table: ResourceTable,
+    pub(crate) termination: TerminationDeadline,
+    pub(crate) logger: GuestLogger,
+    pub(crate) limits: StoreLimits,
+    pub(crate) max_resource_handles: Option<u32>,
 }
 
 impl WasiPreview2Ctx {
@@ -110,10 +140,53 @@ impl WasiPreview2Ctx {
             wasi_ctx: wasi_builder(ctx)?.build(),
             wasi_http: WasiHttpCtx::new(),
             resource_table: ResourceTable::default(),
+            termination: ctx.termination_deadline(),
+            logger: ctx.guest_logger(),
+            limits: store_limits(ctx.component_limits()),
+            max_resource_handles: ctx.component_limits().max_resource_handles,
         })
     }
 }
 
+/// Limits of the stores of components, enforcing `max_instances` at runtime, for precompiled
+/// components which aren't checked before they run.
+pub(crate) fn store_limits(limits: ComponentLimits) -> StoreLimits {
+    let mut builder = StoreLimitsBuilder::new();
+    if let Some(max) = limits.max_instances {
+        builder = builder.instances(max as usize);
+    }
+    builder.build()
+}
+
+/// Traps the guest of `store` when a call to the host returns with the component holding more
+/// than `max` resource handles.
+///
+/// The resource tables of wasmtime can't be bounded, but they reuse the slots of the deleted
+/// handles before they grow, so a handle pushed on the table gets an index beyond `max` only
+/// when all the slots below it hold handles. The table is probed with such a handle after
+/// each host call, which are the only ones pushing handles.
+pub(crate) fn limit_resource_handles<T: wasi_preview2::WasiView>(
+    store: &mut Store<T>,
+    max: Option<u32>,
+) {
+    let Some(max) = max else {
+        return;
+    };
+    store.call_hook(move |mut ctx, hook| {
+        if !matches!(hook, CallHook::ReturningFromHost) {
+            return Ok(());
+        }
+        let table = ctx.data_mut().table();
+        let probe = table.push(())?;
+        let held = probe.rep();
+        table.delete(probe)?;
+        if held > max {
+            bail!("the component holds more than {max} resource handles");
+        }
+        Ok(())
+    });
+}
+
 /// This impl is required to use wasmtime_wasi::preview2::WasiView trait.
 impl wasi_preview2::WasiView for WasiPreview2Ctx {
     fn table(&mut self) -> &mut ResourceTable {
@@ -133,6 +206,18 @@ impl WasiHttpView for WasiPreview2Ctx {
     fn ctx(&mut self) -> &mut wasmtime_wasi_http::WasiHttpCtx {
         &mut self.wasi_http
     }
+
+    // Outgoing requests made while the task is terminating must not outlive it.
+    fn send_request(
+        &mut self,
+        request: hyper::Request<HyperOutgoingBody>,
+        mut config: OutgoingRequestConfig,
+    ) -> HttpResult<HostFutureIncomingResponse> {
+        config.connect_timeout = self.termination.bound(config.connect_timeout);
+        config.first_byte_timeout = self.termination.bound(config.first_byte_timeout);
+        config.between_bytes_timeout = self.termination.bound(config.between_bytes_timeout);
+        Ok(default_send_request(request, config))
+    }
 }
 
 impl Engine for WasmtimeEngine {
@@ -140,6 +225,14 @@ impl Engine for WasmtimeEngine {
         "wasmtime"
     }
 
+    fn try_default() -> Result<Self> {
+        Self::new()
+    }
+
+    fn shared() -> bool {
+        true
+    }
+
     fn run_wasi(&self, ctx: &impl RuntimeContext) -> Result<i32> {
         log::info!("setting up wasi");
         let Entrypoint {
@@ -149,8 +242,21 @@ impl Engine for WasmtimeEngine {
             name: _,
         } = ctx.entrypoint();
 
+        // the guest runs on a runtime of its own, so that its tasks are the tasks of the
+        // runtime, see `HostTasks`
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()?;
+        let _runtime = runtime.enter();
+
+        if let Some(artifact) = ctx.precompiled_artifact() {
+            return WasmtimeEngineImpl::new(self)
+                .execute_artifact(ctx, artifact, func)
+                .into_error_code();
+        }
+
         let wasm_bytes = &source.as_bytes()?;
-        WasmtimeEngineImpl::default()
+        WasmtimeEngineImpl::new(self)
             .execute(ctx, wasm_bytes, func)
             .into_error_code()
     }
@@ -204,8 +310,8 @@ impl WasmtimeEngineImpl {
     ) -> Result<i32> {
         log::debug!("execute module");
 
-        let ctx = wasi_builder(ctx)?.build_p1();
-        let mut store = Store::new(&self.engine, ctx);
+        let p1_ctx = wasi_builder(ctx)?.build_p1();
+        let mut store = Store::new(&self.engine, p1_ctx);
         let mut module_linker = wasmtime::Linker::new(&self.engine);
 
         log::debug!("init linker");
@@ -224,11 +330,11 @@ impl WasmtimeEngineImpl {
                 .context("module does not have a WASI start function")?;
 
             log::info!("running start function {func:?}");
+            ctx.startup_complete();
 
-            start_func
-                .call_async(&mut store, &[], &mut [])
-                .await
-                .into_error_code()
+            let tasks = HostTasks::start();
+            let status = start_func.call_async(&mut store, &[], &mut []).await;
+            tasks.drain_on_success(ctx, status).await
         })
     }
 
@@ -246,42 +352,54 @@ impl WasmtimeEngineImpl {
         );
 
         // This is a adapter logic that converts wasip1 `_start` function to wasip2 `run` function.
-        let status = match target {
+        match target {
             ComponentTarget::HttpProxy => {
                 log::info!("Found HTTP proxy target");
+                if !ctx.capabilities().http() {
+                    bail!("wasi:http is not allowed in strict WASI mode");
+                }
                 let mut linker = component::Linker::new(&self.engine);
                 wasmtime_wasi::add_to_linker_async(&mut linker)?;
                 wasmtime_wasi_http::add_only_http_to_linker_async(&mut linker)?;
+                logging::add_to_linker(&mut linker)?;
 
                 let pre = linker.instantiate_pre(&component)?;
                 log::info!("pre-instantiate_pre");
                 let instance = ProxyPre::new(pre)?;
 
                 log::info!("starting HTTP server");
+                ctx.startup_complete();
                 let cancel = self.cancel.clone();
-                serve_conn(ctx, instance, cancel).await
+                serve_conn(ctx, instance, cancel).await.into_error_code()
             }
             ComponentTarget::Command => {
                 log::info!("Found command target");
                 let wasi_ctx = WasiPreview2Ctx::new(ctx)?;
-                let (mut store, linker) = store_for_context(&self.engine, wasi_ctx)?;
+                let (mut store, mut linker) = store_for_context(&self.engine, wasi_ctx)?;
+                logging::add_to_linker(&mut linker)?;
 
                 let command = Command::instantiate_async(&mut store, &component, &linker).await?;
+                ctx.startup_complete();
 
-                command
+                let tasks = HostTasks::start();
+                let status = command
                     .wasi_cli_run()
                     .call_run(&mut store)
-                    .await?
-                    .map_err(|_| {
-                        anyhow::anyhow!(
-                            "failed to run component targeting `wasi:cli/command` world"
-                        )
-                    })
+                    .await
+                    .and_then(|res| {
+                        res.map_err(|_| {
+                            anyhow::anyhow!(
+                                "failed to run component targeting `wasi:cli/command` world"
+                            )
+                        })
+                    });
+                tasks.drain_on_success(ctx, status).await
             }
             ComponentTarget::Core(func) => {
                 log::info!("Found Core target");
                 let wasi_ctx = WasiPreview2Ctx::new(ctx)?;
-                let (mut store, linker) = store_for_context(&self.engine, wasi_ctx)?;
+                let (mut store, mut linker) = store_for_context(&self.engine, wasi_ctx)?;
+                logging::add_to_linker(&mut linker)?;
 
                 let pre = linker.instantiate_pre(&component)?;
                 let instance = pre.instantiate_async(&mut store).await?;
@@ -292,11 +410,12 @@ impl WasmtimeEngineImpl {
                 ))?;
 
                 log::debug!("running exported function {func:?} {start_func:?}");
-                start_func.call_async(&mut store, &[], &mut []).await
+                ctx.startup_complete();
+                let tasks = HostTasks::start();
+                let status = start_func.call_async(&mut store, &[], &mut []).await;
+                tasks.drain_on_success(ctx, status).await
             }
-        };
-
-        status.into_error_code()
+        }
     }
 
     /// Execute a wasm component.
@@ -311,32 +430,79 @@ impl WasmtimeEngineImpl {
     ) -> Result<i32> {
         log::debug!("loading wasm component");
 
+        // a proxy stops serving gracefully when containerd stops its task, other components
+        // can't tell they're being stopped
+        let is_proxy = matches!(
+            ComponentTarget::new(component.component_type().exports(&self.engine), &func),
+            ComponentTarget::HttpProxy
+        );
+
         wasmtime_wasi::runtime::in_tokio(async move {
             tokio::select! {
                 status = self.execute_component_async(ctx, component, func) => {
                     status
                 }
-                status = self.handle_signals() => {
+                status = self.handle_signals(ctx.termination_deadline(), is_proxy) => {
                     status
                 }
             }
         })
     }
 
-    async fn handle_signals(&self) -> Result<i32> {
-        match wait_for_signal().await? {
-            libc::SIGINT => {
-                // Request graceful shutdown;
-                self.cancel.cancel();
-            }
+    async fn handle_signals(
+        &self,
+        termination: TerminationDeadline,
+        is_proxy: bool,
+    ) -> Result<i32> {
+        let sig = match wait_for_signal().await? {
+            libc::SIGINT => libc::SIGINT,
+            libc::SIGTERM if is_proxy => libc::SIGTERM,
             sig => {
                 // On other signal, terminate the process without waiting for spawned tasks to finish.
                 return Ok(128 + sig);
             }
+        };
+
+        // Request graceful shutdown, starting the countdown of the grace period
+        termination.terminate();
+        self.cancel.cancel();
+
+        // On a second signal, or once the grace period of a SIGTERM elapsed, terminate the
+        // process as well
+        let grace_period = termination.remaining().unwrap_or_default();
+        tokio::select! {
+            status = wait_for_signal() => status,
+            _ = tokio::time::sleep(grace_period), if sig == libc::SIGTERM => Ok(128 + sig),
         }
+    }
 
-        // On a second SIGINT, terminate the process as well
-        wait_for_signal().await
+    /// Execute a precompiled artifact shared with the other containers of the node.
+    ///
+    /// The code is loaded from the file of the artifact, so that its pages are shared too.
+    fn execute_artifact(
+        &self,
+        ctx: &impl RuntimeContext,
+        artifact: &PrecompiledArtifact,
+        func: String,
+    ) -> Result<i32> {
+        // the file is reached through /proc, which might not be mounted in the container
+        if !artifact.path().exists() {
+            return self.execute(ctx, artifact, func);
+        }
+        match self.engine.detect_precompiled(artifact) {
+            Some(Precompiled::Module) => {
+                log::info!("using shared precompiled module");
+                let module = unsafe { Module::deserialize_file(&self.engine, artifact.path()) }?;
+                self.execute_module(ctx, module, &func)
+            }
+            Some(Precompiled::Component) => {
+                log::info!("using shared precompiled component");
+                let component =
+                    unsafe { Component::deserialize_file(&self.engine, artifact.path()) }?;
+                self.execute_component(ctx, component, func)
+            }
+            None => self.execute(ctx, artifact, func),
+        }
     }
 
     fn execute(&self, ctx: &impl RuntimeContext, wasm_binary: &[u8], func: String) -> Result<i32> {
@@ -347,6 +513,7 @@ impl WasmtimeEngineImpl {
                 self.execute_module(ctx, module, &func)
             }
             Some(WasmBinaryType::Component) => {
+                ctx.component_limits().check(wasm_binary)?;
                 let component = Component::from_binary(&self.engine, wasm_binary)?;
                 self.execute_component(ctx, component, func)
             }
@@ -379,11 +546,14 @@ pub(crate) fn envs_from_ctx(ctx: &impl RuntimeContext) -> Vec<(String, String)>
         .collect()
 }
 
-fn store_for_context<T: wasi_preview2::WasiView>(
+fn store_for_context(
     engine: &wasmtime::Engine,
-    ctx: T,
-) -> Result<(Store<T>, component::Linker<T>)> {
-    let store = Store::new(engine, ctx);
+    ctx: WasiPreview2Ctx,
+) -> Result<(Store<WasiPreview2Ctx>, component::Linker<WasiPreview2Ctx>)> {
+    let max_resource_handles = ctx.max_resource_handles;
+    let mut store = Store::new(engine, ctx);
+    store.limiter(|ctx| &mut ctx.limits);
+    limit_resource_handles(&mut store, max_resource_handles);
 
     log::debug!("init linker");
     let mut linker = component::Linker::new(engine);
@@ -396,20 +566,59 @@ fn wasi_builder(ctx: &impl RuntimeContext) -> Result<wasi_preview2::WasiCtxBuild
     // TODO: make this more configurable (e.g. allow the user to specify the
     // preopened directories and their permissions)
     // https://github.com/containerd/runwasi/issues/413
-    let file_perms = wasi_preview2::FilePerms::all();
-    let dir_perms = wasi_preview2::DirPerms::all();
     let envs = envs_from_ctx(ctx);
+    let write_policy = ctx.write_policy()?;
 
     let mut builder = wasi_preview2::WasiCtxBuilder::new();
-    builder
-        .args(ctx.args())
-        .envs(&envs)
-        .inherit_stdio()
-        .inherit_network()
-        .allow_tcp(true)
-        .allow_udp(true)
-        .allow_ip_name_lookup(true)
-        .preopened_dir("/", "/", dir_perms, file_perms)?;
+    builder.args(ctx.args()).envs(&envs).inherit_stdio();
+
+    if ctx.capabilities().network() {
+        builder
+            .inherit_network()
+            .allow_tcp(true)
+            .allow_udp(true)
+            .allow_ip_name_lookup(true);
+    } else {
+        log::info!("networking is disabled in strict WASI mode");
+        builder
+            .allow_tcp(false)
+            .allow_udp(false)
+            .allow_ip_name_lookup(false);
+    }
+
+    if let Some(throttle) = ctx.capabilities().clock_throttle() {
+        log::info!("the clocks of the guest are throttled: {throttle:?}");
+        GuestClocks::new(throttle).apply(&mut builder);
+    }
+
+    if write_policy.is_unrestricted() {
+        builder.preopened_dir(
+            "/",
+            "/",
+            wasi_preview2::DirPerms::all(),
+            wasi_preview2::FilePerms::all(),
+        )?;
+        return Ok(builder);
+    }
+
+    // Preopen the root as read-only, and layer the allowed directories on top of it.
+    // Guests resolve paths against the longest matching preopen.
+    builder.preopened_dir(
+        "/",
+        "/",
+        wasi_preview2::DirPerms::READ,
+        wasi_preview2::FilePerms::READ,
+    )?;
+    for dir in write_policy.writable_dirs() {
+        log::debug!("granting write access to {dir:?}");
+        let guest_path = dir.to_string_lossy();
+        builder.preopened_dir(
+            &dir,
+            guest_path,
+            wasi_preview2::DirPerms::all(),
+            wasi_preview2::FilePerms::all(),
+        )?;
+    }
     Ok(builder)
 }
 
@@ -452,6 +661,35 @@ fn use_pooling_allocator_by_default() -> bool {
     *SUPPORTS_POOLING_ALLOCATOR
 }
 
+/// The tasks the host spawned for a guest, e.g., to stream bodies. The guest runs on a runtime
+/// of its own, which only runs the tasks the host spawns for it, so they are the alive tasks of
+/// the runtime.
+struct HostTasks {
+    metrics: tokio::runtime::RuntimeMetrics,
+}
+
+impl HostTasks {
+    fn start() -> Self {
+        Self {
+            metrics: tokio::runtime::Handle::current().metrics(),
+        }
+    }
+
+    fn pending(&self) -> usize {
+        self.metrics.num_alive_tasks()
+    }
+
+    /// Waits for the pending tasks for the drain window of the container if the guest exited
+    /// with 0, while its store, which the tasks are dropped with, is still alive.
+    async fn drain_on_success(&self, ctx: &impl RuntimeContext, status: Result<()>) -> Result<i32> {
+        let code = status.into_error_code();
+        if matches!(code, Ok(0)) {
+            ctx.drain_window().drain(|| self.pending()).await;
+        }
+        code
+    }
+}
+
 pub trait IntoErrorCode {
     fn into_error_code(self) -> Result<i32>;
 }
@@ -1,5 +1,8 @@
+mod clock;
 mod http_proxy;
 pub mod instance;
+mod logging;
+mod shadow;
 
 pub use instance::WasmtimeInstance;
 
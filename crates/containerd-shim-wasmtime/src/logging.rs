@@ -0,0 +1,55 @@
+//! `wasi:logging` for components, bridged to the shim with
+//! [`RuntimeContext::guest_logger`](containerd_shim_wasm::container::RuntimeContext::guest_logger).
+
+use anyhow::Result;
+use containerd_shim_wasm::container::GuestLogLevel;
+use wasmtime::component::Linker;
+
+use crate::instance::WasiPreview2Ctx;
+
+mod bindings {
+    wasmtime::component::bindgen!({
+        inline: "
+            package wasi:logging;
+
+            interface logging {
+                enum level {
+                    trace,
+                    debug,
+                    info,
+                    warn,
+                    error,
+                    critical,
+                }
+
+                log: func(level: level, context: string, message: string);
+            }
+
+            world imports {
+                import logging;
+            }
+        ",
+        world: "imports",
+    });
+}
+
+use bindings::wasi::logging::logging::{self, Level};
+
+impl logging::Host for WasiPreview2Ctx {
+    fn log(&mut self, level: Level, context: String, message: String) {
+        let level = match level {
+            Level::Trace => GuestLogLevel::Trace,
+            Level::Debug => GuestLogLevel::Debug,
+            Level::Info => GuestLogLevel::Info,
+            Level::Warn => GuestLogLevel::Warn,
+            Level::Error => GuestLogLevel::Error,
+            Level::Critical => GuestLogLevel::Critical,
+        };
+        self.logger.log(level, &context, &message);
+    }
+}
+
+/// Adds `wasi:logging` to the linker of a component.
+pub(crate) fn add_to_linker(linker: &mut Linker<WasiPreview2Ctx>) -> Result<()> {
+    logging::add_to_linker(linker, |ctx| ctx)
+}
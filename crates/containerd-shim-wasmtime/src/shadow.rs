@@ -0,0 +1,140 @@
+//! Shadow runs of a new version of a `wasi:http/proxy` component.
+//!
+//! When `WASMTIME_HTTP_SHADOW_COMPONENT` points to a component in the container, every request
+//! served by the main component is also sent to the shadow component.
+//! The responses of the shadow component are discarded, and are only used to count the
+//! requests where it diverged from the main component.
+//! This can be used to canary a new version of a component with production traffic.
+
+use std::convert::Infallible;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use anyhow::{Context, Result};
+use http_body_util::combinators::BoxBody;
+use http_body_util::{BodyExt, Full};
+use hyper::body::Bytes;
+use hyper::StatusCode;
+use wasmtime::component::{self, Component};
+use wasmtime_wasi_http::bindings::ProxyPre;
+
+use crate::instance::WasiPreview2Ctx;
+use crate::logging;
+
+/// Environment variable with the path of the shadow component.
+pub(crate) const SHADOW_COMPONENT_ENV: &str = "WASMTIME_HTTP_SHADOW_COMPONENT";
+
+pub(crate) type BufferedRequest = hyper::Request<BoxBody<Bytes, hyper::Error>>;
+
+#[derive(Default)]
+struct ShadowStats {
+    requests: AtomicU64,
+    errors: AtomicU64,
+    diverged: AtomicU64,
+}
+
+impl ShadowStats {
+    fn record(&self, req_id: u64, primary: Option<StatusCode>, shadow: Result<StatusCode>) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        let shadow = match shadow {
+            Ok(status) => Some(status),
+            Err(err) => {
+                log::debug!("[{req_id}] shadow component failed: {err:#}");
+                self.errors.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        };
+        if primary != shadow {
+            log::debug!("[{req_id}] shadow response diverged: {primary:?} != {shadow:?}");
+            self.diverged.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// A component that receives a copy of the requests served by the main component.
+pub(crate) struct ShadowProxy {
+    instance_pre: ProxyPre<WasiPreview2Ctx>,
+    stats: ShadowStats,
+}
+
+impl ShadowProxy {
+    pub fn load(engine: &wasmtime::Engine, path: &str) -> Result<Self> {
+        let component = Component::from_file(engine, path)
+            .with_context(|| format!("failed to load shadow component {path:?}"))?;
+
+        let mut linker = component::Linker::new(engine);
+        wasmtime_wasi::add_to_linker_async(&mut linker)?;
+        wasmtime_wasi_http::add_only_http_to_linker_async(&mut linker)?;
+        logging::add_to_linker(&mut linker)?;
+
+        let instance_pre = ProxyPre::new(linker.instantiate_pre(&component)?)?;
+
+        log::info!("shadowing requests to {path:?}");
+
+        Ok(Self {
+            instance_pre,
+            stats: ShadowStats::default(),
+        })
+    }
+
+    pub fn instance_pre(&self) -> &ProxyPre<WasiPreview2Ctx> {
+        &self.instance_pre
+    }
+
+    /// Records the outcome of a request served by both the main and the shadow components.
+    /// `primary` is `None` if the main component failed to produce a response.
+    pub fn record(&self, req_id: u64, primary: Option<StatusCode>, shadow: Result<StatusCode>) {
+        self.stats.record(req_id, primary, shadow);
+    }
+
+    pub fn log_summary(&self) {
+        log::info!(
+            "shadow component served {} requests: {} diverged, {} failed",
+            self.stats.requests.load(Ordering::Relaxed),
+            self.stats.diverged.load(Ordering::Relaxed),
+            self.stats.errors.load(Ordering::Relaxed),
+        );
+    }
+}
+
+/// Buffers the body of `req` so that it can be sent to both the main and the shadow components.
+pub(crate) async fn tee_request(
+    req: hyper::Request<hyper::body::Incoming>,
+) -> Result<(BufferedRequest, BufferedRequest)> {
+    let (parts, body) = req.into_parts();
+    let body = body.collect().await?.to_bytes();
+
+    let mut copy = hyper::Request::new(buffered_body(body.clone()));
+    *copy.method_mut() = parts.method.clone();
+    *copy.uri_mut() = parts.uri.clone();
+    *copy.version_mut() = parts.version;
+    *copy.headers_mut() = parts.headers.clone();
+
+    let req = hyper::Request::from_parts(parts, buffered_body(body));
+
+    Ok((req, copy))
+}
+
+fn buffered_body(body: Bytes) -> BoxBody<Bytes, hyper::Error> {
+    Full::new(body)
+        .map_err(|never: Infallible| match never {})
+        .boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record() {
+        let stats = ShadowStats::default();
+
+        stats.record(0, Some(StatusCode::OK), Ok(StatusCode::OK));
+        stats.record(1, Some(StatusCode::OK), Ok(StatusCode::NOT_FOUND));
+        stats.record(2, Some(StatusCode::OK), Err(anyhow::anyhow!("trap")));
+        stats.record(3, None, Err(anyhow::anyhow!("trap")));
+
+        assert_eq!(stats.requests.load(Ordering::Relaxed), 4);
+        assert_eq!(stats.diverged.load(Ordering::Relaxed), 2);
+        assert_eq!(stats.errors.load(Ordering::Relaxed), 2);
+    }
+}
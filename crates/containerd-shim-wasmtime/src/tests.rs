@@ -1,6 +1,6 @@
 use std::time::Duration;
 
-use containerd_shim_wasm::container::Instance;
+use containerd_shim_wasm::container::{Engine, Instance};
 use containerd_shim_wasm::testing::modules::*;
 use containerd_shim_wasm::testing::{oci_helpers, WasiTest};
 use serial_test::serial;
@@ -34,6 +34,25 @@ fn test_hello_world() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[test]
+#[serial]
+fn test_hello_world_shared_engine() -> anyhow::Result<()> {
+    // the engine is built once in the zygote, and inherited by both containers
+    assert!(WasmtimeEngine::shared());
+    for _ in 0..2 {
+        let (exit_code, stdout, _) = WasiTest::<WasiInstance>::builder()?
+            .with_wasm(HELLO_WORLD)?
+            .build()?
+            .start()?
+            .wait(Duration::from_secs(10))?;
+
+        assert_eq!(exit_code, 0);
+        assert_eq!(stdout, "hello world\n");
+    }
+
+    Ok(())
+}
+
 #[test]
 #[serial]
 fn test_hello_world_oci() -> anyhow::Result<()> {
@@ -302,10 +321,11 @@ fn test_wasip2_component_http_proxy_csharp() -> anyhow::Result<()> {
     Ok(())
 }
 
-// Test that the shim can terminate component targeting wasi:http/proxy by sending SIGTERM.
+// Test that the shim stops a component targeting wasi:http/proxy gracefully on SIGTERM,
+// as sent by containerd to stop its task.
 #[test]
 #[serial]
-fn test_wasip2_component_http_proxy_force_shutdown() -> anyhow::Result<()> {
+fn test_wasip2_component_http_proxy_graceful_shutdown_on_sigterm() -> anyhow::Result<()> {
     let srv = WasiTest::<WasiInstance>::builder()?
         .with_wasm(HELLO_WASI_HTTP)?
         .with_host_network()
@@ -314,10 +334,86 @@ fn test_wasip2_component_http_proxy_force_shutdown() -> anyhow::Result<()> {
     let srv = srv.start()?;
     assert!(http_get().unwrap().status().is_success());
 
-    // Send SIGTERM
+    // Send SIGTERM, the proxy has no request in flight and stops right away
     let (exit_code, _, _) = srv.terminate()?.wait(Duration::from_secs(5))?;
-    // The exit code indicates that the process did not exit cleanly
-    assert_eq!(exit_code, 128 + libc::SIGTERM as u32);
+    assert_eq!(exit_code, 0);
+
+    Ok(())
+}
+
+// Test that a component churning through resource handles is trapped once it holds more
+// than `max_resource_handles` of them.
+#[test]
+fn test_max_resource_handles() -> anyhow::Result<()> {
+    use wasmtime::component::{Component, Linker, ResourceTable};
+    use wasmtime::{Config, Store, StoreContextMut};
+    use wasmtime_wasi::{WasiCtx, WasiCtxBuilder, WasiView};
+
+    use crate::instance::limit_resource_handles;
+
+    struct Host {
+        ctx: WasiCtx,
+        table: ResourceTable,
+        pushed: u32,
+    }
+
+    impl WasiView for Host {
+        fn table(&mut self) -> &mut ResourceTable {
+            &mut self.table
+        }
+
+        fn ctx(&mut self) -> &mut WasiCtx {
+            &mut self.ctx
+        }
+    }
+
+    // calls the host to push a handle, until it traps
+    const LEAKY: &str = r#"(component
+        (import "push" (func $push))
+        (core func $push (canon lower (func $push)))
+        (core module $m
+            (import "host" "push" (func $push))
+            (func (export "run")
+                (loop $l
+                    call $push
+                    br $l)))
+        (core instance $i (instantiate $m
+            (with "host" (instance (export "push" (func $push))))))
+        (func (export "run") (canon lift (core func $i "run")))
+    )"#;
+
+    let mut config = Config::new();
+    config.wasm_component_model(true);
+    let engine = wasmtime::Engine::new(&config)?;
+    let component = Component::new(&engine, LEAKY)?;
+
+    let mut linker = Linker::<Host>::new(&engine);
+    linker
+        .root()
+        .func_wrap("push", |mut store: StoreContextMut<'_, Host>, (): ()| {
+            let host = store.data_mut();
+            host.table.push(())?;
+            host.pushed += 1;
+            Ok(())
+        })?;
+
+    let host = Host {
+        ctx: WasiCtxBuilder::new().build(),
+        table: ResourceTable::new(),
+        pushed: 0,
+    };
+    let mut store = Store::new(&engine, host);
+    limit_resource_handles(&mut store, Some(10));
+
+    let instance = linker.instantiate(&mut store, &component)?;
+    let run = instance.get_typed_func::<(), ()>(&mut store, "run")?;
+    let err = run.call(&mut store, ()).unwrap_err();
+
+    assert!(
+        format!("{err:?}").contains("more than 10 resource handles"),
+        "{err:?}"
+    );
+    assert_eq!(store.data().pushed, 11);
 
     Ok(())
 }